@@ -92,6 +92,20 @@ impl<T: VertexFormat> Vec3<T> {
         self
     }
 
+    // two unit vectors perpendicular to this unit vector, and to each other
+    pub fn orthonormal_basis(&self) -> (Vec3<T>, Vec3<T>) {
+        let helper = if self.x.abs() > T::from(0.9).unwrap() {
+            Vec3::new(T::zero(), T::one(), T::zero())
+        } else {
+            Vec3::new(T::one(), T::zero(), T::zero())
+        };
+
+        let tangent = self.cross(&helper).normalize();
+        let bitangent = self.cross(&tangent);
+
+        (tangent, bitangent)
+    }
+
     pub fn clip(&mut self, value: T) {
         self.x = self.x.min(value);
         self.y = self.y.min(value);
@@ -117,7 +131,7 @@ impl<T: VertexFormat> std::ops::Sub for Vec3<T> {
     }
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone)]
 pub struct Ray<T: VertexFormat> {
     pub origin: Vec3<T>,
     pub direction: Vec3<T>,
@@ -197,13 +211,76 @@ impl<T: VertexFormat> Display for Color<T> {
     }
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone)]
 pub struct Intersection<T: VertexFormat> {
     pub point: Vec3<T>,
     pub normal: Vec3<T>,
+    // surface texture coordinates of the point
+    pub uv: (T, T),
+    // unit vectors perpendicular to the normal, pointing along increasing u and v where the shape
+    // defines them. Tangent space normal and bump maps are relative to this frame.
+    pub tangent: Vec3<T>,
+    pub bitangent: Vec3<T>,
+    // weights of the first, second and third vertex at the point, when a triangle was hit
+    pub barycentric: Option<(T, T, T)>,
+    // whether the ray came from outside, the side the shape's outward normal points to. Two sided
+    // surfaces turn their normal toward the ray, so the normal alone can't tell.
+    pub front_face: bool,
 }
 
 impl<T: VertexFormat> Intersection<T> {
+    pub fn new(point: Vec3<T>, normal: Vec3<T>) -> Intersection<T> {
+        let (tangent, bitangent) = normal.orthonormal_basis();
+
+        Intersection {
+            point,
+            normal,
+            uv: (T::zero(), T::zero()),
+            tangent,
+            bitangent,
+            barycentric: None,
+            front_face: true,
+        }
+    }
+
+    // Sets the tangent frame from the surface's derivatives along u and v, which don't need to be
+    // normalized or perpendicular to the normal. The tangent is made perpendicular to the normal,
+    // and the bitangent only decides on which side of it the frame's third axis lies. Leaves the
+    // frame unchanged if the tangent is degenerate, like at the poles of a sphere.
+    pub fn set_tangent_frame(&mut self, tangent: &Vec3<T>, bitangent: &Vec3<T>) {
+        let tangent = tangent.sub(&self.normal.mul(self.normal.dot(tangent)));
+        if tangent.mag_sqrd() < T::from(1e-12).unwrap() {
+            return;
+        }
+
+        self.tangent = tangent.normalize();
+        self.bitangent = self.normal.cross(&self.tangent);
+        if self.bitangent.dot(bitangent) < T::zero() {
+            self.bitangent = self.bitangent.mul(T::one().neg());
+        }
+    }
+
+    // Ray leaving the surface in direction. The point has been shifted off the surface along the
+    // normal, so rays going into the surface start as far below it instead.
+    pub fn spawn_ray(&self, direction: Vec3<T>) -> Ray<T> {
+        if self.normal.dot(&direction) >= T::zero() {
+            return Ray::new(self.point.clone(), direction);
+        }
+
+        let difference = self.normal.mul(T::from(-2.0 * EPSILON).unwrap());
+
+        Ray::new(self.point.add(&difference), direction)
+    }
+
+    // turns the normal around, moving an epsilon shifted point over to the other side of the
+    // surface with it
+    pub fn flip_normal(&mut self) {
+        self.normal = self.normal.mul(T::one().neg());
+        self.point = self
+            .point
+            .add(&self.normal.mul(T::from(2.0 * EPSILON).unwrap()));
+    }
+
     pub fn epsilon_shift(&mut self) {
         let difference = T::from(EPSILON).unwrap();
 
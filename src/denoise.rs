@@ -0,0 +1,407 @@
+use crate::common::{Color, Vec3, VertexFormat};
+use crate::image::Image;
+use crate::scene::aov::{Aov, RenderPasses};
+use crate::scene::visible::texture::luminance;
+
+// weights of the B3 spline the filter spreads out further with every iteration
+const KERNEL: [f64; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+// albedo below this isn't divided out of the color, since there's no light to recover there
+const MIN_ALBEDO: f64 = 1e-3;
+
+// keeps the luminance weight finite where the variance is zero
+const VARIANCE_EPSILON: f64 = 1e-10;
+
+// Edge-avoiding à-trous wavelet filter, along the lines of SVGF. The 5x5 kernel is applied with
+// holes between its taps that double with every iteration, so a few of them cover a wide area
+// cheaply. Taps are weighted down across edges in the feature buffers: normals that turn away,
+// depths that jump, and luminance that differs by more than the noise, which is estimated from
+// the spread of the luminance around each pixel and filtered along with the color. The albedo is
+// divided out before filtering and multiplied back after, so textures stay sharp.
+pub struct Denoiser<T: VertexFormat> {
+    iterations: u32,
+    luminance_sigma: T,
+    normal_exponent: T,
+    depth_sigma: T,
+}
+
+// feature buffers, in the same order as the pixels. Any of them can be missing.
+struct Features<T: VertexFormat> {
+    albedo: Option<Vec<Vec3<T>>>,
+    normal: Option<Vec<Vec3<T>>>,
+    depth: Option<Vec<T>>,
+}
+
+impl<T: VertexFormat> Denoiser<T> {
+    pub fn new(iterations: u32) -> Denoiser<T> {
+        Denoiser {
+            iterations,
+            luminance_sigma: T::from(4.0).unwrap(),
+            normal_exponent: T::from(32.0).unwrap(),
+            depth_sigma: T::from(0.05).unwrap(),
+        }
+    }
+
+    // how many standard deviations of the noise luminance may differ by before taps are weighted
+    // down. Higher values blur more.
+    pub fn set_luminance_sigma(&mut self, sigma: T) {
+        self.luminance_sigma = sigma;
+    }
+
+    // power the cosine between normals is raised to. Higher values keep creases sharper.
+    pub fn set_normal_exponent(&mut self, exponent: T) {
+        self.normal_exponent = exponent;
+    }
+
+    // change in depth, relative to the depth and for every pixel between taps, that weighs them
+    // down by a factor of e
+    pub fn set_depth_sigma(&mut self, sigma: T) {
+        self.depth_sigma = sigma;
+    }
+
+    // Filters the noise out of an HDR render, as Scene::render_unclipped gives it, guided by the
+    // albedo, normal and depth passes of the same view, as rendered by Scene::render_passes.
+    // Passes that are missing aren't used. Clip the result only once it's denoised.
+    pub fn denoise(&self, beauty: &Image<T>, features: &RenderPasses<T>) -> Image<T> {
+        let (width, height) = (beauty.width() as usize, beauty.height() as usize);
+        let buffer = |aov: Aov| {
+            features.get(aov).map(|image| {
+                assert_eq!(
+                    (beauty.width(), beauty.height()),
+                    (image.width(), image.height()),
+                    "feature buffer size doesn't match the image"
+                );
+                image
+                    .iter()
+                    .map(|pixel| pixel.color_vector().clone())
+                    .collect::<Vec<_>>()
+            })
+        };
+        let features = Features {
+            albedo: buffer(Aov::Albedo).map(|albedo| albedo.iter().map(demodulation).collect()),
+            normal: buffer(Aov::Normal),
+            depth: buffer(Aov::Depth).map(|depth| depth.iter().map(|value| value.x).collect()),
+        };
+
+        let mut color: Vec<Vec3<T>> = beauty
+            .iter()
+            .map(|pixel| pixel.color_vector().clone())
+            .collect();
+        if let Some(albedo) = &features.albedo {
+            for (color, albedo) in color.iter_mut().zip(albedo) {
+                *color = Vec3::new(color.x / albedo.x, color.y / albedo.y, color.z / albedo.z);
+            }
+        }
+
+        let mut variance = luminance_variance(&color, width, height);
+        for i in 0..self.iterations {
+            let (next_color, next_variance) =
+                self.filter(&color, &variance, &features, (width, height), 1 << i);
+            color = next_color;
+            variance = next_variance;
+        }
+
+        if let Some(albedo) = &features.albedo {
+            for (color, albedo) in color.iter_mut().zip(albedo) {
+                *color = color.scalar_mul(albedo);
+            }
+        }
+
+        // the buffers start at the top row, while set_pixel counts from the bottom
+        let mut image = Image::new(beauty.width(), beauty.height());
+        for (index, color) in color.iter().enumerate() {
+            let (x, y) = (index % width, height - index / width - 1);
+            image.set_pixel(
+                x as u32,
+                y as u32,
+                Color::new_unclipped(color.x, color.y, color.z),
+            );
+        }
+
+        image
+    }
+
+    // one iteration of the filter, with taps step pixels apart
+    fn filter(
+        &self,
+        color: &[Vec3<T>],
+        variance: &[T],
+        features: &Features<T>,
+        size: (usize, usize),
+        step: usize,
+    ) -> (Vec<Vec3<T>>, Vec<T>) {
+        let (width, height) = size;
+        let blurred = blur(variance, width, height);
+        let epsilon = T::from(VARIANCE_EPSILON).unwrap();
+
+        let mut next_color = Vec::with_capacity(color.len());
+        let mut next_variance = Vec::with_capacity(color.len());
+        for p in 0..color.len() {
+            let (px, py) = ((p % width) as isize, (p / width) as isize);
+            let luminance_p = luminance(&color[p]);
+            let luminance_scale = self.luminance_sigma * (blurred[p].sqrt() + epsilon);
+
+            let mut sum = Vec3::new(T::zero(), T::zero(), T::zero());
+            let mut variance_sum = T::zero();
+            let mut total = T::zero();
+            for (dy, ky) in KERNEL.iter().enumerate() {
+                for (dx, kx) in KERNEL.iter().enumerate() {
+                    let offset = (dx as isize - 2, dy as isize - 2);
+                    let (qx, qy) = (px + offset.0 * step as isize, py + offset.1 * step as isize);
+                    if qx < 0 || qy < 0 || qx >= width as isize || qy >= height as isize {
+                        continue;
+                    }
+                    let q = qy as usize * width + qx as usize;
+
+                    let distance = (((offset.0 * offset.0 + offset.1 * offset.1) as f64).sqrt()
+                        * step as f64)
+                        .max(1.0);
+                    let difference = (luminance_p - luminance(&color[q])).abs();
+                    let weight = T::from(kx * ky).unwrap()
+                        * (difference / luminance_scale).neg().exp()
+                        * self.edge_weight(features, p, q, T::from(distance).unwrap());
+
+                    sum.mut_add(&color[q].mul(weight));
+                    variance_sum = variance_sum + weight * weight * variance[q];
+                    total = total + weight;
+                }
+            }
+
+            // the pixel itself always has a weight, so the total isn't zero
+            next_color.push(sum.div(total));
+            next_variance.push(variance_sum / (total * total));
+        }
+
+        (next_color, next_variance)
+    }
+
+    // how much pixel q may contribute to p going by their normals and depths, which are distance
+    // pixels apart
+    fn edge_weight(&self, features: &Features<T>, p: usize, q: usize, distance: T) -> T {
+        let mut weight = T::one();
+
+        if let Some(normal) = &features.normal {
+            let (normal_p, normal_q) = (&normal[p], &normal[q]);
+            // rays that miss have no normal, and only blend with each other
+            let missed = |normal: &Vec3<T>| normal.mag_sqrd() == T::zero();
+            if missed(normal_p) != missed(normal_q) {
+                return T::zero();
+            }
+            if !missed(normal_p) {
+                weight = weight
+                    * normal_p
+                        .dot(normal_q)
+                        .max(T::zero())
+                        .powf(self.normal_exponent);
+            }
+        }
+
+        if let Some(depth) = &features.depth {
+            let (depth_p, depth_q) = (depth[p], depth[q]);
+            if depth_p.is_finite() != depth_q.is_finite() {
+                return T::zero();
+            }
+            if depth_p.is_finite() && depth_p > T::zero() {
+                let scale = self.depth_sigma * depth_p * distance;
+                weight = weight * ((depth_p - depth_q).abs() / scale).neg().exp();
+            }
+        }
+
+        weight
+    }
+}
+
+// what the color is divided by for filtering, which leaves black surfaces and misses alone
+fn demodulation<T: VertexFormat>(albedo: &Vec3<T>) -> Vec3<T> {
+    let component = |value: T| {
+        if value < T::from(MIN_ALBEDO).unwrap() {
+            T::one()
+        } else {
+            value
+        }
+    };
+
+    Vec3::new(
+        component(albedo.x),
+        component(albedo.y),
+        component(albedo.z),
+    )
+}
+
+// spread of the luminance in the 3x3 pixels around every pixel, as the estimate of its noise
+fn luminance_variance<T: VertexFormat>(color: &[Vec3<T>], width: usize, height: usize) -> Vec<T> {
+    let luminances: Vec<T> = color.iter().map(luminance).collect();
+
+    (0..color.len())
+        .map(|p| {
+            let (px, py) = (p % width, p / width);
+            let (mut sum, mut sum_sqrd, mut count) = (T::zero(), T::zero(), T::zero());
+            for qy in py.saturating_sub(1)..(py + 2).min(height) {
+                for qx in px.saturating_sub(1)..(px + 2).min(width) {
+                    let value = luminances[qy * width + qx];
+                    sum = sum + value;
+                    sum_sqrd = sum_sqrd + value * value;
+                    count = count + T::one();
+                }
+            }
+
+            let mean = sum / count;
+            (sum_sqrd / count - mean * mean).max(T::zero())
+        })
+        .collect()
+}
+
+// 3x3 binomial blur, which steadies the variance the luminance weights are scaled by
+fn blur<T: VertexFormat>(values: &[T], width: usize, height: usize) -> Vec<T> {
+    let weights = [0.25, 0.5, 0.25];
+
+    (0..values.len())
+        .map(|p| {
+            let (px, py) = ((p % width) as isize, (p / width) as isize);
+            let (mut sum, mut total) = (T::zero(), T::zero());
+            for (dy, wy) in weights.iter().enumerate() {
+                for (dx, wx) in weights.iter().enumerate() {
+                    let (qx, qy) = (px + dx as isize - 1, py + dy as isize - 1);
+                    if qx < 0 || qy < 0 || qx >= width as isize || qy >= height as isize {
+                        continue;
+                    }
+                    let weight = T::from(wx * wy).unwrap();
+                    sum = sum + weight * values[qy as usize * width + qx as usize];
+                    total = total + weight;
+                }
+            }
+
+            sum / total
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::{RandomSampler, Sampler};
+    use crate::scene::camera::Camera;
+    use crate::scene::integrator::PathTracer;
+    use crate::scene::visible::bsdf::Lambertian;
+    use crate::scene::visible::plane::Plane;
+    use crate::scene::visible::sphere::Sphere;
+    use crate::scene::visible::Body;
+    use crate::scene::Scene;
+
+    fn mean_squared_error(image: &Image<f64>, reference: &Image<f64>) -> f64 {
+        let total: f64 = image
+            .iter()
+            .zip(reference.iter())
+            .map(|(a, b)| a.color_vector().sub(b.color_vector()).mag_sqrd())
+            .sum();
+
+        total / (image.width() * image.height()) as f64
+    }
+
+    // 16x16 pixels looking down -z at a plane, which the passes of the features are of
+    fn flat_features() -> RenderPasses<f64> {
+        let camera = Camera::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            16,
+            16,
+            60.0_f64.to_radians(),
+        );
+        let black = Color::new(0.0, 0.0, 0.0).unwrap();
+        let mut scene = Scene::new(camera, black.clone(), black);
+        scene.add_visible(Box::new(Body::new(
+            Box::new(Plane::new(
+                Vec3::new(0.0, 0.0, -1.0),
+                Vec3::new(0.0, 0.0, 1.0),
+            )),
+            Lambertian::new(Color::new(0.5, 0.5, 0.5).unwrap()),
+        )));
+
+        scene.render_passes(&[Aov::Albedo, Aov::Normal, Aov::Depth])
+    }
+
+    #[test]
+    fn smooths_noise_on_a_flat_surface() {
+        let mut sampler = RandomSampler::new(1);
+        let mut noisy = Image::new(16, 16);
+        let mut clean = Image::new(16, 16);
+        for y in 0..16 {
+            for x in 0..16 {
+                let value: f64 = 0.4 * Sampler::<f64>::next_1d(&mut sampler);
+                noisy.set_pixel(x, y, Color::new_unclipped(value, value, value));
+                clean.set_pixel(x, y, Color::new_unclipped(0.2, 0.2, 0.2));
+            }
+        }
+
+        let denoised = Denoiser::new(4).denoise(&noisy, &flat_features());
+
+        let before = mean_squared_error(&noisy, &clean);
+        let after = mean_squared_error(&denoised, &clean);
+        assert!(after < before / 10.0, "{} {}", before, after);
+    }
+
+    #[test]
+    fn keeps_edges_in_the_color() {
+        // noise free, with a bright right half
+        let mut image = Image::new(16, 16);
+        for y in 0..16 {
+            for x in 0..16 {
+                let value = if x < 8 { 0.1 } else { 2.0 };
+                image.set_pixel(x, y, Color::new_unclipped(value, value, value));
+            }
+        }
+
+        let denoised = Denoiser::new(5).denoise(&image, &flat_features());
+
+        // the spread in luminance along the edge looks like noise, so it softens a little, but
+        // stays a steep step with both sides kept further out
+        for y in 0..16 {
+            let value = |x: u32| denoised.pixel(x, y).color_vector().x;
+            assert!(value(8) - value(7) > 1.0);
+            assert!((value(2) - 0.1).abs() < 1e-3);
+            assert!((value(13) - 2.0).abs() < 2e-2);
+        }
+    }
+
+    #[test]
+    fn brings_a_path_traced_render_closer_to_converged() {
+        // a ball on a floor under a white sky, which lights them with noisy soft shadows
+        let camera = Camera::new(
+            Vec3::new(0.0, 0.8, 0.0),
+            Vec3::new(0.0, 1.2, 3.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            48,
+            48,
+            60.0_f64.to_radians(),
+        );
+        let mut scene = Scene::new(
+            camera,
+            Color::new(0.0, 0.0, 0.0).unwrap(),
+            Color::new(1.0, 1.0, 1.0).unwrap(),
+        );
+        scene.add_visible(Box::new(Body::new(
+            Box::new(Sphere::new(Vec3::new(0.0, 0.5, -2.0), 0.5)),
+            Lambertian::new(Color::new(0.8, 0.2, 0.2).unwrap()),
+        )));
+        scene.add_visible(Box::new(Body::new(
+            Box::new(Plane::new(
+                Vec3::new(0.0, 0.0, 0.0),
+                Vec3::new(0.0, 1.0, 0.0),
+            )),
+            Lambertian::new(Color::new(0.6, 0.6, 0.6).unwrap()),
+        )));
+
+        scene.set_integrator(Box::new(PathTracer::new(256)));
+        let reference = scene.render_unclipped();
+        scene.set_integrator(Box::new(PathTracer::new(4)));
+        let noisy = scene.render_unclipped();
+        let features = scene.render_passes(&[Aov::Albedo, Aov::Normal, Aov::Depth]);
+
+        let denoised = Denoiser::new(3).denoise(&noisy, &features);
+
+        let before = mean_squared_error(&noisy, &reference);
+        let after = mean_squared_error(&denoised, &reference);
+        assert!(after < before / 3.0, "{} {}", before, after);
+    }
+}
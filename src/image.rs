@@ -1,6 +1,7 @@
-use crate::common::{Color, VertexFormat};
+use crate::common::{Color, Vec3, VertexFormat};
 use std::slice::Iter;
 
+#[derive(Debug)]
 pub struct Image<T: VertexFormat> {
     width: u32,
     height: u32,
@@ -43,6 +44,31 @@ impl<T: VertexFormat> Image<T> {
         self.buffer[index] = color;
     }
 
+    // Adds light to a pixel without clipping it, for renderers that trace light towards the
+    // camera and spread it over the pixels it lands on
+    pub fn splat(&mut self, x: u32, y: u32, color: &Vec3<T>) {
+        if x >= self.width || y >= self.height {
+            panic!("Index out of bounds");
+        }
+
+        let y = self.height - y - 1;
+
+        let pixel = &mut self.buffer[(y * self.width + x) as usize];
+        let sum = pixel.color_vector().add(color);
+        *pixel = Color::new_unclipped(sum.x, sum.y, sum.z);
+    }
+
+    // same coordinates as set_pixel, with y = 0 being the bottom row
+    pub fn pixel(&self, x: u32, y: u32) -> &Color<T> {
+        if x >= self.width || y >= self.height {
+            panic!("Index out of bounds");
+        }
+
+        let y = self.height - y - 1;
+
+        &self.buffer[(y * self.width + x) as usize]
+    }
+
     pub fn iter(&self) -> ImageIterator<T> {
         ImageIterator {
             buffer_iter: self.buffer.iter(),
@@ -55,6 +81,19 @@ impl<T: VertexFormat> Image<T> {
     pub fn height(&self) -> u32 {
         self.height
     }
+
+    // copy with every color brighter than white clipped to it, for formats that can't hold more
+    pub fn clipped(&self) -> Image<T> {
+        Image {
+            width: self.width,
+            height: self.height,
+            buffer: self
+                .buffer
+                .iter()
+                .map(|pixel| Color::clipped(pixel.color_vector().clone()))
+                .collect(),
+        }
+    }
 }
 
 impl<T: VertexFormat> Iterator for Image<T> {
@@ -1,7 +1,9 @@
-use crate::common::VertexFormat;
+use crate::common::{Color, VertexFormat};
 use crate::image::Image;
+use crate::scene::aov::{Aov, RenderPasses};
+use crate::scene::voxel::DensityGrid;
 use std::fs::File;
-use std::io::{BufWriter, Write};
+use std::io::{self, BufWriter, Read, Write};
 use std::path::Path;
 
 pub fn write_image_ppm<T: VertexFormat>(filename: &str, image: &Image<T>) {
@@ -14,9 +16,667 @@ pub fn write_image_ppm<T: VertexFormat>(filename: &str, image: &Image<T>) {
     write!(output_stream, "{} {}\n", image.width(), image.height()).unwrap();
     write!(output_stream, "{}\n", 255).unwrap();
 
-    for pixel in image.iter() {
+    // bytes can't hold colors brighter than white
+    for pixel in image.clipped().iter() {
         write!(output_stream, " {} ", pixel).unwrap();
     }
 
     output_stream.flush().unwrap();
 }
+
+// Writes a portable float map, which keeps colors as they are instead of clipping them to bytes,
+// for render passes like normals and depth
+pub fn write_image_pfm<T: VertexFormat>(filename: &str, image: &Image<T>) -> io::Result<()> {
+    File::create(Path::new(filename))?.write_all(&encode_pfm(image))
+}
+
+// little endian floats, with the bottom row first
+fn encode_pfm<T: VertexFormat>(image: &Image<T>) -> Vec<u8> {
+    let mut data = format!("PF\n{} {}\n-1.0\n", image.width(), image.height()).into_bytes();
+    for y in 0..image.height() {
+        for x in 0..image.width() {
+            let color = image.pixel(x, y).color_vector();
+            for value in [color.x, color.y, color.z] {
+                data.extend_from_slice(&value.to_f32().unwrap().to_le_bytes());
+            }
+        }
+    }
+
+    data
+}
+
+// Writes every render pass to its own float map, named after the prefix and the pass, like
+// prefix_albedo.pfm
+pub fn write_passes_pfm<T: VertexFormat>(prefix: &str, passes: &RenderPasses<T>) -> io::Result<()> {
+    for (aov, image) in passes.passes() {
+        write_image_pfm(&format!("{}_{}.pfm", prefix, aov.name()), image)?;
+    }
+
+    Ok(())
+}
+
+// Writes the render passes as layers of one uncompressed OpenEXR image, which compositors open
+// as a whole. Channels of a pass are named after it, like albedo.R, except for the beauty pass,
+// which makes up the plain R, G and B channels that viewers show.
+pub fn write_passes_exr<T: VertexFormat>(
+    filename: &str,
+    passes: &RenderPasses<T>,
+) -> io::Result<()> {
+    File::create(Path::new(filename))?.write_all(&encode_exr(passes))
+}
+
+fn encode_exr<T: VertexFormat>(passes: &RenderPasses<T>) -> Vec<u8> {
+    let (width, height) = match passes.passes().first() {
+        Some((_, image)) => (image.width(), image.height()),
+        None => (0, 0),
+    };
+
+    // channels have to be sorted by name
+    let mut channels = Vec::new();
+    for (aov, image) in passes.passes() {
+        let prefix = match aov {
+            Aov::Beauty => String::new(),
+            _ => format!("{}.", aov.name()),
+        };
+        channels.push((format!("{}R", prefix), image, 0));
+        channels.push((format!("{}G", prefix), image, 1));
+        channels.push((format!("{}B", prefix), image, 2));
+    }
+    channels.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut list = Vec::new();
+    for (name, _, _) in &channels {
+        list.extend_from_slice(name.as_bytes());
+        list.push(0);
+        // 32 bit float, not linear, three reserved bytes, and no subsampling along x and y
+        list.extend_from_slice(&2_i32.to_le_bytes());
+        list.extend_from_slice(&[0, 0, 0, 0]);
+        list.extend_from_slice(&1_i32.to_le_bytes());
+        list.extend_from_slice(&1_i32.to_le_bytes());
+    }
+    list.push(0);
+
+    let mut window = Vec::new();
+    for corner in [0, 0, width as i32 - 1, height as i32 - 1] {
+        window.extend_from_slice(&corner.to_le_bytes());
+    }
+
+    // magic number, and version 2 of a single part scanline image
+    let mut data = vec![0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0];
+    let mut attribute = |name: &str, kind: &str, value: &[u8]| {
+        data.extend_from_slice(name.as_bytes());
+        data.push(0);
+        data.extend_from_slice(kind.as_bytes());
+        data.push(0);
+        data.extend_from_slice(&(value.len() as i32).to_le_bytes());
+        data.extend_from_slice(value);
+    };
+    attribute("channels", "chlist", &list);
+    attribute("compression", "compression", &[0]);
+    attribute("dataWindow", "box2i", &window);
+    attribute("displayWindow", "box2i", &window);
+    attribute("lineOrder", "lineOrder", &[0]);
+    attribute("pixelAspectRatio", "float", &1.0_f32.to_le_bytes());
+    attribute("screenWindowCenter", "v2f", &[0; 8]);
+    attribute("screenWindowWidth", "float", &1.0_f32.to_le_bytes());
+    data.push(0);
+
+    // offsets of the scanlines, which each take up their number, their size and the row of
+    // every channel in turn, from the top of the image down
+    let row_size = channels.len() * width as usize * 4;
+    let first = data.len() + height as usize * 8;
+    for y in 0..height as usize {
+        let offset = (first + y * (8 + row_size)) as u64;
+        data.extend_from_slice(&offset.to_le_bytes());
+    }
+
+    for y in 0..height {
+        data.extend_from_slice(&(y as i32).to_le_bytes());
+        data.extend_from_slice(&(row_size as i32).to_le_bytes());
+        for (_, image, component) in &channels {
+            for x in 0..width {
+                let color = image.pixel(x, height - y - 1).color_vector();
+                let value = [color.x, color.y, color.z][*component];
+                data.extend_from_slice(&value.to_f32().unwrap().to_le_bytes());
+            }
+        }
+    }
+
+    data
+}
+
+// Reads a plain (P3) or binary (P6) ppm image, as written by write_image_ppm
+pub fn read_image_ppm<T: VertexFormat>(filename: &str) -> io::Result<Image<T>> {
+    let mut data = Vec::new();
+    File::open(Path::new(filename))?.read_to_end(&mut data)?;
+
+    parse_ppm(&data)
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+// Returns the next whitespace separated token starting at position, skipping comments, and moves
+// position to just after it
+fn next_token<'a>(data: &'a [u8], position: &mut usize) -> io::Result<&'a str> {
+    loop {
+        while *position < data.len() && data[*position].is_ascii_whitespace() {
+            *position += 1;
+        }
+        if *position < data.len() && data[*position] == b'#' {
+            while *position < data.len() && data[*position] != b'\n' {
+                *position += 1;
+            }
+        } else {
+            break;
+        }
+    }
+
+    let start = *position;
+    while *position < data.len() && !data[*position].is_ascii_whitespace() {
+        *position += 1;
+    }
+    if start == *position {
+        return Err(invalid_data("unexpected end of ppm data"));
+    }
+
+    std::str::from_utf8(&data[start..*position]).map_err(|_| invalid_data("invalid ppm token"))
+}
+
+fn next_number(data: &[u8], position: &mut usize) -> io::Result<u32> {
+    next_token(data, position)?
+        .parse()
+        .map_err(|_| invalid_data("invalid number in ppm data"))
+}
+
+// Number of pixels of an image with the size read from a file, unless the image would be empty
+// or have too many pixels to index
+fn pixel_count(width: u32, height: u32) -> Option<usize> {
+    match width.checked_mul(height) {
+        Some(0) | None => None,
+        Some(count) => Some(count as usize),
+    }
+}
+
+fn parse_ppm<T: VertexFormat>(data: &[u8]) -> io::Result<Image<T>> {
+    let mut position = 0;
+
+    let binary = match next_token(data, &mut position)? {
+        "P3" => false,
+        "P6" => true,
+        _ => return Err(invalid_data("only P3 and P6 ppm images are supported")),
+    };
+    let width = next_number(data, &mut position)?;
+    let height = next_number(data, &mut position)?;
+    let pixels = pixel_count(width, height).ok_or_else(|| invalid_data("invalid ppm size"))?;
+    let max_value = next_number(data, &mut position)?;
+    if max_value == 0 || max_value > 65535 {
+        return Err(invalid_data("invalid ppm maximum value"));
+    }
+
+    // binary samples start after a single whitespace character, and take two bytes each for
+    // maximum values above 255
+    position += 1;
+    let sample_size = if max_value > 255 { 2 } else { 1 };
+
+    // every sample takes up at least a byte, so data too short for the size is rejected before
+    // allocating the image
+    let least_bytes = pixels
+        .checked_mul(3 * if binary { sample_size } else { 1 })
+        .ok_or_else(|| invalid_data("ppm image is too large"))?;
+    if data.len().saturating_sub(position) < least_bytes {
+        return Err(invalid_data("ppm data is too short for its size"));
+    }
+
+    let next_sample = |position: &mut usize| -> io::Result<T> {
+        let sample = if binary {
+            let bytes = data
+                .get(*position..*position + sample_size)
+                .ok_or_else(|| invalid_data("unexpected end of ppm data"))?;
+            *position += sample_size;
+            bytes
+                .iter()
+                .fold(0, |value, byte| value * 256 + *byte as u32)
+        } else {
+            next_number(data, position)?
+        };
+
+        Ok(T::from(sample.min(max_value)).unwrap() / T::from(max_value).unwrap())
+    };
+
+    let mut image = Image::new(width, height);
+    // rows are stored from the top down
+    for row in 0..height {
+        for x in 0..width {
+            let red = next_sample(&mut position)?;
+            let green = next_sample(&mut position)?;
+            let blue = next_sample(&mut position)?;
+
+            image.set_pixel(x, height - row - 1, Color::new(red, green, blue).unwrap());
+        }
+    }
+
+    Ok(image)
+}
+
+// Reads a Radiance hdr image, the format most HDR environment maps come in. Colors aren't
+// clipped, so they can be brighter than white.
+pub fn read_image_hdr<T: VertexFormat>(filename: &str) -> io::Result<Image<T>> {
+    let mut data = Vec::new();
+    File::open(Path::new(filename))?.read_to_end(&mut data)?;
+
+    parse_hdr(&data)
+}
+
+// Returns the line starting at position, without its newline, and moves position past it
+fn next_line<'a>(data: &'a [u8], position: &mut usize) -> io::Result<&'a str> {
+    let start = *position;
+    while *position < data.len() && data[*position] != b'\n' {
+        *position += 1;
+    }
+    if *position >= data.len() {
+        return Err(invalid_data("unexpected end of hdr header"));
+    }
+    *position += 1;
+
+    std::str::from_utf8(&data[start..*position - 1]).map_err(|_| invalid_data("invalid hdr header"))
+}
+
+// Reads one scanline of shared exponent pixels, either stored flat or run length encoded one
+// channel at a time
+fn read_scanline(data: &[u8], position: &mut usize, width: usize) -> io::Result<Vec<[u8; 4]>> {
+    let truncated = || invalid_data("unexpected end of hdr data");
+    let header = data.get(*position..*position + 4).ok_or_else(truncated)?;
+
+    let encoded = (8..32768).contains(&width) && header[0] == 2 && header[1] == 2;
+    if !encoded {
+        let bytes = data
+            .get(*position..*position + 4 * width)
+            .ok_or_else(truncated)?;
+        *position += 4 * width;
+
+        return Ok(bytes
+            .chunks_exact(4)
+            .map(|pixel| [pixel[0], pixel[1], pixel[2], pixel[3]])
+            .collect());
+    }
+
+    if ((header[2] as usize) << 8 | header[3] as usize) != width {
+        return Err(invalid_data("hdr scanline width mismatch"));
+    }
+    *position += 4;
+
+    let mut pixels = vec![[0; 4]; width];
+    for channel in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let count = *data.get(*position).ok_or_else(truncated)? as usize;
+            *position += 1;
+
+            // counts above 128 repeat the next byte, others are followed by that many bytes
+            let (run, repeated) = if count > 128 {
+                (count - 128, true)
+            } else {
+                (count, false)
+            };
+            if run == 0 || x + run > width {
+                return Err(invalid_data("invalid hdr run length"));
+            }
+
+            for pixel in pixels.iter_mut().skip(x).take(run) {
+                pixel[channel] = *data.get(*position).ok_or_else(truncated)?;
+                if !repeated {
+                    *position += 1;
+                }
+            }
+            if repeated {
+                *position += 1;
+            }
+            x += run;
+        }
+    }
+
+    Ok(pixels)
+}
+
+fn parse_hdr<T: VertexFormat>(data: &[u8]) -> io::Result<Image<T>> {
+    let mut position = 0;
+
+    let magic = next_line(data, &mut position)?;
+    if magic != "#?RADIANCE" && magic != "#?RGBE" {
+        return Err(invalid_data("not a Radiance hdr image"));
+    }
+    // variables like the pixel format, up to an empty line
+    loop {
+        let line = next_line(data, &mut position)?;
+        if line.is_empty() {
+            break;
+        }
+        if line.starts_with("FORMAT=") && line != "FORMAT=32-bit_rle_rgbe" {
+            return Err(invalid_data("only rgbe hdr images are supported"));
+        }
+    }
+
+    // only the standard orientation is supported, with rows stored from the top down
+    let resolution: Vec<&str> = next_line(data, &mut position)?.split_whitespace().collect();
+    let (height, width) = match resolution.as_slice() {
+        ["-Y", height, "+X", width] => (
+            height
+                .parse::<u32>()
+                .map_err(|_| invalid_data("invalid hdr height"))?,
+            width
+                .parse::<u32>()
+                .map_err(|_| invalid_data("invalid hdr width"))?,
+        ),
+        _ => return Err(invalid_data("unsupported hdr orientation")),
+    };
+    pixel_count(width, height).ok_or_else(|| invalid_data("invalid hdr size"))?;
+
+    // Data too short for the size is rejected before allocating the image. Run length encoded
+    // scanlines take at least their header and two bytes per run of up to 128 pixels in each
+    // channel, flat ones four bytes per pixel.
+    let columns = width as usize;
+    let least_scanline = if (8..32768).contains(&columns) {
+        4 + 8 * columns.div_ceil(128)
+    } else {
+        4 * columns
+    };
+    let least_bytes = least_scanline
+        .checked_mul(height as usize)
+        .ok_or_else(|| invalid_data("hdr image is too large"))?;
+    if data.len() - position < least_bytes {
+        return Err(invalid_data("hdr data is too short for its size"));
+    }
+
+    let mut image = Image::new(width, height);
+    for row in 0..height {
+        let scanline = read_scanline(data, &mut position, width as usize)?;
+
+        for (x, [red, green, blue, exponent]) in scanline.into_iter().enumerate() {
+            // the three mantissas share the exponent, which is offset by 128, plus 8 for the
+            // mantissas being bytes
+            let scale = if exponent == 0 {
+                T::zero()
+            } else {
+                T::from(2.0_f64.powi(exponent as i32 - 136)).unwrap()
+            };
+            let color = Color::new_unclipped(
+                T::from(red).unwrap() * scale,
+                T::from(green).unwrap() * scale,
+                T::from(blue).unwrap() * scale,
+            );
+
+            image.set_pixel(x as u32, height - row - 1, color);
+        }
+    }
+
+    Ok(image)
+}
+
+// Reads a raw density grid: its size along x, y and z as little endian 32 bit integers, then a
+// little endian 32 bit float for every voxel, with x varying fastest, then y, then z
+pub fn read_density_grid<T: VertexFormat>(filename: &str) -> io::Result<DensityGrid<T>> {
+    let mut data = Vec::new();
+    File::open(Path::new(filename))?.read_to_end(&mut data)?;
+
+    parse_density_grid(&data)
+}
+
+fn parse_density_grid<T: VertexFormat>(data: &[u8]) -> io::Result<DensityGrid<T>> {
+    let word = |index: usize| -> io::Result<[u8; 4]> {
+        data.get(index * 4..index * 4 + 4)
+            .map(|bytes| [bytes[0], bytes[1], bytes[2], bytes[3]])
+            .ok_or_else(|| invalid_data("unexpected end of density grid"))
+    };
+
+    let size = (
+        u32::from_le_bytes(word(0)?) as usize,
+        u32::from_le_bytes(word(1)?) as usize,
+        u32::from_le_bytes(word(2)?) as usize,
+    );
+    // sizes read from the file can be large enough to overflow
+    let too_large = || invalid_data("density grid is too large");
+    let count = size
+        .0
+        .checked_mul(size.1)
+        .and_then(|count| count.checked_mul(size.2))
+        .ok_or_else(too_large)?;
+    if count == 0 {
+        return Err(invalid_data("empty density grid"));
+    }
+    let length = count
+        .checked_add(3)
+        .and_then(|words| words.checked_mul(4))
+        .ok_or_else(too_large)?;
+    if data.len() != length {
+        return Err(invalid_data("density grid doesn't match its size"));
+    }
+
+    let values = (0..count)
+        .map(|i| {
+            let value = f32::from_le_bytes(word(3 + i)?);
+            if !value.is_finite() {
+                return Err(invalid_data(
+                    "density grid holds a density that isn't finite",
+                ));
+            }
+
+            Ok(T::from(value.max(0.0)).unwrap())
+        })
+        .collect::<io::Result<Vec<T>>>()?;
+
+    Ok(DensityGrid::new(size, values))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::Vec3;
+    use crate::scene::camera::Camera;
+    use crate::scene::Scene;
+    use std::convert::TryInto;
+
+    #[test]
+    fn parse_plain_ppm() {
+        let data = b"P3\n# comment\n2 1\n255\n255 0 0  0 0 255\n";
+
+        let image: Image<f64> = parse_ppm(data).unwrap();
+
+        assert_eq!(2, image.width());
+        assert_eq!(&Vec3::new(1.0, 0.0, 0.0), image.pixel(0, 0).color_vector());
+        assert_eq!(&Vec3::new(0.0, 0.0, 1.0), image.pixel(1, 0).color_vector());
+    }
+
+    #[test]
+    fn parse_binary_ppm() {
+        let mut data = b"P6 1 2 255\n".to_vec();
+        data.extend_from_slice(&[255, 255, 255, 0, 255, 0]);
+
+        let image: Image<f64> = parse_ppm(&data).unwrap();
+
+        // the first row in the file is the top of the image
+        assert_eq!(&Vec3::new(1.0, 1.0, 1.0), image.pixel(0, 1).color_vector());
+        assert_eq!(&Vec3::new(0.0, 1.0, 0.0), image.pixel(0, 0).color_vector());
+    }
+
+    #[test]
+    fn reject_ppm_sizes_that_are_empty_or_dont_fit_the_data() {
+        for header in [
+            &b"P3 0 0 255\n"[..],
+            b"P6 2 0 255\n",
+            b"P6 65536 65536 255\n",
+            b"P6 60000 60000 255\n",
+            b"P3 60000 60000 255\n0 0 0\n",
+        ]
+        .iter()
+        {
+            let result: io::Result<Image<f64>> = parse_ppm(header);
+
+            assert!(result.is_err());
+        }
+    }
+
+    #[test]
+    fn reject_unsupported_format() {
+        let result: io::Result<Image<f64>> = parse_ppm(b"P2 1 1 255 0");
+
+        assert!(result.is_err());
+    }
+
+    fn hdr_header(width: u32, height: u32) -> Vec<u8> {
+        format!(
+            "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n",
+            height, width
+        )
+        .into_bytes()
+    }
+
+    #[test]
+    fn parse_flat_hdr() {
+        let mut data = hdr_header(2, 1);
+        // 128 * 2^(129 - 136) = 1, and 128 * 2^(131 - 136) = 4
+        data.extend_from_slice(&[128, 64, 0, 129, 128, 128, 128, 131]);
+
+        let image: Image<f64> = parse_hdr(&data).unwrap();
+
+        assert_eq!(&Vec3::new(1.0, 0.5, 0.0), image.pixel(0, 0).color_vector());
+        assert_eq!(&Vec3::new(4.0, 4.0, 4.0), image.pixel(1, 0).color_vector());
+    }
+
+    #[test]
+    fn parse_run_length_encoded_hdr() {
+        let mut data = hdr_header(8, 1);
+        data.extend_from_slice(&[2, 2, 0, 8]);
+        // red: a run of 8, green: 8 literal bytes, blue: two runs of 4, exponent: a run of 8
+        data.extend_from_slice(&[136, 128]);
+        data.extend_from_slice(&[8, 0, 16, 32, 48, 64, 80, 96, 112]);
+        data.extend_from_slice(&[132, 0, 132, 128]);
+        data.extend_from_slice(&[136, 129]);
+
+        let image: Image<f64> = parse_hdr(&data).unwrap();
+
+        assert_eq!(&Vec3::new(1.0, 0.0, 0.0), image.pixel(0, 0).color_vector());
+        assert_eq!(
+            &Vec3::new(1.0, 0.875, 1.0),
+            image.pixel(7, 0).color_vector()
+        );
+    }
+
+    #[test]
+    fn reject_hdr_sizes_that_are_empty_or_dont_fit_the_data() {
+        for (width, height) in [(0, 4), (65536, 65536), (60000, 60000), (8, 100)].iter() {
+            let mut data = hdr_header(*width, *height);
+            data.extend_from_slice(&[2, 2, 0, 8, 136, 1, 136, 2, 136, 3, 136, 128]);
+
+            let result: io::Result<Image<f64>> = parse_hdr(&data);
+
+            assert!(result.is_err());
+        }
+    }
+
+    #[test]
+    fn reject_non_hdr_data() {
+        let result: io::Result<Image<f64>> = parse_hdr(b"P3\n1 1\n255\n0 0 0\n");
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn encode_float_map() {
+        let mut image: Image<f64> = Image::new(1, 2);
+        image.set_pixel(0, 0, Color::new_unclipped(-1.0, 0.5, 2.0));
+
+        let data = encode_pfm(&image);
+        let header = b"PF\n1 2\n-1.0\n";
+        assert_eq!(&header[..], &data[..header.len()]);
+
+        // the bottom row comes first
+        let first: Vec<f32> = data[header.len()..header.len() + 12]
+            .chunks(4)
+            .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            .collect();
+        assert_eq!(vec![-1.0, 0.5, 2.0], first);
+        assert_eq!(header.len() + 24, data.len());
+    }
+
+    #[test]
+    fn encode_passes_as_exr_layers() {
+        let camera = Camera::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            3,
+            2,
+            1.0,
+        );
+        let background = Color::new(0.25, 0.5, 0.75).unwrap();
+        let scene = Scene::new(camera, background.clone(), background);
+        let passes = scene.render_passes(&[Aov::Emission, Aov::Beauty]);
+
+        let data = encode_exr(&passes);
+        assert_eq!(&[0x76, 0x2f, 0x31, 0x01], &data[..4]);
+
+        // the beauty channels come first, since the others sort after them
+        let list = b"channels\0chlist\0";
+        let start = 8 + list.len() + 4;
+        assert_eq!(&list[..], &data[8..8 + list.len()]);
+        assert_eq!(b"B\0", &data[start..start + 2]);
+
+        // the last scanline ends the file, with six channels of three floats
+        let row_size = 6 * 3 * 4;
+        let table_end = data.len() - 2 * (8 + row_size);
+        let last = &data[table_end - 8..table_end];
+        let last = u64::from_le_bytes(last.try_into().unwrap()) as usize;
+        assert_eq!(data.len(), last + 8 + row_size);
+
+        // where the emission's blue, the background's everywhere, follows the beauty's channels
+        let blue = &data[last + 8 + 3 * 3 * 4..last + 8 + 3 * 3 * 4 + 4];
+        assert_eq!(0.75, f32::from_le_bytes(blue.try_into().unwrap()));
+    }
+
+    fn density_grid_data(size: [u32; 3], values: &[f32]) -> Vec<u8> {
+        let mut data = Vec::new();
+        for size in size.iter() {
+            data.extend_from_slice(&size.to_le_bytes());
+        }
+        for value in values {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+
+        data
+    }
+
+    #[test]
+    fn parse_raw_density_grid() {
+        let data = density_grid_data([2, 1, 1], &[0.25, 0.75]);
+
+        let grid: DensityGrid<f64> = parse_density_grid(&data).unwrap();
+        assert_eq!(0.75, grid.max());
+        assert_eq!(0.5, grid.density(&Vec3::new(0.5, 0.5, 0.5)));
+
+        // missing its last value
+        assert!(parse_density_grid::<f64>(&data[..16]).is_err());
+    }
+
+    #[test]
+    fn density_grid_sizes_that_overflow_are_rejected() {
+        // the voxel count overflows, and so does the length of the file it would need
+        for size in [[u32::MAX, u32::MAX, u32::MAX], [1 << 31, 1 << 31, 2]].iter() {
+            let data = density_grid_data(*size, &[1.0]);
+            let error = parse_density_grid::<f64>(&data).err().unwrap();
+
+            assert_eq!(io::ErrorKind::InvalidData, error.kind());
+        }
+    }
+
+    #[test]
+    fn density_grid_values_that_arent_finite_are_rejected() {
+        for value in [f32::NAN, f32::INFINITY].iter() {
+            let data = density_grid_data([2, 1, 1], &[0.5, *value]);
+            let error = parse_density_grid::<f64>(&data).err().unwrap();
+
+            assert_eq!(io::ErrorKind::InvalidData, error.kind());
+        }
+    }
+}
@@ -1,4 +1,8 @@
 pub mod common;
+pub mod denoise;
 pub mod image;
 pub mod io;
+pub mod noise;
+pub mod polynomial;
+pub mod sampler;
 pub mod scene;
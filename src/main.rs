@@ -1,18 +1,27 @@
 use ray_tracer::common::{Color, Vec3};
 use ray_tracer::io;
 use ray_tracer::scene::camera::Camera;
+use ray_tracer::scene::integrator::PathTracer;
 use ray_tracer::scene::light::{DirectionalLight, PointLight};
 use ray_tracer::scene::visible::material::Material;
 use ray_tracer::scene::visible::mesh::Triangle;
+use ray_tracer::scene::visible::pbr::PbrMaterial;
+use ray_tracer::scene::visible::plane::{Plane, Quad};
+use ray_tracer::scene::visible::procedural::{Checker, Mapping, Marble, Wood};
+use ray_tracer::scene::visible::sdf::{self, SdfShape};
 use ray_tracer::scene::visible::sphere::Sphere;
+use ray_tracer::scene::visible::texture::ConstantTexture;
 use ray_tracer::scene::visible::Body;
 use ray_tracer::scene::Scene;
+use std::rc::Rc;
 
 fn main() {
     // test("test.ppm");
     // diffuse("diffuse.ppm");
     // reflection("reflection.ppm")
     demo_complex("custom.ppm");
+    // mandelbulb("mandelbulb.ppm");
+    // pbr_spheres("pbr.ppm");
 }
 
 // execute to render a quick example scene
@@ -354,17 +363,12 @@ fn demo_complex(filename: &str) {
     );
     let body4 = Body::new(Box::new(sphere4), material4);
 
-    let floor1 = Triangle::new(
-        Vec3::new(-10.0, -1.0, 10.0),
-        Vec3::new(10.0, -1.0, -10.0),
+    let floor = Quad::new(
         Vec3::new(-10.0, -1.0, -10.0),
+        Vec3::new(20.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, 20.0),
     );
-    let floor2 = Triangle::new(
-        Vec3::new(10.0, -1.0, 10.0),
-        Vec3::new(10.0, -1.0, -10.0),
-        Vec3::new(-10.0, -1.0, 10.0),
-    );
-    let floor_mat = Material::new(
+    let mut floor_mat = Material::new(
         0.6,
         Color::new(1.0, 1.0, 1.0).unwrap(),
         0.4,
@@ -374,18 +378,28 @@ fn demo_complex(filename: &str) {
         ambiant.clone(),
         0.2,
     );
-    let floor_body1 = Body::new(Box::new(floor1), floor_mat.clone());
-    let floor_body2 = Body::new(Box::new(floor2), floor_mat);
-
-    let back_mirror1 = Triangle::new(
-        Vec3::new(-10.0, 10.0, -10.0),
+    // marble tiles, alternating with darker wood
+    let mut marble = Marble::new(
+        Rc::new(ConstantTexture::new(Color::new(0.95, 0.95, 0.92).unwrap())),
+        Rc::new(ConstantTexture::new(Color::new(0.3, 0.32, 0.35).unwrap())),
+        Mapping::Point,
+    );
+    marble.set_scale(0.8);
+    let mut wood = Wood::new(
+        Rc::new(ConstantTexture::new(Color::new(0.55, 0.35, 0.18).unwrap())),
+        Rc::new(ConstantTexture::new(Color::new(0.3, 0.16, 0.07).unwrap())),
+        Mapping::Point,
+    );
+    wood.set_scale(4.0);
+    let mut tiles = Checker::new(Rc::new(marble), Rc::new(wood), Mapping::Uv);
+    tiles.set_scale(10.0);
+    floor_mat.set_diffuse_texture(Rc::new(tiles));
+    let floor_body = Body::new(Box::new(floor), floor_mat);
+
+    let back_mirror = Quad::new(
         Vec3::new(-10.0, -1.0, -10.0),
-        Vec3::new(10.0, -1.0, -10.0),
-    );
-    let back_mirror2 = Triangle::new(
-        Vec3::new(10.0, -1.0, -10.0),
-        Vec3::new(10.0, 10.0, -10.0),
-        Vec3::new(-10.0, 10.0, -10.0),
+        Vec3::new(20.0, 0.0, 0.0),
+        Vec3::new(0.0, 11.0, 0.0),
     );
     let mirror_mat = Material::new(
         0.02,
@@ -397,8 +411,7 @@ fn demo_complex(filename: &str) {
         ambiant.clone(),
         0.95,
     );
-    let mirror_body1 = Body::new(Box::new(back_mirror1), mirror_mat.clone());
-    let mirror_body2 = Body::new(Box::new(back_mirror2), mirror_mat);
+    let mirror_body = Body::new(Box::new(back_mirror), mirror_mat);
 
     let body = Body::new(Box::new(sphere), material);
     let body1 = Body::new(Box::new(sphere1), material1);
@@ -408,10 +421,8 @@ fn demo_complex(filename: &str) {
     let visible2 = Box::new(body2);
     let visible3 = Box::new(body3);
     let visible4 = Box::new(body4);
-    let floor_vis1 = Box::new(floor_body1);
-    let floor_vis2 = Box::new(floor_body2);
-    let mirror_vis1 = Box::new(mirror_body1);
-    let mirror_vis2 = Box::new(mirror_body2);
+    let floor_vis = Box::new(floor_body);
+    let mirror_vis = Box::new(mirror_body);
 
     let light_source0 = Box::new(PointLight::new(
         Color::new(0.5, 0.5, 0.5).unwrap(),
@@ -471,10 +482,127 @@ fn demo_complex(filename: &str) {
     scene.add_visible(visible2);
     scene.add_visible(visible3);
     scene.add_visible(visible4);
-    scene.add_visible(floor_vis1);
-    scene.add_visible(floor_vis2);
-    scene.add_visible(mirror_vis1);
-    scene.add_visible(mirror_vis2);
+    scene.add_visible(floor_vis);
+    scene.add_visible(mirror_vis);
+
+    let image = scene.render();
+
+    io::write_image_ppm(filename, &image);
+}
+
+// execute to render a mandelbulb fractal, traced as a signed distance field, next to a smoothly
+// blended and twisted pair of rounded boxes
+fn mandelbulb(filename: &str) {
+    let camera = Camera::new(
+        Vec3::new(2.256, 0.705, 0.353),
+        Vec3::new(3.2, 1.0, 0.5),
+        Vec3::new(0.0, 1.0, 0.0),
+        1080,
+        1080,
+        45.0_f64.to_radians(),
+    );
+
+    let ambient_color = Color::new(0.15, 0.15, 0.2).unwrap();
+    let background_color = Color::new(0.05, 0.05, 0.08).unwrap();
+
+    let mut bulb = SdfShape::new(sdf::mandelbulb(8.0, 10), Vec3::new(0.0, 0.0, 0.0));
+    bulb.set_step_scale(0.7);
+    let bulb_mat = Material::new(
+        0.8,
+        Color::new(0.9, 0.6, 0.3).unwrap(),
+        0.3,
+        Color::new(1.0, 1.0, 1.0).unwrap(),
+        32.0,
+        1.0,
+        ambient_color.clone(),
+        0.0,
+    );
+    let bulb_body = Body::new(Box::new(bulb), bulb_mat);
+
+    let blend = sdf::smooth_union(
+        sdf::round_box(Vec3::new(0.15, 0.5, 0.15), 0.05),
+        sdf::translate(sdf::sphere(0.2), Vec3::new(0.0, 0.5, 0.0)),
+        0.15,
+    );
+    let mut pillar = SdfShape::new(
+        sdf::translate(sdf::twist(blend, 2.0), Vec3::new(0.4, -0.2, 1.25)),
+        Vec3::new(0.4, -0.2, 1.25),
+    );
+    pillar.set_step_scale(0.5);
+    let pillar_mat = Material::new(
+        0.7,
+        Color::new(0.3, 0.5, 0.9).unwrap(),
+        0.5,
+        Color::new(1.0, 1.0, 1.0).unwrap(),
+        64.0,
+        1.0,
+        ambient_color.clone(),
+        0.1,
+    );
+    let pillar_body = Body::new(Box::new(pillar), pillar_mat);
+
+    let key_light = DirectionalLight::new(
+        Color::new_unclipped(0.9, 0.9, 0.8),
+        Vec3::new(1.0, 1.0, 0.5).normalize(),
+    );
+    let fill_light = PointLight::new(
+        Color::new(0.3, 0.3, 0.5).unwrap(),
+        Vec3::new(-2.0, -1.0, 2.0),
+    );
+
+    let mut scene = Scene::new(camera, ambient_color, background_color);
+
+    scene.add_light(Box::new(key_light));
+    scene.add_light(Box::new(fill_light));
+
+    scene.add_visible(Box::new(bulb_body));
+    scene.add_visible(Box::new(pillar_body));
+
+    let image = scene.render();
+
+    io::write_image_ppm(filename, &image);
+}
+
+// execute to render rows of physically based spheres, metals at the back and plastics at the
+// front, getting rougher from left to right. Path traced, with the background acting as a sky.
+fn pbr_spheres(filename: &str) {
+    let camera = Camera::new(
+        Vec3::new(0.0, 1.194, 2.286),
+        Vec3::new(0.0, 1.6, 3.2),
+        Vec3::new(0.0, 1.0, 0.0),
+        1920,
+        1080,
+        60.0_f64.to_radians(),
+    );
+
+    let ambient_color = Color::new(0.1, 0.1, 0.1).unwrap();
+    let background_color = Color::new(0.5, 0.6, 0.75).unwrap();
+
+    let mut scene = Scene::new(camera, ambient_color, background_color);
+
+    for i in 0..5 {
+        let roughness = 0.05 + 0.2 * i as f64;
+        let x = -2.0 + i as f64;
+
+        let gold = PbrMaterial::new(Color::new(1.0, 0.78, 0.34).unwrap(), 1.0, roughness);
+        let metal_sphere = Sphere::new(Vec3::new(x, 0.0, -1.0), 0.4);
+        scene.add_visible(Box::new(Body::new(Box::new(metal_sphere), gold)));
+
+        let plastic = PbrMaterial::new(Color::new(0.7, 0.1, 0.1).unwrap(), 0.0, roughness);
+        let plastic_sphere = Sphere::new(Vec3::new(x, 0.0, 0.2), 0.4);
+        scene.add_visible(Box::new(Body::new(Box::new(plastic_sphere), plastic)));
+    }
+
+    let floor = Plane::new(Vec3::new(0.0, -0.4, 0.0), Vec3::new(0.0, 1.0, 0.0));
+    let floor_mat = PbrMaterial::new(Color::new(0.5, 0.5, 0.5).unwrap(), 0.0, 0.8);
+    scene.add_visible(Box::new(Body::new(Box::new(floor), floor_mat)));
+
+    scene.add_light(Box::new(DirectionalLight::new(
+        Color::new(0.8, 0.75, 0.7).unwrap(),
+        Vec3::new(-1.0, 2.0, 1.0).normalize(),
+    )));
+
+    scene.set_integrator(Box::new(PathTracer::new(64)));
 
     let image = scene.render();
 
@@ -0,0 +1,246 @@
+use crate::common::{Vec3, VertexFormat};
+
+// Deterministic noise functions over 3D space, used by the procedural textures. Every function
+// takes a seed, so different textures can use uncorrelated noise.
+
+fn constant<T: VertexFormat>(value: f64) -> T {
+    T::from(value).unwrap()
+}
+
+// finalizer of the splitmix64 generator, scrambles every input bit into every output bit
+fn mix(mut h: u64) -> u64 {
+    h = (h ^ (h >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    h = (h ^ (h >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    h ^ (h >> 31)
+}
+
+// number of lattice cells after which the noise repeats along each axis
+const PERIOD: i64 = 1 << 32;
+
+// lattice cell a coordinate falls into. The coordinate is wrapped to the period first, so points
+// too far out to convert to an integer still land in a cell. Coordinates that aren't numbers
+// land in the cell at the origin.
+fn cell<T: VertexFormat>(coordinate: T) -> i64 {
+    (coordinate.floor() % constant(PERIOD as f64))
+        .to_i64()
+        .unwrap_or(0)
+}
+
+// pseudo random value for an integer lattice point
+fn hash(x: i64, y: i64, z: i64, seed: u64) -> u64 {
+    let mut h = mix(seed.wrapping_add(0x9e37_79b9_7f4a_7c15));
+    for coordinate in [x, y, z].iter() {
+        h = mix(h ^ coordinate.rem_euclid(PERIOD) as u64);
+    }
+    h
+}
+
+// maps a hash to [0, 1)
+fn unit<T: VertexFormat>(h: u64) -> T {
+    T::from((h >> 11) as f64 / (1u64 << 53) as f64).unwrap()
+}
+
+// 6t^5 - 15t^4 + 10t^3, which has zero first and second derivatives at 0 and 1
+fn fade<T: VertexFormat>(t: T) -> T {
+    t * t * t * (t * (t * constant(6.0) - constant(15.0)) + constant(10.0))
+}
+
+fn lerp<T: VertexFormat>(t: T, a: T, b: T) -> T {
+    a + (b - a) * t
+}
+
+// dot product of the offset from a lattice point with one of the 12 gradients pointing to the
+// edges of a cube, picked by the lattice point's hash
+fn gradient<T: VertexFormat>(h: u64, x: T, y: T, z: T) -> T {
+    match h % 12 {
+        0 => x + y,
+        1 => -x + y,
+        2 => x - y,
+        3 => -x - y,
+        4 => x + z,
+        5 => -x + z,
+        6 => x - z,
+        7 => -x - z,
+        8 => y + z,
+        9 => -y + z,
+        10 => y - z,
+        _ => -y - z,
+    }
+}
+
+// Improved Perlin gradient noise. Zero at every integer lattice point, and roughly within
+// [-1, 1] elsewhere.
+pub fn perlin<T: VertexFormat>(point: &Vec3<T>, seed: u64) -> T {
+    let (x0, y0, z0) = (point.x.floor(), point.y.floor(), point.z.floor());
+    let (x, y, z) = (point.x - x0, point.y - y0, point.z - z0);
+    let (xi, yi, zi) = (cell(x0), cell(y0), cell(z0));
+    let (u, v, w) = (fade(x), fade(y), fade(z));
+
+    let corner = |dx: i64, dy: i64, dz: i64| {
+        gradient(
+            hash(xi + dx, yi + dy, zi + dz, seed),
+            x - T::from(dx).unwrap(),
+            y - T::from(dy).unwrap(),
+            z - T::from(dz).unwrap(),
+        )
+    };
+
+    lerp(
+        w,
+        lerp(
+            v,
+            lerp(u, corner(0, 0, 0), corner(1, 0, 0)),
+            lerp(u, corner(0, 1, 0), corner(1, 1, 0)),
+        ),
+        lerp(
+            v,
+            lerp(u, corner(0, 0, 1), corner(1, 0, 1)),
+            lerp(u, corner(0, 1, 1), corner(1, 1, 1)),
+        ),
+    )
+}
+
+// Fractal Brownian motion: octaves of Perlin noise, each one lacunarity times the frequency and
+// gain times the amplitude of the previous one. Normalized to stay roughly within [-1, 1].
+pub fn fbm<T: VertexFormat>(point: &Vec3<T>, octaves: u32, lacunarity: T, gain: T, seed: u64) -> T {
+    let mut sum = T::zero();
+    let mut total_amplitude = T::zero();
+    let mut amplitude = T::one();
+    let mut frequency = T::one();
+
+    for octave in 0..octaves {
+        sum = sum + perlin(&point.mul(frequency), seed.wrapping_add(octave as u64)) * amplitude;
+        total_amplitude = total_amplitude + amplitude;
+        amplitude = amplitude * gain;
+        frequency = frequency * lacunarity;
+    }
+
+    sum / total_amplitude
+}
+
+// Like fbm, but summing the absolute value of each octave, which gives sharp creases. Roughly
+// within [0, 1].
+pub fn turbulence<T: VertexFormat>(
+    point: &Vec3<T>,
+    octaves: u32,
+    lacunarity: T,
+    gain: T,
+    seed: u64,
+) -> T {
+    let mut sum = T::zero();
+    let mut total_amplitude = T::zero();
+    let mut amplitude = T::one();
+    let mut frequency = T::one();
+
+    for octave in 0..octaves {
+        sum =
+            sum + perlin(&point.mul(frequency), seed.wrapping_add(octave as u64)).abs() * amplitude;
+        total_amplitude = total_amplitude + amplitude;
+        amplitude = amplitude * gain;
+        frequency = frequency * lacunarity;
+    }
+
+    sum / total_amplitude
+}
+
+// where the worley noise places the random point of a lattice cell, relative to the cell's
+// lowest corner
+fn feature_offset<T: VertexFormat>(x: i64, y: i64, z: i64, seed: u64) -> Vec3<T> {
+    let h = hash(x, y, z, seed);
+
+    Vec3::new(unit(h), unit(mix(h ^ 1)), unit(mix(h ^ 2)))
+}
+
+// Worley (cellular) noise: the distance to the closest of a set of random points, one per
+// lattice cell. Within [0, sqrt(3)], but mostly below 1.
+pub fn worley<T: VertexFormat>(point: &Vec3<T>, seed: u64) -> T {
+    let corner = Vec3::new(point.x.floor(), point.y.floor(), point.z.floor());
+    let (xi, yi, zi) = (cell(corner.x), cell(corner.y), cell(corner.z));
+    // distances are measured within the neighbourhood of the cell, which stays precise far out
+    let local = point.sub(&corner);
+
+    let mut nearest = T::infinity();
+    for dx in -1..=1 {
+        for dy in -1..=1 {
+            for dz in -1..=1 {
+                let feature = Vec3::new(
+                    T::from(dx).unwrap(),
+                    T::from(dy).unwrap(),
+                    T::from(dz).unwrap(),
+                )
+                .add(&feature_offset(xi + dx, yi + dy, zi + dz, seed));
+                nearest = nearest.min(feature.sub(&local).mag_sqrd());
+            }
+        }
+    }
+
+    nearest.sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_points() -> Vec<Vec3<f64>> {
+        (0..200)
+            .map(|i| {
+                let i = i as f64;
+                Vec3::new(i * 0.37 - 20.0, i * 0.11, -i * 0.23)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn perlin_zero_on_lattice() {
+        for point in &[
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(3.0, -2.0, 7.0),
+            Vec3::new(-5.0, 1.0, -1.0),
+        ] {
+            assert_eq!(0.0, perlin(point, 3));
+        }
+    }
+
+    #[test]
+    fn perlin_bounded_and_seeded() {
+        let points = sample_points();
+
+        assert!(points.iter().all(|p| perlin(p, 0).abs() <= 1.0));
+        assert_eq!(perlin(&points[5], 7), perlin(&points[5], 7));
+        assert!(points.iter().any(|p| perlin(p, 0) != perlin(p, 1)));
+    }
+
+    #[test]
+    fn single_octave_fbm_is_perlin() {
+        for point in sample_points() {
+            assert_eq!(perlin(&point, 4), fbm(&point, 1, 2.0, 0.5, 4));
+        }
+    }
+
+    #[test]
+    fn worley_zero_at_feature_points() {
+        let feature = Vec3::new(2.0, -1.0, 0.0).add(&feature_offset(2, -1, 0, 9));
+
+        assert!(worley(&feature, 9) < 1e-12);
+        assert!(sample_points()
+            .iter()
+            .all(|p| (0.0..=3.0_f64.sqrt()).contains(&worley(p, 9))));
+    }
+
+    #[test]
+    fn far_away_points_dont_leave_the_lattice() {
+        let far: Vec3<f64> = Vec3::new(0.5, 1e20, -1e20);
+        assert!(perlin(&far, 2).abs() <= 1.0);
+        assert!((0.0..=3.0_f64.sqrt()).contains(&worley(&far, 2)));
+        // points that aren't finite give values that aren't either, but still have a cell
+        let broken: Vec3<f64> = Vec3::new(f64::NAN, 0.5, f64::INFINITY);
+        assert!(perlin(&broken, 2).is_nan());
+        worley(&broken, 2);
+
+        // the noise repeats with the lattice period
+        let point = Vec3::new(0.3, 0.6, 0.2);
+        let repeated = point.add(&Vec3::new(PERIOD as f64, 0.0, -(PERIOD as f64)));
+        assert!((perlin(&point, 5) - perlin(&repeated, 5)).abs() < 1e-6);
+        assert!((worley(&point, 5) - worley(&repeated, 5)).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,282 @@
+use crate::common::VertexFormat;
+
+// Real root finding for low order polynomials, used by the analytic primitives. Coefficients are
+// given from the highest order term down, and roots are returned in ascending order.
+
+// most Newton steps taken to polish a root. Roots next to nearly real complex pairs can come out
+// of the closed forms with only a few digits right, and take a few steps more than the rest.
+const NEWTON_ITERATIONS: u32 = 8;
+
+fn constant<T: VertexFormat>(value: f64) -> T {
+    T::from(value).unwrap()
+}
+
+// values within this distance of zero are treated as zero when deciding the number of roots
+fn is_zero<T: VertexFormat>(value: T) -> bool {
+    value.abs() < constant(1e-9)
+}
+
+// real cube root that keeps the sign of its argument
+fn cbrt<T: VertexFormat>(value: T) -> T {
+    if value < T::zero() {
+        -(-value).powf(constant(1.0 / 3.0))
+    } else {
+        value.powf(constant(1.0 / 3.0))
+    }
+}
+
+// evaluates the polynomial and its derivative at x using Horner's method
+fn evaluate<T: VertexFormat>(coefficients: &[T], x: T) -> (T, T) {
+    let mut value = T::zero();
+    let mut derivative = T::zero();
+
+    for coefficient in coefficients {
+        derivative = derivative * x + value;
+        value = value * x + *coefficient;
+    }
+
+    (value, derivative)
+}
+
+// refines roots found in closed form, which can lose a lot of precision for the quartic
+fn polish<T: VertexFormat>(coefficients: &[T], roots: &mut Vec<T>) {
+    for root in roots.iter_mut() {
+        for _ in 0..NEWTON_ITERATIONS {
+            let (value, derivative) = evaluate(coefficients, *root);
+            if derivative == T::zero() {
+                break;
+            }
+            let step = value / derivative;
+            *root = *root - step;
+            if step.abs() <= root.abs() * T::epsilon() {
+                break;
+            }
+        }
+    }
+
+    // coefficients far out of range can turn roots into NaN
+    roots.retain(|root| root.is_finite());
+    roots.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    roots.dedup();
+}
+
+// solves a x^2 + b x + c = 0. Degrades to the linear equation if a is zero.
+pub fn solve_quadratic<T: VertexFormat>(a: T, b: T, c: T) -> Vec<T> {
+    if a == T::zero() {
+        if b == T::zero() {
+            return Vec::new();
+        }
+        return vec![-c / b];
+    }
+
+    let discriminant = b * b - constant::<T>(4.0) * a * c;
+
+    if discriminant < T::zero() {
+        return Vec::new();
+    }
+
+    if discriminant == T::zero() {
+        return vec![-b / (constant::<T>(2.0) * a)];
+    }
+
+    // avoids the cancellation of -b + sqrt(discriminant) when b is large
+    let q = if b < T::zero() {
+        constant::<T>(-0.5) * (b - discriminant.sqrt())
+    } else {
+        constant::<T>(-0.5) * (b + discriminant.sqrt())
+    };
+
+    let mut roots = if q == T::zero() {
+        vec![T::zero()]
+    } else {
+        vec![q / a, c / q]
+    };
+
+    // coefficients too large to square make the discriminant NaN
+    roots.retain(|root| root.is_finite());
+    roots.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    roots
+}
+
+// solves a x^3 + b x^2 + c x + d = 0
+pub fn solve_cubic<T: VertexFormat>(a: T, b: T, c: T, d: T) -> Vec<T> {
+    if a == T::zero() {
+        return solve_quadratic(b, c, d);
+    }
+
+    // normal form x^3 + A x^2 + B x + C = 0
+    let a_n = b / a;
+    let b_n = c / a;
+    let c_n = d / a;
+
+    // substitute x = y - A / 3 to get the depressed cubic y^3 + 3 p y + 2 q = 0
+    let sq_a = a_n * a_n;
+    let p = constant::<T>(1.0 / 3.0) * (constant::<T>(-1.0 / 3.0) * sq_a + b_n);
+    let q = constant::<T>(0.5)
+        * (constant::<T>(2.0 / 27.0) * a_n * sq_a - constant::<T>(1.0 / 3.0) * a_n * b_n + c_n);
+
+    let cb_p = p * p * p;
+    let discriminant = q * q + cb_p;
+
+    let mut roots = if is_zero(discriminant) {
+        if is_zero(q) {
+            // one triple root
+            vec![T::zero()]
+        } else {
+            // one single and one double root
+            let u = cbrt(-q);
+            vec![constant::<T>(2.0) * u, -u]
+        }
+    } else if discriminant < T::zero() {
+        // three real roots, found using the trigonometric method
+        let phi = constant::<T>(1.0 / 3.0) * (-q / (-cb_p).sqrt()).acos();
+        let t = constant::<T>(2.0) * (-p).sqrt();
+        let third = constant::<T>(std::f64::consts::PI / 3.0);
+
+        vec![
+            t * phi.cos(),
+            -t * (phi + third).cos(),
+            -t * (phi - third).cos(),
+        ]
+    } else {
+        // one real root
+        let sqrt_d = discriminant.sqrt();
+        vec![cbrt(sqrt_d - q) - cbrt(sqrt_d + q)]
+    };
+
+    let shift = constant::<T>(1.0 / 3.0) * a_n;
+    for root in roots.iter_mut() {
+        *root = *root - shift;
+    }
+
+    polish(&[a, b, c, d], &mut roots);
+    roots
+}
+
+// solves a x^4 + b x^3 + c x^2 + d x + e = 0, using Ferrari's method
+pub fn solve_quartic<T: VertexFormat>(a: T, b: T, c: T, d: T, e: T) -> Vec<T> {
+    if a == T::zero() {
+        return solve_cubic(b, c, d, e);
+    }
+
+    // normal form x^4 + A x^3 + B x^2 + C x + D = 0
+    let a_n = b / a;
+    let b_n = c / a;
+    let c_n = d / a;
+    let d_n = e / a;
+
+    // substitute x = y - A / 4 to get the depressed quartic y^4 + p y^2 + q y + r = 0
+    let sq_a = a_n * a_n;
+    let p = constant::<T>(-3.0 / 8.0) * sq_a + b_n;
+    let q = constant::<T>(1.0 / 8.0) * sq_a * a_n - constant::<T>(0.5) * a_n * b_n + c_n;
+    let r = constant::<T>(-3.0 / 256.0) * sq_a * sq_a + constant::<T>(1.0 / 16.0) * sq_a * b_n
+        - constant::<T>(0.25) * a_n * c_n
+        + d_n;
+
+    let mut roots = if is_zero(r) {
+        // no absolute term: y (y^3 + p y + q) = 0
+        let mut roots = solve_cubic(T::one(), T::zero(), p, q);
+        roots.push(T::zero());
+        roots
+    } else {
+        // solve the resolvent cubic, and use its largest root to split the quartic into two
+        // quadratic equations. Smaller roots can make z^2 - r or 2 z - p negative and lose real
+        // roots, while for the largest one that only happens through rounding. Coefficients far
+        // out of range can leave it without any finite root, and the quartic without any too.
+        let resolvent = solve_cubic(
+            T::one(),
+            constant::<T>(-0.5) * p,
+            -r,
+            constant::<T>(0.5) * r * p - constant::<T>(1.0 / 8.0) * q * q,
+        );
+        let z = match resolvent.last() {
+            Some(z) => *z,
+            None => return Vec::new(),
+        };
+
+        let root = |value: T| {
+            if is_zero(value) || value < T::zero() {
+                T::zero()
+            } else {
+                value.sqrt()
+            }
+        };
+        let u = root(z * z - r);
+        let v = root(constant::<T>(2.0) * z - p);
+
+        let v_signed = if q < T::zero() { -v } else { v };
+
+        let mut roots = solve_quadratic(T::one(), v_signed, z - u);
+        roots.append(&mut solve_quadratic(T::one(), -v_signed, z + u));
+        roots
+    };
+
+    let shift = constant::<T>(0.25) * a_n;
+    for root in roots.iter_mut() {
+        *root = *root - shift;
+    }
+
+    polish(&[a, b, c, d, e], &mut roots);
+    roots
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_roots(expected: &[f64], roots: &[f64]) {
+        assert_eq!(expected.len(), roots.len(), "roots: {:?}", roots);
+        for (expected, root) in expected.iter().zip(roots) {
+            assert!((expected - root).abs() < 1e-9, "roots: {:?}", roots);
+        }
+    }
+
+    #[test]
+    fn quadratic_roots() {
+        // (x - 1)(x + 3)
+        assert_roots(&[-3.0, 1.0], &solve_quadratic(1.0, 2.0, -3.0));
+        assert_roots(&[], &solve_quadratic(1.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn cubic_roots() {
+        // (x - 1)(x - 2)(x - 3)
+        assert_roots(&[1.0, 2.0, 3.0], &solve_cubic(1.0, -6.0, 11.0, -6.0));
+        // (x - 2)(x^2 + 1)
+        assert_roots(&[2.0], &solve_cubic(1.0, -2.0, 1.0, -2.0));
+    }
+
+    #[test]
+    fn quartic_roots() {
+        // (x - 1)(x - 2)(x - 3)(x - 4)
+        assert_roots(
+            &[1.0, 2.0, 3.0, 4.0],
+            &solve_quartic(1.0, -10.0, 35.0, -50.0, 24.0),
+        );
+        // (x^2 - 4)(x^2 + 1)
+        assert_roots(&[-2.0, 2.0], &solve_quartic(1.0, 0.0, -3.0, 0.0, -4.0));
+        // (x^2 + 1)(x^2 + 4)
+        assert_roots(&[], &solve_quartic(1.0, 0.0, 5.0, 0.0, 4.0));
+    }
+
+    #[test]
+    fn quartic_roots_next_to_a_complex_pair() {
+        // (x + 4.5)(x + 4)((x + 4.3)^2 + 0.01), which the smallest root of the resolvent cubic
+        // splits into two quadratics without real roots
+        let roots: Vec<f64> = solve_quartic(1.0, 17.1, 109.6, 312.05, 333.0);
+
+        assert_eq!(2, roots.len(), "roots: {:?}", roots);
+        assert!((roots[0] + 4.5).abs() < 1e-6, "roots: {:?}", roots);
+        assert!((roots[1] + 4.0).abs() < 1e-6, "roots: {:?}", roots);
+    }
+
+    #[test]
+    fn coefficients_out_of_range_give_no_roots() {
+        // b^2 and 4ac both overflow, leaving the discriminant NaN
+        assert_roots(&[], &solve_quadratic(1e200, 1e200, 1e200));
+
+        // the normal form overflows, and the resolvent cubic has no finite roots
+        let roots: Vec<f64> = solve_quartic(1e-300, 1e300, 1e300, 1e300, 1e300);
+        assert!(roots.iter().all(|root| root.is_finite()));
+    }
+}
@@ -0,0 +1,177 @@
+use crate::common::{Vec3, VertexFormat};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+// Source of sample values in [0, 1) for the sampled integrators
+pub trait Sampler<T: VertexFormat> {
+    fn next_1d(&mut self) -> T;
+
+    fn next_2d(&mut self) -> (T, T) {
+        (self.next_1d(), self.next_1d())
+    }
+}
+
+// Independent uniform random samples. Seeded, so renders are reproducible.
+pub struct RandomSampler {
+    rng: StdRng,
+}
+
+impl RandomSampler {
+    pub fn new(seed: u64) -> RandomSampler {
+        RandomSampler {
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+}
+
+impl<T: VertexFormat> Sampler<T> for RandomSampler {
+    fn next_1d(&mut self) -> T {
+        T::from(self.rng.gen::<f64>()).unwrap()
+    }
+}
+
+// Discrete distribution over indices, with chances proportional to the given weights
+#[derive(Debug, Clone)]
+pub struct Distribution<T: VertexFormat> {
+    // running sums of the weights
+    cdf: Vec<T>,
+    total: T,
+}
+
+impl<T: VertexFormat> Distribution<T> {
+    // all indices are equally likely if the weights are all zero
+    pub fn new(weights: &[T]) -> Distribution<T> {
+        let uniform = weights.iter().all(|weight| *weight <= T::zero());
+
+        let mut total = T::zero();
+        let cdf = weights
+            .iter()
+            .map(|weight| {
+                total = total
+                    + if uniform {
+                        T::one()
+                    } else {
+                        weight.max(T::zero())
+                    };
+                total
+            })
+            .collect();
+
+        Distribution { cdf, total }
+    }
+
+    pub fn len(&self) -> usize {
+        self.cdf.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cdf.is_empty()
+    }
+
+    // picks an index with a sample in [0, 1), returning it with its chance
+    pub fn sample(&self, u: T) -> (usize, T) {
+        let target = u * self.total;
+        let index = self
+            .cdf
+            .partition_point(|sum| *sum <= target)
+            .min(self.cdf.len() - 1);
+
+        (index, self.probability(index))
+    }
+
+    pub fn probability(&self, index: usize) -> T {
+        let previous = if index == 0 {
+            T::zero()
+        } else {
+            self.cdf[index - 1]
+        };
+
+        (self.cdf[index] - previous) / self.total
+    }
+}
+
+// Direction on the hemisphere around +z, with a density proportional to its z component. The
+// density is z / pi.
+pub fn cosine_hemisphere<T: VertexFormat>(sample: (T, T)) -> Vec3<T> {
+    let two_pi = T::from(2.0 * std::f64::consts::PI).unwrap();
+    let radius = sample.0.sqrt();
+    let angle = two_pi * sample.1;
+
+    Vec3::new(
+        radius * angle.cos(),
+        radius * angle.sin(),
+        (T::one() - sample.0).max(T::zero()).sqrt(),
+    )
+}
+
+// Direction on the cone around +z with the given cosine of its angle to the axis
+pub fn cone_direction<T: VertexFormat>(cos_theta: T, u: T) -> Vec3<T> {
+    let two_pi = T::from(2.0 * std::f64::consts::PI).unwrap();
+    let sin_theta = (T::one() - cos_theta * cos_theta).max(T::zero()).sqrt();
+    let angle = two_pi * u;
+
+    Vec3::new(sin_theta * angle.cos(), sin_theta * angle.sin(), cos_theta)
+}
+
+// Transforms a direction given relative to the frame (x, y, z) into world space
+pub fn to_world<T: VertexFormat>(
+    local: &Vec3<T>,
+    x: &Vec3<T>,
+    y: &Vec3<T>,
+    z: &Vec3<T>,
+) -> Vec3<T> {
+    x.mul(local.x).add(&y.mul(local.y)).add(&z.mul(local.z))
+}
+
+// Mirrors the direction v, which points away from the surface, about the normal
+pub fn reflect<T: VertexFormat>(v: &Vec3<T>, normal: &Vec3<T>) -> Vec3<T> {
+    normal.mul(T::from(2.0).unwrap() * normal.dot(v)).sub(v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeded_samplers_repeat() {
+        let mut first = RandomSampler::new(42);
+        let mut second = RandomSampler::new(42);
+
+        for _ in 0..10 {
+            let a: f64 = first.next_1d();
+            let b: f64 = second.next_1d();
+
+            assert_eq!(a, b);
+            assert!((0.0..1.0).contains(&a));
+        }
+    }
+
+    #[test]
+    fn cosine_hemisphere_directions_are_unit_and_upward() {
+        let mut sampler = RandomSampler::new(1);
+
+        for _ in 0..100 {
+            let direction: Vec3<f64> = cosine_hemisphere(sampler.next_2d());
+
+            assert!((direction.mag_sqrd() - 1.0).abs() < 1e-12);
+            assert!(direction.z >= 0.0);
+        }
+    }
+
+    #[test]
+    fn reflect_about_normal() {
+        let reflected = reflect(&Vec3::new(1.0, 1.0, 0.0), &Vec3::new(0.0, 1.0, 0.0));
+
+        assert_eq!(Vec3::new(-1.0, 1.0, 0.0), reflected);
+    }
+
+    #[test]
+    fn distribution_follows_weights() {
+        let distribution = Distribution::new(&[0.0, 1.0, 3.0]);
+
+        assert_eq!((1, 0.25), distribution.sample(0.1));
+        assert_eq!((2, 0.75), distribution.sample(0.25));
+        assert_eq!((2, 0.75), distribution.sample(0.999));
+        assert_eq!(0.0, distribution.probability(0));
+    }
+}
@@ -0,0 +1,351 @@
+use crate::common::{Color, Ray, Spacial, Vec3, VertexFormat};
+use crate::image::Image;
+use crate::scene::debug::id_color;
+use crate::scene::light::{IncidentLight, LightSource};
+use crate::scene::Scene;
+
+// Buffer a render can produce besides the final color, for compositing
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Aov {
+    // the final color, as the scene's integrator renders it
+    Beauty,
+    // fraction of the light the surface reflects, without any lighting
+    Albedo,
+    // shading normal in world space, with components from -1 to 1
+    Normal,
+    // distance from the camera, infinite where rays miss
+    Depth,
+    // a color for each visible, the same as in the debug view
+    ObjectId,
+    // light reflected diffusely straight from the lights
+    DirectDiffuse,
+    // highlights of the lights
+    DirectSpecular,
+    // mirror and glossy reflections, already weighted by how much the surface reflects
+    Reflection,
+    // direct light the surface would get from the lights that are blocked from it
+    Shadow,
+    // light reaching the camera straight from the background or environment
+    Emission,
+}
+
+impl Aov {
+    pub const ALL: [Aov; 10] = [
+        Aov::Beauty,
+        Aov::Albedo,
+        Aov::Normal,
+        Aov::Depth,
+        Aov::ObjectId,
+        Aov::DirectDiffuse,
+        Aov::DirectSpecular,
+        Aov::Reflection,
+        Aov::Shadow,
+        Aov::Emission,
+    ];
+
+    // name files and layers are saved under
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::Beauty => "beauty",
+            Aov::Albedo => "albedo",
+            Aov::Normal => "normal",
+            Aov::Depth => "depth",
+            Aov::ObjectId => "object_id",
+            Aov::DirectDiffuse => "direct_diffuse",
+            Aov::DirectSpecular => "direct_specular",
+            Aov::Reflection => "reflection",
+            Aov::Shadow => "shadow",
+            Aov::Emission => "emission",
+        }
+    }
+}
+
+// Buffers of one render, in the order they were asked for. Only the beauty pass is clipped, the
+// rest keep their values as they are, so normals can be negative and depths past one.
+pub struct RenderPasses<T: VertexFormat> {
+    passes: Vec<(Aov, Image<T>)>,
+}
+
+impl<T: VertexFormat> RenderPasses<T> {
+    pub fn get(&self, aov: Aov) -> Option<&Image<T>> {
+        self.passes
+            .iter()
+            .find(|(other, _)| *other == aov)
+            .map(|(_, image)| image)
+    }
+
+    pub fn passes(&self) -> &[(Aov, Image<T>)] {
+        &self.passes
+    }
+}
+
+// Renders the passes. The beauty pass is what the scene's integrator renders, the others come
+// from one ray per pixel, like the Whitted renderer. Each of those rays is intersected with the
+// scene once, and the passes split up the Whitted shading of the surface it hits, with ambient
+// light and refraction left out, as well as media.
+pub fn render_passes<T: VertexFormat>(scene: &Scene<T>, aovs: &[Aov]) -> RenderPasses<T> {
+    let camera = scene.camera();
+    let mut passes: Vec<(Aov, Image<T>)> = aovs
+        .iter()
+        .map(|aov| match aov {
+            Aov::Beauty => (*aov, scene.render()),
+            _ => (*aov, Image::new(camera.width(), camera.height())),
+        })
+        .collect();
+
+    for y in 0..camera.height() {
+        for x in 0..camera.width() {
+            let ray = camera.ray(T::from(x).unwrap(), T::from(y).unwrap());
+            let values = pixel_values(scene, &ray, aovs);
+
+            for ((aov, image), value) in passes.iter_mut().zip(values) {
+                if *aov != Aov::Beauty {
+                    image.set_pixel(x, y, Color::new_unclipped(value.x, value.y, value.z));
+                }
+            }
+        }
+    }
+
+    RenderPasses { passes }
+}
+
+// what each pass shows for the ray, black for the beauty pass, which is rendered separately
+fn pixel_values<T: VertexFormat>(scene: &Scene<T>, ray: &Ray<T>, aovs: &[Aov]) -> Vec<Vec3<T>> {
+    let black = Vec3::new(T::zero(), T::zero(), T::zero());
+
+    let (intersection, visible) = match scene.intersect(ray) {
+        Some(hit) => hit,
+        None => {
+            return aovs
+                .iter()
+                .map(|aov| match aov {
+                    Aov::Depth => Vec3::new(T::infinity(), T::infinity(), T::infinity()),
+                    Aov::Emission => scene.background(&ray.direction),
+                    _ => black.clone(),
+                })
+                .collect()
+        }
+    };
+    let bsdf = visible.bsdf();
+    let shading = bsdf.shading_intersection(&intersection);
+    let wo = ray.direction.mul(T::one().neg());
+    let viewpoint = scene.camera().location();
+
+    // one shadow ray to each light facing the surface splits them into those reaching it and
+    // those blocked from it, only if a pass lights the surface
+    let lit = aovs
+        .iter()
+        .any(|aov| matches!(aov, Aov::DirectDiffuse | Aov::DirectSpecular | Aov::Shadow));
+    let (direct, shadow) = if lit {
+        let (reaching, blocked): (Vec<_>, Vec<_>) = scene
+            .lights()
+            .iter()
+            .map(|light| light.as_ref())
+            .filter(|light| {
+                intersection
+                    .normal
+                    .dot(&light.light_vector(&intersection.point))
+                    > T::zero()
+            })
+            .partition(|light| scene.light_reaches(&intersection.point, *light));
+
+        (
+            bsdf.shade_parts(&shading, &without_media(reaching), viewpoint),
+            bsdf.shade_parts(&shading, &without_media(blocked), viewpoint),
+        )
+    } else {
+        (
+            (black.clone(), black.clone()),
+            (black.clone(), black.clone()),
+        )
+    };
+
+    aovs.iter()
+        .map(|aov| match aov {
+            Aov::Beauty | Aov::Emission => black.clone(),
+            Aov::Albedo => bsdf.reflectance(&shading, &wo),
+            Aov::Normal => shading.normal.clone(),
+            Aov::Depth => {
+                let depth = intersection.point.sub(&ray.origin).mag_sqrd().sqrt();
+                Vec3::new(depth, depth, depth)
+            }
+            Aov::ObjectId => match scene
+                .visibles()
+                .iter()
+                .position(|other| std::ptr::eq(other, visible))
+            {
+                Some(index) => id_color(index),
+                None => black.clone(),
+            },
+            Aov::DirectDiffuse => direct.0.clone(),
+            Aov::DirectSpecular => direct.1.clone(),
+            Aov::Reflection if bsdf.is_reflective() => scene
+                .trace_reflection(&intersection, visible.as_ref(), ray, 0)
+                .color_vector()
+                .clone(),
+            Aov::Reflection => black.clone(),
+            // direct light from the lights facing the surface that something stands in front of
+            Aov::Shadow => shadow.0.add(&shadow.1),
+        })
+        .collect()
+}
+
+// lights at their full brightness, as media are left out of the passes
+fn without_media<T: VertexFormat>(lights: Vec<&dyn LightSource<T>>) -> Vec<IncidentLight<'_, T>> {
+    lights.into_iter().map(IncidentLight::new).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::camera::Camera;
+    use crate::scene::integrator::PathTracer;
+    use crate::scene::light::PointLight;
+    use crate::scene::visible::material::Material;
+    use crate::scene::visible::plane::Plane;
+    use crate::scene::visible::sphere::Sphere;
+    use crate::scene::visible::Body;
+
+    // a red ball resting on a grey floor, lit from above, seen from the front
+    fn scene() -> Scene<f64> {
+        let camera = Camera::new(
+            Vec3::new(0.0, 1.4, 5.0),
+            Vec3::new(0.0, 1.5, 6.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            16,
+            16,
+            60.0_f64.to_radians(),
+        );
+        let mut scene = Scene::new(
+            camera,
+            Color::new(0.1, 0.1, 0.1).unwrap(),
+            Color::new(0.0, 0.0, 0.5).unwrap(),
+        );
+
+        let material = |color: Color<f64>, reflective: f64| {
+            Material::new(
+                0.8,
+                color,
+                0.3,
+                Color::new(1.0, 1.0, 1.0).unwrap(),
+                32.0,
+                0.1,
+                Color::new(1.0, 1.0, 1.0).unwrap(),
+                reflective,
+            )
+        };
+        scene.add_visible(Box::new(Body::new(
+            Box::new(Sphere::new(Vec3::new(0.0, 1.0, 0.0), 1.0)),
+            material(Color::new(0.9, 0.1, 0.1).unwrap(), 0.0),
+        )));
+        scene.add_visible(Box::new(Body::new(
+            Box::new(Plane::new(
+                Vec3::new(0.0, 0.0, 0.0),
+                Vec3::new(0.0, 1.0, 0.0),
+            )),
+            material(Color::new(0.5, 0.5, 0.5).unwrap(), 0.3),
+        )));
+        scene.add_light(Box::new(PointLight::new(
+            Color::new(1.0, 1.0, 1.0).unwrap(),
+            Vec3::new(0.0, 5.0, 0.0),
+        )));
+
+        scene
+    }
+
+    #[test]
+    fn passes_come_in_the_order_asked_for() {
+        let scene = scene();
+        let passes = scene.render_passes(&[Aov::Depth, Aov::Beauty]);
+
+        let names: Vec<_> = passes.passes().iter().map(|(aov, _)| aov.name()).collect();
+        assert_eq!(vec!["depth", "beauty"], names);
+        assert!(passes.get(Aov::Normal).is_none());
+
+        // the beauty pass is what rendering the scene gives
+        let beauty = passes.get(Aov::Beauty).unwrap();
+        for (pass, render) in beauty.iter().zip(scene.render().iter()) {
+            assert_eq!(render.color_vector(), pass.color_vector());
+        }
+    }
+
+    #[test]
+    fn beauty_follows_the_integrator() {
+        let mut scene = scene();
+        scene.set_integrator(Box::new(PathTracer::new(2)));
+
+        let passes = scene.render_passes(&[Aov::Albedo, Aov::Beauty]);
+
+        let beauty = passes.get(Aov::Beauty).unwrap();
+        for (pass, render) in beauty.iter().zip(scene.render().iter()) {
+            assert_eq!(render.color_vector(), pass.color_vector());
+        }
+        let whitted = scene.trace_ray(scene.camera().ray(8.0, 8.0), 0);
+        assert_ne!(whitted.color_vector(), beauty.pixel(8, 8).color_vector());
+    }
+
+    #[test]
+    fn surface_passes_describe_what_is_hit() {
+        let scene = scene();
+        let passes = scene.render_passes(&Aov::ALL);
+
+        // the middle of the image sees the front of the ball, and the top the sky
+        let pixel = |aov: Aov, y: u32| passes.get(aov).unwrap().pixel(8, y).color_vector().clone();
+        assert_eq!(Vec3::new(0.9, 0.1, 0.1), pixel(Aov::Albedo, 8));
+        assert!(pixel(Aov::Normal, 8).z > 0.9);
+        assert!((4.5..5.5).contains(&pixel(Aov::Depth, 8).x));
+        assert_eq!(Vec3::new(0.0, 0.0, 0.0), pixel(Aov::Emission, 8));
+
+        assert!(pixel(Aov::Depth, 15).x.is_infinite());
+        assert_eq!(Vec3::new(0.0, 0.0, 0.5), pixel(Aov::Emission, 15));
+        assert_eq!(Vec3::new(0.0, 0.0, 0.0), pixel(Aov::Albedo, 15));
+        assert_ne!(pixel(Aov::ObjectId, 8), pixel(Aov::ObjectId, 0));
+    }
+
+    #[test]
+    fn shading_passes_split_the_beauty() {
+        let scene = scene();
+        let passes = scene.render_passes(&Aov::ALL);
+        let sum = |aov: Aov| {
+            passes
+                .get(aov)
+                .unwrap()
+                .iter()
+                .map(|pixel| {
+                    let color = pixel.color_vector();
+                    color.x + color.y + color.z
+                })
+                .sum::<f64>()
+        };
+
+        // the floor under the ball is in its shadow and reflects it, and the ball has highlights
+        assert!(sum(Aov::Shadow) > 0.0);
+        assert!(sum(Aov::Reflection) > 0.0);
+        assert!(sum(Aov::DirectSpecular) > 0.0);
+
+        // without clipping, ambient light or refraction to make up the difference, the beauty
+        // pass is the sum of the others
+        let camera = scene.camera();
+        for y in 0..camera.height() {
+            for x in 0..camera.width() {
+                let ray = camera.ray(x as f64, y as f64);
+                let (intersection, visible) = match scene.intersect(&ray) {
+                    Some(hit) => hit,
+                    None => continue,
+                };
+                let shading = visible.bsdf().shading_intersection(&intersection);
+                let ambient = visible.bsdf().ambient(&shading);
+
+                let parts = [Aov::DirectDiffuse, Aov::DirectSpecular, Aov::Reflection]
+                    .iter()
+                    .fold(ambient, |total, aov| {
+                        total.add(passes.get(*aov).unwrap().pixel(x, y).color_vector())
+                    });
+                let beauty = passes.get(Aov::Beauty).unwrap().pixel(x, y).color_vector();
+                if parts.x < 1.0 && parts.y < 1.0 && parts.z < 1.0 {
+                    assert!(parts.sub(beauty).mag_sqrd() < 1e-18);
+                }
+            }
+        }
+    }
+}
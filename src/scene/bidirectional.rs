@@ -0,0 +1,685 @@
+use crate::common::{Intersection, Ray, Vec3, VertexFormat};
+use crate::image::Image;
+use crate::sampler::{Distribution, RandomSampler, Sampler};
+use crate::scene::camera::Camera;
+use crate::scene::integrator::{survive, Integrator, LightSelection};
+use crate::scene::light::LightSource;
+use crate::scene::visible::bsdf::Bsdf;
+use crate::scene::Scene;
+
+// how far short of the far end of a connection something still blocks it
+const SHADOW_EPSILON: f64 = 1e-4;
+
+// Bidirectional path tracer. Every sample traces a path from the camera and one from a light, and
+// joins each point on one to each point on the other. The same path can be made in several of
+// these ways, so each is weighted against the others with the power heuristic. This finds light
+// that paths from the camera rarely do, like caustics seen through glass or a room lit by a lamp
+// shining into its shade. Joins straight to the camera are splatted onto the pixel they land on.
+//
+// Only point and spot lights start paths. Lights at infinity and the environment are sampled at
+// the points of camera paths like in the path tracer, and media and subsurface scattering are
+// left out, so scenes with those are better off with the path tracer.
+pub struct BidirectionalPathTracer {
+    samples_per_pixel: u32,
+    max_depth: u32,
+    seed: u64,
+}
+
+impl BidirectionalPathTracer {
+    pub fn new(samples_per_pixel: u32) -> BidirectionalPathTracer {
+        BidirectionalPathTracer {
+            samples_per_pixel,
+            max_depth: 8,
+            seed: 0,
+        }
+    }
+
+    // number of surfaces a path can scatter off of, counting both sides of a join
+    pub fn set_max_depth(&mut self, max_depth: u32) {
+        self.max_depth = max_depth;
+    }
+
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+
+    // Light arriving at the camera along the ray. Light the joins to the camera land elsewhere on
+    // the image is splatted onto it, scaled by the given weight.
+    fn sample<'a, T: VertexFormat>(
+        &self,
+        scene: &'a Scene<T>,
+        lights: &Lights<'a, T>,
+        ray: Ray<T>,
+        image: &mut Image<T>,
+        weight: T,
+        sampler: &mut dyn Sampler<T>,
+    ) -> Vec3<T> {
+        let camera = scene.camera();
+        let max_vertices = self.max_depth as usize + 1;
+
+        let mut camera_path = vec![Vertex::new(
+            Kind::Camera,
+            ray.origin.clone(),
+            Vec3::new(T::one(), T::one(), T::one()),
+        )];
+        let pdf = camera.pdf(&ray.direction);
+        let beta = Vec3::new(T::one(), T::one(), T::one());
+        let escape = extend(
+            scene,
+            ray,
+            beta,
+            pdf,
+            &mut camera_path,
+            max_vertices,
+            sampler,
+        );
+
+        let mut radiance = Vec3::new(T::zero(), T::zero(), T::zero());
+
+        // lights at infinity, from each point of the camera path
+        for (i, vertex) in camera_path.iter().enumerate().skip(1) {
+            if let (false, Kind::Surface(surface)) = (vertex.delta, &vertex.kind) {
+                let wo = camera_path[i - 1].point.sub(&vertex.point).normalize();
+                let scatter = |wi: &Vec3<T>| {
+                    (
+                        surface.bsdf.evaluate(&surface.shading, &wo, wi),
+                        surface.bsdf.pdf(&surface.shading, &wo, wi),
+                    )
+                };
+                let direct = lights.at_infinity.direct(
+                    &vertex.point,
+                    Some(&surface.intersection.normal),
+                    &scatter,
+                    sampler,
+                );
+
+                radiance.mut_add(&vertex.beta.scalar_mul(&direct));
+            }
+        }
+
+        if let Some(escape) = escape {
+            // the background after the last bounce is only picked up as the environment, like
+            // in the path tracer
+            if escape.bounces < self.max_depth as usize || scene.environment().is_some() {
+                let mut background = scene.background(&escape.direction);
+                if !escape.specular {
+                    background = background.mul(
+                        lights
+                            .at_infinity
+                            .escape_weight(&escape.direction, escape.pdf),
+                    );
+                }
+
+                radiance.mut_add(&escape.beta.scalar_mul(&background));
+            }
+        }
+
+        let light_path = match lights.start(sampler) {
+            Some((vertex, ray, beta, pdf)) => {
+                let mut light_path = vec![vertex];
+                extend(
+                    scene,
+                    ray,
+                    beta,
+                    pdf,
+                    &mut light_path,
+                    max_vertices,
+                    sampler,
+                );
+                light_path
+            }
+            None => return radiance,
+        };
+
+        for t in 1..=camera_path.len() {
+            for s in 1..=light_path.len() {
+                if s + t == 2 || s + t - 2 > self.max_depth as usize {
+                    continue;
+                }
+
+                let (contribution, raster) = match connect(scene, &light_path, &camera_path, s, t) {
+                    Some(connection) => connection,
+                    None => continue,
+                };
+                let contribution =
+                    contribution.mul(mis_weight(camera, &light_path, &camera_path, s, t));
+
+                match raster {
+                    Some((i, j)) => image.splat(
+                        i.to_u32().unwrap(),
+                        j.to_u32().unwrap(),
+                        &contribution.mul(weight),
+                    ),
+                    None => radiance.mut_add(&contribution),
+                }
+            }
+        }
+
+        radiance
+    }
+}
+
+impl<T: VertexFormat> Integrator<T> for BidirectionalPathTracer {
+    fn render(&self, scene: &Scene<T>) -> Image<T> {
+        self.render_unclipped(scene).clipped()
+    }
+
+    fn render_unclipped(&self, scene: &Scene<T>) -> Image<T> {
+        let camera = scene.camera();
+        let mut image = Image::new(camera.width(), camera.height());
+        let mut sampler = RandomSampler::new(self.seed);
+        let samples = T::from(self.samples_per_pixel).unwrap();
+        let lights = Lights::new(scene);
+
+        // every pixel's share of the light paths is one per sample
+        let weight = T::one() / samples;
+
+        for y in 0..camera.height() {
+            for x in 0..camera.width() {
+                let mut total = Vec3::new(T::zero(), T::zero(), T::zero());
+
+                for _ in 0..self.samples_per_pixel {
+                    let (dx, dy) = sampler.next_2d();
+                    let ray = camera.ray(T::from(x).unwrap() + dx, T::from(y).unwrap() + dy);
+
+                    total.mut_add(&self.sample(
+                        scene,
+                        &lights,
+                        ray,
+                        &mut image,
+                        weight,
+                        &mut sampler,
+                    ));
+                }
+
+                image.splat(x, y, &total.mul(weight));
+            }
+        }
+
+        image
+    }
+}
+
+// Lights paths start from, chosen by their estimated power, and the rest
+struct Lights<'a, T: VertexFormat> {
+    sources: Vec<&'a dyn LightSource<T>>,
+    distribution: Distribution<T>,
+    at_infinity: LightSelection<'a, T>,
+}
+
+impl<'a, T: VertexFormat> Lights<'a, T> {
+    fn new(scene: &'a Scene<T>) -> Lights<'a, T> {
+        let sources: Vec<&'a dyn LightSource<T>> = scene
+            .lights()
+            .iter()
+            .map(|light| light.as_ref())
+            .filter(|light| light.location().x.is_finite())
+            .collect();
+        let powers: Vec<T> = sources.iter().map(|light| light.power()).collect();
+
+        Lights {
+            distribution: Distribution::new(&powers),
+            sources,
+            at_infinity: LightSelection::at_infinity(scene),
+        }
+    }
+
+    // Picks a light and the direction of the first ray leaving it. Returns the light's vertex,
+    // the ray, the throughput along it and its density per unit solid angle.
+    fn start(&self, sampler: &mut dyn Sampler<T>) -> Option<PathStart<'a, T>> {
+        if self.sources.is_empty() {
+            return None;
+        }
+
+        let (index, chance) = self.distribution.sample(sampler.next_1d());
+        let light = self.sources[index];
+        let (direction, pdf) = light.sample_emission(sampler.next_2d())?;
+        if pdf <= T::zero() {
+            return None;
+        }
+
+        let position = light.location().clone();
+        let pi = T::from(std::f64::consts::PI).unwrap();
+        // a light's color is its irradiance at normal incidence over pi
+        let beta = light
+            .color_at(&position.add(&direction))
+            .mul(pi / (chance * pdf));
+
+        // the color is left out of the light's own throughput, as it can depend on the direction
+        let mut vertex = Vertex::new(
+            Kind::Light(light),
+            position.clone(),
+            Vec3::new(pi, pi, pi).div(chance),
+        );
+        // lights are points, so there's no choice of where on them paths start
+        vertex.pdf_fwd = chance;
+
+        Some((vertex, Ray::new(position, direction), beta, pdf))
+    }
+}
+
+// vertex a light path starts at, with its first ray, the throughput along it and its density
+type PathStart<'a, T> = (Vertex<'a, T>, Ray<T>, Vec3<T>, T);
+
+// where a surface vertex is and how it scatters light
+struct Surface<'a, T: VertexFormat> {
+    intersection: Intersection<T>,
+    shading: Intersection<T>,
+    bsdf: &'a dyn Bsdf<T>,
+}
+
+enum Kind<'a, T: VertexFormat> {
+    Camera,
+    Light(&'a dyn LightSource<T>),
+    Surface(Surface<'a, T>),
+}
+
+// Point on a path from the camera or a light
+struct Vertex<'a, T: VertexFormat> {
+    kind: Kind<'a, T>,
+    point: Vec3<T>,
+    // throughput of the path from its start up to the vertex
+    beta: Vec3<T>,
+    // scattered by a perfectly specular lobe, so it can't be joined to other vertices
+    delta: bool,
+    // densities per unit area of the vertex being sampled from the path's own start, and from
+    // the other end of a joined path
+    pdf_fwd: T,
+    pdf_rev: T,
+}
+
+impl<'a, T: VertexFormat> Vertex<'a, T> {
+    fn new(kind: Kind<'a, T>, point: Vec3<T>, beta: Vec3<T>) -> Vertex<'a, T> {
+        Vertex {
+            kind,
+            point,
+            beta,
+            delta: false,
+            pdf_fwd: T::zero(),
+            pdf_rev: T::zero(),
+        }
+    }
+
+    fn surface(&self) -> Option<&Surface<'a, T>> {
+        match &self.kind {
+            Kind::Surface(surface) => Some(surface),
+            _ => None,
+        }
+    }
+
+    fn direction_to(&self, other: &Vertex<T>) -> Vec3<T> {
+        other.point.sub(&self.point).normalize()
+    }
+
+    // turns a density per unit solid angle of directions leaving the vertex into one per unit
+    // area at the next vertex
+    fn area_density(&self, pdf: T, next: &Vertex<T>) -> T {
+        let offset = next.point.sub(&self.point);
+        let distance_sqrd = offset.mag_sqrd();
+        if distance_sqrd <= T::zero() {
+            return T::zero();
+        }
+
+        let cos = match next.surface() {
+            Some(surface) => surface
+                .intersection
+                .normal
+                .dot(&offset)
+                .abs()
+                .div(distance_sqrd.sqrt()),
+            None => T::one(),
+        };
+
+        pdf * cos / distance_sqrd
+    }
+
+    // density per unit area of sampling next from the vertex, when the path came from prev
+    fn pdf(&self, camera: &Camera<T>, prev: Option<&Vertex<T>>, next: &Vertex<T>) -> T {
+        let direction = self.direction_to(next);
+
+        let pdf = match &self.kind {
+            Kind::Camera => camera.pdf(&direction),
+            Kind::Light(light) => light.emission_pdf(&direction),
+            Kind::Surface(surface) => match prev {
+                Some(prev) => {
+                    let wo = self.direction_to(prev);
+                    surface.bsdf.pdf(&surface.shading, &wo, &direction)
+                }
+                None => T::zero(),
+            },
+        };
+
+        self.area_density(pdf, next)
+    }
+
+    // light arriving from next scattered towards prev, cosine included
+    fn evaluate(&self, prev: &Vertex<T>, next: &Vertex<T>) -> Vec3<T> {
+        match &self.kind {
+            Kind::Surface(surface) => {
+                let wo = self.direction_to(prev);
+                let wi = self.direction_to(next);
+
+                surface.bsdf.evaluate(&surface.shading, &wo, &wi)
+            }
+            _ => Vec3::new(T::zero(), T::zero(), T::zero()),
+        }
+    }
+
+    fn spawn_ray(&self, direction: Vec3<T>) -> Ray<T> {
+        match &self.kind {
+            Kind::Surface(surface) => surface.intersection.spawn_ray(direction),
+            _ => Ray::new(self.point.clone(), direction),
+        }
+    }
+}
+
+// ray a camera path leaves the scene along
+struct Escape<T: VertexFormat> {
+    direction: Vec3<T>,
+    beta: Vec3<T>,
+    // density the direction was sampled with per unit solid angle, unless it was specular
+    pdf: T,
+    specular: bool,
+    bounces: usize,
+}
+
+// Continues a path along the ray, which leaves its last vertex with the given throughput and
+// density per unit solid angle, until it has max_vertices. Camera paths trace one more ray past
+// their last vertex, to see whether it leaves the scene, like the path tracer does.
+fn extend<'a, T: VertexFormat>(
+    scene: &'a Scene<T>,
+    ray: Ray<T>,
+    beta: Vec3<T>,
+    pdf: T,
+    path: &mut Vec<Vertex<'a, T>>,
+    max_vertices: usize,
+    sampler: &mut dyn Sampler<T>,
+) -> Option<Escape<T>> {
+    let (mut ray, mut beta, mut pdf) = (ray, beta, pdf);
+    let escapes = matches!(path[0].kind, Kind::Camera);
+
+    loop {
+        let last = path.len() - 1;
+        let (intersection, visible) = match scene.intersect(&ray) {
+            Some(_) if path.len() >= max_vertices => return None,
+            Some(hit) => hit,
+            None => {
+                return Some(Escape {
+                    direction: ray.direction,
+                    beta,
+                    pdf,
+                    specular: last == 0 || path[last].delta,
+                    bounces: last,
+                })
+            }
+        };
+
+        let bsdf = visible.bsdf();
+        let shading = bsdf.shading_intersection(&intersection);
+        let point = intersection.point.clone();
+
+        // Lights are as bright at any distance, so the light spreading out from them is made up
+        // for over the first ray
+        if let Kind::Light(_) = path[last].kind {
+            beta = beta.mul(point.sub(&path[last].point).mag_sqrd());
+        }
+
+        let surface = Surface {
+            intersection,
+            shading,
+            bsdf,
+        };
+        let mut vertex = Vertex::new(Kind::Surface(surface), point, beta.clone());
+        vertex.pdf_fwd = path[last].area_density(pdf, &vertex);
+        path.push(vertex);
+
+        if path.len() >= max_vertices && !escapes {
+            return None;
+        }
+
+        let current = path.len() - 1;
+        let surface = match &path[current].kind {
+            Kind::Surface(surface) => surface,
+            _ => return None,
+        };
+        let wo = ray.direction.mul(T::one().neg());
+        let sample = surface.bsdf.sample(&surface.shading, &wo, sampler)?;
+
+        beta = survive(beta.scalar_mul(&sample.weight), current as u32, sampler)?;
+        let reverse = if sample.specular {
+            pdf = T::zero();
+            T::zero()
+        } else {
+            pdf = sample.pdf;
+            surface.bsdf.pdf(&surface.shading, &sample.direction, &wo)
+        };
+        ray = surface.intersection.spawn_ray(sample.direction);
+
+        path[current].delta = sample.specular;
+        let pdf_rev = path[current].area_density(reverse, &path[current - 1]);
+        path[current - 1].pdf_rev = pdf_rev;
+    }
+}
+
+// light carried by a joined path, and where on the image it lands if it's joined to the camera
+type Connection<T> = (Vec3<T>, Option<(T, T)>);
+
+// whether nothing stands between the two vertices
+fn unoccluded<T: VertexFormat>(scene: &Scene<T>, from: &Vertex<T>, to: &Vertex<T>) -> bool {
+    let ray = from.spawn_ray(from.direction_to(to));
+    let distance = to.point.sub(&ray.origin).mag_sqrd().sqrt();
+
+    match scene.intersect(&ray) {
+        Some((intersection, _)) => {
+            intersection.point.sub(&ray.origin).mag_sqrd().sqrt()
+                >= distance - T::from(SHADOW_EPSILON).unwrap()
+        }
+        None => true,
+    }
+}
+
+// Light carried by the path joining the first s vertices of the light path to the first t of the
+// camera path, before weighting. Joins straight to the camera come with the point on the image
+// they land on.
+fn connect<T: VertexFormat>(
+    scene: &Scene<T>,
+    light_path: &[Vertex<T>],
+    camera_path: &[Vertex<T>],
+    s: usize,
+    t: usize,
+) -> Option<Connection<T>> {
+    let (light_end, camera_end) = (&light_path[s - 1], &camera_path[t - 1]);
+    if light_end.delta || camera_end.delta {
+        return None;
+    }
+
+    let contribution = if t == 1 {
+        // onto the camera, through its importance
+        let camera = scene.camera();
+        let raster = camera.raster(&light_end.point)?;
+        let direction = camera_end.direction_to(light_end);
+        let distance_sqrd = light_end.point.sub(&camera_end.point).mag_sqrd();
+        let cos = direction.dot(&camera.view_direction());
+        let contribution = light_end
+            .beta
+            .scalar_mul(&light_end.evaluate(&light_path[s - 2], camera_end))
+            .mul(camera.importance(&direction) * cos / distance_sqrd);
+
+        (contribution, Some(raster))
+    } else if s == 1 {
+        // straight from the light, which doesn't fall off with distance
+        let light = match light_end.kind {
+            Kind::Light(light) => light,
+            _ => return None,
+        };
+        let contribution = camera_end
+            .beta
+            .scalar_mul(&camera_end.evaluate(&camera_path[t - 2], light_end))
+            .scalar_mul(&light.color_at(&camera_end.point))
+            .scalar_mul(&light_end.beta);
+
+        (contribution, None)
+    } else {
+        let distance_sqrd = light_end.point.sub(&camera_end.point).mag_sqrd();
+        let contribution = light_end
+            .beta
+            .scalar_mul(&light_end.evaluate(&light_path[s - 2], camera_end))
+            .scalar_mul(&camera_end.evaluate(&camera_path[t - 2], light_end))
+            .scalar_mul(&camera_end.beta)
+            .div(distance_sqrd);
+
+        (contribution, None)
+    };
+
+    if contribution.0.mag_sqrd() <= T::zero() || !unoccluded(scene, camera_end, light_end) {
+        return None;
+    }
+
+    Some(contribution)
+}
+
+// Weight of the path joined after s light and t camera vertices, against every other way of
+// making it from the same vertices, by the power heuristic. The densities of making each vertex
+// from either side are stepped through from the join outwards.
+fn mis_weight<T: VertexFormat>(
+    camera: &Camera<T>,
+    light_path: &[Vertex<T>],
+    camera_path: &[Vertex<T>],
+    s: usize,
+    t: usize,
+) -> T {
+    // (forward, reverse) densities and whether the vertex is specular, along each path
+    let mut light: Vec<(T, T, bool)> = light_path[..s]
+        .iter()
+        .map(|vertex| (vertex.pdf_fwd, vertex.pdf_rev, vertex.delta))
+        .collect();
+    let mut eye: Vec<(T, T, bool)> = camera_path[..t]
+        .iter()
+        .map(|vertex| (vertex.pdf_fwd, vertex.pdf_rev, vertex.delta))
+        .collect();
+
+    // the densities at the join and the vertices next to it change with the join
+    let (light_end, camera_end) = (&light_path[s - 1], &camera_path[t - 1]);
+    let light_before = if s > 1 {
+        Some(&light_path[s - 2])
+    } else {
+        None
+    };
+    let camera_before = if t > 1 {
+        Some(&camera_path[t - 2])
+    } else {
+        None
+    };
+
+    eye[t - 1].1 = light_end.pdf(camera, light_before, camera_end);
+    eye[t - 1].2 = false;
+    if let Some(before) = camera_before {
+        eye[t - 2].1 = camera_end.pdf(camera, Some(light_end), before);
+    }
+    light[s - 1].1 = camera_end.pdf(camera, camera_before, light_end);
+    light[s - 1].2 = false;
+    if let Some(before) = light_before {
+        light[s - 2].1 = light_end.pdf(camera, Some(camera_end), before);
+    }
+
+    // specular vertices have no density, and cancel out
+    let remap = |pdf: T| if pdf > T::zero() { pdf } else { T::one() };
+    let mut sum = T::zero();
+
+    let mut ratio = T::one();
+    for i in (1..t).rev() {
+        ratio = ratio * remap(eye[i].1) / remap(eye[i].0);
+        if !eye[i].2 && !eye[i - 1].2 {
+            sum = sum + ratio * ratio;
+        }
+    }
+
+    // the lights are points, which paths can't hit, so the path always starts at one
+    let mut ratio = T::one();
+    for i in (1..s).rev() {
+        ratio = ratio * remap(light[i].1) / remap(light[i].0);
+        if !light[i].2 && !light[i - 1].2 {
+            sum = sum + ratio * ratio;
+        }
+    }
+
+    T::one() / (T::one() + sum)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::Spacial;
+    use crate::scene::integrator::PathTracer;
+    use crate::scene::test_scenes::{closed_room, matte, mean};
+    use crate::scene::visible::sphere::Sphere;
+    use crate::scene::visible::Body;
+
+    // the closed room, with a ball on the floor for light to bounce around
+    fn room() -> Scene<f64> {
+        let mut scene = closed_room();
+        scene.add_visible(Box::new(Body::new(
+            Box::new(Sphere::new(Vec3::new(0.5, -1.4, -0.5), 0.6)),
+            matte(0.8),
+        )));
+
+        scene
+    }
+
+    #[test]
+    fn matches_path_tracer_in_a_closed_room() {
+        let scene = room();
+
+        let mut bidirectional = BidirectionalPathTracer::new(64);
+        bidirectional.set_max_depth(3);
+        let mut path_tracer = PathTracer::new(256);
+        path_tracer.set_max_depth(3);
+
+        let expected = mean(&path_tracer.render(&scene));
+        let actual = mean(&bidirectional.render(&scene));
+
+        assert!(expected > 0.1);
+        assert!((actual - expected).abs() < 0.02 * expected.max(1.0));
+    }
+
+    #[test]
+    fn joins_at_either_end_weigh_up_to_one() {
+        // a path from the light to a point on the floor and on to the camera, which can be made by
+        // joining the point to the light or to the camera
+        let scene = room();
+        let camera = scene.camera();
+        let light = scene.lights()[0].as_ref();
+        let floor = Vec3::new(-0.3, -2.0, -0.8);
+        let mut sampler = RandomSampler::new(1);
+
+        let eye = camera.location().clone();
+        let direction = floor.sub(&eye).normalize();
+        let mut camera_path = vec![Vertex::new(
+            Kind::Camera,
+            eye.clone(),
+            Vec3::new(1.0, 1.0, 1.0),
+        )];
+        let (pdf, beta) = (camera.pdf(&direction), Vec3::new(1.0, 1.0, 1.0));
+        let ray = Ray::new(eye, direction);
+        extend(&scene, ray, beta, pdf, &mut camera_path, 2, &mut sampler);
+
+        let position = light.location().clone();
+        let direction = floor.sub(&position).normalize();
+        let mut light_vertex = Vertex::new(
+            Kind::Light(light),
+            position.clone(),
+            Vec3::new(1.0, 1.0, 1.0),
+        );
+        light_vertex.pdf_fwd = 1.0;
+        let mut light_path = vec![light_vertex];
+        let (pdf, beta) = (light.emission_pdf(&direction), Vec3::new(1.0, 1.0, 1.0));
+        let ray = Ray::new(position, direction);
+        extend(&scene, ray, beta, pdf, &mut light_path, 2, &mut sampler);
+
+        let direct = mis_weight(camera, &light_path[..1], &camera_path, 1, 2);
+        let traced = mis_weight(camera, &light_path, &camera_path[..1], 2, 1);
+
+        assert!(direct > 0.0 && traced > 0.0);
+        assert!((direct + traced - 1.0).abs() < 1e-6);
+    }
+}
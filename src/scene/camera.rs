@@ -89,6 +89,61 @@ impl<T: VertexFormat> Camera<T> {
 
         Ray::new(origin, direction)
     }
+
+    // direction the camera looks in, through the middle of the image
+    pub fn view_direction(&self) -> Vec3<T> {
+        self.w.mul(T::one().neg())
+    }
+
+    // Where the point shows up on the image, in the coordinates ray takes. None if it's behind
+    // the camera or outside the image.
+    pub fn raster(&self, point: &Vec3<T>) -> Option<(T, T)> {
+        let direction = point.sub(&self.look_from);
+        // w points back, away from the image plane
+        let depth = direction.dot(&self.w).neg();
+        if depth <= T::zero() {
+            return None;
+        }
+
+        let scale = self.look_at.sub(&self.look_from).mag_sqrd().sqrt() / depth;
+        let u = direction.dot(&self.u) * scale;
+        let v = direction.dot(&self.v) * scale;
+        let i = (u - self.view_min.x) / ((self.view_max.x - self.view_min.x) / self.x_res);
+        let j = (v - self.view_min.y) / ((self.view_max.y - self.view_min.y) / self.y_res);
+
+        if i < T::zero() || i >= self.x_res || j < T::zero() || j >= self.y_res {
+            return None;
+        }
+
+        Some((i, j))
+    }
+
+    // Density of camera rays heading in the direction, per unit solid angle, with points on the
+    // image picked evenly. Zero outside of the image.
+    pub fn pdf(&self, direction: &Vec3<T>) -> T {
+        let cos = direction.dot(&self.w).neg();
+        if cos <= T::zero() || self.raster(&self.look_from.add(direction)).is_none() {
+            return T::zero();
+        }
+
+        let distance_sqrd = self.look_at.sub(&self.look_from).mag_sqrd();
+        let area = (self.view_max.x - self.view_min.x) * (self.view_max.y - self.view_min.y);
+
+        distance_sqrd / (area * cos * cos * cos)
+    }
+
+    // How much light arriving from the direction counts towards the image, for tracing light
+    // towards the camera. It's normalized over the whole image, so light landing on a pixel is
+    // its share of the light paths traced for that pixel.
+    pub fn importance(&self, direction: &Vec3<T>) -> T {
+        let cos = direction.dot(&self.w).neg();
+        if cos <= T::zero() {
+            return T::zero();
+        }
+
+        self.pdf(direction) / cos
+    }
+
     pub fn width(&self) -> u32 {
         self.width
     }
@@ -125,4 +180,54 @@ mod tests {
         assert_eq!(ray1.direction.y, -ray2.direction.y);
         assert_eq!(ray1.direction.z, ray2.direction.z);
     }
+
+    #[test]
+    fn raster_finds_where_rays_come_from() {
+        let camera: Camera<f64> = Camera::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 5.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            64,
+            48,
+            1.0,
+        );
+
+        let ray = camera.ray(12.25, 40.5);
+        let point = ray.origin.add(&ray.direction.mul(7.0));
+        let (i, j) = camera.raster(&point).unwrap();
+
+        assert!((i - 12.25).abs() < 1e-9);
+        assert!((j - 40.5).abs() < 1e-9);
+        assert!(camera.raster(&Vec3::new(0.0, 1.0, 10.0)).is_none());
+    }
+
+    #[test]
+    fn ray_density_covers_the_image() {
+        let camera: Camera<f64> = Camera::new(
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            32,
+            16,
+            1.2,
+        );
+
+        // sums the density over small patches of solid angle, through the image plane
+        let steps = 400;
+        let mut total = 0.0;
+        for y in 0..steps {
+            for x in 0..steps {
+                let (u, v) = (
+                    -2.0 + 4.0 * (x as f64 + 0.5) / steps as f64,
+                    -2.0 + 4.0 * (y as f64 + 0.5) / steps as f64,
+                );
+                let length: f64 = (u * u + v * v + 1.0).sqrt();
+                let solid_angle = (4.0 / steps as f64).powi(2) / length.powi(3);
+
+                total += camera.pdf(&Vec3::new(u, v, -1.0).normalize()) * solid_angle;
+            }
+        }
+
+        assert!((total - 1.0).abs() < 0.01);
+    }
 }
@@ -0,0 +1,305 @@
+use crate::common::{Color, Ray, Vec3, VertexFormat};
+use crate::image::Image;
+use crate::scene::integrator::Integrator;
+use crate::scene::Scene;
+
+// width of the lines along triangle edges in the wireframe, as a fraction of the distance from
+// each edge to the opposite vertex
+const WIRE_WIDTH: f64 = 0.03;
+
+// What the debug view shows in place of shading
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DebugMode {
+    // geometric normals, with each component mapped from [-1, 1] to [0, 1]
+    Normals,
+    // normals after normal and bump maps, mapped the same way
+    ShadingNormals,
+    // distance from the camera, white at the nearest surface seen and darkening with the inverse
+    // of the distance past that, so surfaces reaching out to the horizon don't wash the rest out
+    Depth,
+    // a color for each visible, picked at random from its place in the scene
+    ObjectId,
+    // texture coordinates as red and green, repeating past 1
+    Uv,
+    // weights of the vertices of triangles as red, green and blue, with other shapes black
+    Barycentrics,
+    // triangle edges in black over surfaces shaded by how squarely they face the camera
+    Wireframe,
+    // intersection tests the Whitted renderer makes for each pixel, shadows and reflections
+    // included, from blue for the fewest to red for the most
+    IntersectionTests,
+}
+
+// Renders diagnostics instead of shading, for telling whether geometry, normals or lighting is
+// what makes a scene look wrong. One ray per pixel, like the Whitted renderer, so the two line up.
+pub struct DebugView {
+    mode: DebugMode,
+}
+
+impl DebugView {
+    pub fn new(mode: DebugMode) -> DebugView {
+        DebugView { mode }
+    }
+
+    // What the ray sees, before depths and test counts are scaled to the image. Those come back
+    // in every component, infinite for depths of rays that miss.
+    fn inspect<T: VertexFormat>(&self, scene: &Scene<T>, ray: &Ray<T>) -> Vec3<T> {
+        let black = Vec3::new(T::zero(), T::zero(), T::zero());
+
+        if self.mode == DebugMode::IntersectionTests {
+            let before = scene.intersection_tests();
+            scene.trace_ray(Ray::new(ray.origin.clone(), ray.direction.clone()), 0);
+            let tests = T::from(scene.intersection_tests() - before).unwrap();
+
+            return Vec3::new(tests, tests, tests);
+        }
+
+        let (intersection, visible) = match scene.intersect(ray) {
+            Some(hit) => hit,
+            None if self.mode == DebugMode::Depth => {
+                return Vec3::new(T::infinity(), T::infinity(), T::infinity())
+            }
+            None => return black,
+        };
+        let half = T::from(0.5).unwrap();
+        let as_color = |normal: &Vec3<T>| normal.mul(half).add(&Vec3::new(half, half, half));
+
+        match self.mode {
+            DebugMode::Normals => as_color(&intersection.normal),
+            DebugMode::ShadingNormals => {
+                as_color(&visible.bsdf().shading_intersection(&intersection).normal)
+            }
+            DebugMode::Depth => {
+                let depth = intersection.point.sub(&ray.origin).mag_sqrd().sqrt();
+                Vec3::new(depth, depth, depth)
+            }
+            DebugMode::ObjectId => {
+                match scene
+                    .visibles()
+                    .iter()
+                    .position(|other| std::ptr::eq(other, visible))
+                {
+                    Some(index) => id_color(index),
+                    None => black,
+                }
+            }
+            DebugMode::Uv => {
+                let (u, v) = intersection.uv;
+                Vec3::new(u - u.floor(), v - v.floor(), T::zero())
+            }
+            DebugMode::Barycentrics => match intersection.barycentric {
+                Some((w1, w2, w3)) => Vec3::new(w1, w2, w3),
+                None => black,
+            },
+            DebugMode::Wireframe => {
+                let on_edge = match intersection.barycentric {
+                    Some((w1, w2, w3)) => w1.min(w2).min(w3) < T::from(WIRE_WIDTH).unwrap(),
+                    None => false,
+                };
+                if on_edge {
+                    return black;
+                }
+
+                let facing = intersection.normal.dot(&ray.direction).abs();
+                Vec3::new(facing, facing, facing)
+            }
+            DebugMode::IntersectionTests => black,
+        }
+    }
+}
+
+// bright, random looking color for the index
+pub(crate) fn id_color<T: VertexFormat>(index: usize) -> Vec3<T> {
+    let hash = (index as u64 + 1).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    let channel = |shift: u32| {
+        let byte = ((hash >> shift) & 0xff) as f64 / 255.0;
+        T::from(0.2 + 0.8 * byte).unwrap()
+    };
+
+    Vec3::new(channel(40), channel(48), channel(56))
+}
+
+// blue, through cyan, green and yellow, to red as the value goes from 0 to 1
+fn heat<T: VertexFormat>(value: T) -> Vec3<T> {
+    let stops = [
+        (0.0, 0.0, 1.0),
+        (0.0, 1.0, 1.0),
+        (0.0, 1.0, 0.0),
+        (1.0, 1.0, 0.0),
+        (1.0, 0.0, 0.0),
+    ];
+    let scaled = value.to_f64().unwrap().clamp(0.0, 1.0) * 4.0;
+    let index = (scaled.floor() as usize).min(3);
+    let fraction = scaled - index as f64;
+    let (from, to) = (stops[index], stops[index + 1]);
+    let mix = |a: f64, b: f64| T::from(a + (b - a) * fraction).unwrap();
+
+    Vec3::new(mix(from.0, to.0), mix(from.1, to.1), mix(from.2, to.2))
+}
+
+impl<T: VertexFormat> Integrator<T> for DebugView {
+    fn render(&self, scene: &Scene<T>) -> Image<T> {
+        let camera = scene.camera();
+        let mut image = Image::new(camera.width(), camera.height());
+
+        let mut values = Vec::with_capacity((camera.width() * camera.height()) as usize);
+        for y in 0..camera.height() {
+            for x in 0..camera.width() {
+                let ray = camera.ray(T::from(x).unwrap(), T::from(y).unwrap());
+                values.push(self.inspect(scene, &ray));
+            }
+        }
+
+        // depths are scaled to the nearest in the image, and test counts to the range of them
+        let finite = || {
+            values
+                .iter()
+                .map(|value| value.x)
+                .filter(|value| value.is_finite())
+        };
+        let largest = finite().fold(T::zero(), T::max);
+        let smallest = finite().fold(largest, T::min);
+
+        let mut values = values.into_iter();
+        for y in 0..camera.height() {
+            for x in 0..camera.width() {
+                let value = values.next().unwrap();
+                let color = match self.mode {
+                    DebugMode::Depth if value.x.is_finite() && value.x > T::zero() => {
+                        let brightness = smallest / value.x;
+                        Vec3::new(brightness, brightness, brightness)
+                    }
+                    DebugMode::Depth => Vec3::new(T::zero(), T::zero(), T::zero()),
+                    DebugMode::IntersectionTests if largest > smallest => {
+                        heat((value.x - smallest) / (largest - smallest))
+                    }
+                    DebugMode::IntersectionTests => heat(T::zero()),
+                    _ => value,
+                };
+
+                image.set_pixel(x, y, Color::clipped(color));
+            }
+        }
+
+        image
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::camera::Camera;
+    use crate::scene::light::PointLight;
+    use crate::scene::visible::bsdf::Lambertian;
+    use crate::scene::visible::mesh::Triangle;
+    use crate::scene::visible::sphere::Sphere;
+    use crate::scene::visible::Body;
+
+    // looking down -z from z = 1
+    fn scene() -> Scene<f64> {
+        let camera = Camera::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            8,
+            8,
+            60.0_f64.to_radians(),
+        );
+        let black = Color::new(0.0, 0.0, 0.0).unwrap();
+
+        Scene::new(camera, black.clone(), black)
+    }
+
+    fn grey() -> Lambertian<f64> {
+        Lambertian::new(Color::new(0.5, 0.5, 0.5).unwrap())
+    }
+
+    #[test]
+    fn triangle_facing_the_camera() {
+        let mut scene = scene();
+        let triangle = Triangle::new(
+            Vec3::new(-5.0, -5.0, -1.0),
+            Vec3::new(5.0, -5.0, -1.0),
+            Vec3::new(0.0, 5.0, -1.0),
+        );
+        scene.add_visible(Box::new(Body::new(Box::new(triangle), grey())));
+
+        let normals = DebugView::new(DebugMode::Normals).render(&scene);
+        for pixel in normals.iter() {
+            assert_eq!(&Vec3::new(0.5, 0.5, 1.0), pixel.color_vector());
+        }
+
+        let barycentrics = DebugView::new(DebugMode::Barycentrics).render(&scene);
+        for pixel in barycentrics.iter() {
+            let weights = pixel.color_vector();
+            assert!(weights.x > 0.0 && weights.y > 0.0 && weights.z > 0.0);
+            assert!((weights.x + weights.y + weights.z - 1.0).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn objects_get_their_own_colors_and_depths() {
+        let mut scene = scene();
+        let near = Sphere::new(Vec3::new(-0.3, 0.0, -1.0), 0.25);
+        let far = Sphere::new(Vec3::new(0.6, 0.0, -3.0), 0.5);
+        scene.add_visible(Box::new(Body::new(Box::new(near), grey())));
+        scene.add_visible(Box::new(Body::new(Box::new(far), grey())));
+
+        // towards the near sphere, between the two and towards the far one
+        let view = DebugView::new(DebugMode::ObjectId);
+        let eye = Vec3::new(0.0, 0.0, 1.0);
+        let ids = [
+            Vec3::new(-0.3, 0.0, -1.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.6, 0.0, -3.0),
+        ]
+        .map(|target| view.inspect(&scene, &Ray::new(eye.clone(), target.sub(&eye).normalize())));
+        assert_ne!(ids[0], ids[2]);
+        assert_eq!(Vec3::new(0.0, 0.0, 0.0), ids[1]);
+
+        let depths = DebugView::new(DebugMode::Depth).render(&scene);
+        let brightest = depths
+            .iter()
+            .map(|pixel| pixel.color_vector().x)
+            .fold(0.0, f64::max);
+        // the nearest surface is white, and the far sphere about half as bright
+        assert_eq!(1.0, brightest);
+        assert!(depths
+            .iter()
+            .any(|pixel| (0.3..0.6).contains(&pixel.color_vector().x)));
+    }
+
+    #[test]
+    fn shading_takes_more_tests_than_missing() {
+        let mut scene = scene();
+        scene.add_visible(Box::new(Body::new(
+            Box::new(Sphere::new(Vec3::new(0.0, 0.0, -1.0), 0.3)),
+            grey(),
+        )));
+        scene.add_visible(Box::new(Body::new(
+            Box::new(Sphere::new(Vec3::new(0.0, 3.0, -1.0), 0.3)),
+            grey(),
+        )));
+        scene.add_light(Box::new(PointLight::new(
+            Color::new(1.0, 1.0, 1.0).unwrap(),
+            Vec3::new(0.0, 0.0, 2.0),
+        )));
+
+        // a ray that misses only tests both spheres once
+        let before = scene.intersection_tests();
+        let miss = Ray::new(Vec3::new(0.0, 0.0, 1.0), Vec3::new(1.0, 0.0, 0.0));
+        scene.trace_ray(miss, 0);
+        assert_eq!(2, scene.intersection_tests() - before);
+
+        // the middle of the image sees the sphere and sends a shadow ray, so it's hottest
+        let heatmap = DebugView::new(DebugMode::IntersectionTests).render(&scene);
+        assert_eq!(
+            &Vec3::new(1.0, 0.0, 0.0),
+            heatmap.pixel(4, 4).color_vector()
+        );
+        assert_eq!(
+            &Vec3::new(0.0, 0.0, 1.0),
+            heatmap.pixel(0, 0).color_vector()
+        );
+    }
+}
@@ -0,0 +1,373 @@
+use crate::common::{Vec3, VertexFormat};
+use crate::image::Image;
+use crate::sampler::{Distribution, Sampler};
+use crate::scene::visible::spherical_uv;
+use crate::scene::visible::texture::luminance;
+
+// How directions map onto the images of an environment
+#[derive(Debug)]
+enum Layout<T: VertexFormat> {
+    // one image, with u running around the y axis and v from straight down to straight up
+    Equirectangular(Image<T>),
+    // six square images, for the faces of a cube around the scene
+    CubeMap(Vec<Image<T>>),
+}
+
+// Axes of the cube map faces, in the order +x, -x, +y, -y, +z, -z. Each face is the plane one
+// unit along its major axis, with image columns running along s and rows, from the top, along t.
+// This is the layout OpenGL uses.
+const CUBE_FACES: [([f64; 3], [f64; 3], [f64; 3]); 6] = [
+    ([1.0, 0.0, 0.0], [0.0, 0.0, -1.0], [0.0, -1.0, 0.0]),
+    ([-1.0, 0.0, 0.0], [0.0, 0.0, 1.0], [0.0, -1.0, 0.0]),
+    ([0.0, 1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0]),
+    ([0.0, -1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, -1.0]),
+    ([0.0, 0.0, 1.0], [1.0, 0.0, 0.0], [0.0, -1.0, 0.0]),
+    ([0.0, 0.0, -1.0], [-1.0, 0.0, 0.0], [0.0, -1.0, 0.0]),
+];
+
+fn axis<T: VertexFormat>(components: &[f64; 3]) -> Vec3<T> {
+    Vec3::new(
+        T::from(components[0]).unwrap(),
+        T::from(components[1]).unwrap(),
+        T::from(components[2]).unwrap(),
+    )
+}
+
+// Light arriving from infinitely far away, from every direction, like the sky or a studio HDRI.
+// Shown behind the scene and in reflections, and sampled by the path tracer to light surfaces,
+// choosing bright parts of the image more often.
+#[derive(Debug)]
+pub struct Environment<T: VertexFormat> {
+    layout: Layout<T>,
+    intensity: T,
+    // turn around the y axis, in radians
+    rotation: T,
+    // chances of sampling each texel, proportional to the light arriving from it
+    distribution: Distribution<T>,
+    // mean luminance over all directions, before the intensity
+    mean_luminance: T,
+}
+
+impl<T: VertexFormat> Environment<T> {
+    pub fn equirectangular(image: Image<T>) -> Environment<T> {
+        Environment::new(Layout::Equirectangular(image))
+    }
+
+    // faces in the order +x, -x, +y, -y, +z, -z, all with the same size
+    pub fn cube_map(faces: [Image<T>; 6]) -> Environment<T> {
+        Environment::new(Layout::CubeMap(faces.into()))
+    }
+
+    fn new(layout: Layout<T>) -> Environment<T> {
+        let mut environment = Environment {
+            layout,
+            intensity: T::one(),
+            rotation: T::zero(),
+            distribution: Distribution::new(&[]),
+            mean_luminance: T::zero(),
+        };
+
+        let weights: Vec<T> = (0..environment.texel_count())
+            .map(|texel| {
+                luminance(&environment.texel_radiance(texel)) * environment.solid_angle(texel)
+            })
+            .collect();
+        environment.distribution = Distribution::new(&weights);
+        environment.mean_luminance = weights.iter().fold(T::zero(), |sum, weight| sum + *weight)
+            / T::from(4.0 * std::f64::consts::PI).unwrap();
+
+        environment
+    }
+
+    // scales the radiance of the images
+    pub fn set_intensity(&mut self, intensity: T) {
+        self.intensity = intensity;
+    }
+
+    pub fn set_rotation(&mut self, rotation: T) {
+        self.rotation = rotation;
+    }
+
+    // light arriving from the direction
+    pub fn radiance(&self, direction: &Vec3<T>) -> Vec3<T> {
+        let texel = self.texel_at(&rotate_y(direction, self.rotation.neg()));
+
+        self.texel_radiance(texel).mul(self.intensity)
+    }
+
+    // Estimate of how much light it gives the scene, comparable to LightSource::power. A light's
+    // color is the irradiance it gives over pi, which for an even environment is its radiance.
+    pub fn power(&self) -> T {
+        self.mean_luminance * self.intensity
+    }
+
+    // Picks a direction to gather light from, returning it with its density. Texels are picked
+    // by their share of the light, and the direction is spread evenly within the texel.
+    pub fn sample(&self, sampler: &mut dyn Sampler<T>) -> Option<(Vec3<T>, T)> {
+        let (texel, chance) = self.distribution.sample(sampler.next_1d());
+        let solid_angle = self.solid_angle(texel);
+        if chance <= T::zero() || solid_angle <= T::zero() {
+            return None;
+        }
+
+        let direction = self.texel_direction(texel, sampler.next_2d());
+
+        Some((rotate_y(&direction, self.rotation), chance / solid_angle))
+    }
+
+    // density with which sample picks the direction
+    pub fn pdf(&self, direction: &Vec3<T>) -> T {
+        let texel = self.texel_at(&rotate_y(direction, self.rotation.neg()));
+        let solid_angle = self.solid_angle(texel);
+        if solid_angle <= T::zero() {
+            return T::zero();
+        }
+
+        self.distribution.probability(texel) / solid_angle
+    }
+
+    // Texels are numbered face by face, and row by row within each face. Equirectangular rows
+    // start at the bottom, like Image's, and cube map rows start at the top.
+
+    fn face_size(&self) -> (u32, u32) {
+        match &self.layout {
+            Layout::Equirectangular(image) => (image.width(), image.height()),
+            Layout::CubeMap(faces) => (faces[0].width(), faces[0].height()),
+        }
+    }
+
+    fn texel_count(&self) -> usize {
+        let (width, height) = self.face_size();
+        let faces = match &self.layout {
+            Layout::Equirectangular(_) => 1,
+            Layout::CubeMap(faces) => faces.len(),
+        };
+
+        faces * (width * height) as usize
+    }
+
+    // face, column and row of a texel
+    fn texel_position(&self, texel: usize) -> (usize, u32, u32) {
+        let (width, height) = self.face_size();
+        let face_texels = (width * height) as usize;
+        let index = (texel % face_texels) as u32;
+
+        (texel / face_texels, index % width, index / width)
+    }
+
+    fn texel_radiance(&self, texel: usize) -> Vec3<T> {
+        let (face, column, row) = self.texel_position(texel);
+
+        match &self.layout {
+            Layout::Equirectangular(image) => image.pixel(column, row).color_vector().clone(),
+            Layout::CubeMap(faces) => {
+                let image = &faces[face];
+                image
+                    .pixel(column, image.height() - row - 1)
+                    .color_vector()
+                    .clone()
+            }
+        }
+    }
+
+    // texel containing the direction, given in the environment's own orientation
+    fn texel_at(&self, direction: &Vec3<T>) -> usize {
+        let (width, height) = self.face_size();
+        let to_index = |coordinate: T, size: u32| {
+            (coordinate * T::from(size).unwrap())
+                .to_u32()
+                .unwrap_or(0)
+                .min(size - 1)
+        };
+
+        match &self.layout {
+            Layout::Equirectangular(_) => {
+                let (u, v) = spherical_uv(direction);
+
+                (to_index(v, height) * width + to_index(u, width)) as usize
+            }
+            Layout::CubeMap(_) => {
+                let components = [direction.x, direction.y, direction.z];
+                let major = (0..3)
+                    .max_by(|a, b| {
+                        components[*a]
+                            .abs()
+                            .partial_cmp(&components[*b].abs())
+                            .unwrap()
+                    })
+                    .unwrap();
+                let face = 2 * major + if components[major] < T::zero() { 1 } else { 0 };
+
+                let (_, s_axis, t_axis) = &CUBE_FACES[face];
+                let scale = components[major].abs();
+                let half = T::from(0.5).unwrap();
+                let s = (direction.dot(&axis(s_axis)) / scale + T::one()) * half;
+                let t = (direction.dot(&axis(t_axis)) / scale + T::one()) * half;
+
+                face * (width * height) as usize
+                    + (to_index(t, height) * width + to_index(s, width)) as usize
+            }
+        }
+    }
+
+    // direction through a point of a texel, with offset in [0, 1) across it
+    fn texel_direction(&self, texel: usize, offset: (T, T)) -> Vec3<T> {
+        let (width, height) = self.face_size();
+        let (face, column, row) = self.texel_position(texel);
+        let u = (T::from(column).unwrap() + offset.0) / T::from(width).unwrap();
+        let v = (T::from(row).unwrap() + offset.1) / T::from(height).unwrap();
+
+        match &self.layout {
+            Layout::Equirectangular(_) => {
+                let pi = T::from(std::f64::consts::PI).unwrap();
+                let phi = T::from(2.0).unwrap() * pi * u - pi;
+                let theta = pi * v;
+                let (sin_theta, cos_theta) = theta.sin_cos();
+
+                Vec3::new(
+                    phi.cos() * sin_theta,
+                    cos_theta.neg(),
+                    phi.sin().neg() * sin_theta,
+                )
+            }
+            Layout::CubeMap(_) => {
+                let two = T::from(2.0).unwrap();
+                let (major, s_axis, t_axis) = &CUBE_FACES[face];
+
+                axis::<T>(major)
+                    .add(&axis::<T>(s_axis).mul(u * two - T::one()))
+                    .add(&axis::<T>(t_axis).mul(v * two - T::one()))
+                    .normalize()
+            }
+        }
+    }
+
+    // solid angle covered by a texel, measured at its center
+    fn solid_angle(&self, texel: usize) -> T {
+        let (width, height) = self.face_size();
+        let (_, column, row) = self.texel_position(texel);
+        let pi = T::from(std::f64::consts::PI).unwrap();
+        let two = T::from(2.0).unwrap();
+        let size = T::from(width).unwrap() * T::from(height).unwrap();
+
+        match &self.layout {
+            Layout::Equirectangular(_) => {
+                let theta =
+                    pi * (T::from(row).unwrap() + T::from(0.5).unwrap()) / T::from(height).unwrap();
+
+                two * pi * pi * theta.sin() / size
+            }
+            Layout::CubeMap(_) => {
+                let center = |index: u32, size: u32| {
+                    (T::from(index).unwrap() + T::from(0.5).unwrap()) / T::from(size).unwrap() * two
+                        - T::one()
+                };
+                let s = center(column, width);
+                let t = center(row, height);
+                let distance2 = T::one() + s * s + t * t;
+
+                T::from(4.0).unwrap() / (size * distance2 * distance2.sqrt())
+            }
+        }
+    }
+}
+
+// turns a direction around the y axis by the angle
+fn rotate_y<T: VertexFormat>(direction: &Vec3<T>, angle: T) -> Vec3<T> {
+    let (sin, cos) = angle.sin_cos();
+
+    Vec3::new(
+        cos * direction.x + sin * direction.z,
+        direction.y,
+        cos * direction.z - sin * direction.x,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::Color;
+    use crate::sampler::RandomSampler;
+
+    fn constant_image(width: u32, height: u32, value: f64) -> Image<f64> {
+        let mut image = Image::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                image.set_pixel(x, y, Color::new_unclipped(value, value, value));
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn texel_directions_map_back_to_their_texel() {
+        let equirectangular = Environment::equirectangular(constant_image(16, 8, 1.0));
+        let cube = Environment::cube_map([
+            constant_image(4, 4, 1.0),
+            constant_image(4, 4, 1.0),
+            constant_image(4, 4, 1.0),
+            constant_image(4, 4, 1.0),
+            constant_image(4, 4, 1.0),
+            constant_image(4, 4, 1.0),
+        ]);
+
+        for environment in &[equirectangular, cube] {
+            for texel in 0..environment.texel_count() {
+                let direction = environment.texel_direction(texel, (0.5, 0.5));
+
+                assert_eq!(texel, environment.texel_at(&direction));
+            }
+        }
+    }
+
+    #[test]
+    fn texel_solid_angles_cover_the_sphere() {
+        let equirectangular = Environment::equirectangular(constant_image(64, 32, 1.0));
+        let total: f64 = (0..equirectangular.texel_count())
+            .map(|texel| equirectangular.solid_angle(texel))
+            .sum();
+
+        assert!((total - 4.0 * std::f64::consts::PI).abs() < 0.01);
+    }
+
+    #[test]
+    fn even_environment_power_is_its_radiance() {
+        let mut environment = Environment::equirectangular(constant_image(64, 32, 0.5));
+        environment.set_intensity(2.0);
+
+        assert!((environment.power() - 1.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn cube_map_faces_follow_axes() {
+        let faces = [
+            constant_image(2, 2, 1.0),
+            constant_image(2, 2, 2.0),
+            constant_image(2, 2, 3.0),
+            constant_image(2, 2, 4.0),
+            constant_image(2, 2, 5.0),
+            constant_image(2, 2, 6.0),
+        ];
+        let environment = Environment::cube_map(faces);
+
+        assert_eq!(2.0, environment.radiance(&Vec3::new(-1.0, 0.1, 0.2)).x);
+        assert_eq!(3.0, environment.radiance(&Vec3::new(0.1, 1.0, -0.2)).x);
+        assert_eq!(6.0, environment.radiance(&Vec3::new(0.3, 0.2, -1.0)).x);
+    }
+
+    #[test]
+    fn samples_favor_bright_texels() {
+        let mut image = constant_image(8, 4, 0.0);
+        image.set_pixel(2, 1, Color::new_unclipped(10.0, 10.0, 10.0));
+        let mut environment = Environment::equirectangular(image);
+        environment.set_rotation(1.0);
+        let mut sampler = RandomSampler::new(6);
+
+        for _ in 0..20 {
+            let (direction, pdf) = environment.sample(&mut sampler).unwrap();
+
+            assert_eq!(10.0, environment.radiance(&direction).x);
+            assert!((environment.pdf(&direction) - pdf).abs() < 1e-9);
+        }
+    }
+}
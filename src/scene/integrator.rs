@@ -0,0 +1,870 @@
+use crate::common::{Color, Intersection, Ray, Vec3, VertexFormat};
+use crate::image::Image;
+use crate::sampler::{cosine_hemisphere, to_world, Distribution, RandomSampler, Sampler};
+use crate::scene::light::LightSource;
+use crate::scene::medium::{Medium, Segment};
+use crate::scene::visible::Visible;
+use crate::scene::Scene;
+
+// Turns a scene into an image, by deciding which rays to trace and how to combine them
+pub trait Integrator<T: VertexFormat> {
+    fn render(&self, scene: &Scene<T>) -> Image<T>;
+
+    // the render with colors brighter than white kept as they are, for denoising. Integrators
+    // whose colors can't get past white have nothing to clip and leave this as render.
+    fn render_unclipped(&self, scene: &Scene<T>) -> Image<T> {
+        self.render(scene)
+    }
+}
+
+// One ray per pixel, shaded with Bsdf::shade, plus perfect mirror reflections and refractions
+pub struct Whitted;
+
+impl<T: VertexFormat> Integrator<T> for Whitted {
+    fn render(&self, scene: &Scene<T>) -> Image<T> {
+        let camera = scene.camera();
+        let mut image = Image::new(camera.width(), camera.height());
+
+        let mut j = T::zero();
+        while j < *camera.y_res() {
+            let mut i = T::zero();
+            while i < *camera.x_res() {
+                let ray = camera.ray(i, j);
+
+                let pixel = scene.trace_ray(ray, 0);
+
+                image.set_pixel(i.to_u32().unwrap(), j.to_u32().unwrap(), pixel);
+
+                i = i + T::one();
+            }
+
+            j = j + T::one();
+        }
+
+        image
+    }
+}
+
+// Unidirectional path tracer. Paths are continued by sampling the surface scattering, or the phase
+// function where media scatter them, and lit at each bounce by a shadow ray towards one light,
+// chosen by its estimated power. Rays leaving the scene see the background color, or the
+// environment. Environments are reached both by shadow rays and by paths leaving the scene, and
+// the two are weighted against each other with the power heuristic.
+pub struct PathTracer {
+    samples_per_pixel: u32,
+    max_depth: u32,
+    seed: u64,
+}
+
+// bounces before paths may be ended early with russian roulette
+const ROULETTE_DEPTH: u32 = 3;
+
+// scattering events inside a subsurface material before a random walk is given up on
+const WALK_STEPS: u32 = 256;
+
+impl PathTracer {
+    pub fn new(samples_per_pixel: u32) -> PathTracer {
+        PathTracer {
+            samples_per_pixel,
+            max_depth: 8,
+            seed: 0,
+        }
+    }
+
+    // number of surfaces a path can scatter off of
+    pub fn set_max_depth(&mut self, max_depth: u32) {
+        self.max_depth = max_depth;
+    }
+
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+
+    // light arriving at the ray origin along the ray
+    pub fn radiance<T: VertexFormat>(
+        &self,
+        scene: &Scene<T>,
+        ray: Ray<T>,
+        sampler: &mut dyn Sampler<T>,
+    ) -> Vec3<T> {
+        let lights = LightSelection::new(scene);
+        let mut radiance = Vec3::new(T::zero(), T::zero(), T::zero());
+        let mut throughput = Vec3::new(T::one(), T::one(), T::one());
+        let mut ray = ray;
+        let mut specular = true;
+        // density the direction of the last bounce was sampled with, unless it was specular
+        let mut scatter_pdf = T::zero();
+        // medium of the subsurface material the path is inside of
+        let mut interior: Option<&Medium<T>> = None;
+
+        for depth in 0..=self.max_depth {
+            // past the last bounce, paths only go on to pick up their share of the environment
+            let last = depth == self.max_depth;
+            if last && (scene.environment().is_none() || interior.is_some()) {
+                break;
+            }
+
+            let hit = match interior {
+                Some(medium) => match random_walk(scene, &ray, medium, sampler) {
+                    Some((walked, mut intersection, visible, weight)) => {
+                        throughput = throughput.scalar_mul(&weight);
+                        // seen from inside, the surface faces out of the material
+                        if intersection.normal.dot(&walked.direction) < T::zero() {
+                            intersection.flip_normal();
+                        }
+                        ray = walked;
+
+                        Some((intersection, visible))
+                    }
+                    None => break,
+                },
+                None => scene.intersect(&ray),
+            };
+
+            if !last && interior.is_none() && scene.has_media() {
+                let distance = match &hit {
+                    Some((intersection, _)) => {
+                        intersection.point.sub(&ray.origin).mag_sqrd().sqrt()
+                    }
+                    None => T::infinity(),
+                };
+
+                // grid volumes are delta tracked first, and homogeneous media only sampled short
+                // of where the ray collides with them
+                let collision = scene.sample_grid_collision(&ray, distance, sampler);
+                let horizon = collision.as_ref().map_or(distance, |(t, _)| *t);
+                let (weight, scattering) = sample_media(scene, &ray, horizon, sampler);
+                throughput = throughput.scalar_mul(&weight);
+
+                let scattered = if let Some((t, segment)) = scattering {
+                    let point = ray.origin.add(&ray.direction.mul(t));
+                    let phase = |wi: &Vec3<T>| {
+                        (
+                            segment.phase(&ray.direction, wi),
+                            segment.phase_pdf(&ray.direction, wi),
+                        )
+                    };
+                    radiance.mut_add(
+                        &throughput.scalar_mul(&lights.direct(&point, None, &phase, sampler)),
+                    );
+
+                    let u = sampler.next_1d();
+                    let (wi, weight) = segment.sample_phase(&ray.direction, u, sampler.next_2d());
+                    let pdf = segment.phase_pdf(&ray.direction, &wi);
+                    Some((point, wi, weight, pdf))
+                } else if let Some((t, volume)) = collision {
+                    // a real collision, which scatters the albedo of the light
+                    let point = ray.origin.add(&ray.direction.mul(t));
+                    throughput = throughput.scalar_mul(volume.albedo());
+                    let phase = |wi: &Vec3<T>| {
+                        let value = volume.phase(&ray.direction, wi);
+                        (Vec3::new(value, value, value), value)
+                    };
+                    radiance.mut_add(
+                        &throughput.scalar_mul(&lights.direct(&point, None, &phase, sampler)),
+                    );
+
+                    let wi = volume.sample_phase(&ray.direction, sampler.next_2d());
+                    let pdf = volume.phase(&ray.direction, &wi);
+                    Some((point, wi, Vec3::new(T::one(), T::one(), T::one()), pdf))
+                } else {
+                    None
+                };
+
+                if let Some((point, wi, weight, pdf)) = scattered {
+                    throughput = match survive(throughput.scalar_mul(&weight), depth, sampler) {
+                        Some(throughput) => throughput,
+                        None => break,
+                    };
+                    specular = false;
+                    scatter_pdf = pdf;
+
+                    ray = Ray::new(point, wi);
+                    continue;
+                }
+            }
+
+            let (intersection, visible) = match hit {
+                Some(_) if last => break,
+                Some(hit) => hit,
+                None => {
+                    let mut background = scene.background(&ray.direction);
+                    if !specular {
+                        background =
+                            background.mul(lights.escape_weight(&ray.direction, scatter_pdf));
+                    }
+                    // media weren't sampled along the last ray
+                    if last {
+                        background =
+                            background.scalar_mul(&scene.transmittance(&ray, T::infinity(), true));
+                    }
+
+                    radiance.mut_add(&throughput.scalar_mul(&background));
+                    break;
+                }
+            };
+            let wo = ray.direction.mul(T::one().neg());
+            let bsdf = visible.bsdf();
+            let shading = bsdf.shading_intersection(&intersection);
+
+            let scatter = |wi: &Vec3<T>| {
+                (
+                    bsdf.evaluate(&shading, &wo, wi),
+                    bsdf.pdf(&shading, &wo, wi),
+                )
+            };
+            let direct = lights.direct(
+                &intersection.point,
+                Some(&intersection.normal),
+                &scatter,
+                sampler,
+            );
+            radiance.mut_add(&throughput.scalar_mul(&direct));
+
+            let sample = match bsdf.sample(&shading, &wo, sampler) {
+                Some(sample) => sample,
+                None => break,
+            };
+            throughput = match survive(throughput.scalar_mul(&sample.weight), depth, sampler) {
+                Some(throughput) => throughput,
+                None => break,
+            };
+            specular = sample.specular;
+            scatter_pdf = sample.pdf;
+            interior = match bsdf.interior() {
+                Some(medium) if intersection.normal.dot(&sample.direction) < T::zero() => {
+                    Some(medium)
+                }
+                _ => None,
+            };
+
+            ray = intersection.spawn_ray(sample.direction);
+        }
+
+        radiance
+    }
+}
+
+// Russian roulette, which ends paths carrying little light once they're past a few bounces. The
+// throughput of surviving paths is raised to make up for the ones ended.
+pub(crate) fn survive<T: VertexFormat>(
+    throughput: Vec3<T>,
+    depth: u32,
+    sampler: &mut dyn Sampler<T>,
+) -> Option<Vec3<T>> {
+    if depth < ROULETTE_DEPTH {
+        return Some(throughput);
+    }
+
+    let survival = throughput
+        .x
+        .max(throughput.y)
+        .max(throughput.z)
+        .min(T::from(0.95).unwrap());
+    if sampler.next_1d() >= survival {
+        return None;
+    }
+
+    Some(throughput.div(survival))
+}
+
+// last ray of a random walk, the surface it reached and the walk's weight
+type WalkEnd<'a, T> = (Ray<T>, Intersection<T>, &'a Box<dyn Visible<T>>, Vec3<T>);
+
+// Follows a path scattering around inside the medium of a subsurface material until it reaches a
+// surface again. None if the path was absorbed.
+fn random_walk<'a, T: VertexFormat>(
+    scene: &'a Scene<T>,
+    ray: &Ray<T>,
+    medium: &Medium<T>,
+    sampler: &mut dyn Sampler<T>,
+) -> Option<WalkEnd<'a, T>> {
+    let mut ray = Ray::new(ray.origin.clone(), ray.direction.clone());
+    let mut weight = Vec3::new(T::one(), T::one(), T::one());
+
+    for step in 0..WALK_STEPS {
+        let (intersection, visible) = scene.intersect(&ray)?;
+        let distance = intersection.point.sub(&ray.origin).mag_sqrd().sqrt();
+
+        let u = sampler.next_1d();
+        let (offset, step_weight) = medium.sample_distance(distance, u, sampler);
+        weight = weight.scalar_mul(&step_weight);
+
+        let offset = match offset {
+            Some(offset) => offset,
+            None => return Some((ray, intersection, visible, weight)),
+        };
+        weight = survive(weight, step, sampler)?;
+
+        let point = ray.origin.add(&ray.direction.mul(offset));
+        let direction = medium.sample_phase(&ray.direction, sampler.next_2d());
+        ray = Ray::new(point, direction);
+    }
+
+    None
+}
+
+// Picks the distance along the ray, short of the given one, at which media scatter it, if any.
+// Distances are drawn by the extinction averaged over the color channels, and the returned weight
+// corrects for each channel's own.
+fn sample_media<'a, T: VertexFormat>(
+    scene: &'a Scene<T>,
+    ray: &Ray<T>,
+    distance: T,
+    sampler: &mut dyn Sampler<T>,
+) -> (Vec3<T>, Option<(T, Segment<'a, T>)>) {
+    let mut weight = Vec3::new(T::one(), T::one(), T::one());
+
+    for segment in scene.media_segments(ray, distance, true) {
+        let extinction = segment.mean_extinction();
+        if extinction <= T::zero() {
+            continue;
+        }
+
+        let offset = (T::one() - sampler.next_1d()).ln().neg() / extinction;
+        if offset < segment.length() {
+            let pdf = extinction * (extinction.neg() * offset).exp();
+            let scattered = segment
+                .transmittance(offset)
+                .scalar_mul(&segment.scattering())
+                .div(pdf);
+
+            return (
+                weight.scalar_mul(&scattered),
+                Some((segment.start + offset, segment)),
+            );
+        }
+
+        let passed = (extinction.neg() * segment.length()).exp();
+        weight = weight.scalar_mul(&segment.transmittance(segment.length()).div(passed));
+    }
+
+    (weight, None)
+}
+
+// Balances two ways of sampling the same light by the squares of their densities
+pub(crate) fn power_heuristic<T: VertexFormat>(pdf: T, other: T) -> T {
+    let (pdf, other) = (pdf * pdf, other * other);
+    if pdf + other <= T::zero() {
+        return T::zero();
+    }
+
+    pdf / (pdf + other)
+}
+
+// fraction of the light arriving from a direction that a bounce scatters along the path, and the
+// density of the bounce being sampled in that direction
+pub(crate) type Scatter<'a, T> = dyn Fn(&Vec3<T>) -> (Vec3<T>, T) + 'a;
+
+// Chooses the light each shadow ray is sent towards, with chances proportional to the lights'
+// estimated power. The environment, if there is one, is chosen like one more light after the
+// scene's own.
+pub(crate) struct LightSelection<'a, T: VertexFormat> {
+    scene: &'a Scene<T>,
+    lights: Vec<&'a dyn LightSource<T>>,
+    distribution: Distribution<T>,
+}
+
+impl<'a, T: VertexFormat> LightSelection<'a, T> {
+    pub(crate) fn new(scene: &'a Scene<T>) -> LightSelection<'a, T> {
+        LightSelection::of(
+            scene,
+            scene.lights().iter().map(|light| light.as_ref()).collect(),
+        )
+    }
+
+    // only the lights at infinity and the environment, for renderers that reach the other
+    // lights some other way
+    pub(crate) fn at_infinity(scene: &'a Scene<T>) -> LightSelection<'a, T> {
+        let lights = scene
+            .lights()
+            .iter()
+            .map(|light| light.as_ref())
+            .filter(|light| !light.location().x.is_finite())
+            .collect();
+
+        LightSelection::of(scene, lights)
+    }
+
+    fn of(scene: &'a Scene<T>, lights: Vec<&'a dyn LightSource<T>>) -> LightSelection<'a, T> {
+        let mut powers: Vec<T> = lights.iter().map(|light| light.power()).collect();
+        if let Some(environment) = scene.environment() {
+            powers.push(environment.power());
+        }
+
+        LightSelection {
+            scene,
+            lights,
+            distribution: Distribution::new(&powers),
+        }
+    }
+
+    // Density of a shadow ray being sent along the direction, towards the environment. Paths
+    // can't leave the scene through fog, so shadow rays are all there is to light it with then.
+    fn environment_pdf(&self, direction: &Vec3<T>) -> Option<T> {
+        let environment = self.scene.environment()?;
+        if self.scene.fog().is_some() {
+            return None;
+        }
+        let chance = self.distribution.probability(self.lights.len());
+
+        Some(chance * environment.pdf(direction))
+    }
+
+    // weight of the environment seen by a path leaving the scene after sampling a bounce
+    pub(crate) fn escape_weight(&self, direction: &Vec3<T>, scatter_pdf: T) -> T {
+        match (self.scene.environment(), self.scene.fog()) {
+            (None, _) => T::one(),
+            (Some(_), Some(_)) => T::zero(),
+            (Some(_), None) => {
+                let light_pdf = self.environment_pdf(direction).unwrap_or_else(T::zero);
+                power_heuristic(scatter_pdf, light_pdf)
+            }
+        }
+    }
+
+    // Light from one chosen light scattered at the point, towards where the path came from, with
+    // the cosine included in the scattering. Lights behind the normal, when there is one, can't
+    // reach the point.
+    pub(crate) fn direct(
+        &self,
+        point: &Vec3<T>,
+        normal: Option<&Vec3<T>>,
+        scatter: &Scatter<T>,
+        sampler: &mut dyn Sampler<T>,
+    ) -> Vec3<T> {
+        let none = Vec3::new(T::zero(), T::zero(), T::zero());
+        if self.distribution.is_empty() {
+            return none;
+        }
+        let (index, chance) = self.distribution.sample(sampler.next_1d());
+        let facing = |wi: &Vec3<T>| normal.is_none_or(|normal| normal.dot(wi) > T::zero());
+
+        if let Some(light) = self.lights.get(index) {
+            let wi = light.light_vector(point);
+            if !facing(&wi) || !self.scene.light_reaches(point, *light) {
+                return none;
+            }
+
+            // a light's color is its irradiance at normal incidence over pi, which keeps
+            // lambertian surfaces as bright as in the Whitted renderer
+            let pi = T::from(std::f64::consts::PI).unwrap();
+            return scatter(&wi)
+                .0
+                .scalar_mul(&light.color_at(point))
+                .scalar_mul(&self.scene.light_transmittance(point, *light))
+                .mul(pi / chance);
+        }
+
+        let environment = match self.scene.environment() {
+            Some(environment) => environment,
+            None => return none,
+        };
+        let (wi, pdf) = match environment.sample(sampler) {
+            Some(sample) => sample,
+            None => return none,
+        };
+        let shadow_ray = Ray::new(point.clone(), wi.clone());
+        if !facing(&wi) || self.scene.intersect(&shadow_ray).is_some() {
+            return none;
+        }
+
+        let (value, scatter_pdf) = scatter(&wi);
+        let weight = match self.environment_pdf(&wi) {
+            Some(light_pdf) => power_heuristic(light_pdf, scatter_pdf),
+            None => T::one(),
+        };
+
+        value
+            .scalar_mul(&environment.radiance(&wi))
+            .scalar_mul(&self.scene.transmittance(&shadow_ray, T::infinity(), false))
+            .mul(weight / (chance * pdf))
+    }
+}
+
+impl<T: VertexFormat> Integrator<T> for PathTracer {
+    fn render(&self, scene: &Scene<T>) -> Image<T> {
+        self.render_unclipped(scene).clipped()
+    }
+
+    fn render_unclipped(&self, scene: &Scene<T>) -> Image<T> {
+        let camera = scene.camera();
+        let mut image = Image::new(camera.width(), camera.height());
+        let mut sampler = RandomSampler::new(self.seed);
+        let samples = T::from(self.samples_per_pixel).unwrap();
+
+        for y in 0..camera.height() {
+            for x in 0..camera.width() {
+                let mut total = Vec3::new(T::zero(), T::zero(), T::zero());
+
+                for _ in 0..self.samples_per_pixel {
+                    // jittered within the pixel, which also antialiases edges
+                    let (dx, dy) = sampler.next_2d();
+                    let ray = camera.ray(T::from(x).unwrap() + dx, T::from(y).unwrap() + dy);
+
+                    total.mut_add(&self.radiance(scene, ray, &mut sampler));
+                }
+
+                let color = total.div(samples);
+                image.set_pixel(x, y, Color::new_unclipped(color.x, color.y, color.z));
+            }
+        }
+
+        image
+    }
+}
+
+// Clay render for checking models, lit by nothing but how open each surface is. Rays are cast over
+// the hemisphere around the first surface each pixel sees, with densities following the cosine,
+// and the pixel is as bright as the fraction of them that get further than the max distance.
+// Materials and lights are ignored, and the background is fully open.
+pub struct AmbientOcclusion<T: VertexFormat> {
+    samples_per_pixel: u32,
+    max_distance: T,
+    seed: u64,
+}
+
+impl<T: VertexFormat> AmbientOcclusion<T> {
+    // every sample is one ray from the camera and one over the hemisphere
+    pub fn new(samples_per_pixel: u32) -> AmbientOcclusion<T> {
+        AmbientOcclusion {
+            samples_per_pixel,
+            max_distance: T::one(),
+            seed: 0,
+        }
+    }
+
+    // surfaces further than this don't occlude
+    pub fn set_max_distance(&mut self, distance: T) {
+        self.max_distance = distance;
+    }
+
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+
+    // how open the surface seen along the ray is, from 0 when closed in to 1
+    pub fn openness(&self, scene: &Scene<T>, ray: Ray<T>, sampler: &mut dyn Sampler<T>) -> T {
+        let mut intersection = match scene.intersect(&ray) {
+            Some((intersection, _)) => intersection,
+            None => return T::one(),
+        };
+        // the side facing the camera
+        if intersection.normal.dot(&ray.direction) > T::zero() {
+            intersection.flip_normal();
+        }
+
+        let local = cosine_hemisphere(sampler.next_2d());
+        let direction = to_world(
+            &local,
+            &intersection.tangent,
+            &intersection.bitangent,
+            &intersection.normal,
+        );
+        let occlusion_ray = intersection.spawn_ray(direction);
+
+        let distance = match scene.intersect(&occlusion_ray) {
+            Some((hit, _)) => hit.point.sub(&occlusion_ray.origin).mag_sqrd().sqrt(),
+            None => T::infinity(),
+        };
+
+        if distance < self.max_distance {
+            T::zero()
+        } else {
+            T::one()
+        }
+    }
+}
+
+impl<T: VertexFormat> Integrator<T> for AmbientOcclusion<T> {
+    fn render(&self, scene: &Scene<T>) -> Image<T> {
+        let camera = scene.camera();
+        let mut image = Image::new(camera.width(), camera.height());
+        let mut sampler = RandomSampler::new(self.seed);
+        let samples = T::from(self.samples_per_pixel).unwrap();
+
+        for y in 0..camera.height() {
+            for x in 0..camera.width() {
+                let mut total = T::zero();
+
+                for _ in 0..self.samples_per_pixel {
+                    let (dx, dy) = sampler.next_2d();
+                    let ray = camera.ray(T::from(x).unwrap() + dx, T::from(y).unwrap() + dy);
+
+                    total = total + self.openness(scene, ray, &mut sampler);
+                }
+
+                let value = total / samples;
+                image.set_pixel(x, y, Color::clipped(Vec3::new(value, value, value)));
+            }
+        }
+
+        image
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::camera::Camera;
+    use crate::scene::environment::Environment;
+    use crate::scene::light::DirectionalLight;
+    use crate::scene::medium::Medium;
+    use crate::scene::visible::bsdf::Lambertian;
+    use crate::scene::visible::material::Material;
+    use crate::scene::visible::plane::Plane;
+    use crate::scene::visible::sphere::Sphere;
+    use crate::scene::visible::subsurface::Subsurface;
+    use crate::scene::visible::Body;
+
+    fn scene() -> Scene<f64> {
+        let camera = Camera::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            4,
+            4,
+            60.0_f64.to_radians(),
+        );
+
+        Scene::new(
+            camera,
+            Color::new(0.0, 0.0, 0.0).unwrap(),
+            Color::new(0.25, 0.5, 0.75).unwrap(),
+        )
+    }
+
+    #[test]
+    fn empty_scene_shows_background() {
+        let scene = scene();
+
+        let image = PathTracer::new(2).render(&scene);
+
+        for pixel in image.iter() {
+            assert_eq!(&Vec3::new(0.25, 0.5, 0.75), pixel.color_vector());
+        }
+    }
+
+    #[test]
+    fn direct_light_matches_whitted_diffuse() {
+        let mut scene = scene();
+        // white lambertian floor under a light shining straight down
+        let floor = Plane::new(Vec3::new(0.0, -1.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        let material = Material::new(
+            1.0,
+            Color::new(1.0, 1.0, 1.0).unwrap(),
+            0.0,
+            Color::new(0.0, 0.0, 0.0).unwrap(),
+            1.0,
+            0.0,
+            Color::new(0.0, 0.0, 0.0).unwrap(),
+            0.0,
+        );
+        scene.add_visible(Box::new(Body::new(Box::new(floor), material)));
+        scene.add_light(Box::new(DirectionalLight::new(
+            Color::new(0.5, 0.5, 0.5).unwrap(),
+            Vec3::new(0.0, 1.0, 0.0),
+        )));
+
+        let mut tracer = PathTracer::new(1);
+        tracer.set_max_depth(1);
+
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        let radiance = tracer.radiance(&scene, ray, &mut RandomSampler::new(0));
+        let whitted = scene.trace_ray(
+            Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, -1.0, 0.0)),
+            0,
+        );
+
+        assert!((radiance.y - 0.5).abs() < 1e-12);
+        assert!((whitted.color_vector().y - 0.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn one_light_at_a_time_adds_up_to_all_of_them() {
+        let mut scene = scene();
+        let floor = Plane::new(Vec3::new(0.0, -1.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        let white = Color::new(1.0, 1.0, 1.0).unwrap();
+        let black = Color::new(0.0, 0.0, 0.0).unwrap();
+        let material = Material::new(1.0, white, 0.0, black.clone(), 1.0, 0.0, black, 0.0);
+        scene.add_visible(Box::new(Body::new(Box::new(floor), material)));
+        for (brightness, x) in [(0.2, 1.0), (0.6, -1.0)] {
+            scene.add_light(Box::new(DirectionalLight::new(
+                Color::new(brightness, brightness, brightness).unwrap(),
+                Vec3::new(x, 1.0, 0.0).normalize(),
+            )));
+        }
+
+        // the brighter light gets most of the shadow rays
+        let lights = LightSelection::new(&scene);
+        assert!((lights.distribution.probability(1) - 0.75).abs() < 1e-9);
+
+        let mut tracer = PathTracer::new(1);
+        tracer.set_max_depth(1);
+        let mut sampler = RandomSampler::new(3);
+
+        let count = 4000;
+        let mut total = 0.0;
+        for _ in 0..count {
+            let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+            total += tracer.radiance(&scene, ray, &mut sampler).y;
+        }
+
+        // both lights come in at 45 degrees
+        let expected = 0.8 * std::f64::consts::FRAC_1_SQRT_2;
+        assert!((total / count as f64 - expected).abs() < 0.02);
+    }
+
+    #[test]
+    fn uniform_environment_lights_white_floor() {
+        let mut scene = scene();
+        let floor = Plane::new(Vec3::new(0.0, -1.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        let white = Color::new(1.0, 1.0, 1.0).unwrap();
+        let black = Color::new(0.0, 0.0, 0.0).unwrap();
+        let material = Material::new(1.0, white, 0.0, black.clone(), 1.0, 0.0, black, 0.0);
+        scene.add_visible(Box::new(Body::new(Box::new(floor), material)));
+
+        let mut sky = Image::new(32, 16);
+        for y in 0..16 {
+            for x in 0..32 {
+                sky.set_pixel(x, y, Color::new(0.5, 0.5, 0.5).unwrap());
+            }
+        }
+        scene.set_environment(Environment::equirectangular(sky));
+
+        let mut tracer = PathTracer::new(1);
+        tracer.set_max_depth(1);
+        let mut sampler = RandomSampler::new(1);
+
+        // a white lambertian surface under the sky reflects all of it
+        let count = 4000;
+        let mut total = 0.0;
+        for _ in 0..count {
+            let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+            total += tracer.radiance(&scene, ray, &mut sampler).y;
+        }
+
+        assert!((total / count as f64 - 0.5).abs() < 0.02);
+    }
+
+    #[test]
+    fn colors_past_white_are_kept_until_clipped() {
+        let mut scene = scene();
+        let mut sky = Image::new(8, 4);
+        for y in 0..4 {
+            for x in 0..8 {
+                sky.set_pixel(x, y, Color::new_unclipped(2.0, 2.0, 2.0));
+            }
+        }
+        scene.set_environment(Environment::equirectangular(sky));
+        let tracer = PathTracer::new(1);
+
+        let unclipped = tracer.render_unclipped(&scene);
+        let clipped = tracer.render(&scene);
+
+        assert!(unclipped
+            .iter()
+            .all(|pixel| (pixel.color_vector().y - 2.0).abs() < 1e-9));
+        assert!(clipped.iter().all(|pixel| pixel.color_vector().y == 1.0));
+    }
+
+    #[test]
+    fn fog_scatters_light_like_whitted() {
+        let mut scene = scene();
+        scene.add_light(Box::new(DirectionalLight::new(
+            Color::new(1.0, 1.0, 1.0).unwrap(),
+            Vec3::new(0.0, 1.0, 0.0),
+        )));
+        scene.set_fog(Medium::new(
+            Vec3::new(0.5, 0.5, 0.5),
+            Vec3::new(0.5, 0.5, 0.5),
+            0.3,
+        ));
+
+        let mut tracer = PathTracer::new(1);
+        tracer.set_max_depth(1);
+        let ray = || Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+
+        let radiance = tracer.radiance(&scene, ray(), &mut RandomSampler::new(0));
+        let whitted = scene.trace_ray(ray(), 0);
+
+        assert!((radiance.x - whitted.color_vector().x).abs() < 1e-9);
+    }
+
+    #[test]
+    fn open_floor_is_white() {
+        let mut scene = scene();
+        let floor = Plane::new(Vec3::new(0.0, -1.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        let white = Color::new(1.0, 1.0, 1.0).unwrap();
+        scene.add_visible(Box::new(Body::new(Box::new(floor), Lambertian::new(white))));
+
+        let image = AmbientOcclusion::new(4).render(&scene);
+
+        for pixel in image.iter() {
+            assert_eq!(&Vec3::new(1.0, 1.0, 1.0), pixel.color_vector());
+        }
+    }
+
+    #[test]
+    fn wall_hides_half_the_floor_at_its_foot() {
+        let mut scene = scene();
+        let white = Color::new(1.0, 1.0, 1.0).unwrap();
+        for (point, normal) in [
+            (Vec3::new(0.0, -1.0, 0.0), Vec3::new(0.0, 1.0, 0.0)),
+            (Vec3::new(1.0, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0)),
+        ] {
+            let plane = Plane::new(point, normal);
+            scene.add_visible(Box::new(Body::new(
+                Box::new(plane),
+                Lambertian::new(white.clone()),
+            )));
+        }
+
+        let mut occlusion = AmbientOcclusion::new(1);
+        occlusion.set_max_distance(f64::INFINITY);
+        let mut sampler = RandomSampler::new(6);
+        let towards = |x: f64| {
+            Ray::new(
+                Vec3::new(0.0, 0.0, 0.0),
+                Vec3::new(x, -1.0, 0.0).normalize(),
+            )
+        };
+
+        // the wall covers half the cosine weighted hemisphere of a point right next to it
+        let count = 4000;
+        let mut total = 0.0;
+        for _ in 0..count {
+            total += occlusion.openness(&scene, towards(0.999), &mut sampler);
+        }
+        assert!((total / count as f64 - 0.5).abs() < 0.03);
+
+        // and none of it once it's out of reach
+        occlusion.set_max_distance(0.1);
+        for _ in 0..100 {
+            assert_eq!(1.0, occlusion.openness(&scene, towards(0.5), &mut sampler));
+        }
+    }
+
+    #[test]
+    fn white_subsurface_sphere_keeps_its_light() {
+        let mut scene = scene();
+        let sphere = Sphere::new(Vec3::new(0.0, 0.0, 5.0), 1.0);
+        let material =
+            Subsurface::new(Color::new(1.0, 1.0, 1.0).unwrap(), Vec3::new(0.2, 0.2, 0.2));
+        scene.add_visible(Box::new(Body::new(Box::new(sphere), material)));
+
+        let mut tracer = PathTracer::new(1);
+        tracer.set_max_depth(64);
+        let mut sampler = RandomSampler::new(8);
+
+        // nothing is absorbed, so the sphere is as bright as the background lighting it
+        let count = 2000;
+        let mut total = 0.0;
+        for _ in 0..count {
+            let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0));
+            total += tracer.radiance(&scene, ray, &mut sampler).y;
+        }
+
+        assert!((total / count as f64 - 0.5).abs() < 0.03);
+    }
+}
@@ -1,9 +1,65 @@
 use crate::common::{Color, Spacial, Vec3, VertexFormat};
+use crate::sampler::{cone_direction, to_world};
+use crate::scene::visible::texture::luminance;
 
 pub trait LightSource<T: VertexFormat>: Spacial<T> {
     fn set_color(&mut self, color: Color<T>);
     fn color(&self) -> &Color<T>;
     fn light_vector(&self, point: &Vec3<T>) -> Vec3<T>;
+
+    // color of the light reaching the point, for lights that aren't equally bright in every
+    // direction
+    fn color_at(&self, _point: &Vec3<T>) -> Vec3<T> {
+        self.color().color_vector().clone()
+    }
+
+    // Estimate of how much light it gives the scene, for choosing which lights to send shadow
+    // rays to. Lights are as bright at any distance, so this is their brightness times the share
+    // of directions they shine in.
+    fn power(&self) -> T {
+        luminance(self.color().color_vector())
+    }
+
+    // Picks a direction for light to leave the light in, for tracing paths that start from it,
+    // returning it with its density per unit solid angle. None for lights at infinity, which
+    // have nowhere to start from.
+    fn sample_emission(&self, _sample: (T, T)) -> Option<(Vec3<T>, T)> {
+        None
+    }
+
+    // density with which sample_emission picks the direction
+    fn emission_pdf(&self, _direction: &Vec3<T>) -> T {
+        T::zero()
+    }
+}
+
+// A light as it reaches a surface, dimmed by the media it passes through on the way. The Whitted
+// renderer shades surfaces with these.
+pub struct IncidentLight<'a, T: VertexFormat> {
+    light: &'a dyn LightSource<T>,
+    transmittance: Vec3<T>,
+}
+
+impl<'a, T: VertexFormat> IncidentLight<'a, T> {
+    pub fn new(light: &'a dyn LightSource<T>) -> IncidentLight<'a, T> {
+        IncidentLight {
+            light,
+            transmittance: Vec3::new(T::one(), T::one(), T::one()),
+        }
+    }
+
+    // fraction of the light's color making it to the surface
+    pub fn set_transmittance(&mut self, transmittance: Vec3<T>) {
+        self.transmittance = transmittance;
+    }
+
+    pub fn light_vector(&self, point: &Vec3<T>) -> Vec3<T> {
+        self.light.light_vector(point)
+    }
+
+    pub fn color_at(&self, point: &Vec3<T>) -> Vec3<T> {
+        self.light.color_at(point).scalar_mul(&self.transmittance)
+    }
 }
 
 pub struct PointLight<T: VertexFormat> {
@@ -35,6 +91,19 @@ impl<T: VertexFormat> LightSource<T> for PointLight<T> {
     fn light_vector(&self, point: &Vec3<T>) -> Vec3<T> {
         self.position.sub(point).normalize()
     }
+
+    // evenly over the sphere
+    fn sample_emission(&self, sample: (T, T)) -> Option<(Vec3<T>, T)> {
+        let cos_theta = T::one() - T::from(2.0).unwrap() * sample.0;
+        let direction = cone_direction(cos_theta, sample.1);
+        let pdf = self.emission_pdf(&direction);
+
+        Some((direction, pdf))
+    }
+
+    fn emission_pdf(&self, _direction: &Vec3<T>) -> T {
+        T::one() / T::from(4.0 * std::f64::consts::PI).unwrap()
+    }
 }
 
 pub struct DirectionalLight<T: VertexFormat> {
@@ -73,6 +142,101 @@ impl<T: VertexFormat> LightSource<T> for DirectionalLight<T> {
     }
 }
 
+// Point light shining in a cone around its direction. Full brightness within the inner angle,
+// fading out smoothly to nothing at the outer angle.
+pub struct SpotLight<T: VertexFormat> {
+    color: Color<T>,
+    position: Vec3<T>,
+    direction: Vec3<T>,
+    cos_inner: T,
+    cos_outer: T,
+}
+
+impl<T: VertexFormat> SpotLight<T> {
+    // the angle is the cone's half angle in radians. The light fades over its outer fifth.
+    pub fn new(color: Color<T>, position: Vec3<T>, direction: Vec3<T>, angle: T) -> SpotLight<T> {
+        SpotLight {
+            color,
+            position,
+            direction: direction.normalize(),
+            cos_inner: (angle * T::from(0.8).unwrap()).cos(),
+            cos_outer: angle.cos(),
+        }
+    }
+
+    // half angle within which the light is at full brightness, at most the cone's angle
+    pub fn set_inner_angle(&mut self, angle: T) {
+        self.cos_inner = angle.cos().max(self.cos_outer);
+    }
+}
+
+impl<T: VertexFormat> Spacial<T> for SpotLight<T> {
+    fn location(&self) -> &Vec3<T> {
+        &self.position
+    }
+}
+
+impl<T: VertexFormat> LightSource<T> for SpotLight<T> {
+    fn set_color(&mut self, color: Color<T>) {
+        self.color = color;
+    }
+
+    fn color(&self) -> &Color<T> {
+        &self.color
+    }
+
+    fn light_vector(&self, point: &Vec3<T>) -> Vec3<T> {
+        self.position.sub(point).normalize()
+    }
+
+    fn color_at(&self, point: &Vec3<T>) -> Vec3<T> {
+        let cos_theta = point.sub(&self.position).normalize().dot(&self.direction);
+        let range = self.cos_inner - self.cos_outer;
+
+        let falloff = if range <= T::zero() {
+            if cos_theta >= self.cos_outer {
+                T::one()
+            } else {
+                T::zero()
+            }
+        } else {
+            // smoothstep between the outer and inner edges
+            let x = ((cos_theta - self.cos_outer) / range)
+                .max(T::zero())
+                .min(T::one());
+            x * x * (T::from(3.0).unwrap() - T::from(2.0).unwrap() * x)
+        };
+
+        self.color.color_vector().mul(falloff)
+    }
+
+    // share of the sphere of directions inside the cone, halfway through its falloff
+    fn power(&self) -> T {
+        let cos_mid = (self.cos_inner + self.cos_outer) / T::from(2.0).unwrap();
+
+        luminance(self.color.color_vector()) * (T::one() - cos_mid) / T::from(2.0).unwrap()
+    }
+
+    // evenly within the cone
+    fn sample_emission(&self, sample: (T, T)) -> Option<(Vec3<T>, T)> {
+        let cos_theta = T::one() - sample.0 * (T::one() - self.cos_outer);
+        let local = cone_direction(cos_theta, sample.1);
+        let (tangent, bitangent) = self.direction.orthonormal_basis();
+        let direction = to_world(&local, &tangent, &bitangent, &self.direction);
+        let pdf = self.emission_pdf(&direction);
+
+        Some((direction, pdf))
+    }
+
+    fn emission_pdf(&self, direction: &Vec3<T>) -> T {
+        if direction.dot(&self.direction) < self.cos_outer {
+            return T::zero();
+        }
+
+        T::one() / (T::from(2.0 * std::f64::consts::PI).unwrap() * (T::one() - self.cos_outer))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -123,4 +287,41 @@ mod tests {
             ulps = 2
         ));
     }
+
+    #[test]
+    fn spot_light_fades_outside_its_cone() {
+        let color = Color::new(1.0, 1.0, 1.0).unwrap();
+        let light = SpotLight::new(
+            color,
+            Vec3::new(0.0, 10.0, 0.0),
+            Vec3::new(0.0, -1.0, 0.0),
+            30.0_f64.to_radians(),
+        );
+
+        assert_eq!(1.0, light.color_at(&Vec3::new(0.0, 0.0, 0.0)).x);
+        // 45 degrees off its axis
+        assert_eq!(0.0, light.color_at(&Vec3::new(10.0, 0.0, 0.0)).x);
+        // 27 degrees, part of the way through the fade
+        let edge = light
+            .color_at(&Vec3::new(10.0 * 27.0_f64.to_radians().tan(), 0.0, 0.0))
+            .x;
+        assert!(edge > 0.0 && edge < 1.0);
+    }
+
+    #[test]
+    fn spot_light_power_is_its_share_of_a_point_light() {
+        let color = Color::new(1.0, 1.0, 1.0).unwrap();
+        let point = PointLight::new(color.clone(), Vec3::new(0.0, 0.0, 0.0));
+        let mut spot = SpotLight::new(
+            color,
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, -1.0, 0.0),
+            std::f64::consts::FRAC_PI_2,
+        );
+        spot.set_inner_angle(std::f64::consts::FRAC_PI_2);
+
+        // a hemisphere
+        assert!((point.power() - 1.0).abs() < 1e-12);
+        assert!((spot.power() - 0.5).abs() < 1e-12);
+    }
 }
@@ -0,0 +1,398 @@
+use crate::common::{Ray, Vec3, VertexFormat};
+use crate::sampler::{cone_direction, to_world, Distribution, Sampler};
+use crate::scene::visible::Solid;
+
+// Homogeneous participating medium, such as fog, smoke or murky water. Coefficients are per unit
+// of distance, and per color channel. Light scattered by the medium follows the Henyey-Greenstein
+// phase function, whose asymmetry runs from -1 (scattering back) through 0 (evenly in every
+// direction) to 1 (scattering forward).
+#[derive(Debug, Clone)]
+pub struct Medium<T: VertexFormat> {
+    absorption: Vec3<T>,
+    scattering: Vec3<T>,
+    asymmetry: T,
+}
+
+impl<T: VertexFormat> Medium<T> {
+    pub fn new(absorption: Vec3<T>, scattering: Vec3<T>, asymmetry: T) -> Medium<T> {
+        Medium {
+            absorption,
+            scattering,
+            asymmetry: clamp_asymmetry(asymmetry),
+        }
+    }
+
+    pub fn absorption(&self) -> &Vec3<T> {
+        &self.absorption
+    }
+
+    pub fn scattering(&self) -> &Vec3<T> {
+        &self.scattering
+    }
+
+    // light lost to the medium per unit of distance, by absorption or scattering
+    pub fn extinction(&self) -> Vec3<T> {
+        self.absorption.add(&self.scattering)
+    }
+
+    // fraction of light making it through the distance
+    pub fn transmittance(&self, distance: T) -> Vec3<T> {
+        attenuation(&self.extinction(), distance)
+    }
+
+    // Distance to the next time light is scattered or absorbed, for a color channel picked at
+    // random, and the weight of scattering there. Picking the channel combines the densities of
+    // every channel, which keeps the weights bounded when they differ a lot. None when the
+    // distance isn't reached, along with the weight of passing through it.
+    pub fn sample_distance(
+        &self,
+        distance: T,
+        u: T,
+        sampler: &mut dyn Sampler<T>,
+    ) -> (Option<T>, Vec3<T>) {
+        let extinction = self.extinction();
+        let channels = [extinction.x, extinction.y, extinction.z];
+        let three = T::from(3.0).unwrap();
+        let channel = (u * three).to_usize().unwrap_or(0).min(2);
+
+        let offset = if channels[channel] > T::zero() {
+            (T::one() - sampler.next_1d()).ln().neg() / channels[channel]
+        } else {
+            T::infinity()
+        };
+
+        if offset < distance {
+            let pdf = channels.iter().fold(T::zero(), |sum, sigma| {
+                sum + *sigma * (sigma.neg() * offset).exp()
+            }) / three;
+            let weight = self
+                .transmittance(offset)
+                .scalar_mul(&self.scattering)
+                .div(pdf);
+
+            (Some(offset), weight)
+        } else {
+            let chance = channels.iter().fold(T::zero(), |sum, sigma| {
+                sum + attenuation_channel(*sigma, distance)
+            }) / three;
+
+            (None, self.transmittance(distance).div(chance))
+        }
+    }
+
+    // density of light travelling along direction being scattered into wi
+    pub fn phase(&self, direction: &Vec3<T>, wi: &Vec3<T>) -> T {
+        henyey_greenstein(self.asymmetry, direction, wi)
+    }
+
+    // direction light travelling along direction is scattered into, distributed by the phase
+    // function, so its weight is always one
+    pub fn sample_phase(&self, direction: &Vec3<T>, sample: (T, T)) -> Vec3<T> {
+        sample_henyey_greenstein(self.asymmetry, direction, sample)
+    }
+}
+
+// keeps the asymmetry of the phase function away from the degenerate -1 and 1
+pub(crate) fn clamp_asymmetry<T: VertexFormat>(asymmetry: T) -> T {
+    let limit = T::from(0.99).unwrap();
+
+    asymmetry.max(limit.neg()).min(limit)
+}
+
+pub(crate) fn henyey_greenstein<T: VertexFormat>(g: T, direction: &Vec3<T>, wi: &Vec3<T>) -> T {
+    let cos_theta = direction.dot(wi);
+    let denominator = T::one() + g * g - T::from(2.0).unwrap() * g * cos_theta;
+
+    (T::one() - g * g)
+        / (T::from(4.0 * std::f64::consts::PI).unwrap() * denominator * denominator.sqrt())
+}
+
+pub(crate) fn sample_henyey_greenstein<T: VertexFormat>(
+    g: T,
+    direction: &Vec3<T>,
+    sample: (T, T),
+) -> Vec3<T> {
+    let two = T::from(2.0).unwrap();
+
+    let cos_theta = if g.abs() < T::from(1e-3).unwrap() {
+        T::one() - two * sample.0
+    } else {
+        let ratio = (T::one() - g * g) / (T::one() - g + two * g * sample.0);
+        (T::one() + g * g - ratio * ratio) / (two * g)
+    };
+
+    let (tangent, bitangent) = direction.orthonormal_basis();
+    let local = cone_direction(cos_theta.max(T::one().neg()).min(T::one()), sample.1);
+
+    to_world(&local, &tangent, &bitangent, direction).normalize()
+}
+
+// Medium filling a closed shape. The shape has no surface of its own, so to render a glass of
+// murky water, add a body with a dielectric material over the same shape.
+pub struct Volume<T: VertexFormat> {
+    shape: Box<dyn Solid<T>>,
+    medium: Medium<T>,
+}
+
+impl<T: VertexFormat> Volume<T> {
+    pub fn new(shape: Box<dyn Solid<T>>, medium: Medium<T>) -> Volume<T> {
+        Volume { shape, medium }
+    }
+
+    pub fn medium(&self) -> &Medium<T> {
+        &self.medium
+    }
+
+    // spans of the ray inside the volume, as distances along it
+    pub fn spans(&self, ray: &Ray<T>) -> Vec<(T, T)> {
+        self.shape
+            .intervals(ray)
+            .into_iter()
+            .map(|interval| (interval.entry.t, interval.exit.t))
+            .collect()
+    }
+}
+
+// Stretch of a ray from start to end that passes through the same media, which may overlap
+pub struct Segment<'a, T: VertexFormat> {
+    pub start: T,
+    pub end: T,
+    pub media: Vec<&'a Medium<T>>,
+}
+
+impl<'a, T: VertexFormat> Segment<'a, T> {
+    // splits the spans of every medium along a ray into segments that don't overlap, in order
+    // along the ray. Spans may run off to infinity, but those with a NaN bound can't be placed
+    // along the ray and are dropped.
+    pub fn split(mut spans: Vec<(T, T, &'a Medium<T>)>) -> Vec<Segment<'a, T>> {
+        spans.retain(|(start, end, _)| !start.is_nan() && !end.is_nan());
+        let mut bounds: Vec<T> = spans
+            .iter()
+            .flat_map(|(start, end, _)| vec![*start, *end])
+            .collect();
+        bounds.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        bounds.dedup();
+
+        bounds
+            .windows(2)
+            .filter_map(|pair| {
+                let (start, end) = (pair[0], pair[1]);
+                let media: Vec<&Medium<T>> = spans
+                    .iter()
+                    .filter(|(from, to, _)| *from <= start && *to >= end)
+                    .map(|(_, _, medium)| *medium)
+                    .collect();
+
+                if media.is_empty() {
+                    None
+                } else {
+                    Some(Segment { start, end, media })
+                }
+            })
+            .collect()
+    }
+
+    pub fn length(&self) -> T {
+        self.end - self.start
+    }
+
+    pub fn extinction(&self) -> Vec3<T> {
+        self.media
+            .iter()
+            .fold(Vec3::new(T::zero(), T::zero(), T::zero()), |sum, medium| {
+                sum.add(&medium.extinction())
+            })
+    }
+
+    pub fn scattering(&self) -> Vec3<T> {
+        self.media
+            .iter()
+            .fold(Vec3::new(T::zero(), T::zero(), T::zero()), |sum, medium| {
+                sum.add(medium.scattering())
+            })
+    }
+
+    // fraction of light making it a distance into the segment
+    pub fn transmittance(&self, distance: T) -> Vec3<T> {
+        attenuation(&self.extinction(), distance)
+    }
+
+    // light per unit of distance scattered from direction into wi, by all of the media
+    pub fn in_scattering(&self, direction: &Vec3<T>, wi: &Vec3<T>) -> Vec3<T> {
+        self.media
+            .iter()
+            .fold(Vec3::new(T::zero(), T::zero(), T::zero()), |sum, medium| {
+                sum.add(&medium.scattering().mul(medium.phase(direction, wi)))
+            })
+    }
+
+    // phase function of the media together, weighted by how much each of them scatters
+    pub fn phase(&self, direction: &Vec3<T>, wi: &Vec3<T>) -> Vec3<T> {
+        let scattering = self.scattering();
+        let in_scattering = self.in_scattering(direction, wi);
+        let ratio = |a: T, b: T| if b > T::zero() { a / b } else { T::zero() };
+
+        Vec3::new(
+            ratio(in_scattering.x, scattering.x),
+            ratio(in_scattering.y, scattering.y),
+            ratio(in_scattering.z, scattering.z),
+        )
+    }
+
+    // Direction light travelling along direction is scattered into, and its weight. One medium is
+    // picked by how much it scatters, and its phase function sampled.
+    pub fn sample_phase(&self, direction: &Vec3<T>, u: T, sample: (T, T)) -> (Vec3<T>, Vec3<T>) {
+        let (index, _) = self.phase_distribution().sample(u);
+
+        let wi = self.media[index].sample_phase(direction, sample);
+        let pdf = self.phase_pdf(direction, &wi);
+
+        if pdf <= T::zero() {
+            return (wi, Vec3::new(T::zero(), T::zero(), T::zero()));
+        }
+
+        let weight = self.phase(direction, &wi).div(pdf);
+        (wi, weight)
+    }
+
+    // density with which sample_phase picks wi
+    pub fn phase_pdf(&self, direction: &Vec3<T>, wi: &Vec3<T>) -> T {
+        let distribution = self.phase_distribution();
+
+        self.media
+            .iter()
+            .enumerate()
+            .fold(T::zero(), |sum, (i, medium)| {
+                sum + distribution.probability(i) * medium.phase(direction, wi)
+            })
+    }
+
+    fn phase_distribution(&self) -> Distribution<T> {
+        let weights: Vec<T> = self
+            .media
+            .iter()
+            .map(|medium| mean(medium.scattering()))
+            .collect();
+
+        Distribution::new(&weights)
+    }
+
+    // Extinction averaged over the color channels. Distances are sampled with it, and their
+    // weights correct for each channel.
+    pub fn mean_extinction(&self) -> T {
+        mean(&self.extinction())
+    }
+}
+
+fn mean<T: VertexFormat>(v: &Vec3<T>) -> T {
+    (v.x + v.y + v.z) / T::from(3.0).unwrap()
+}
+
+// fraction of light left after the distance, for each channel of the extinction. Channels without
+// any extinction let everything through, even over an infinite distance.
+fn attenuation<T: VertexFormat>(extinction: &Vec3<T>, distance: T) -> Vec3<T> {
+    Vec3::new(
+        attenuation_channel(extinction.x, distance),
+        attenuation_channel(extinction.y, distance),
+        attenuation_channel(extinction.z, distance),
+    )
+}
+
+fn attenuation_channel<T: VertexFormat>(extinction: T, distance: T) -> T {
+    if extinction > T::zero() {
+        (extinction.neg() * distance).exp()
+    } else {
+        T::one()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::RandomSampler;
+
+    fn medium(asymmetry: f64) -> Medium<f64> {
+        Medium::new(
+            Vec3::new(0.1, 0.1, 0.1),
+            Vec3::new(0.5, 0.5, 0.5),
+            asymmetry,
+        )
+    }
+
+    #[test]
+    fn phase_function_integrates_to_one() {
+        let direction = Vec3::new(0.0, 0.0, 1.0);
+
+        for asymmetry in [-0.6, 0.0, 0.8] {
+            let medium = medium(asymmetry);
+            // integrated over the angle to the direction
+            let steps = 20000;
+            let mut total = 0.0;
+            for i in 0..steps {
+                let theta = std::f64::consts::PI * (i as f64 + 0.5) / steps as f64;
+                let wi = Vec3::new(theta.sin(), 0.0, theta.cos());
+                total += medium.phase(&direction, &wi)
+                    * 2.0
+                    * std::f64::consts::PI
+                    * theta.sin()
+                    * std::f64::consts::PI
+                    / steps as f64;
+            }
+
+            assert!((total - 1.0).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn forward_scattering_samples_stay_ahead() {
+        let medium = medium(0.8);
+        let direction = Vec3::new(0.0, 1.0, 0.0);
+        let mut sampler = RandomSampler::new(3);
+
+        let count = 2000;
+        let mut total = 0.0;
+        for _ in 0..count {
+            let wi = medium.sample_phase(&direction, sampler.next_2d());
+            assert!((wi.mag_sqrd() - 1.0).abs() < 1e-9);
+            total += wi.dot(&direction);
+        }
+
+        // the mean cosine of Henyey-Greenstein is its asymmetry
+        assert!((total / count as f64 - 0.8).abs() < 0.03);
+    }
+
+    #[test]
+    fn overlapping_spans_split_into_segments() {
+        let fog = medium(0.0);
+        let smoke = medium(0.5);
+
+        let segments = Segment::split(vec![(0.0, 4.0, &fog), (2.0, 6.0, &smoke)]);
+
+        assert_eq!(3, segments.len());
+        assert_eq!(
+            (0.0, 2.0, 1),
+            (segments[0].start, segments[0].end, segments[0].media.len())
+        );
+        assert_eq!(
+            (2.0, 4.0, 2),
+            (segments[1].start, segments[1].end, segments[1].media.len())
+        );
+        assert_eq!(
+            (4.0, 6.0, 1),
+            (segments[2].start, segments[2].end, segments[2].media.len())
+        );
+        assert!((segments[1].transmittance(1.0).x - (-1.2_f64).exp()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn spans_with_nan_bounds_are_dropped() {
+        let fog = medium(0.0);
+        let smoke = medium(0.5);
+
+        let segments = Segment::split(vec![(f64::NAN, 4.0, &smoke), (1.0, f64::INFINITY, &fog)]);
+
+        assert_eq!(1, segments.len());
+        assert_eq!((1.0, f64::INFINITY), (segments[0].start, segments[0].end));
+        assert_eq!(1, segments[0].media.len());
+    }
+}
@@ -0,0 +1,380 @@
+use crate::common::{Color, Vec3, VertexFormat};
+use crate::image::Image;
+use crate::sampler::{Distribution, Sampler};
+use crate::scene::camera::Camera;
+use crate::scene::integrator::{Integrator, PathTracer};
+use crate::scene::visible::texture::luminance;
+use crate::scene::Scene;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+// Metropolis light transport in primary sample space, after Kelemen et al.'s "A Simple and Robust
+// Mutation Strategy for the Metropolis Light Transport Algorithm". Paths are made by the path
+// tracer from a list of random numbers, the first two of which pick the point on the image. Chains
+// of paths wander through the space of these lists, changing a few of the numbers a little with
+// small steps or starting over with a large one, and keeping each new path with a chance that
+// makes them end up spending time on each path in proportion to its brightness. Once a bright but
+// hard to find path turns up, like light coming through a small opening, the chain keeps exploring
+// its neighbours.
+//
+// How bright the image is on the whole is measured beforehand, by a bootstrap phase of
+// independent paths, which also picks the paths the chains start from.
+pub struct MetropolisLightTransport {
+    mutations_per_pixel: u32,
+    bootstrap_samples: u32,
+    chains: u32,
+    large_step_probability: f64,
+    mutation_size: f64,
+    max_depth: u32,
+    seed: u64,
+}
+
+impl MetropolisLightTransport {
+    pub fn new(mutations_per_pixel: u32) -> MetropolisLightTransport {
+        MetropolisLightTransport {
+            mutations_per_pixel,
+            bootstrap_samples: 100000,
+            chains: 64,
+            large_step_probability: 0.3,
+            mutation_size: 0.01,
+            max_depth: 8,
+            seed: 0,
+        }
+    }
+
+    pub fn set_bootstrap_samples(&mut self, samples: u32) {
+        self.bootstrap_samples = samples;
+    }
+
+    // independent chains the mutations are shared between. More chains spread out over the image
+    // more evenly, but each one gets less time to explore.
+    pub fn set_chains(&mut self, chains: u32) {
+        self.chains = chains;
+    }
+
+    pub fn set_large_step_probability(&mut self, probability: f64) {
+        self.large_step_probability = probability;
+    }
+
+    // standard deviation of the small steps, in primary sample space
+    pub fn set_mutation_size(&mut self, size: f64) {
+        self.mutation_size = size;
+    }
+
+    // number of surfaces a path can scatter off of
+    pub fn set_max_depth(&mut self, max_depth: u32) {
+        self.max_depth = max_depth;
+    }
+
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+
+    fn sampler(&self, stream: u64) -> PrimarySampler {
+        PrimarySampler::new(
+            self.seed.wrapping_add(stream),
+            self.mutation_size,
+            self.large_step_probability,
+        )
+    }
+}
+
+// Light arriving through the point on the image picked by the first two samples, and the point
+fn contribution<T: VertexFormat>(
+    scene: &Scene<T>,
+    camera: &Camera<T>,
+    tracer: &PathTracer,
+    sampler: &mut PrimarySampler,
+) -> (Vec3<T>, (T, T)) {
+    let (u, v): (T, T) = sampler.next_2d();
+    let raster = (*camera.x_res() * u, *camera.y_res() * v);
+    let ray = camera.ray(raster.0, raster.1);
+
+    (tracer.radiance(scene, ray, sampler), raster)
+}
+
+impl<T: VertexFormat> Integrator<T> for MetropolisLightTransport {
+    fn render(&self, scene: &Scene<T>) -> Image<T> {
+        self.render_unclipped(scene).clipped()
+    }
+
+    fn render_unclipped(&self, scene: &Scene<T>) -> Image<T> {
+        let camera = scene.camera();
+        let mut image = Image::new(camera.width(), camera.height());
+        let mut tracer = PathTracer::new(1);
+        tracer.set_max_depth(self.max_depth);
+
+        // every bootstrap path comes from a sampler of its own, so the chains can start over
+        // from any of them
+        let weights: Vec<T> = (0..self.bootstrap_samples)
+            .map(|i| {
+                let mut sampler = self.sampler(i as u64);
+                luminance(&contribution(scene, camera, &tracer, &mut sampler).0)
+            })
+            .collect();
+        let bootstrap = Distribution::new(&weights);
+        let brightness = weights.iter().fold(T::zero(), |sum, weight| sum + *weight)
+            / T::from(self.bootstrap_samples).unwrap();
+        if bootstrap.is_empty() || brightness <= T::zero() || self.chains == 0 {
+            return image;
+        }
+
+        let pixels = camera.width() as u64 * camera.height() as u64;
+        let mutations = self.mutations_per_pixel as u64 * pixels;
+        let mut rng = StdRng::seed_from_u64(self.seed.wrapping_add(self.bootstrap_samples as u64));
+        let splat = |image: &mut Image<T>, raster: (T, T), color: &Vec3<T>| {
+            let x = raster.0.to_u32().unwrap().min(camera.width() - 1);
+            let y = raster.1.to_u32().unwrap().min(camera.height() - 1);
+            image.splat(x, y, color);
+        };
+
+        for chain in 0..self.chains as u64 {
+            let (index, _) = bootstrap.sample(T::from(rng.gen::<f64>()).unwrap());
+            let mut sampler = self.sampler(index as u64);
+            let (mut current, mut current_raster) =
+                contribution(scene, camera, &tracer, &mut sampler);
+
+            // the chains share the mutations out between them
+            let start = mutations * chain / self.chains as u64;
+            let end = mutations * (chain + 1) / self.chains as u64;
+
+            for _ in start..end {
+                sampler.start_iteration();
+                let (proposed, proposed_raster) =
+                    contribution(scene, camera, &tracer, &mut sampler);
+                let (current_luminance, proposed_luminance) =
+                    (luminance(&current), luminance(&proposed));
+
+                let accept = if current_luminance > T::zero() {
+                    (proposed_luminance / current_luminance).min(T::one())
+                } else {
+                    T::one()
+                };
+
+                // both paths are splatted, weighted by the chance of the chain being on each next,
+                // which makes for less noise than only splatting the one it moves to
+                if accept > T::zero() && proposed_luminance > T::zero() {
+                    splat(
+                        &mut image,
+                        proposed_raster,
+                        &proposed.mul(accept / proposed_luminance),
+                    );
+                }
+                if accept < T::one() && current_luminance > T::zero() {
+                    splat(
+                        &mut image,
+                        current_raster,
+                        &current.mul((T::one() - accept) / current_luminance),
+                    );
+                }
+
+                if T::from(rng.gen::<f64>()).unwrap() < accept {
+                    sampler.accept();
+                    current = proposed;
+                    current_raster = proposed_raster;
+                } else {
+                    sampler.reject();
+                }
+            }
+        }
+
+        // chains spend time on each path in proportion to its brightness, so the splats only
+        // need scaling by the brightness of the image on the whole
+        let scale = brightness / T::from(self.mutations_per_pixel).unwrap();
+        for y in 0..camera.height() {
+            for x in 0..camera.width() {
+                let color = image.pixel(x, y).color_vector().mul(scale);
+                image.set_pixel(x, y, Color::new_unclipped(color.x, color.y, color.z));
+            }
+        }
+
+        image
+    }
+}
+
+// a sample in primary sample space, with the value it had before the current step
+struct PrimarySample {
+    value: f64,
+    // iteration it was last changed in
+    modified: u64,
+    backup: f64,
+    backup_modified: u64,
+}
+
+// Sampler handing out the numbers of the current path of a chain, which are changed lazily as they
+// are asked for. Samples not asked for in some iterations catch up on the small steps they missed
+// all at once, and on any large step since.
+struct PrimarySampler {
+    rng: StdRng,
+    samples: Vec<PrimarySample>,
+    mutation_size: f64,
+    large_step_probability: f64,
+    iteration: u64,
+    large_step: bool,
+    last_large_step: u64,
+    index: usize,
+}
+
+impl PrimarySampler {
+    // the first path is drawn at random, as if by a large step
+    fn new(seed: u64, mutation_size: f64, large_step_probability: f64) -> PrimarySampler {
+        PrimarySampler {
+            rng: StdRng::seed_from_u64(seed),
+            samples: Vec::new(),
+            mutation_size,
+            large_step_probability,
+            iteration: 0,
+            large_step: true,
+            last_large_step: 0,
+            index: 0,
+        }
+    }
+
+    fn start_iteration(&mut self) {
+        self.iteration += 1;
+        self.large_step = self.rng.gen::<f64>() < self.large_step_probability;
+        self.index = 0;
+    }
+
+    fn accept(&mut self) {
+        if self.large_step {
+            self.last_large_step = self.iteration;
+        }
+    }
+
+    // goes back to the path from before the iteration
+    fn reject(&mut self) {
+        for sample in self.samples.iter_mut() {
+            if sample.modified == self.iteration {
+                sample.value = sample.backup;
+                sample.modified = sample.backup_modified;
+            }
+        }
+        self.iteration -= 1;
+    }
+
+    fn mutate(&mut self, index: usize) -> f64 {
+        if index >= self.samples.len() {
+            self.samples.resize_with(index + 1, || PrimarySample {
+                value: 0.0,
+                modified: 0,
+                backup: 0.0,
+                backup_modified: 0,
+            });
+        }
+        let sample = &mut self.samples[index];
+
+        if sample.modified < self.last_large_step {
+            sample.value = self.rng.gen();
+            sample.modified = self.last_large_step;
+        }
+        sample.backup = sample.value;
+        sample.backup_modified = sample.modified;
+
+        if self.large_step {
+            sample.value = self.rng.gen();
+        } else {
+            // a normal step, with the spread of all the ones missed, by Box-Muller
+            let missed = (self.iteration - sample.modified) as f64;
+            let radius = (-2.0 * (1.0 - self.rng.gen::<f64>()).ln()).sqrt();
+            let angle = 2.0 * std::f64::consts::PI * self.rng.gen::<f64>();
+            let step = radius * angle.cos() * self.mutation_size * missed.sqrt();
+
+            sample.value = (sample.value + step).rem_euclid(1.0);
+            if sample.value >= 1.0 {
+                sample.value = 0.0;
+            }
+        }
+        sample.modified = self.iteration;
+
+        sample.value
+    }
+}
+
+impl<T: VertexFormat> Sampler<T> for PrimarySampler {
+    fn next_1d(&mut self) -> T {
+        let value = self.mutate(self.index);
+        self.index += 1;
+
+        T::from(value).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::light::PointLight;
+    use crate::scene::test_scenes::walled_in;
+
+    fn values(sampler: &mut PrimarySampler, count: usize) -> Vec<f64> {
+        (0..count).map(|_| sampler.next_1d()).collect()
+    }
+
+    #[test]
+    fn small_steps_stay_close_and_rejects_go_back() {
+        let mut sampler = PrimarySampler::new(3, 0.01, 0.0);
+        let first = values(&mut sampler, 8);
+
+        sampler.start_iteration();
+        let stepped = values(&mut sampler, 8);
+        for (a, b) in first.iter().zip(stepped.iter()) {
+            let distance = (a - b).abs();
+            assert!(distance > 0.0 && distance.min(1.0 - distance) < 0.1);
+        }
+
+        sampler.reject();
+        for (sample, value) in sampler.samples.iter().zip(first.iter()) {
+            assert_eq!(*value, sample.value);
+        }
+    }
+
+    #[test]
+    fn same_seed_repeats_the_first_path() {
+        let mut sampler = PrimarySampler::new(7, 0.01, 0.3);
+        let mut again = PrimarySampler::new(7, 0.01, 0.3);
+
+        assert_eq!(values(&mut sampler, 16), values(&mut again, 16));
+    }
+
+    #[test]
+    fn chains_spread_over_bright_and_dark_halves() {
+        let camera = Camera::new(
+            Vec3::new(0.0, 0.0, 0.9),
+            Vec3::new(0.0, 0.0, 1.9),
+            Vec3::new(0.0, 1.0, 0.0),
+            8,
+            8,
+            90.0_f64.to_radians(),
+        );
+        // one wall dark, so the chains have to find their way around the image
+        let mut scene = walled_in(camera, &|normal| if normal.x > 0.0 { 0.1 } else { 0.8 });
+        scene.add_light(Box::new(PointLight::new(
+            Color::new(0.3, 0.3, 0.3).unwrap(),
+            Vec3::new(-1.5, 1.8, 0.0),
+        )));
+
+        let mut metropolis = MetropolisLightTransport::new(256);
+        metropolis.set_bootstrap_samples(10000);
+        metropolis.set_max_depth(3);
+        let mut path_tracer = PathTracer::new(256);
+        path_tracer.set_max_depth(3);
+
+        // the mean of the left and right halves of the image
+        let halves = |image: &Image<f64>| {
+            let mut halves = [0.0, 0.0];
+            for y in 0..8 {
+                for x in 0..8 {
+                    halves[x as usize / 4] += image.pixel(x, y).color_vector().y / 32.0;
+                }
+            }
+            halves
+        };
+        let expected = halves(&path_tracer.render(&scene));
+        let actual = halves(&metropolis.render(&scene));
+
+        assert!(expected[0] < expected[1] * 0.8);
+        for (expected, actual) in expected.iter().zip(actual.iter()) {
+            assert!((actual - expected).abs() < 0.04 * expected);
+        }
+    }
+}
@@ -1,21 +1,58 @@
 use crate::common::{Color, Intersection, Ray, Spacial, Vec3, VertexFormat};
 use crate::image::Image;
+use crate::sampler::{RandomSampler, Sampler};
+use crate::scene::aov::{Aov, RenderPasses};
 use crate::scene::camera::Camera;
-use crate::scene::light::LightSource;
+use crate::scene::environment::Environment;
+use crate::scene::integrator::{Integrator, Whitted};
+use crate::scene::light::{IncidentLight, LightSource};
+use crate::scene::medium::{Medium, Segment, Volume};
+use crate::scene::visible::pbr::sample_ggx_reflection;
 use crate::scene::visible::Visible;
+use crate::scene::voxel::GridVolume;
+use std::cell::Cell;
 
+pub mod aov;
+pub mod bidirectional;
 pub mod camera;
+pub mod debug;
+pub mod environment;
+pub mod integrator;
 pub mod light;
+pub mod medium;
+pub mod metropolis;
+pub mod photon;
+pub mod sky;
+#[cfg(test)]
+mod test_scenes;
 pub mod visible;
+pub mod voxel;
 
 const REFLECTION_BOUNCES: u32 = 12;
 
+// rays traced for every blurred reflection seen directly by the camera
+const GLOSSY_SAMPLES: u32 = 16;
+
+// distances sampled along every camera ray for the light scattered by media
+const VOLUME_SAMPLES: u32 = 16;
+
 pub struct Scene<T: VertexFormat> {
     camera: Camera<T>,
     visibles: Vec<Box<dyn Visible<T>>>,
     ambient_color: Color<T>,
     background_color: Color<T>,
     lights: Vec<Box<dyn LightSource<T>>>,
+    integrator: Box<dyn Integrator<T>>,
+    glossy_samples: u32,
+    // replaces the background color when set
+    environment: Option<Environment<T>>,
+    // fills all of space, along with any volumes
+    fog: Option<Medium<T>>,
+    volumes: Vec<Volume<T>>,
+    grid_volumes: Vec<GridVolume<T>>,
+    volume_samples: u32,
+    // ray-object intersection tests made so far, for measuring where rendering takes the most work
+    intersection_tests: Cell<u64>,
 }
 
 impl<T: VertexFormat> Scene<T> {
@@ -26,6 +63,14 @@ impl<T: VertexFormat> Scene<T> {
             ambient_color,
             background_color,
             lights: Vec::new(),
+            integrator: Box::new(Whitted),
+            glossy_samples: GLOSSY_SAMPLES,
+            environment: None,
+            fog: None,
+            volumes: Vec::new(),
+            grid_volumes: Vec::new(),
+            volume_samples: VOLUME_SAMPLES,
+            intersection_tests: Cell::new(0),
         }
     }
 
@@ -42,58 +87,386 @@ impl<T: VertexFormat> Scene<T> {
         self.ambient_color = color;
     }
 
+    pub fn set_environment(&mut self, environment: Environment<T>) {
+        self.environment = Some(environment);
+    }
+
+    pub fn set_fog(&mut self, fog: Medium<T>) {
+        self.fog = Some(fog);
+    }
+
+    pub fn add_volume(&mut self, volume: Volume<T>) {
+        self.volumes.push(volume);
+    }
+
+    pub fn add_grid_volume(&mut self, volume: GridVolume<T>) {
+        self.grid_volumes.push(volume);
+    }
+
+    // number of distances sampled along camera rays for light scattered by media. Like glossy
+    // reflections, deeper rays only use one.
+    pub fn set_volume_samples(&mut self, samples: u32) {
+        self.volume_samples = samples;
+    }
+
+    // the Whitted renderer is used by default
+    pub fn set_integrator(&mut self, integrator: Box<dyn Integrator<T>>) {
+        self.integrator = integrator;
+    }
+
+    // number of jittered rays averaged for rough reflections. Only reflections seen directly by
+    // the camera use all of them, deeper ones trace a single ray so the count doesn't multiply
+    // with every bounce.
+    pub fn set_glossy_samples(&mut self, samples: u32) {
+        self.glossy_samples = samples;
+    }
+
     // immutable self borrows
     pub fn render(&self) -> Image<T> {
-        let mut image = Image::new(self.camera.width(), self.camera.height());
+        self.integrator.render(self)
+    }
 
-        let mut j = T::zero();
-        while j < *self.camera.y_res() {
-            let mut i = T::zero();
-            while i < *self.camera.x_res() {
-                let ray = self.camera.ray(i, j);
+    // the render before it's clipped to white, to denoise it. See Integrator::render_unclipped.
+    pub fn render_unclipped(&self) -> Image<T> {
+        self.integrator.render_unclipped(self)
+    }
 
-                let pixel = self.trace_ray(ray, 0);
+    // named buffers for compositing, next to the render itself. See aov::render_passes.
+    pub fn render_passes(&self, aovs: &[Aov]) -> RenderPasses<T> {
+        aov::render_passes(self, aovs)
+    }
 
-                image.set_pixel(i.to_u32().unwrap(), j.to_u32().unwrap(), pixel);
+    pub fn camera(&self) -> &Camera<T> {
+        &self.camera
+    }
 
-                i = i + T::one();
+    pub fn visibles(&self) -> &Vec<Box<dyn Visible<T>>> {
+        &self.visibles
+    }
+
+    pub fn intersection_tests(&self) -> u64 {
+        self.intersection_tests.get()
+    }
+
+    pub fn lights(&self) -> &Vec<Box<dyn LightSource<T>>> {
+        &self.lights
+    }
+
+    pub fn background_color(&self) -> &Color<T> {
+        &self.background_color
+    }
+
+    pub fn environment(&self) -> Option<&Environment<T>> {
+        self.environment.as_ref()
+    }
+
+    pub fn fog(&self) -> Option<&Medium<T>> {
+        self.fog.as_ref()
+    }
+
+    // light arriving along a ray that leaves the scene
+    pub fn background(&self, direction: &Vec3<T>) -> Vec3<T> {
+        match &self.environment {
+            Some(environment) => environment.radiance(direction),
+            None => self.background_color.color_vector().clone(),
+        }
+    }
+
+    pub fn has_media(&self) -> bool {
+        self.fog.is_some() || !self.volumes.is_empty() || !self.grid_volumes.is_empty()
+    }
+
+    // Stretches of the ray up to the distance that pass through media. Fog can be left out, for
+    // rays towards lights at infinity, which are taken to light the fog evenly rather than
+    // through an endless amount of it.
+    pub fn media_segments(&self, ray: &Ray<T>, distance: T, fog: bool) -> Vec<Segment<'_, T>> {
+        let mut spans = Vec::new();
+        if let (true, Some(medium)) = (fog, &self.fog) {
+            spans.push((T::zero(), distance, medium));
+        }
+
+        for volume in &self.volumes {
+            for (start, end) in volume.spans(ray) {
+                let (start, end) = (start.max(T::zero()), end.min(distance));
+                if start < end {
+                    spans.push((start, end, volume.medium()));
+                }
             }
+        }
+
+        Segment::split(spans)
+    }
+
+    // Fraction of light making it along the ray up to the distance. Grid volumes are ratio
+    // tracked, seeded by the ray.
+    pub fn transmittance(&self, ray: &Ray<T>, distance: T, fog: bool) -> Vec3<T> {
+        let mut sampler = RandomSampler::new(ray_seed(ray));
+
+        self.homogeneous_transmittance(ray, distance, fog)
+            .mul(self.grid_transmittance(ray, distance, &mut sampler))
+    }
+
+    fn homogeneous_transmittance(&self, ray: &Ray<T>, distance: T, fog: bool) -> Vec3<T> {
+        self.media_segments(ray, distance, fog).iter().fold(
+            Vec3::new(T::one(), T::one(), T::one()),
+            |transmittance, segment| {
+                transmittance.scalar_mul(&segment.transmittance(segment.length()))
+            },
+        )
+    }
+
+    // estimate of the fraction of light making it through the grid volumes along the ray
+    pub fn grid_transmittance(&self, ray: &Ray<T>, distance: T, sampler: &mut dyn Sampler<T>) -> T {
+        self.grid_volumes
+            .iter()
+            .fold(T::one(), |transmittance, volume| {
+                transmittance * volume.transmittance(ray, distance, sampler)
+            })
+    }
 
-            j = j + T::one();
+    // nearest collision of the ray with any of the grid volumes, short of the distance
+    pub fn sample_grid_collision(
+        &self,
+        ray: &Ray<T>,
+        distance: T,
+        sampler: &mut dyn Sampler<T>,
+    ) -> Option<(T, &GridVolume<T>)> {
+        let mut nearest: Option<(T, &GridVolume<T>)> = None;
+
+        for volume in &self.grid_volumes {
+            let limit = nearest.as_ref().map_or(distance, |(t, _)| *t);
+            if let Some(t) = volume.sample_collision(ray, limit, sampler) {
+                nearest = Some((t, volume));
+            }
         }
 
-        image
+        nearest
+    }
+
+    // fraction of a light's color making it through media to the point
+    pub fn light_transmittance(&self, point: &Vec3<T>, light: &dyn LightSource<T>) -> Vec3<T> {
+        let distance = light.location().sub(point).mag_sqrd().sqrt();
+        let ray = Ray::new(point.clone(), light.light_vector(point));
+
+        self.transmittance(&ray, distance, distance.is_finite())
     }
 
     // depth parameter currently unused
     pub fn trace_ray(&self, ray: Ray<T>, depth: u32) -> Color<T> {
         let nearest = self.intersect(&ray);
+        let distance = match &nearest {
+            Some((intersection, _)) => intersection.point.sub(&ray.origin).mag_sqrd().sqrt(),
+            None => T::infinity(),
+        };
 
-        match nearest {
+        let color = match nearest {
             Some((intersection, visible)) => {
-                let visible_lights = self.visible_lights(&intersection);
+                let bsdf = visible.bsdf();
+                let shading = bsdf.shading_intersection(&intersection);
+                let wo = ray.direction.mul(T::one().neg());
+                let lights = self.incident_lights(&intersection);
+
+                let mut color =
+                    Color::clipped(bsdf.shade(&shading, &lights, self.camera.location()));
+
+                if bsdf.is_reflective() && depth < REFLECTION_BOUNCES {
+                    color.clip_add(&self.trace_reflection(
+                        &intersection,
+                        visible.as_ref(),
+                        &ray,
+                        depth,
+                    ));
+                }
+
+                if depth < REFLECTION_BOUNCES {
+                    if let Some((direction, weight)) = bsdf.refraction(&shading, &wo) {
+                        let refraction_ray = intersection.spawn_ray(direction);
+                        let refraction_color = self.trace_ray(refraction_ray, depth + 1);
+
+                        color.clip_add(&Color::clipped(
+                            refraction_color.color_vector().scalar_mul(&weight),
+                        ));
+                    }
+                }
+
+                color
+            }
+            None => Color::clipped(self.background(&ray.direction)),
+        };
+
+        if self.has_media() {
+            self.trace_media(&ray, distance, color, depth)
+        } else {
+            color
+        }
+    }
 
-                let mut color = visible.calculate_lighting(
-                    &intersection,
-                    &visible_lights,
-                    self.camera.location(),
-                );
+    // Mirror or glossy reflection off the surface the ray hit, weighted by how much of it the
+    // surface reflects
+    pub fn trace_reflection(
+        &self,
+        intersection: &Intersection<T>,
+        visible: &dyn Visible<T>,
+        ray: &Ray<T>,
+        depth: u32,
+    ) -> Color<T> {
+        let bsdf = visible.bsdf();
+        let shading = bsdf.shading_intersection(intersection);
+        let wo = ray.direction.mul(T::one().neg());
+        let roughness = bsdf.reflection_roughness(&shading, &wo);
+
+        let mut reflection_color = if roughness > T::zero() {
+            self.trace_glossy_reflection(intersection, &shading, ray, roughness, depth)
+        } else {
+            let reflection_ray = Scene::calculate_reflection(intersection, ray);
+            self.trace_ray(reflection_ray, depth + 1)
+        };
+
+        // weight calculated colors
+        reflection_color.clip_mul(bsdf.reflection_coefficient(&shading, &wo));
+
+        reflection_color
+    }
 
-                if visible.is_reflective() && depth < REFLECTION_BOUNCES {
-                    let reflection_ray = Scene::calculate_reflection(&intersection, &ray);
+    // Dims the light arriving along the ray by the media it passes through, and adds the light
+    // they scatter towards its origin straight from the lights. Distances in homogeneous media
+    // are stratified and drawn in proportion to the transmittance, while grid volumes are delta
+    // and ratio tracked. Seeded by the ray like glossy reflections.
+    fn trace_media(&self, ray: &Ray<T>, distance: T, color: Color<T>, depth: u32) -> Color<T> {
+        let pi = T::from(std::f64::consts::PI).unwrap();
+        let samples = if depth == 0 {
+            self.volume_samples.max(1)
+        } else {
+            1
+        };
+        let mut sampler = RandomSampler::new(ray_seed(ray));
+
+        let mut transmittance = Vec3::new(T::one(), T::one(), T::one());
+        let mut scattered = Vec3::new(T::zero(), T::zero(), T::zero());
+        for segment in self.media_segments(ray, distance, true) {
+            let extinction = segment.mean_extinction();
+            if extinction <= T::zero() {
+                continue;
+            }
+            // chance of the sampled distance falling inside the segment, which is one for fog
+            // reaching to infinity
+            let inside = T::one() - (extinction.neg() * segment.length()).exp();
+
+            for i in 0..samples {
+                let u = (T::from(i).unwrap() + sampler.next_1d()) / T::from(samples).unwrap();
+                let offset = (T::one() - u * inside).ln().neg() / extinction;
+                let pdf = extinction * (extinction.neg() * offset).exp() / inside;
+                let t = segment.start + offset;
+                let weight = transmittance
+                    .scalar_mul(&segment.transmittance(offset))
+                    .mul(self.grid_transmittance(ray, t, &mut sampler))
+                    .div(pdf * T::from(samples).unwrap());
+
+                let point = ray.origin.add(&ray.direction.mul(t));
+                for light in &self.lights {
+                    if !self.light_reaches(&point, light.as_ref()) {
+                        continue;
+                    }
 
-                    let mut reflection_color = self.trace_ray(reflection_ray, depth + 1);
+                    let wi = light.light_vector(&point);
+                    let incoming = light
+                        .color_at(&point)
+                        .scalar_mul(&self.light_transmittance(&point, light.as_ref()))
+                        .mul(pi);
+
+                    scattered.mut_add(
+                        &weight
+                            .scalar_mul(&segment.in_scattering(&ray.direction, &wi))
+                            .scalar_mul(&incoming),
+                    );
+                }
+            }
 
-                    // weight calculated colors
-                    reflection_color.clip_mul(visible.reflection_coefficient());
+            transmittance = transmittance.scalar_mul(&segment.transmittance(segment.length()));
+        }
 
-                    color.clip_add(&reflection_color);
+        let mut grid_transmittance = T::zero();
+        for _ in 0..samples {
+            grid_transmittance =
+                grid_transmittance + self.grid_transmittance(ray, distance, &mut sampler);
+
+            // light scattered at the first collision with a grid, dimmed by homogeneous media on
+            // the way
+            let (t, volume) = match self.sample_grid_collision(ray, distance, &mut sampler) {
+                Some(collision) => collision,
+                None => continue,
+            };
+            let point = ray.origin.add(&ray.direction.mul(t));
+            let weight = volume
+                .albedo()
+                .scalar_mul(&self.homogeneous_transmittance(ray, t, true))
+                .div(T::from(samples).unwrap());
+
+            for light in &self.lights {
+                if !self.light_reaches(&point, light.as_ref()) {
+                    continue;
                 }
 
-                color
+                let wi = light.light_vector(&point);
+                let incoming = light
+                    .color_at(&point)
+                    .scalar_mul(&self.light_transmittance(&point, light.as_ref()))
+                    .mul(pi * volume.phase(&ray.direction, &wi));
+
+                scattered.mut_add(&weight.scalar_mul(&incoming));
             }
-            None => self.background_color.clone(),
         }
+        transmittance = transmittance.mul(grid_transmittance / T::from(samples).unwrap());
+
+        Color::clipped(
+            color
+                .color_vector()
+                .scalar_mul(&transmittance)
+                .add(&scattered),
+        )
+    }
+
+    // Averages reflection rays scattered around the mirror direction with the GGX distribution.
+    // The jitter is seeded by the ray, so renders are reproducible.
+    fn trace_glossy_reflection(
+        &self,
+        intersection: &Intersection<T>,
+        shading: &Intersection<T>,
+        ray: &Ray<T>,
+        roughness: T,
+        depth: u32,
+    ) -> Color<T> {
+        let samples = if depth == 0 {
+            self.glossy_samples.max(1)
+        } else {
+            1
+        };
+        let wo = ray.direction.mul(T::one().neg());
+        let alpha = roughness * roughness;
+        let mut sampler = RandomSampler::new(ray_seed(ray));
+
+        let mut total = Vec3::new(T::zero(), T::zero(), T::zero());
+        let mut count = 0;
+        for i in 0..samples {
+            // stratified along the angle to the mirror direction
+            let (u1, u2) = sampler.next_2d();
+            let u1 = (T::from(i).unwrap() + u1) / T::from(samples).unwrap();
+
+            let direction = sample_ggx_reflection(shading, &wo, alpha, (u1, u2));
+            if intersection.normal.dot(&direction) <= T::zero() {
+                continue;
+            }
+
+            let color = self.trace_ray(intersection.spawn_ray(direction), depth + 1);
+            total.mut_add(color.color_vector());
+            count += 1;
+        }
+
+        if count == 0 {
+            return Color::new(T::zero(), T::zero(), T::zero()).unwrap();
+        }
+
+        Color::clipped(total.div(T::from(count).unwrap()))
     }
 
     fn calculate_reflection(intersection: &Intersection<T>, ray: &Ray<T>) -> Ray<T> {
@@ -105,14 +478,16 @@ impl<T: VertexFormat> Scene<T> {
             .sub(&l)
             .normalize();
 
-        Ray::new(intersection.point.clone(), reflection)
+        intersection.spawn_ray(reflection)
     }
 
     // intersects a ray with every visible in the scene, returning the nearest intersection (and a
     // reference to the visible it belongs to)
-    fn intersect(&self, ray: &Ray<T>) -> Option<(Intersection<T>, &Box<dyn Visible<T>>)> {
+    pub fn intersect(&self, ray: &Ray<T>) -> Option<(Intersection<T>, &Box<dyn Visible<T>>)> {
         let mut dist = T::infinity();
         let mut nearest = None;
+        self.intersection_tests
+            .set(self.intersection_tests.get() + self.visibles.len() as u64);
 
         for visible in &self.visibles {
             if let Some(mut i) = visible.intersect(&ray) {
@@ -127,40 +502,79 @@ impl<T: VertexFormat> Scene<T> {
         nearest
     }
 
-    fn visible_lights(&self, intersection: &Intersection<T>) -> Vec<&Box<dyn LightSource<T>>> {
+    // lights that aren't shadowed at the intersection
+    pub fn visible_lights(&self, intersection: &Intersection<T>) -> Vec<&dyn LightSource<T>> {
         let mut lights = Vec::new();
         for light in &self.lights {
             let light_vector = light.light_vector(&intersection.point);
-            let ray = Ray::new(intersection.point.clone(), light_vector);
-
-            let nearest_intersection = self.intersect(&ray);
 
-            match nearest_intersection {
-                Some((intrsct, vis)) => {
-                    let dist_to_light = light.location().sub(&intersection.point).mag_sqrd();
-                    let dist_to_object = intrsct.point.sub(&intersection.point).mag_sqrd();
+            // lights behind the geometric surface can't reach it, even if a perturbed shading
+            // normal faces them
+            if intersection.normal.dot(&light_vector) <= T::zero() {
+                continue;
+            }
 
-                    if dist_to_light <= dist_to_object {
-                        lights.push(light);
-                    }
-                }
-                None => {
-                    lights.push(light);
-                }
+            if self.light_reaches(&intersection.point, light.as_ref()) {
+                lights.push(light.as_ref());
             }
         }
 
         lights
     }
+
+    // lights that aren't shadowed at the intersection, dimmed by the media between them and it
+    pub fn incident_lights(&self, intersection: &Intersection<T>) -> Vec<IncidentLight<'_, T>> {
+        self.visible_lights(intersection)
+            .into_iter()
+            .map(|light| {
+                let mut incident = IncidentLight::new(light);
+                if self.has_media() {
+                    incident
+                        .set_transmittance(self.light_transmittance(&intersection.point, light));
+                }
+
+                incident
+            })
+            .collect()
+    }
+
+    // whether nothing stands between the point and the light
+    pub fn light_reaches(&self, point: &Vec3<T>, light: &dyn LightSource<T>) -> bool {
+        let ray = Ray::new(point.clone(), light.light_vector(point));
+
+        match self.intersect(&ray) {
+            Some((intersection, _)) => {
+                let dist_to_light = light.location().sub(point).mag_sqrd();
+                let dist_to_object = intersection.point.sub(point).mag_sqrd();
+
+                dist_to_light <= dist_to_object
+            }
+            None => true,
+        }
+    }
+}
+
+// seed for the random numbers used along a ray, mixed from the bits of its origin and direction
+fn ray_seed<T: VertexFormat>(ray: &Ray<T>) -> u64 {
+    let bits = |value: T| value.to_f64().unwrap().to_bits();
+
+    bits(ray.direction.x)
+        ^ bits(ray.direction.y).rotate_left(21)
+        ^ bits(ray.direction.z).rotate_left(42)
+        ^ bits(ray.origin.x).rotate_left(7)
+        ^ bits(ray.origin.y).rotate_left(28)
+        ^ bits(ray.origin.z).rotate_left(49)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::scene::light::PointLight;
+    use crate::scene::light::{DirectionalLight, PointLight, SpotLight};
     use crate::scene::visible::material::Material;
+    use crate::scene::visible::plane::Plane;
     use crate::scene::visible::sphere::Sphere;
     use crate::scene::visible::Body;
+    use crate::scene::voxel::DensityGrid;
 
     #[test]
     fn test_single_traced_ray() {
@@ -247,4 +661,146 @@ mod tests {
 
         let image = scene.render();
     }
+
+    // camera looking down at a mirror floor, with a white ball straight above that is lit only
+    // by its ambient color
+    fn mirror_scene(roughness: f64) -> Scene<f64> {
+        let camera = Camera::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, -1.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            4,
+            4,
+            60.0_f64.to_radians(),
+        );
+        let black = Color::new(0.0, 0.0, 0.0).unwrap();
+        let white = Color::new(1.0, 1.0, 1.0).unwrap();
+        let mut scene = Scene::new(camera, black.clone(), black.clone());
+
+        let mut mirror =
+            Material::new(0.0, black.clone(), 0.0, black.clone(), 1.0, 0.0, black, 1.0);
+        mirror.set_reflection_roughness(roughness);
+        let floor = Plane::new(Vec3::new(0.0, -1.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        scene.add_visible(Box::new(Body::new(Box::new(floor), mirror)));
+
+        let ball = Material::new(0.0, white.clone(), 0.0, white.clone(), 1.0, 1.0, white, 0.0);
+        let sphere = Sphere::new(Vec3::new(0.0, 5.0, 0.0), 1.0);
+        scene.add_visible(Box::new(Body::new(Box::new(sphere), ball)));
+
+        scene
+    }
+
+    #[test]
+    fn glossy_reflection_blurs_mirror_image() {
+        let ray = || Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+
+        let sharp = mirror_scene(0.0).trace_ray(ray(), 0);
+        let mut glossy_scene = mirror_scene(0.6);
+        glossy_scene.set_glossy_samples(64);
+        let glossy = glossy_scene.trace_ray(ray(), 0);
+
+        assert!((sharp.color_vector().x - 1.0).abs() < 1e-9);
+        // some of the jittered rays miss the ball and see the black background
+        assert!(glossy.color_vector().x > 0.05);
+        assert!(glossy.color_vector().x < 0.95);
+        // and the jitter is the same every time
+        assert_eq!(
+            glossy.color_vector(),
+            glossy_scene.trace_ray(ray(), 0).color_vector()
+        );
+    }
+
+    fn fog() -> Medium<f64> {
+        Medium::new(Vec3::new(0.5, 0.5, 0.5), Vec3::new(0.5, 0.5, 0.5), 0.0)
+    }
+
+    #[test]
+    fn fog_hides_background_and_scatters_light() {
+        let mut scene = mirror_scene(0.0);
+        scene.visibles.clear();
+        scene.add_light(Box::new(DirectionalLight::new(
+            Color::new(1.0, 1.0, 1.0).unwrap(),
+            Vec3::new(0.0, 1.0, 0.0),
+        )));
+        scene.set_fog(fog());
+
+        // endless fog scatters albedo / 4 of an even light, with a light's color being its
+        // irradiance over pi
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        let color = scene.trace_ray(ray, 0);
+
+        assert!((color.color_vector().x - 0.125).abs() < 1e-9);
+    }
+
+    #[test]
+    fn spot_light_beam_shows_in_volume() {
+        let mut scene = mirror_scene(0.0);
+        scene.visibles.clear();
+        scene.add_light(Box::new(SpotLight::new(
+            Color::new(1.0, 1.0, 1.0).unwrap(),
+            Vec3::new(0.0, 10.0, 0.0),
+            Vec3::new(0.0, -1.0, 0.0),
+            10.0_f64.to_radians(),
+        )));
+        let shape = Sphere::new(Vec3::new(0.0, 0.0, 0.0), 20.0);
+        let haze = Medium::new(
+            Vec3::new(0.05, 0.05, 0.05),
+            Vec3::new(0.05, 0.05, 0.05),
+            0.0,
+        );
+        scene.add_volume(Volume::new(Box::new(shape), haze));
+
+        // across the beam, and alongside it without crossing it
+        let through = Ray::new(Vec3::new(-5.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        let beside = Ray::new(Vec3::new(-5.0, 0.0, 5.0), Vec3::new(1.0, 0.0, 0.0));
+
+        assert!(scene.trace_ray(through, 0).color_vector().x > 0.005);
+        assert_eq!(0.0, scene.trace_ray(beside, 0).color_vector().x);
+    }
+
+    #[test]
+    fn volume_between_light_and_surface_shades_it() {
+        let mut scene = mirror_scene(0.0);
+        scene.visibles.clear();
+        let white = Color::new(1.0, 1.0, 1.0).unwrap();
+        let black = Color::new(0.0, 0.0, 0.0).unwrap();
+        let floor = Material::new(1.0, white, 0.0, black.clone(), 1.0, 0.0, black, 0.0);
+        let plane = Plane::new(Vec3::new(0.0, -1.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        scene.add_visible(Box::new(Body::new(Box::new(plane), floor)));
+        scene.add_light(Box::new(PointLight::new(
+            Color::new(1.0, 1.0, 1.0).unwrap(),
+            Vec3::new(3.0, 5.0, 0.0),
+        )));
+
+        // straight down onto the floor, beside the volume but under it
+        let ray = || Ray::new(Vec3::new(3.0, 0.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        let lit = scene.trace_ray(ray(), 0);
+        assert!(lit.color_vector().x > 0.5);
+
+        let shape = Sphere::new(Vec3::new(3.0, 2.0, 0.0), 1.0);
+        let ink = Medium::new(Vec3::new(50.0, 50.0, 50.0), Vec3::new(0.0, 0.0, 0.0), 0.0);
+        scene.add_volume(Volume::new(Box::new(shape), ink));
+
+        assert!(scene.trace_ray(ray(), 0).color_vector().x < 1e-9);
+    }
+
+    #[test]
+    fn grid_volume_dims_what_lies_behind() {
+        let mut scene = mirror_scene(0.0);
+        scene.visibles.clear();
+        scene.background_color = Color::new(1.0, 1.0, 1.0).unwrap();
+        scene.set_volume_samples(256);
+
+        // black smoke, absorbing half the grid's density of two per unit, across a unit box
+        let grid = DensityGrid::new((2, 2, 2), vec![0.5; 8]);
+        let mut smoke = GridVolume::new(grid, Vec3::new(1.0, -0.5, -0.5), Vec3::new(2.0, 0.5, 0.5));
+        smoke.set_density(2.0);
+        smoke.set_albedo(Color::new(0.0, 0.0, 0.0).unwrap());
+        scene.add_grid_volume(smoke);
+
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        let color = scene.trace_ray(ray, 0);
+
+        assert!((color.color_vector().x - (-1.0_f64).exp()).abs() < 0.05);
+    }
 }
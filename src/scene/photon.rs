@@ -0,0 +1,578 @@
+use crate::common::{Color, Intersection, Ray, Vec3, VertexFormat};
+use crate::image::Image;
+use crate::sampler::{Distribution, RandomSampler, Sampler};
+use crate::scene::integrator::{survive, Integrator, LightSelection};
+use crate::scene::light::LightSource;
+use crate::scene::visible::bsdf::Bsdf;
+use crate::scene::Scene;
+use std::cmp::Ordering;
+
+// how much each pass shrinks the area photons are gathered from, from Knaus and Zwicker's
+// probabilistic progressive photon mapping. Lower shrinks faster, trading noise for blur.
+const RADIUS_ALPHA: f64 = 2.0 / 3.0;
+
+// photons are only gathered from surfaces facing about the same way, so light doesn't bleed
+// around corners
+const NORMAL_AGREEMENT: f64 = 0.9;
+
+// Photon mapper. Photons are shot from the lights and left on every surface they hit, and the
+// light at a point is estimated from the density of the photons around it. Caustics, the light
+// focused onto a surface by mirrors and glass, are hard for paths from the camera to find, so
+// photons that only ever bounced off of perfectly specular surfaces also go into a caustic map,
+// gathered from directly where the camera sees a surface. The rest of the light there comes from
+// shadow rays and a final gather, one more bounce looking up the global map where it lands.
+//
+// Rendering happens in passes, each tracing a new set of photons and shrinking the radius they're
+// gathered over. Blur and noise go away with more passes, while only one pass's photons are kept.
+//
+// Only point and spot lights shoot photons. Lights at infinity and the environment light the
+// camera's and the final gather's surfaces with shadow rays, which leaves out their caustics and
+// the light reaching the final gather after more than one bounce. Media and subsurface scattering
+// are left out, like in the bidirectional path tracer.
+pub struct PhotonMapper<T: VertexFormat> {
+    samples_per_pixel: u32,
+    photons: usize,
+    passes: u32,
+    radius: T,
+    caustic_radius: T,
+    max_depth: u32,
+    seed: u64,
+}
+
+impl<T: VertexFormat> PhotonMapper<T> {
+    // samples per pixel and photons shot from the lights, in every pass
+    pub fn new(samples_per_pixel: u32, photons: usize) -> PhotonMapper<T> {
+        PhotonMapper {
+            samples_per_pixel,
+            photons,
+            passes: 1,
+            radius: T::from(0.1).unwrap(),
+            caustic_radius: T::from(0.025).unwrap(),
+            max_depth: 8,
+            seed: 0,
+        }
+    }
+
+    pub fn set_passes(&mut self, passes: u32) {
+        self.passes = passes;
+    }
+
+    // distance photons are gathered from in the first pass, in the global map
+    pub fn set_radius(&mut self, radius: T) {
+        self.radius = radius;
+    }
+
+    // and in the caustic map, which is seen directly and so needs to be sharper
+    pub fn set_caustic_radius(&mut self, radius: T) {
+        self.caustic_radius = radius;
+    }
+
+    // number of surfaces photons and paths from the camera can scatter off of
+    pub fn set_max_depth(&mut self, max_depth: u32) {
+        self.max_depth = max_depth;
+    }
+
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+
+    // Shoots the photons of one pass from the lights, chosen by their estimated power. Returns the
+    // global and the caustic map.
+    fn trace_photons(
+        &self,
+        scene: &Scene<T>,
+        sampler: &mut dyn Sampler<T>,
+    ) -> (PhotonMap<T>, PhotonMap<T>) {
+        let sources: Vec<&dyn LightSource<T>> = scene
+            .lights()
+            .iter()
+            .map(|light| light.as_ref())
+            .filter(|light| light.location().x.is_finite())
+            .collect();
+        let powers: Vec<T> = sources.iter().map(|light| light.power()).collect();
+        let distribution = Distribution::new(&powers);
+
+        let mut global = Vec::new();
+        let mut caustic = Vec::new();
+        if distribution.is_empty() {
+            return (PhotonMap::new(global), PhotonMap::new(caustic));
+        }
+
+        let pi = T::from(std::f64::consts::PI).unwrap();
+        let count = T::from(self.photons).unwrap();
+
+        for _ in 0..self.photons {
+            let (index, chance) = distribution.sample(sampler.next_1d());
+            let light = sources[index];
+            let (direction, pdf) = match light.sample_emission(sampler.next_2d()) {
+                Some(sample) if sample.1 > T::zero() => sample,
+                _ => continue,
+            };
+
+            // a light's color is its irradiance at normal incidence over pi
+            let position = light.location().clone();
+            let mut power = light
+                .color_at(&position.add(&direction))
+                .mul(pi / (chance * pdf * count));
+            let mut beta = Vec3::new(T::one(), T::one(), T::one());
+            // whether the photon has only been scattered by perfectly specular surfaces so far
+            let mut focused = false;
+            let mut ray = Ray::new(position, direction);
+
+            for depth in 0..self.max_depth {
+                let (intersection, visible) = match scene.intersect(&ray) {
+                    Some(hit) => hit,
+                    None => break,
+                };
+
+                // lights are as bright at any distance, so the light spreading out from them is
+                // made up for over the first ray
+                if depth == 0 {
+                    power = power.mul(intersection.point.sub(&ray.origin).mag_sqrd());
+                }
+
+                let wo = ray.direction.mul(T::one().neg());
+                let photon = Photon {
+                    point: intersection.point.clone(),
+                    normal: intersection.normal.clone(),
+                    direction: wo.clone(),
+                    power: power.scalar_mul(&beta),
+                    bounces: depth,
+                };
+                if focused {
+                    caustic.push(photon.clone());
+                }
+                global.push(photon);
+
+                let bsdf = visible.bsdf();
+                let shading = bsdf.shading_intersection(&intersection);
+                let sample = match bsdf.sample(&shading, &wo, sampler) {
+                    Some(sample) => sample,
+                    None => break,
+                };
+                beta = match survive(beta.scalar_mul(&sample.weight), depth, sampler) {
+                    Some(beta) => beta,
+                    None => break,
+                };
+                focused = sample.specular && (depth == 0 || focused);
+
+                ray = intersection.spawn_ray(sample.direction);
+            }
+        }
+
+        (PhotonMap::new(global), PhotonMap::new(caustic))
+    }
+
+    // Light arriving at the ray origin along the ray. Paths from the camera follow perfectly
+    // specular bounces until they sample any other kind, which makes the final gather.
+    fn radiance(
+        &self,
+        scene: &Scene<T>,
+        pass: &Pass<T>,
+        ray: Ray<T>,
+        sampler: &mut dyn Sampler<T>,
+    ) -> Vec3<T> {
+        let mut radiance = Vec3::new(T::zero(), T::zero(), T::zero());
+        let mut throughput = Vec3::new(T::one(), T::one(), T::one());
+        let mut ray = ray;
+        let mut specular = true;
+        let mut scatter_pdf = T::zero();
+        // Bounces not perfectly specular so far. Where the camera sees a surface, it's lit by
+        // shadow rays and the caustic map, and after the final gather by the lights at infinity
+        // and the global map. Past that, paths only look for the background.
+        let mut diffuse_bounces = 0;
+        // lights shadow rays went to from the last surface scattering the path, which weigh its
+        // share of the environment
+        let mut last_lights = &pass.lights;
+
+        for depth in 0..=self.max_depth {
+            let (intersection, visible) = match scene.intersect(&ray) {
+                Some(_) if depth == self.max_depth => break,
+                Some(hit) => hit,
+                None => {
+                    let mut background = scene.background(&ray.direction);
+                    if !specular {
+                        background =
+                            background.mul(last_lights.escape_weight(&ray.direction, scatter_pdf));
+                    }
+
+                    radiance.mut_add(&throughput.scalar_mul(&background));
+                    break;
+                }
+            };
+            if diffuse_bounces > 1 {
+                break;
+            }
+
+            let wo = ray.direction.mul(T::one().neg());
+            let bsdf = visible.bsdf();
+            let shading = bsdf.shading_intersection(&intersection);
+
+            let scatter = |wi: &Vec3<T>| {
+                (
+                    bsdf.evaluate(&shading, &wo, wi),
+                    bsdf.pdf(&shading, &wo, wi),
+                )
+            };
+            let gathering = diffuse_bounces == 1;
+            let lights = if gathering {
+                &pass.at_infinity
+            } else {
+                &pass.lights
+            };
+            let mut light = lights.direct(
+                &intersection.point,
+                Some(&intersection.normal),
+                &scatter,
+                sampler,
+            );
+            let (map, radius_sqrd) = if gathering {
+                (&pass.global, pass.radius_sqrd)
+            } else {
+                (&pass.caustic, pass.caustic_radius_sqrd)
+            };
+            // paths joined to the photons scatter off of as many surfaces as the camera's paths
+            let max_bounces = self.max_depth - depth - 1;
+            light.mut_add(&map.radiance(
+                &intersection,
+                &shading,
+                bsdf,
+                &wo,
+                radius_sqrd,
+                max_bounces,
+            ));
+            radiance.mut_add(&throughput.scalar_mul(&light));
+
+            let sample = match bsdf.sample(&shading, &wo, sampler) {
+                Some(sample) => sample,
+                None => break,
+            };
+            throughput = match survive(throughput.scalar_mul(&sample.weight), depth, sampler) {
+                Some(throughput) => throughput,
+                None => break,
+            };
+            if !sample.specular {
+                diffuse_bounces += 1;
+                last_lights = lights;
+            }
+            specular = sample.specular;
+            scatter_pdf = sample.pdf;
+
+            ray = intersection.spawn_ray(sample.direction);
+        }
+
+        radiance
+    }
+}
+
+impl<T: VertexFormat> Integrator<T> for PhotonMapper<T> {
+    fn render(&self, scene: &Scene<T>) -> Image<T> {
+        self.render_unclipped(scene).clipped()
+    }
+
+    fn render_unclipped(&self, scene: &Scene<T>) -> Image<T> {
+        let camera = scene.camera();
+        let mut image = Image::new(camera.width(), camera.height());
+        let mut sampler = RandomSampler::new(self.seed);
+        let width = camera.width() as usize;
+        let mut totals =
+            vec![Vec3::new(T::zero(), T::zero(), T::zero()); width * camera.height() as usize];
+
+        let mut radius_sqrd = self.radius * self.radius;
+        let mut caustic_radius_sqrd = self.caustic_radius * self.caustic_radius;
+
+        for i in 0..self.passes {
+            let (global, caustic) = self.trace_photons(scene, &mut sampler);
+            let pass = Pass {
+                lights: LightSelection::new(scene),
+                at_infinity: LightSelection::at_infinity(scene),
+                global,
+                caustic,
+                radius_sqrd,
+                caustic_radius_sqrd,
+            };
+
+            for y in 0..camera.height() {
+                for x in 0..camera.width() {
+                    for _ in 0..self.samples_per_pixel {
+                        let (dx, dy) = sampler.next_2d();
+                        let ray = camera.ray(T::from(x).unwrap() + dx, T::from(y).unwrap() + dy);
+
+                        totals[y as usize * width + x as usize].mut_add(&self.radiance(
+                            scene,
+                            &pass,
+                            ray,
+                            &mut sampler,
+                        ));
+                    }
+                }
+            }
+
+            let i = T::from(i + 1).unwrap();
+            let shrink = (i + T::from(RADIUS_ALPHA).unwrap()) / (i + T::one());
+            radius_sqrd = radius_sqrd * shrink;
+            caustic_radius_sqrd = caustic_radius_sqrd * shrink;
+        }
+
+        let samples = T::from(self.samples_per_pixel * self.passes).unwrap();
+        for y in 0..camera.height() {
+            for x in 0..camera.width() {
+                let color = totals[y as usize * width + x as usize].div(samples);
+                image.set_pixel(x, y, Color::new_unclipped(color.x, color.y, color.z));
+            }
+        }
+
+        image
+    }
+}
+
+// photon maps of a pass, with the radii they're gathered over, and the lights shadow rays go to
+struct Pass<'a, T: VertexFormat> {
+    lights: LightSelection<'a, T>,
+    at_infinity: LightSelection<'a, T>,
+    global: PhotonMap<T>,
+    caustic: PhotonMap<T>,
+    radius_sqrd: T,
+    caustic_radius_sqrd: T,
+}
+
+// Light left on a surface by a photon
+#[derive(Debug, Clone)]
+struct Photon<T: VertexFormat> {
+    point: Vec3<T>,
+    // of the surface it was left on
+    normal: Vec3<T>,
+    // towards where it came from
+    direction: Vec3<T>,
+    power: Vec3<T>,
+    // surfaces it scattered off of before this one
+    bounces: u32,
+}
+
+// Photons in a kd-tree. Every range of the list holds the node splitting it in its middle, with
+// the photons before it on the lower side along its axis and the ones after it on the upper side.
+struct PhotonMap<T: VertexFormat> {
+    photons: Vec<Photon<T>>,
+    axes: Vec<usize>,
+}
+
+fn coordinate<T: VertexFormat>(point: &Vec3<T>, axis: usize) -> T {
+    match axis {
+        0 => point.x,
+        1 => point.y,
+        _ => point.z,
+    }
+}
+
+impl<T: VertexFormat> PhotonMap<T> {
+    fn new(photons: Vec<Photon<T>>) -> PhotonMap<T> {
+        let mut photons = photons;
+        let mut axes = vec![0; photons.len()];
+        PhotonMap::build(&mut photons, &mut axes);
+
+        PhotonMap { photons, axes }
+    }
+
+    // splits the photons along the axis they're most spread out on, at their median
+    fn build(photons: &mut [Photon<T>], axes: &mut [usize]) {
+        if photons.is_empty() {
+            return;
+        }
+
+        let mut lower = photons[0].point.clone();
+        let mut upper = photons[0].point.clone();
+        for photon in photons.iter() {
+            lower = Vec3::new(
+                lower.x.min(photon.point.x),
+                lower.y.min(photon.point.y),
+                lower.z.min(photon.point.z),
+            );
+            upper = Vec3::new(
+                upper.x.max(photon.point.x),
+                upper.y.max(photon.point.y),
+                upper.z.max(photon.point.z),
+            );
+        }
+        let extent = upper.sub(&lower);
+        let axis = if extent.x >= extent.y && extent.x >= extent.z {
+            0
+        } else if extent.y >= extent.z {
+            1
+        } else {
+            2
+        };
+
+        let middle = photons.len() / 2;
+        photons.select_nth_unstable_by(middle, |a, b| {
+            coordinate(&a.point, axis)
+                .partial_cmp(&coordinate(&b.point, axis))
+                .unwrap_or(Ordering::Equal)
+        });
+        axes[middle] = axis;
+
+        let (below, rest) = photons.split_at_mut(middle);
+        let (axes_below, axes_rest) = axes.split_at_mut(middle);
+        PhotonMap::build(below, axes_below);
+        PhotonMap::build(&mut rest[1..], &mut axes_rest[1..]);
+    }
+
+    // calls visit with every photon closer to the point than the radius
+    fn within(&self, point: &Vec3<T>, radius_sqrd: T, visit: &mut dyn FnMut(&Photon<T>)) {
+        PhotonMap::search(&self.photons, &self.axes, point, radius_sqrd, visit);
+    }
+
+    fn search(
+        photons: &[Photon<T>],
+        axes: &[usize],
+        point: &Vec3<T>,
+        radius_sqrd: T,
+        visit: &mut dyn FnMut(&Photon<T>),
+    ) {
+        if photons.is_empty() {
+            return;
+        }
+
+        let middle = photons.len() / 2;
+        let node = &photons[middle];
+        let axis = axes[middle];
+        let offset = coordinate(point, axis) - coordinate(&node.point, axis);
+
+        let below = (&photons[..middle], &axes[..middle]);
+        let above = (&photons[middle + 1..], &axes[middle + 1..]);
+        let (near, far) = if offset <= T::zero() {
+            (below, above)
+        } else {
+            (above, below)
+        };
+
+        PhotonMap::search(near.0, near.1, point, radius_sqrd, visit);
+        if node.point.sub(point).mag_sqrd() <= radius_sqrd {
+            visit(node);
+        }
+        if offset * offset <= radius_sqrd {
+            PhotonMap::search(far.0, far.1, point, radius_sqrd, visit);
+        }
+    }
+
+    // Light scattered towards wo by the surface, estimated from the density of the photons around
+    // the intersection that bounced at most max_bounces times on the way
+    fn radiance(
+        &self,
+        intersection: &Intersection<T>,
+        shading: &Intersection<T>,
+        bsdf: &dyn Bsdf<T>,
+        wo: &Vec3<T>,
+        radius_sqrd: T,
+        max_bounces: u32,
+    ) -> Vec3<T> {
+        let mut total = Vec3::new(T::zero(), T::zero(), T::zero());
+        let agreement = T::from(NORMAL_AGREEMENT).unwrap();
+
+        self.within(&intersection.point, radius_sqrd, &mut |photon| {
+            if photon.bounces > max_bounces || photon.normal.dot(&intersection.normal) < agreement {
+                return;
+            }
+            // photons carry flux, which has the cosine evaluate includes in it already
+            let cos = shading.normal.dot(&photon.direction);
+            if cos <= T::zero() {
+                return;
+            }
+
+            total.mut_add(
+                &bsdf
+                    .evaluate(shading, wo, &photon.direction)
+                    .scalar_mul(&photon.power)
+                    .div(cos),
+            );
+        });
+
+        let pi = T::from(std::f64::consts::PI).unwrap();
+        total.div(pi * radius_sqrd)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::integrator::PathTracer;
+    use crate::scene::test_scenes::{closed_room, matte, mean};
+    use crate::scene::visible::specular::Dielectric;
+    use crate::scene::visible::sphere::Sphere;
+    use crate::scene::visible::Body;
+
+    #[test]
+    fn finds_the_photons_within_the_radius() {
+        let mut sampler = RandomSampler::new(5);
+        let photons: Vec<Photon<f64>> = (0..500)
+            .map(|_| {
+                let (x, y) = sampler.next_2d();
+                Photon {
+                    point: Vec3::new(x, y, sampler.next_1d()),
+                    normal: Vec3::new(0.0, 1.0, 0.0),
+                    direction: Vec3::new(0.0, 1.0, 0.0),
+                    power: Vec3::new(1.0, 1.0, 1.0),
+                    bounces: 0,
+                }
+            })
+            .collect();
+        let map = PhotonMap::new(photons.clone());
+        assert_eq!(500, map.photons.len());
+
+        for _ in 0..20 {
+            let (x, y) = sampler.next_2d();
+            let point = Vec3::new(x, y, sampler.next_1d());
+            let mut found = 0;
+            map.within(&point, 0.04, &mut |_| found += 1);
+
+            let expected = photons
+                .iter()
+                .filter(|photon| photon.point.sub(&point).mag_sqrd() <= 0.04)
+                .count();
+            assert_eq!(expected, found);
+        }
+    }
+
+    #[test]
+    fn density_estimates_match_path_tracer() {
+        let scene = closed_room();
+
+        let mut photon_mapper = PhotonMapper::new(16, 20000);
+        photon_mapper.set_max_depth(3);
+        photon_mapper.set_radius(0.25);
+        photon_mapper.set_passes(4);
+        let mut path_tracer = PathTracer::new(256);
+        path_tracer.set_max_depth(3);
+
+        let expected = mean(&path_tracer.render(&scene));
+        let actual = mean(&photon_mapper.render(&scene));
+
+        assert!(expected > 0.1);
+        assert!((actual - expected).abs() < 0.03 * expected.max(1.0));
+    }
+
+    #[test]
+    fn glass_sphere_focuses_photons_into_a_caustic() {
+        let mut scene = closed_room();
+        scene.add_visible(Box::new(Body::new(
+            Box::new(Sphere::new(Vec3::new(-0.5, 0.0, 0.0), 0.6)),
+            Dielectric::new(1.5),
+        )));
+
+        let photon_mapper = PhotonMapper::new(1, 20000);
+        let (_, caustic) = photon_mapper.trace_photons(&scene, &mut RandomSampler::new(2));
+        assert!(!caustic.photons.is_empty());
+
+        // the floor right under the sphere and the light, and off to the side
+        let floor = matte(0.8);
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        let brightness = |x: f64| {
+            let intersection = Intersection::new(Vec3::new(x, -2.0, 0.0), up.clone());
+            caustic
+                .radiance(&intersection, &intersection, &floor, &up, 0.01, 8)
+                .y
+        };
+
+        assert!(brightness(-0.5) > 0.5);
+        assert_eq!(0.0, brightness(1.5));
+    }
+}
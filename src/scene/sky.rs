@@ -0,0 +1,359 @@
+use crate::common::{Color, Vec3, VertexFormat};
+use crate::image::Image;
+use crate::scene::environment::Environment;
+use crate::scene::light::DirectionalLight;
+
+// Illuminance of the sun above the atmosphere, in the same units of thousands as the sky model's
+// luminance
+const SOLAR_ILLUMINANCE: f64 = 128.0;
+
+// wavelengths in micrometers used for the red, green and blue transmittance of the atmosphere
+const WAVELENGTHS: [f64; 3] = [0.680, 0.550, 0.440];
+
+// Preetham, Shirley and Smits' analytic daylight model. The sky's brightness and color depend on
+// the angle to the zenith and to the sun, and on the turbidity, which is how hazy the air is: 2 for
+// a very clear sky, up to about 10 for a hazy one. Directions use y as up, and -z as north.
+#[derive(Debug, Clone)]
+pub struct Sky<T: VertexFormat> {
+    sun_direction: Vec3<T>,
+    turbidity: T,
+    // scales the luminance in thousands of cd/m^2 down to the scene's radiance
+    intensity: T,
+    // luminance Y and chromaticity x and y at the zenith
+    zenith: [T; 3],
+    // coefficients A to E of the distribution of Y, x and y
+    coefficients: [[T; 5]; 3],
+}
+
+impl<T: VertexFormat> Sky<T> {
+    // the elevation is the sun's angle above the horizon, and the azimuth its angle clockwise from
+    // north, both in radians
+    pub fn new(elevation: T, azimuth: T, turbidity: T) -> Sky<T> {
+        let (sin_elevation, cos_elevation) = elevation.sin_cos();
+        let sun_direction = Vec3::new(
+            azimuth.sin() * cos_elevation,
+            sin_elevation,
+            azimuth.cos().neg() * cos_elevation,
+        );
+
+        let t = turbidity;
+        let linear = |a: f64, b: f64| T::from(a).unwrap() * t + T::from(b).unwrap();
+        let coefficients = [
+            [
+                linear(0.1787, -1.4630),
+                linear(-0.3554, 0.4275),
+                linear(-0.0227, 5.3251),
+                linear(0.1206, -2.5771),
+                linear(-0.0670, 0.3703),
+            ],
+            [
+                linear(-0.0193, -0.2592),
+                linear(-0.0665, 0.0008),
+                linear(-0.0004, 0.2125),
+                linear(-0.0641, -0.8989),
+                linear(-0.0033, 0.0452),
+            ],
+            [
+                linear(-0.0167, -0.2608),
+                linear(-0.0950, 0.0092),
+                linear(-0.0079, 0.2102),
+                linear(-0.0441, -1.6537),
+                linear(-0.0109, 0.0529),
+            ],
+        ];
+
+        let mut sky = Sky {
+            sun_direction,
+            turbidity,
+            intensity: T::from(0.05).unwrap(),
+            zenith: [T::zero(); 3],
+            coefficients,
+        };
+        sky.zenith = sky.zenith_values();
+
+        sky
+    }
+
+    // scales both the sky and the sun
+    pub fn set_intensity(&mut self, intensity: T) {
+        self.intensity = intensity;
+    }
+
+    pub fn sun_direction(&self) -> &Vec3<T> {
+        &self.sun_direction
+    }
+
+    // angle between the sun and the zenith. The model only holds down to the horizon.
+    fn sun_theta(&self) -> T {
+        let limit = T::from(0.499 * std::f64::consts::PI).unwrap();
+
+        self.sun_direction
+            .y
+            .max(T::one().neg())
+            .min(T::one())
+            .acos()
+            .min(limit)
+    }
+
+    fn zenith_values(&self) -> [T; 3] {
+        let theta = self.sun_theta();
+        let t = self.turbidity;
+        let pi = T::from(std::f64::consts::PI).unwrap();
+
+        let chi = (T::from(4.0 / 9.0).unwrap() - t / T::from(120.0).unwrap())
+            * (pi - T::from(2.0).unwrap() * theta);
+        let luminance = (T::from(4.0453).unwrap() * t - T::from(4.9710).unwrap()) * chi.tan()
+            - T::from(0.2155).unwrap() * t
+            + T::from(2.4192).unwrap();
+
+        // polynomials in the turbidity and the sun's zenith angle
+        let chromaticity = |matrix: [[f64; 4]; 3]| {
+            let turbidities = [t * t, t, T::one()];
+            let thetas = [theta * theta * theta, theta * theta, theta, T::one()];
+
+            (0..3).fold(T::zero(), |sum, row| {
+                let inner = (0..4).fold(T::zero(), |sum, column| {
+                    sum + T::from(matrix[row][column]).unwrap() * thetas[column]
+                });
+                sum + turbidities[row] * inner
+            })
+        };
+        let x = chromaticity([
+            [0.00166, -0.00375, 0.00209, 0.0],
+            [-0.02903, 0.06377, -0.03202, 0.00394],
+            [0.11693, -0.21196, 0.06052, 0.25886],
+        ]);
+        let y = chromaticity([
+            [0.00275, -0.00610, 0.00317, 0.0],
+            [-0.04214, 0.08970, -0.04153, 0.00516],
+            [0.15346, -0.26756, 0.06670, 0.26688],
+        ]);
+
+        [luminance.max(T::zero()), x, y]
+    }
+
+    // Perez' distribution, for the angle theta to the zenith and gamma to the sun
+    fn perez(coefficients: &[T; 5], theta: T, gamma: T) -> T {
+        let [a, b, c, d, e] = *coefficients;
+        let cos_gamma = gamma.cos();
+
+        (T::one() + a * (b / theta.cos()).exp())
+            * (T::one() + c * (d * gamma).exp() + e * cos_gamma * cos_gamma)
+    }
+
+    // light arriving from the sky in the direction, not counting the sun itself
+    pub fn radiance(&self, direction: &Vec3<T>) -> Vec3<T> {
+        let direction = direction.clone().normalize();
+        // below the horizon the sky is continued from the horizon
+        let limit = T::from(0.499 * std::f64::consts::PI).unwrap();
+        let theta = direction
+            .y
+            .max(T::one().neg())
+            .min(T::one())
+            .acos()
+            .min(limit);
+        let gamma = direction
+            .dot(&self.sun_direction)
+            .max(T::one().neg())
+            .min(T::one())
+            .acos();
+        let sun_theta = self.sun_theta();
+
+        let mut values = [T::zero(); 3];
+        for (value, (zenith, coefficients)) in values
+            .iter_mut()
+            .zip(self.zenith.iter().zip(self.coefficients.iter()))
+        {
+            *value = *zenith * Sky::perez(coefficients, theta, gamma)
+                / Sky::perez(coefficients, T::zero(), sun_theta);
+        }
+
+        xyy_to_rgb(values[0], values[1], values[2]).mul(self.intensity)
+    }
+
+    // Light from the sun, reddened and dimmed by the air it passes through, with aerosols
+    // following the turbidity. Black once the sun has set.
+    pub fn sun(&self) -> DirectionalLight<T> {
+        let direction = self.sun_direction.clone();
+        let black = Color::new(T::zero(), T::zero(), T::zero()).unwrap();
+        if direction.y <= T::zero() {
+            return DirectionalLight::new(black, direction);
+        }
+
+        // Kasten's relative optical mass of the air above, for the sun's elevation in degrees
+        let zenith_degrees = self.sun_theta().to_degrees();
+        let mass = T::one()
+            / (direction.y
+                + T::from(0.15).unwrap()
+                    * (T::from(93.885).unwrap() - zenith_degrees).powf(T::from(-1.253).unwrap()));
+
+        // Rayleigh scattering by the air, and Angstrom's formula for the aerosols
+        let beta = T::from(0.04608).unwrap() * self.turbidity - T::from(0.04586).unwrap();
+        let transmittance = |wavelength: f64| {
+            let wavelength = T::from(wavelength).unwrap();
+            let rayleigh = T::from(0.008735).unwrap() * wavelength.powf(T::from(-4.08).unwrap());
+            let aerosol = beta * wavelength.powf(T::from(-1.3).unwrap());
+
+            (mass.neg() * (rayleigh + aerosol)).exp()
+        };
+
+        // a light's color is its irradiance over pi
+        let pi = T::from(std::f64::consts::PI).unwrap();
+        let scale = T::from(SOLAR_ILLUMINANCE).unwrap() * self.intensity / pi;
+
+        DirectionalLight::new(
+            Color::new_unclipped(
+                transmittance(WAVELENGTHS[0]) * scale,
+                transmittance(WAVELENGTHS[1]) * scale,
+                transmittance(WAVELENGTHS[2]) * scale,
+            ),
+            direction,
+        )
+    }
+
+    // bakes the sky into an equirectangular environment, which can be shown behind the scene and
+    // sampled by the path tracer
+    pub fn environment(&self, width: u32, height: u32) -> Environment<T> {
+        let pi = T::from(std::f64::consts::PI).unwrap();
+        let mut image = Image::new(width, height);
+
+        for y in 0..height {
+            for x in 0..width {
+                // same mapping as the environment's, through the center of the pixel
+                let u = (T::from(x).unwrap() + T::from(0.5).unwrap()) / T::from(width).unwrap();
+                let v = (T::from(y).unwrap() + T::from(0.5).unwrap()) / T::from(height).unwrap();
+                let phi = T::from(2.0).unwrap() * pi * u - pi;
+                let (sin_theta, cos_theta) = (pi * v).sin_cos();
+                let direction = Vec3::new(
+                    phi.cos() * sin_theta,
+                    cos_theta.neg(),
+                    phi.sin().neg() * sin_theta,
+                );
+
+                let radiance = self.radiance(&direction);
+                image.set_pixel(
+                    x,
+                    y,
+                    Color::new_unclipped(radiance.x, radiance.y, radiance.z),
+                );
+            }
+        }
+
+        Environment::equirectangular(image)
+    }
+}
+
+// from luminance and chromaticity to linear sRGB
+fn xyy_to_rgb<T: VertexFormat>(luminance: T, x: T, y: T) -> Vec3<T> {
+    if y <= T::zero() {
+        return Vec3::new(T::zero(), T::zero(), T::zero());
+    }
+
+    let big_x = x * luminance / y;
+    let big_z = (T::one() - x - y) * luminance / y;
+    let row = |a: f64, b: f64, c: f64| {
+        (T::from(a).unwrap() * big_x
+            + T::from(b).unwrap() * luminance
+            + T::from(c).unwrap() * big_z)
+            .max(T::zero())
+    };
+
+    Vec3::new(
+        row(3.2406, -1.5372, -0.4986),
+        row(-0.9689, 1.8758, 0.0415),
+        row(0.0557, -0.2040, 1.0570),
+    )
+}
+
+// Elevation and azimuth of the sun in radians, as taken by Sky::new, for a place and time. The
+// latitude and longitude are in radians, positive to the north and east, the day of the year
+// starts at 1 for January 1st, and the time is in hours UTC. Uses NOAA's approximation, which is
+// good to within a degree or so.
+pub fn sun_position<T: VertexFormat>(
+    latitude: T,
+    longitude: T,
+    day_of_year: u32,
+    utc_hours: T,
+) -> (T, T) {
+    let pi = T::from(std::f64::consts::PI).unwrap();
+    let c = |value: f64| T::from(value).unwrap();
+
+    // fraction of the year, in radians
+    let gamma = c(2.0) * pi / c(365.0)
+        * (T::from(day_of_year).unwrap() - T::one() + (utc_hours - c(12.0)) / c(24.0));
+    let (sin1, cos1) = gamma.sin_cos();
+    let (sin2, cos2) = (c(2.0) * gamma).sin_cos();
+    let (sin3, cos3) = (c(3.0) * gamma).sin_cos();
+
+    // equation of time, in minutes, and the sun's declination
+    let equation_of_time = c(229.18)
+        * (c(0.000075) + c(0.001868) * cos1
+            - c(0.032077) * sin1
+            - c(0.014615) * cos2
+            - c(0.040849) * sin2);
+    let declination = c(0.006918) - c(0.399912) * cos1 + c(0.070257) * sin1 - c(0.006758) * cos2
+        + c(0.000907) * sin2
+        - c(0.002697) * cos3
+        + c(0.00148) * sin3;
+
+    // solar time in minutes, and the hour angle from solar noon
+    let solar_minutes = utc_hours * c(60.0) + equation_of_time + c(4.0) * longitude.to_degrees();
+    let hour_angle = (solar_minutes / c(4.0) - c(180.0)).to_radians();
+
+    let sin_elevation =
+        latitude.sin() * declination.sin() + latitude.cos() * declination.cos() * hour_angle.cos();
+    let elevation = sin_elevation.max(T::one().neg()).min(T::one()).asin();
+
+    // measured from south towards west, then turned to be clockwise from north
+    let azimuth = hour_angle
+        .sin()
+        .atan2(hour_angle.cos() * latitude.sin() - declination.tan() * latitude.cos())
+        + pi;
+
+    (elevation, azimuth)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::light::LightSource;
+
+    #[test]
+    fn sky_is_brightest_around_the_sun() {
+        let sky = Sky::new(30.0_f64.to_radians(), 0.0, 3.0);
+        let towards_sun = Vec3::new(0.0, 0.6, -1.0);
+        let away_from_sun = Vec3::new(0.0, 0.6, 1.0);
+
+        assert!(sky.radiance(&towards_sun).y > sky.radiance(&away_from_sun).y);
+        // and blue overhead
+        let zenith = sky.radiance(&Vec3::new(0.0, 1.0, 0.0));
+        assert!(zenith.z > zenith.x);
+    }
+
+    #[test]
+    fn low_sun_is_redder_and_dimmer() {
+        let noon = Sky::new(70.0_f64.to_radians(), 0.0, 3.0).sun();
+        let evening = Sky::new(5.0_f64.to_radians(), 0.0, 3.0).sun();
+
+        let noon = noon.color().color_vector();
+        let evening = evening.color().color_vector();
+
+        assert!(evening.x / evening.z > noon.x / noon.z);
+        assert!(evening.y < noon.y);
+    }
+
+    #[test]
+    fn sun_position_at_equinox() {
+        // spring equinox, at solar noon on the equator and sunrise further north
+        let (elevation, _) = sun_position(0.0_f64, 0.0, 80, 12.1);
+        assert!((elevation.to_degrees() - 90.0).abs() < 2.0);
+
+        let (elevation, azimuth) = sun_position(45.0_f64.to_radians(), 0.0, 80, 12.1);
+        assert!((elevation.to_degrees() - 45.0).abs() < 2.0);
+        assert!((azimuth.to_degrees() - 180.0).abs() < 5.0);
+
+        let (elevation, azimuth) = sun_position(45.0_f64.to_radians(), 0.0, 80, 6.1);
+        assert!(elevation.to_degrees().abs() < 2.0);
+        assert!((azimuth.to_degrees() - 90.0).abs() < 3.0);
+    }
+}
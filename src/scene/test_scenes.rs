@@ -0,0 +1,75 @@
+use crate::common::{Color, Vec3};
+use crate::image::Image;
+use crate::scene::camera::Camera;
+use crate::scene::light::PointLight;
+use crate::scene::visible::material::Material;
+use crate::scene::visible::plane::Plane;
+use crate::scene::visible::Body;
+use crate::scene::Scene;
+
+// Scenes shared by the tests of the integrators, which check their renders against each other
+
+// purely diffuse material, without highlights or ambient light
+pub fn matte(albedo: f64) -> Material<f64> {
+    let black = Color::new(0.0, 0.0, 0.0).unwrap();
+    Material::new(
+        1.0,
+        Color::new(albedo, albedo, albedo).unwrap(),
+        0.0,
+        black.clone(),
+        1.0,
+        0.0,
+        black,
+        0.0,
+    )
+}
+
+// Six matte walls from -2 to 2 along every axis, with nothing getting in or out. The albedo of
+// each wall is picked by its normal, which points into the room.
+pub fn walled_in(camera: Camera<f64>, albedo: &dyn Fn(&Vec3<f64>) -> f64) -> Scene<f64> {
+    let black = Color::new(0.0, 0.0, 0.0).unwrap();
+    let mut scene = Scene::new(camera, black.clone(), black);
+
+    let walls = [
+        (Vec3::new(0.0, -2.0, 0.0), Vec3::new(0.0, 1.0, 0.0)),
+        (Vec3::new(0.0, 2.0, 0.0), Vec3::new(0.0, -1.0, 0.0)),
+        (Vec3::new(-2.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0)),
+        (Vec3::new(2.0, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0)),
+        (Vec3::new(0.0, 0.0, -2.0), Vec3::new(0.0, 0.0, 1.0)),
+        (Vec3::new(0.0, 0.0, 2.0), Vec3::new(0.0, 0.0, -1.0)),
+    ];
+    for (point, normal) in walls {
+        let material = matte(albedo(&normal));
+        scene.add_visible(Box::new(Body::new(
+            Box::new(Plane::new(point, normal)),
+            material,
+        )));
+    }
+
+    scene
+}
+
+// a box of white walls around a point light, seen from inside
+pub fn closed_room() -> Scene<f64> {
+    let camera = Camera::new(
+        Vec3::new(0.0, 0.0, -1.0),
+        Vec3::new(0.0, 0.0, 1.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        8,
+        8,
+        60.0_f64.to_radians(),
+    );
+    let mut scene = walled_in(camera, &|_| 0.8);
+    scene.add_light(Box::new(PointLight::new(
+        Color::new(0.5, 0.5, 0.5).unwrap(),
+        Vec3::new(-0.5, 1.5, 0.0),
+    )));
+
+    scene
+}
+
+// mean of the image's green channel
+pub fn mean(image: &Image<f64>) -> f64 {
+    let pixels: Vec<f64> = image.iter().map(|pixel| pixel.color_vector().y).collect();
+    pixels.iter().sum::<f64>() / pixels.len() as f64
+}
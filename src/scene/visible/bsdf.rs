@@ -0,0 +1,353 @@
+use crate::common::{Color, Intersection, Vec3, VertexFormat};
+use crate::sampler::{cosine_hemisphere, to_world, RandomSampler, Sampler};
+use crate::scene::light::IncidentLight;
+use crate::scene::medium::Medium;
+use crate::scene::visible::texture::Texture;
+use std::fmt::Debug;
+use std::rc::Rc;
+
+// samples averaged by the default Bsdf::reflectance
+const REFLECTANCE_SAMPLES: u32 = 64;
+
+// Direction picked by Bsdf::sample
+#[derive(Debug, Clone)]
+pub struct ScatterSample<T: VertexFormat> {
+    pub direction: Vec3<T>,
+    // bsdf times cosine over pdf, which scales the light arriving from direction
+    pub weight: Vec3<T>,
+    pub pdf: T,
+    // picked from a perfectly specular lobe, which Bsdf::evaluate can't evaluate
+    pub specular: bool,
+}
+
+// How a surface scatters light. Directions point away from the surface, with wo towards where the
+// light leaves to, and wi towards where it arrives from. Every method is given the shading
+// intersection.
+pub trait Bsdf<T: VertexFormat>: Debug {
+    // bsdf for the pair of directions, times the cosine between wi and the shading normal
+    fn evaluate(&self, intersection: &Intersection<T>, wo: &Vec3<T>, wi: &Vec3<T>) -> Vec3<T>;
+
+    // density with which sample picks wi, not counting perfectly specular lobes
+    fn pdf(&self, intersection: &Intersection<T>, wo: &Vec3<T>, wi: &Vec3<T>) -> T;
+
+    // picks a direction to continue a path in. None if the path is absorbed.
+    fn sample(
+        &self,
+        intersection: &Intersection<T>,
+        wo: &Vec3<T>,
+        sampler: &mut dyn Sampler<T>,
+    ) -> Option<ScatterSample<T>>;
+
+    // copy of the intersection, with the normal the lighting calculations should use
+    fn shading_intersection(&self, intersection: &Intersection<T>) -> Intersection<T> {
+        intersection.clone()
+    }
+
+    // Fraction of the light from all around that is scattered towards wo, which is what the
+    // albedo render pass shows. Estimated from a fixed set of samples by default, which is exact
+    // for lambertian surfaces.
+    fn reflectance(&self, intersection: &Intersection<T>, wo: &Vec3<T>) -> Vec3<T> {
+        let mut sampler = RandomSampler::new(0);
+        let mut total = Vec3::new(T::zero(), T::zero(), T::zero());
+        for _ in 0..REFLECTANCE_SAMPLES {
+            if let Some(sample) = self.sample(intersection, wo, &mut sampler) {
+                total.mut_add(&sample.weight);
+            }
+        }
+
+        total.div(T::from(REFLECTANCE_SAMPLES).unwrap())
+    }
+
+    // medium filling the inside of a closed surface, which paths entering it take a random walk
+    // through, for subsurface scattering
+    fn interior(&self) -> Option<&Medium<T>> {
+        None
+    }
+
+    // The rest is only used by the Whitted renderer, which has no indirect light. By default
+    // surfaces get no ambient light, reflect the lights as evaluate describes, and have no
+    // mirror reflection or refraction.
+
+    fn ambient(&self, _intersection: &Intersection<T>) -> Vec3<T> {
+        Vec3::new(T::zero(), T::zero(), T::zero())
+    }
+
+    // a light's color is its irradiance at normal incidence over pi, which keeps lambertian
+    // surfaces as bright as the phong diffuse term
+    fn shade(
+        &self,
+        intersection: &Intersection<T>,
+        lights: &[IncidentLight<T>],
+        viewpoint: &Vec3<T>,
+    ) -> Vec3<T> {
+        let pi = T::from(std::f64::consts::PI).unwrap();
+        let wo = viewpoint.sub(&intersection.point).normalize();
+
+        let mut color = self.ambient(intersection);
+        for light in lights {
+            let wi = light.light_vector(&intersection.point);
+            let reflected = self
+                .evaluate(intersection, &wo, &wi)
+                .scalar_mul(&light.color_at(&intersection.point))
+                .mul(pi);
+
+            color.mut_add(&reflected);
+        }
+
+        color
+    }
+
+    // Light reflected straight from the lights, split into its diffuse and specular parts for
+    // render passes. Together they make up shade without the ambient light, all of which counts
+    // as diffuse by default.
+    fn shade_parts(
+        &self,
+        intersection: &Intersection<T>,
+        lights: &[IncidentLight<T>],
+        viewpoint: &Vec3<T>,
+    ) -> (Vec3<T>, Vec3<T>) {
+        let direct = self
+            .shade(intersection, lights, viewpoint)
+            .sub(&self.ambient(intersection));
+
+        (direct, Vec3::new(T::zero(), T::zero(), T::zero()))
+    }
+
+    fn is_reflective(&self) -> bool {
+        false
+    }
+
+    // fraction of the light from the mirror direction of wo that is reflected
+    fn reflection_coefficient(&self, _intersection: &Intersection<T>, _wo: &Vec3<T>) -> T {
+        T::zero()
+    }
+
+    // roughness of the mirror reflection, which blurs it like on satin finishes
+    fn reflection_roughness(&self, _intersection: &Intersection<T>, _wo: &Vec3<T>) -> T {
+        T::zero()
+    }
+
+    // direction light is refracted from, and the fraction of it that passes through
+    fn refraction(
+        &self,
+        _intersection: &Intersection<T>,
+        _wo: &Vec3<T>,
+    ) -> Option<(Vec3<T>, Vec3<T>)> {
+        None
+    }
+}
+
+// Ideal diffuse surface, reflecting equally in every direction
+#[derive(Debug, Clone)]
+pub struct Lambertian<T: VertexFormat> {
+    albedo: Color<T>,
+    albedo_texture: Option<Rc<dyn Texture<T>>>,
+}
+
+impl<T: VertexFormat> Lambertian<T> {
+    pub fn new(albedo: Color<T>) -> Lambertian<T> {
+        Lambertian {
+            albedo,
+            albedo_texture: None,
+        }
+    }
+
+    pub fn set_albedo_texture(&mut self, texture: Rc<dyn Texture<T>>) {
+        self.albedo_texture = Some(texture);
+    }
+
+    fn albedo(&self, intersection: &Intersection<T>) -> Vec3<T> {
+        match &self.albedo_texture {
+            Some(texture) => texture.value(intersection),
+            None => self.albedo.color_vector().clone(),
+        }
+    }
+}
+
+// samples the cosine weighted hemisphere around the normal, and weighs the result with the
+// evaluated bsdf
+fn sample_hemisphere<T: VertexFormat>(
+    bsdf: &dyn Bsdf<T>,
+    intersection: &Intersection<T>,
+    wo: &Vec3<T>,
+    sampler: &mut dyn Sampler<T>,
+) -> Option<ScatterSample<T>> {
+    if intersection.normal.dot(wo) <= T::zero() {
+        return None;
+    }
+
+    let direction = to_world(
+        &cosine_hemisphere(sampler.next_2d()),
+        &intersection.tangent,
+        &intersection.bitangent,
+        &intersection.normal,
+    );
+    let pdf = bsdf.pdf(intersection, wo, &direction);
+    if pdf <= T::zero() {
+        return None;
+    }
+
+    Some(ScatterSample {
+        weight: bsdf.evaluate(intersection, wo, &direction).div(pdf),
+        direction,
+        pdf,
+        specular: false,
+    })
+}
+
+// Schlick's approximation of the Fresnel reflectance
+pub(crate) fn schlick<T: VertexFormat>(f0: &Vec3<T>, cos: T) -> Vec3<T> {
+    let weight = (T::one() - cos).max(T::zero()).powi(5);
+    let one = Vec3::new(T::one(), T::one(), T::one());
+
+    f0.add(&one.sub(f0).mul(weight))
+}
+
+fn cosine_pdf<T: VertexFormat>(intersection: &Intersection<T>, wo: &Vec3<T>, wi: &Vec3<T>) -> T {
+    let cos_i = intersection.normal.dot(wi);
+    if cos_i <= T::zero() || intersection.normal.dot(wo) <= T::zero() {
+        return T::zero();
+    }
+
+    cos_i / T::from(std::f64::consts::PI).unwrap()
+}
+
+impl<T: VertexFormat> Bsdf<T> for Lambertian<T> {
+    fn evaluate(&self, intersection: &Intersection<T>, wo: &Vec3<T>, wi: &Vec3<T>) -> Vec3<T> {
+        self.albedo(intersection)
+            .mul(cosine_pdf(intersection, wo, wi))
+    }
+
+    fn pdf(&self, intersection: &Intersection<T>, wo: &Vec3<T>, wi: &Vec3<T>) -> T {
+        cosine_pdf(intersection, wo, wi)
+    }
+
+    fn sample(
+        &self,
+        intersection: &Intersection<T>,
+        wo: &Vec3<T>,
+        sampler: &mut dyn Sampler<T>,
+    ) -> Option<ScatterSample<T>> {
+        sample_hemisphere(self, intersection, wo, sampler)
+    }
+}
+
+// Diffuse surface made of tiny lambertian facets, with roughness the standard deviation of their
+// slope angle in radians. Looks flatter than lambertian, like clay or the moon.
+#[derive(Debug, Clone)]
+pub struct OrenNayar<T: VertexFormat> {
+    albedo: Color<T>,
+    a: T,
+    b: T,
+}
+
+impl<T: VertexFormat> OrenNayar<T> {
+    pub fn new(albedo: Color<T>, roughness: T) -> OrenNayar<T> {
+        let sigma2 = roughness * roughness;
+
+        OrenNayar {
+            albedo,
+            a: T::one() - sigma2 / (T::from(2.0).unwrap() * (sigma2 + T::from(0.33).unwrap())),
+            b: T::from(0.45).unwrap() * sigma2 / (sigma2 + T::from(0.09).unwrap()),
+        }
+    }
+}
+
+impl<T: VertexFormat> Bsdf<T> for OrenNayar<T> {
+    fn evaluate(&self, intersection: &Intersection<T>, wo: &Vec3<T>, wi: &Vec3<T>) -> Vec3<T> {
+        let cosine = cosine_pdf(intersection, wo, wi);
+        if cosine <= T::zero() {
+            return Vec3::new(T::zero(), T::zero(), T::zero());
+        }
+
+        let normal = &intersection.normal;
+        let cos_i = normal.dot(wi).min(T::one());
+        let cos_o = normal.dot(wo).min(T::one());
+        let sin_i = (T::one() - cos_i * cos_i).sqrt();
+        let sin_o = (T::one() - cos_o * cos_o).sqrt();
+
+        // cosine of the azimuth between the directions, projected onto the surface
+        let projected_i = wi.sub(&normal.mul(cos_i));
+        let projected_o = wo.sub(&normal.mul(cos_o));
+        let lengths = (projected_i.mag_sqrd() * projected_o.mag_sqrd()).sqrt();
+        let cos_phi = if lengths > T::zero() {
+            (projected_i.dot(&projected_o) / lengths).max(T::zero())
+        } else {
+            T::zero()
+        };
+
+        // sin(alpha) tan(beta), with alpha the larger and beta the smaller of the two angles
+        let (sin_alpha, tan_beta) = if cos_i > cos_o {
+            (sin_o, sin_i / cos_i)
+        } else {
+            (sin_i, sin_o / cos_o)
+        };
+
+        self.albedo
+            .color_vector()
+            .mul(cosine * (self.a + self.b * cos_phi * sin_alpha * tan_beta))
+    }
+
+    fn pdf(&self, intersection: &Intersection<T>, wo: &Vec3<T>, wi: &Vec3<T>) -> T {
+        cosine_pdf(intersection, wo, wi)
+    }
+
+    fn sample(
+        &self,
+        intersection: &Intersection<T>,
+        wo: &Vec3<T>,
+        sampler: &mut dyn Sampler<T>,
+    ) -> Option<ScatterSample<T>> {
+        sample_hemisphere(self, intersection, wo, sampler)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::RandomSampler;
+
+    fn surface() -> Intersection<f64> {
+        Intersection::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0))
+    }
+
+    #[test]
+    fn lambertian_albedo_over_pi() {
+        let lambertian = Lambertian::new(Color::new(0.5, 0.5, 0.5).unwrap());
+        let up = Vec3::new(0.0, 0.0, 1.0);
+
+        let value = lambertian.evaluate(&surface(), &up, &up);
+
+        assert!((value.x - 0.5 / std::f64::consts::PI).abs() < 1e-12);
+        assert_eq!(Vec3::new(0.0, 0.0, 0.0), {
+            lambertian.evaluate(&surface(), &up, &Vec3::new(0.0, 0.0, -1.0))
+        });
+    }
+
+    #[test]
+    fn lambertian_samples_weigh_albedo() {
+        let lambertian = Lambertian::new(Color::new(0.25, 0.5, 0.75).unwrap());
+        let wo = Vec3::new(0.0, 0.6, 0.8);
+        let mut sampler = RandomSampler::new(5);
+
+        let sample = lambertian.sample(&surface(), &wo, &mut sampler).unwrap();
+
+        assert!((sample.weight.x - 0.25).abs() < 1e-12);
+        assert!((sample.weight.z - 0.75).abs() < 1e-12);
+        assert!(!sample.specular);
+    }
+
+    #[test]
+    fn smooth_oren_nayar_is_lambertian() {
+        let oren_nayar = OrenNayar::new(Color::new(0.5, 0.5, 0.5).unwrap(), 0.0);
+        let lambertian = Lambertian::new(Color::new(0.5, 0.5, 0.5).unwrap());
+        let wo = Vec3::new(0.0, 0.6, 0.8);
+        let wi = Vec3::new(0.8, 0.0, 0.6);
+
+        let difference = oren_nayar
+            .evaluate(&surface(), &wo, &wi)
+            .sub(&lambertian.evaluate(&surface(), &wo, &wi));
+
+        assert!(difference.mag_sqrd() < 1e-20);
+    }
+}
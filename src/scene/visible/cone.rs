@@ -0,0 +1,193 @@
+use crate::common::{Intersection, Ray, Spacial, Vec3, VertexFormat};
+use crate::polynomial::solve_quadratic;
+use crate::scene::visible::cylinder::{axial_uv, AxialRay};
+use crate::scene::visible::{intervals_from_hits, nearest_hit, Intersectable, Interval, Solid};
+
+// Cone around the line segment from base to top, with a radius that changes linearly from
+// base_radius to top_radius. A top radius of zero gives a pointed cone, anything else a
+// truncated one. Uncapped cones are an open, two sided surface.
+#[derive(Debug)]
+pub struct Cone<T: VertexFormat> {
+    base: Vec3<T>,
+    axis: Vec3<T>,
+    height: T,
+    base_radius: T,
+    top_radius: T,
+    // change in radius per unit of height
+    slope: T,
+    capped: bool,
+    center: Vec3<T>,
+}
+
+impl<T: VertexFormat> Cone<T> {
+    pub fn new(
+        base: Vec3<T>,
+        top: Vec3<T>,
+        base_radius: T,
+        top_radius: T,
+        capped: bool,
+    ) -> Cone<T> {
+        let axis = top.sub(&base);
+        let height = axis.mag_sqrd().sqrt();
+        let center = base.add(&top).div(T::from(2.0).unwrap());
+
+        Cone {
+            base,
+            axis: axis.normalize(),
+            height,
+            base_radius,
+            top_radius,
+            slope: (top_radius - base_radius) / height,
+            capped,
+            center,
+        }
+    }
+
+    // every hit of the ray's line with the cone surface, with outward facing normals
+    fn hits(&self, ray: &Ray<T>, capped: bool) -> Vec<(T, Vec3<T>)> {
+        let axial = AxialRay::new(ray, &self.base, &self.axis);
+
+        // radius of the cone at the ray origin's height, and its change along the ray
+        let origin_radius = self.base_radius + self.slope * axial.origin_along;
+        let radius_change = self.slope * axial.direction_along;
+
+        let mut hits: Vec<(T, Vec3<T>)> = solve_quadratic(
+            axial.direction_perp.mag_sqrd() - radius_change * radius_change,
+            T::from(2.0).unwrap()
+                * (axial.direction_perp.dot(&axial.origin_perp) - radius_change * origin_radius),
+            axial.origin_perp.mag_sqrd() - origin_radius * origin_radius,
+        )
+        .into_iter()
+        .filter(|t| {
+            let along = axial.along(*t);
+            along >= T::zero() && along <= self.height
+        })
+        .map(|t| {
+            // gradient of |p_perp| - radius(p_along)
+            let normal = axial
+                .radial(t)
+                .normalize()
+                .sub(&self.axis.mul(self.slope))
+                .normalize();
+            (t, normal)
+        })
+        .collect();
+
+        if capped {
+            hits.append(&mut axial.cap_hits(
+                &self.axis,
+                self.height,
+                (self.base_radius, self.top_radius),
+            ));
+        }
+
+        hits
+    }
+
+    fn uv(&self, point: &Vec3<T>, normal: &Vec3<T>) -> (T, T) {
+        axial_uv(
+            &point.sub(&self.base),
+            normal,
+            &self.axis,
+            self.height,
+            (self.base_radius, self.top_radius),
+        )
+    }
+}
+
+impl<T: VertexFormat> Intersectable<T> for Cone<T> {
+    fn intersect(&self, ray: &Ray<T>) -> Option<Intersection<T>> {
+        let hits = self.hits(ray, self.capped);
+
+        nearest_hit(ray, &hits, !self.capped, &|point, normal| {
+            self.uv(point, normal)
+        })
+    }
+}
+
+// as a solid, the cone is always treated as closed, even if its caps aren't rendered
+impl<T: VertexFormat> Solid<T> for Cone<T> {
+    fn intervals(&self, ray: &Ray<T>) -> Vec<Interval<T>> {
+        intervals_from_hits(ray, self.hits(ray, true), &|point, normal| {
+            self.uv(point, normal)
+        })
+    }
+}
+
+impl<T: VertexFormat> Spacial<T> for Cone<T> {
+    fn location(&self) -> &Vec3<T> {
+        &self.center
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cone_side_intersection() {
+        // 45 degree cone with its tip at y = 1
+        let cone = Cone::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            1.0,
+            0.0,
+            true,
+        );
+
+        let ray = Ray::new(Vec3::new(10.0, 0.5, 0.0), Vec3::new(-1.0, 0.0, 0.0));
+
+        let intersection = cone.intersect(&ray).unwrap();
+        let half_sqrt2 = 0.5_f64.sqrt();
+
+        assert_eq!(Vec3::new(0.5, 0.5, 0.0), intersection.point);
+        assert!((intersection.normal.x - half_sqrt2).abs() < 1e-12);
+        assert!((intersection.normal.y - half_sqrt2).abs() < 1e-12);
+    }
+
+    #[test]
+    fn cone_base_cap() {
+        let capped = Cone::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            1.0,
+            0.0,
+            true,
+        );
+        let open = Cone::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            1.0,
+            0.0,
+            false,
+        );
+
+        let ray = Ray::new(Vec3::new(0.5, -10.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+
+        let intersection = capped.intersect(&ray).unwrap();
+
+        assert_eq!(Vec3::new(0.5, 0.0, 0.0), intersection.point);
+        assert_eq!(Vec3::new(0.0, -1.0, 0.0), intersection.normal);
+
+        // without a cap, the ray hits the inside of the cone wall
+        let inside = open.intersect(&ray).unwrap();
+        assert_eq!(Vec3::new(0.5, 0.5, 0.0), inside.point);
+        assert!(inside.normal.y < 0.0);
+    }
+
+    #[test]
+    fn cone_miss_beyond_tip() {
+        let cone = Cone::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            1.0,
+            0.0,
+            true,
+        );
+
+        // would hit the mirrored nappe of the infinite cone
+        let ray = Ray::new(Vec3::new(10.0, 1.5, 0.0), Vec3::new(-1.0, 0.0, 0.0));
+
+        assert_eq!(None, cone.intersect(&ray));
+    }
+}
@@ -0,0 +1,281 @@
+use crate::common::{Intersection, Ray, Spacial, Vec3, VertexFormat};
+use crate::scene::visible::{Hit, Intersectable, Interval, Solid};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CsgOperation {
+    // everything inside of either child
+    Union,
+    // everything inside of both children
+    Intersection,
+    // everything inside of the left child, but outside of the right child
+    Difference,
+}
+
+impl CsgOperation {
+    fn contains(&self, in_left: bool, in_right: bool) -> bool {
+        match self {
+            CsgOperation::Union => in_left || in_right,
+            CsgOperation::Intersection => in_left && in_right,
+            CsgOperation::Difference => in_left && !in_right,
+        }
+    }
+}
+
+// Constructive solid geometry node, combining two solids with a boolean operation. Since Csg is a
+// solid itself, nodes can be nested to build up more complex shapes.
+pub struct Csg<T: VertexFormat> {
+    operation: CsgOperation,
+    left: Box<dyn Solid<T>>,
+    right: Box<dyn Solid<T>>,
+}
+
+impl<T: VertexFormat> Csg<T> {
+    pub fn new(
+        operation: CsgOperation,
+        left: Box<dyn Solid<T>>,
+        right: Box<dyn Solid<T>>,
+    ) -> Csg<T> {
+        Csg {
+            operation,
+            left,
+            right,
+        }
+    }
+
+    pub fn union(left: Box<dyn Solid<T>>, right: Box<dyn Solid<T>>) -> Csg<T> {
+        Csg::new(CsgOperation::Union, left, right)
+    }
+
+    pub fn intersection(left: Box<dyn Solid<T>>, right: Box<dyn Solid<T>>) -> Csg<T> {
+        Csg::new(CsgOperation::Intersection, left, right)
+    }
+
+    pub fn difference(left: Box<dyn Solid<T>>, right: Box<dyn Solid<T>>) -> Csg<T> {
+        Csg::new(CsgOperation::Difference, left, right)
+    }
+}
+
+impl<T: VertexFormat> Solid<T> for Csg<T> {
+    fn intervals(&self, ray: &Ray<T>) -> Vec<Interval<T>> {
+        // every boundary of either child along the ray: (hit, is from the left child, is entry).
+        // Spans with a bound that isn't finite can't be ordered against the others, so they're
+        // skipped.
+        let mut events = Vec::new();
+        let children = [(&self.left, true), (&self.right, false)];
+        for (child, from_left) in children.iter() {
+            for interval in child.intervals(ray) {
+                if !interval.entry.t.is_finite() || !interval.exit.t.is_finite() {
+                    continue;
+                }
+                events.push((interval.entry, *from_left, true));
+                events.push((interval.exit, *from_left, false));
+            }
+        }
+        events.sort_by(|(a, _, _), (b, _, _)| a.t.partial_cmp(&b.t).unwrap());
+
+        let mut in_left = false;
+        let mut in_right = false;
+        let mut inside = false;
+        let mut entry = None;
+        let mut intervals = Vec::new();
+
+        for (hit, from_left, is_entry) in events {
+            if from_left {
+                in_left = is_entry;
+            } else {
+                in_right = is_entry;
+            }
+
+            let now_inside = self.operation.contains(in_left, in_right);
+            if now_inside == inside {
+                continue;
+            }
+            inside = now_inside;
+
+            // surfaces of the subtracted solid face into it, which is out of the difference
+            let hit = if self.operation == CsgOperation::Difference && !from_left {
+                Hit {
+                    normal: hit.normal.mul(T::one().neg()),
+                    ..hit
+                }
+            } else {
+                hit
+            };
+
+            if inside {
+                entry = Some(hit);
+            } else if let Some(entry) = entry.take() {
+                intervals.push(Interval { entry, exit: hit });
+            }
+        }
+
+        intervals
+    }
+}
+
+impl<T: VertexFormat> Intersectable<T> for Csg<T> {
+    fn intersect(&self, ray: &Ray<T>) -> Option<Intersection<T>> {
+        let hit = self
+            .intervals(ray)
+            .into_iter()
+            .flat_map(|interval| vec![interval.entry, interval.exit])
+            .find(|hit| hit.t >= T::zero())?;
+
+        let mut intersection =
+            Intersection::new(ray.origin.add(&ray.direction.mul(hit.t)), hit.normal);
+        intersection.uv = hit.uv;
+        intersection.front_face = intersection.normal.dot(&ray.direction) < T::zero();
+
+        Some(intersection)
+    }
+}
+
+impl<T: VertexFormat> Spacial<T> for Csg<T> {
+    fn location(&self) -> &Vec3<T> {
+        self.left.location()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::visible::cuboid::AxisAlignedBox;
+    use crate::scene::visible::intervals_from_hits;
+    use crate::scene::visible::sphere::Sphere;
+
+    // solid that reports a single span, regardless of the ray
+    #[derive(Debug)]
+    struct FixedSpan {
+        location: Vec3<f64>,
+        entry: f64,
+        exit: f64,
+    }
+
+    impl Spacial<f64> for FixedSpan {
+        fn location(&self) -> &Vec3<f64> {
+            &self.location
+        }
+    }
+
+    impl Intersectable<f64> for FixedSpan {
+        fn intersect(&self, _ray: &Ray<f64>) -> Option<Intersection<f64>> {
+            None
+        }
+    }
+
+    impl Solid<f64> for FixedSpan {
+        fn intervals(&self, _ray: &Ray<f64>) -> Vec<Interval<f64>> {
+            let hit = |t| Hit {
+                t,
+                normal: Vec3::new(1.0, 0.0, 0.0),
+                uv: (0.0, 0.0),
+            };
+            vec![Interval {
+                entry: hit(self.entry),
+                exit: hit(self.exit),
+            }]
+        }
+    }
+
+    fn spheres() -> (Box<dyn Solid<f64>>, Box<dyn Solid<f64>>) {
+        (
+            Box::new(Sphere::new(Vec3::new(-0.5, 0.0, 0.0), 1.0)),
+            Box::new(Sphere::new(Vec3::new(0.5, 0.0, 0.0), 1.0)),
+        )
+    }
+
+    #[test]
+    fn union_of_overlapping_spheres() {
+        let (left, right) = spheres();
+        let union = Csg::union(left, right);
+
+        let ray = Ray::new(Vec3::new(10.0, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0));
+
+        let intervals = union.intervals(&ray);
+
+        // one merged span from x = 1.5 to x = -1.5
+        assert_eq!(1, intervals.len());
+        assert_eq!(8.5, intervals[0].entry.t);
+        assert_eq!(11.5, intervals[0].exit.t);
+    }
+
+    #[test]
+    fn intersection_of_overlapping_spheres() {
+        let (left, right) = spheres();
+        let lens = Csg::intersection(left, right);
+
+        let ray = Ray::new(Vec3::new(10.0, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0));
+
+        let intersection = lens.intersect(&ray).unwrap();
+
+        assert_eq!(Vec3::new(0.5, 0.0, 0.0), intersection.point);
+        assert_eq!(Vec3::new(1.0, 0.0, 0.0), intersection.normal);
+    }
+
+    #[test]
+    fn difference_flips_subtracted_normals() {
+        let (left, right) = spheres();
+        let bitten = Csg::difference(left, right);
+
+        let ray = Ray::new(Vec3::new(10.0, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0));
+
+        // the ray passes through the bite, and enters the remaining solid at the exit of the
+        // right sphere, at x = -0.5
+        let intersection = bitten.intersect(&ray).unwrap();
+
+        assert_eq!(Vec3::new(-0.5, 0.0, 0.0), intersection.point);
+        assert_eq!(Vec3::new(1.0, 0.0, 0.0), intersection.normal);
+    }
+
+    #[test]
+    fn box_with_hole() {
+        let cube = AxisAlignedBox::new(Vec3::new(-1.0, -1.0, -1.0), Vec3::new(1.0, 1.0, 1.0));
+        let hole = Sphere::new(Vec3::new(0.0, 0.0, 0.0), 0.5);
+        let part = Csg::difference(Box::new(cube), Box::new(hole));
+
+        let through_hole = Ray::new(Vec3::new(0.0, 0.0, 10.0), Vec3::new(0.0, 0.0, -1.0));
+
+        let intervals = part.intervals(&through_hole);
+
+        assert_eq!(2, intervals.len());
+        assert_eq!(11.0, intervals[1].exit.t);
+        assert_eq!(Vec3::new(0.0, 0.0, -1.0), intervals[0].exit.normal);
+    }
+
+    #[test]
+    fn spans_without_finite_bounds_are_skipped() {
+        let broken = Box::new(FixedSpan {
+            location: Vec3::new(0.0, 0.0, 0.0),
+            entry: f64::NAN,
+            exit: 2.0,
+        });
+        let sphere = Box::new(Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0));
+        let union = Csg::union(broken, sphere);
+
+        let ray = Ray::new(Vec3::new(10.0, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0));
+
+        let intervals = union.intervals(&ray);
+
+        assert_eq!(1, intervals.len());
+        assert_eq!(9.0, intervals[0].entry.t);
+        assert_eq!(11.0, intervals[0].exit.t);
+    }
+
+    #[test]
+    fn hits_that_arent_finite_are_left_out_of_spans() {
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        let normal = Vec3::new(1.0, 0.0, 0.0);
+        let hits = vec![
+            (3.0, normal.clone()),
+            (f64::NAN, normal.clone()),
+            (1.0, normal.clone()),
+            (f64::INFINITY, normal),
+        ];
+
+        let intervals = intervals_from_hits(&ray, hits, &|_, _| (0.0, 0.0));
+
+        assert_eq!(1, intervals.len());
+        assert_eq!(1.0, intervals[0].entry.t);
+        assert_eq!(3.0, intervals[0].exit.t);
+    }
+}
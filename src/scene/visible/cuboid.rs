@@ -0,0 +1,340 @@
+use crate::common::{Intersection, Ray, Spacial, Vec3, VertexFormat};
+use crate::scene::visible::{Hit, Intersectable, Interval, Solid};
+
+fn components<T: VertexFormat>(v: &Vec3<T>) -> [T; 3] {
+    [v.x, v.y, v.z]
+}
+
+// unit vector along the given axis (0 = x, 1 = y, 2 = z), scaled by sign
+fn axis_vector<T: VertexFormat>(axis: usize, sign: T) -> Vec3<T> {
+    match axis {
+        0 => Vec3::new(sign, T::zero(), T::zero()),
+        1 => Vec3::new(T::zero(), sign, T::zero()),
+        _ => Vec3::new(T::zero(), T::zero(), sign),
+    }
+}
+
+// Texture coordinates on the face of a box perpendicular to the given axis, from the position of
+// the point within the box, relative to its size (so each component is in [0, 1])
+fn face_uv<T: VertexFormat>(relative: [T; 3], axis: usize) -> (T, T) {
+    match axis {
+        0 => (relative[2], relative[1]),
+        1 => (relative[0], relative[2]),
+        _ => (relative[0], relative[1]),
+    }
+}
+
+// Slab test of a ray against the axis aligned box [min, max]. Returns the span of the ray's line
+// inside the box.
+fn slab_span<T: VertexFormat>(
+    origin: &Vec3<T>,
+    direction: &Vec3<T>,
+    min: &Vec3<T>,
+    max: &Vec3<T>,
+) -> Option<Interval<T>> {
+    let origin = components(origin);
+    let direction = components(direction);
+    let min = components(min);
+    let max = components(max);
+
+    let mut t_near = T::neg_infinity();
+    let mut t_far = T::infinity();
+    let mut near_normal = Vec3::new(T::zero(), T::zero(), T::zero());
+    let mut far_normal = Vec3::new(T::zero(), T::zero(), T::zero());
+    let mut near_axis = 0;
+    let mut far_axis = 0;
+
+    for axis in 0..3 {
+        if direction[axis] == T::zero() {
+            // parallel to this slab, so the origin must lie between the two planes
+            if origin[axis] < min[axis] || origin[axis] > max[axis] {
+                return None;
+            }
+            continue;
+        }
+
+        let t_min = (min[axis] - origin[axis]) / direction[axis];
+        let t_max = (max[axis] - origin[axis]) / direction[axis];
+
+        // the slab plane the ray enters through faces against the ray direction
+        let (t_enter, t_exit, sign) = if t_min < t_max {
+            (t_min, t_max, T::one())
+        } else {
+            (t_max, t_min, T::one().neg())
+        };
+
+        if t_enter > t_near {
+            t_near = t_enter;
+            near_normal = axis_vector(axis, sign.neg());
+            near_axis = axis;
+        }
+        if t_exit < t_far {
+            t_far = t_exit;
+            far_normal = axis_vector(axis, sign);
+            far_axis = axis;
+        }
+    }
+
+    if t_near > t_far {
+        return None;
+    }
+
+    let uv = |t: T, axis: usize| {
+        let relative =
+            [0, 1, 2].map(|i| (origin[i] + direction[i] * t - min[i]) / (max[i] - min[i]));
+        face_uv(relative, axis)
+    };
+
+    Some(Interval {
+        entry: Hit {
+            t: t_near,
+            normal: near_normal,
+            uv: uv(t_near, near_axis),
+        },
+        exit: Hit {
+            t: t_far,
+            normal: far_normal,
+            uv: uv(t_far, far_axis),
+        },
+    })
+}
+
+// Nearest intersection of a ray with the axis aligned box [min, max] in front of the ray origin.
+// If the ray origin is inside the box, the exit point is returned.
+fn slab_intersection<T: VertexFormat>(
+    origin: &Vec3<T>,
+    direction: &Vec3<T>,
+    min: &Vec3<T>,
+    max: &Vec3<T>,
+) -> Option<Hit<T>> {
+    let span = slab_span(origin, direction, min, max)?;
+
+    if span.entry.t >= T::zero() {
+        Some(span.entry)
+    } else if span.exit.t >= T::zero() {
+        // ray origin is inside the box
+        Some(span.exit)
+    } else {
+        None
+    }
+}
+
+// Box with faces aligned to the world axes
+#[derive(Debug)]
+pub struct AxisAlignedBox<T: VertexFormat> {
+    min: Vec3<T>,
+    max: Vec3<T>,
+    center: Vec3<T>,
+}
+
+impl<T: VertexFormat> AxisAlignedBox<T> {
+    // creates a box spanning the two given opposite corners
+    pub fn new(corner1: Vec3<T>, corner2: Vec3<T>) -> AxisAlignedBox<T> {
+        let min = Vec3::new(
+            corner1.x.min(corner2.x),
+            corner1.y.min(corner2.y),
+            corner1.z.min(corner2.z),
+        );
+        let max = Vec3::new(
+            corner1.x.max(corner2.x),
+            corner1.y.max(corner2.y),
+            corner1.z.max(corner2.z),
+        );
+        let center = min.add(&max).div(T::from(2.0).unwrap());
+
+        AxisAlignedBox { min, max, center }
+    }
+
+    pub fn min(&self) -> &Vec3<T> {
+        &self.min
+    }
+
+    pub fn max(&self) -> &Vec3<T> {
+        &self.max
+    }
+}
+
+impl<T: VertexFormat> Intersectable<T> for AxisAlignedBox<T> {
+    fn intersect(&self, ray: &Ray<T>) -> Option<Intersection<T>> {
+        let hit = slab_intersection(&ray.origin, &ray.direction, &self.min, &self.max)?;
+
+        let point = ray.origin.add(&ray.direction.mul(hit.t));
+
+        let mut intersection = Intersection::new(point, hit.normal);
+        intersection.uv = hit.uv;
+        intersection.front_face = intersection.normal.dot(&ray.direction) < T::zero();
+
+        Some(intersection)
+    }
+}
+
+impl<T: VertexFormat> Solid<T> for AxisAlignedBox<T> {
+    fn intervals(&self, ray: &Ray<T>) -> Vec<Interval<T>> {
+        slab_span(&ray.origin, &ray.direction, &self.min, &self.max)
+            .into_iter()
+            .collect()
+    }
+}
+
+impl<T: VertexFormat> Spacial<T> for AxisAlignedBox<T> {
+    fn location(&self) -> &Vec3<T> {
+        &self.center
+    }
+}
+
+// Box with an arbitrary orientation, defined by its center, a local x and y axis, and the half
+// size of the box along each of its local axes.
+#[derive(Debug)]
+pub struct OrientedBox<T: VertexFormat> {
+    center: Vec3<T>,
+    axes: [Vec3<T>; 3],
+    half_extents: Vec3<T>,
+}
+
+impl<T: VertexFormat> OrientedBox<T> {
+    // x_axis and y_axis don't need to be unit length or exactly perpendicular, they are
+    // orthonormalized, keeping the direction of x_axis.
+    pub fn new(
+        center: Vec3<T>,
+        x_axis: Vec3<T>,
+        y_axis: Vec3<T>,
+        half_extents: Vec3<T>,
+    ) -> OrientedBox<T> {
+        let x_axis = x_axis.normalize();
+        let z_axis = x_axis.cross(&y_axis).normalize();
+        let y_axis = z_axis.cross(&x_axis);
+
+        OrientedBox {
+            center,
+            axes: [x_axis, y_axis, z_axis],
+            half_extents,
+        }
+    }
+
+    // transforms a world space vector into box space
+    fn to_local(&self, vector: &Vec3<T>) -> Vec3<T> {
+        Vec3::new(
+            vector.dot(&self.axes[0]),
+            vector.dot(&self.axes[1]),
+            vector.dot(&self.axes[2]),
+        )
+    }
+
+    // transforms a box space vector into world space
+    fn to_world(&self, vector: &Vec3<T>) -> Vec3<T> {
+        self.axes[0]
+            .mul(vector.x)
+            .add(&self.axes[1].mul(vector.y))
+            .add(&self.axes[2].mul(vector.z))
+    }
+}
+
+impl<T: VertexFormat> Intersectable<T> for OrientedBox<T> {
+    fn intersect(&self, ray: &Ray<T>) -> Option<Intersection<T>> {
+        let origin = self.to_local(&ray.origin.sub(&self.center));
+        let direction = self.to_local(&ray.direction);
+        let min = self.half_extents.mul(T::one().neg());
+
+        let hit = slab_intersection(&origin, &direction, &min, &self.half_extents)?;
+
+        let point = ray.origin.add(&ray.direction.mul(hit.t));
+
+        let mut intersection = Intersection::new(point, self.to_world(&hit.normal));
+        intersection.uv = hit.uv;
+        intersection.front_face = intersection.normal.dot(&ray.direction) < T::zero();
+
+        Some(intersection)
+    }
+}
+
+impl<T: VertexFormat> Solid<T> for OrientedBox<T> {
+    fn intervals(&self, ray: &Ray<T>) -> Vec<Interval<T>> {
+        let origin = self.to_local(&ray.origin.sub(&self.center));
+        let direction = self.to_local(&ray.direction);
+        let min = self.half_extents.mul(T::one().neg());
+
+        slab_span(&origin, &direction, &min, &self.half_extents)
+            .map(|span| Interval {
+                entry: Hit {
+                    normal: self.to_world(&span.entry.normal),
+                    ..span.entry
+                },
+                exit: Hit {
+                    normal: self.to_world(&span.exit.normal),
+                    ..span.exit
+                },
+            })
+            .into_iter()
+            .collect()
+    }
+}
+
+impl<T: VertexFormat> Spacial<T> for OrientedBox<T> {
+    fn location(&self) -> &Vec3<T> {
+        &self.center
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn axis_aligned_box_intersection() {
+        let aabb = AxisAlignedBox::new(Vec3::new(1.0, 1.0, 1.0), Vec3::new(-1.0, -1.0, -1.0));
+
+        let ray = Ray::new(Vec3::new(10.0, 0.5, 0.0), Vec3::new(-1.0, 0.0, 0.0));
+
+        let mut expected_intersection =
+            Intersection::new(Vec3::new(1.0, 0.5, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        expected_intersection.uv = (0.5, 0.75);
+
+        assert_eq!(expected_intersection, aabb.intersect(&ray).unwrap());
+    }
+
+    #[test]
+    fn axis_aligned_box_from_inside() {
+        let aabb = AxisAlignedBox::new(Vec3::new(-1.0, -1.0, -1.0), Vec3::new(1.0, 1.0, 1.0));
+
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+
+        let mut expected_intersection =
+            Intersection::new(Vec3::new(0.0, 0.0, -1.0), Vec3::new(0.0, 0.0, -1.0));
+        expected_intersection.uv = (0.5, 0.5);
+        expected_intersection.front_face = false;
+
+        assert_eq!(expected_intersection, aabb.intersect(&ray).unwrap());
+    }
+
+    #[test]
+    fn axis_aligned_box_miss() {
+        let aabb = AxisAlignedBox::new(Vec3::new(-1.0, -1.0, -1.0), Vec3::new(1.0, 1.0, 1.0));
+
+        let beside = Ray::new(Vec3::new(10.0, 2.0, 0.0), Vec3::new(-1.0, 0.0, 0.0));
+        let behind = Ray::new(Vec3::new(10.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+
+        assert_eq!(None, aabb.intersect(&beside));
+        assert_eq!(None, aabb.intersect(&behind));
+    }
+
+    #[test]
+    fn rotated_box_intersection() {
+        // unit cube rotated 45 degrees around the y axis
+        let obb = OrientedBox::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(1.0, 0.0, 1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(1.0, 1.0, 1.0),
+        );
+
+        let ray = Ray::new(Vec3::new(10.0, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0));
+
+        let intersection = obb.intersect(&ray).unwrap();
+        let half_sqrt2 = 0.5_f64.sqrt();
+
+        // the corner of the rotated cube points along the x axis
+        assert!((intersection.point.x - 2.0_f64.sqrt()).abs() < 1e-12);
+        assert!((intersection.normal.x - half_sqrt2).abs() < 1e-12);
+        assert!((intersection.normal.z.abs() - half_sqrt2).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,373 @@
+use crate::common::{Intersection, Ray, Spacial, Vec3, VertexFormat};
+use crate::polynomial::solve_quadratic;
+use crate::scene::visible::{
+    angle_around, intervals_from_hits, nearest_hit, Intersectable, Interval, Solid,
+};
+
+// Ray expressed relative to the axis of a shape: the components of the ray origin (relative to
+// the base point) and direction along the axis, and the parts perpendicular to it.
+pub(crate) struct AxialRay<T: VertexFormat> {
+    pub origin_along: T,
+    pub direction_along: T,
+    pub origin_perp: Vec3<T>,
+    pub direction_perp: Vec3<T>,
+}
+
+impl<T: VertexFormat> AxialRay<T> {
+    pub fn new(ray: &Ray<T>, base: &Vec3<T>, axis: &Vec3<T>) -> AxialRay<T> {
+        let origin = ray.origin.sub(base);
+        let origin_along = origin.dot(axis);
+        let direction_along = ray.direction.dot(axis);
+
+        AxialRay {
+            origin_along,
+            direction_along,
+            origin_perp: origin.sub(&axis.mul(origin_along)),
+            direction_perp: ray.direction.sub(&axis.mul(direction_along)),
+        }
+    }
+
+    // distance along the axis at ray parameter t
+    pub fn along(&self, t: T) -> T {
+        self.origin_along + self.direction_along * t
+    }
+
+    // offset from the axis at ray parameter t
+    pub fn radial(&self, t: T) -> Vec3<T> {
+        self.origin_perp.add(&self.direction_perp.mul(t))
+    }
+
+    // hits with the planes perpendicular to the axis at the base and at height, that lie within
+    // the given radii of the axis. Normals point away from the shape.
+    pub fn cap_hits(&self, axis: &Vec3<T>, height: T, radii: (T, T)) -> Vec<(T, Vec3<T>)> {
+        let mut hits = Vec::new();
+
+        if self.direction_along == T::zero() {
+            return hits;
+        }
+
+        let caps = [
+            (T::zero(), radii.0, axis.mul(T::one().neg())),
+            (height, radii.1, axis.clone()),
+        ];
+
+        for (plane, radius, normal) in caps.iter() {
+            let t = (*plane - self.origin_along) / self.direction_along;
+            if *radius > T::zero() && self.radial(t).mag_sqrd() <= *radius * *radius {
+                hits.push((t, normal.clone()));
+            }
+        }
+
+        hits
+    }
+}
+
+// Texture coordinates on a shape around an axis, with flat caps of the given radii at its base and
+// top. On the side, u runs around the axis and v along it. Each cap has the texture's unit square
+// stretched over its bounding square.
+pub(crate) fn axial_uv<T: VertexFormat>(
+    offset: &Vec3<T>,
+    normal: &Vec3<T>,
+    axis: &Vec3<T>,
+    height: T,
+    cap_radii: (T, T),
+) -> (T, T) {
+    let along = offset.dot(axis);
+    let radial = offset.sub(&axis.mul(along));
+    let facing = normal.dot(axis);
+
+    if facing.abs() < T::one() - T::from(1e-9).unwrap() {
+        return (angle_around(&radial, axis), along / height);
+    }
+
+    let radius = if facing < T::zero() {
+        cap_radii.0
+    } else {
+        cap_radii.1
+    };
+    let (tangent, bitangent) = axis.orthonormal_basis();
+    let half = T::from(0.5).unwrap();
+    let diameter = radius * T::from(2.0).unwrap();
+
+    (
+        radial.dot(&tangent) / diameter + half,
+        radial.dot(&bitangent) / diameter + half,
+    )
+}
+
+// Cylinder of a given radius around the line segment from base to top. Uncapped cylinders are an
+// open, two sided tube.
+#[derive(Debug)]
+pub struct Cylinder<T: VertexFormat> {
+    base: Vec3<T>,
+    axis: Vec3<T>,
+    height: T,
+    radius: T,
+    capped: bool,
+    center: Vec3<T>,
+}
+
+impl<T: VertexFormat> Cylinder<T> {
+    pub fn new(base: Vec3<T>, top: Vec3<T>, radius: T, capped: bool) -> Cylinder<T> {
+        let axis = top.sub(&base);
+        let height = axis.mag_sqrd().sqrt();
+        let center = base.add(&top).div(T::from(2.0).unwrap());
+
+        Cylinder {
+            base,
+            axis: axis.normalize(),
+            height,
+            radius,
+            capped,
+            center,
+        }
+    }
+
+    // every hit of the ray's line with the cylinder surface, with outward facing normals
+    fn hits(&self, ray: &Ray<T>, capped: bool) -> Vec<(T, Vec3<T>)> {
+        let axial = AxialRay::new(ray, &self.base, &self.axis);
+
+        let mut hits: Vec<(T, Vec3<T>)> = solve_quadratic(
+            axial.direction_perp.mag_sqrd(),
+            T::from(2.0).unwrap() * axial.direction_perp.dot(&axial.origin_perp),
+            axial.origin_perp.mag_sqrd() - self.radius * self.radius,
+        )
+        .into_iter()
+        .filter(|t| {
+            let along = axial.along(*t);
+            along >= T::zero() && along <= self.height
+        })
+        .map(|t| (t, axial.radial(t).div(self.radius)))
+        .collect();
+
+        if capped {
+            hits.append(&mut axial.cap_hits(&self.axis, self.height, (self.radius, self.radius)));
+        }
+
+        hits
+    }
+
+    fn uv(&self, point: &Vec3<T>, normal: &Vec3<T>) -> (T, T) {
+        axial_uv(
+            &point.sub(&self.base),
+            normal,
+            &self.axis,
+            self.height,
+            (self.radius, self.radius),
+        )
+    }
+}
+
+impl<T: VertexFormat> Intersectable<T> for Cylinder<T> {
+    fn intersect(&self, ray: &Ray<T>) -> Option<Intersection<T>> {
+        let hits = self.hits(ray, self.capped);
+
+        nearest_hit(ray, &hits, !self.capped, &|point, normal| {
+            self.uv(point, normal)
+        })
+    }
+}
+
+// as a solid, the cylinder is always treated as closed, even if its caps aren't rendered
+impl<T: VertexFormat> Solid<T> for Cylinder<T> {
+    fn intervals(&self, ray: &Ray<T>) -> Vec<Interval<T>> {
+        intervals_from_hits(ray, self.hits(ray, true), &|point, normal| {
+            self.uv(point, normal)
+        })
+    }
+}
+
+impl<T: VertexFormat> Spacial<T> for Cylinder<T> {
+    fn location(&self) -> &Vec3<T> {
+        &self.center
+    }
+}
+
+// Cylinder with hemispherical ends, i.e. every point within radius of the segment from start to
+// end.
+#[derive(Debug)]
+pub struct Capsule<T: VertexFormat> {
+    start: Vec3<T>,
+    end: Vec3<T>,
+    axis: Vec3<T>,
+    height: T,
+    radius: T,
+    center: Vec3<T>,
+}
+
+impl<T: VertexFormat> Capsule<T> {
+    pub fn new(start: Vec3<T>, end: Vec3<T>, radius: T) -> Capsule<T> {
+        let axis = end.sub(&start);
+        let height = axis.mag_sqrd().sqrt();
+        let center = start.add(&end).div(T::from(2.0).unwrap());
+
+        Capsule {
+            start,
+            end,
+            axis: axis.normalize(),
+            height,
+            radius,
+            center,
+        }
+    }
+
+    // hits of the ray's line with the sphere at one end of the capsule
+    fn sphere_hits(&self, ray: &Ray<T>, center: &Vec3<T>) -> Vec<(T, Vec3<T>)> {
+        let oc = ray.origin.sub(center);
+
+        solve_quadratic(
+            T::one(),
+            T::from(2.0).unwrap() * oc.dot(&ray.direction),
+            oc.mag_sqrd() - self.radius * self.radius,
+        )
+        .into_iter()
+        .map(|t| (t, oc.add(&ray.direction.mul(t)).div(self.radius)))
+        .collect()
+    }
+
+    // every hit of the ray's line with the capsule surface, with outward facing normals
+    fn hits(&self, ray: &Ray<T>) -> Vec<(T, Vec3<T>)> {
+        let axial = AxialRay::new(ray, &self.start, &self.axis);
+
+        let mut hits: Vec<(T, Vec3<T>)> = solve_quadratic(
+            axial.direction_perp.mag_sqrd(),
+            T::from(2.0).unwrap() * axial.direction_perp.dot(&axial.origin_perp),
+            axial.origin_perp.mag_sqrd() - self.radius * self.radius,
+        )
+        .into_iter()
+        .filter(|t| {
+            let along = axial.along(*t);
+            along >= T::zero() && along <= self.height
+        })
+        .map(|t| (t, axial.radial(t).div(self.radius)))
+        .collect();
+
+        // only the outer half of each end sphere is part of the surface
+        for (t, normal) in self.sphere_hits(ray, &self.start) {
+            if axial.along(t) < T::zero() {
+                hits.push((t, normal));
+            }
+        }
+        for (t, normal) in self.sphere_hits(ray, &self.end) {
+            if axial.along(t) > self.height {
+                hits.push((t, normal));
+            }
+        }
+
+        hits
+    }
+
+    // u runs around the axis, and v along it from the tip of one end to the other
+    fn uv(&self, point: &Vec3<T>) -> (T, T) {
+        let offset = point.sub(&self.start);
+        let along = offset.dot(&self.axis);
+        let radial = offset.sub(&self.axis.mul(along));
+
+        (
+            angle_around(&radial, &self.axis),
+            (along + self.radius) / (self.height + self.radius * T::from(2.0).unwrap()),
+        )
+    }
+}
+
+impl<T: VertexFormat> Intersectable<T> for Capsule<T> {
+    fn intersect(&self, ray: &Ray<T>) -> Option<Intersection<T>> {
+        nearest_hit(ray, &self.hits(ray), false, &|point, _| self.uv(point))
+    }
+}
+
+impl<T: VertexFormat> Solid<T> for Capsule<T> {
+    fn intervals(&self, ray: &Ray<T>) -> Vec<Interval<T>> {
+        intervals_from_hits(ray, self.hits(ray), &|point, _| self.uv(point))
+    }
+}
+
+impl<T: VertexFormat> Spacial<T> for Capsule<T> {
+    fn location(&self) -> &Vec3<T> {
+        &self.center
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cylinder_side_intersection() {
+        let cylinder = Cylinder::new(
+            Vec3::new(0.0, -1.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            0.5,
+            true,
+        );
+
+        let ray = Ray::new(Vec3::new(10.0, 0.5, 0.0), Vec3::new(-1.0, 0.0, 0.0));
+
+        let intersection = cylinder.intersect(&ray).unwrap();
+
+        assert_eq!(Vec3::new(0.5, 0.5, 0.0), intersection.point);
+        assert_eq!(Vec3::new(1.0, 0.0, 0.0), intersection.normal);
+        assert_eq!(0.75, intersection.uv.1);
+    }
+
+    #[test]
+    fn cylinder_cap_intersection() {
+        let capped = Cylinder::new(
+            Vec3::new(0.0, -1.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            0.5,
+            true,
+        );
+        let open = Cylinder::new(
+            Vec3::new(0.0, -1.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            0.5,
+            false,
+        );
+
+        let ray = Ray::new(Vec3::new(0.25, 10.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+
+        let intersection = capped.intersect(&ray).unwrap();
+
+        assert_eq!(Vec3::new(0.25, 1.0, 0.0), intersection.point);
+        assert_eq!(Vec3::new(0.0, 1.0, 0.0), intersection.normal);
+        // looking down an open tube, along its axis
+        assert_eq!(None, open.intersect(&ray));
+    }
+
+    #[test]
+    fn open_cylinder_normal_faces_ray_from_inside() {
+        let open = Cylinder::new(
+            Vec3::new(0.0, -1.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            0.5,
+            false,
+        );
+
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+
+        let intersection = open.intersect(&ray).unwrap();
+
+        assert_eq!(Vec3::new(0.5, 0.0, 0.0), intersection.point);
+        assert_eq!(Vec3::new(-1.0, 0.0, 0.0), intersection.normal);
+    }
+
+    #[test]
+    fn capsule_end_intersection() {
+        let capsule = Capsule::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 2.0, 0.0), 0.5);
+
+        let top = Ray::new(Vec3::new(0.0, 10.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        let side = Ray::new(Vec3::new(10.0, 1.0, 0.0), Vec3::new(-1.0, 0.0, 0.0));
+        let miss = Ray::new(Vec3::new(10.0, 2.6, 0.0), Vec3::new(-1.0, 0.0, 0.0));
+
+        let top_intersection = capsule.intersect(&top).unwrap();
+
+        assert_eq!(Vec3::new(0.0, 2.5, 0.0), top_intersection.point);
+        assert_eq!(Vec3::new(0.0, 1.0, 0.0), top_intersection.normal);
+        assert_eq!(
+            Vec3::new(0.5, 1.0, 0.0),
+            capsule.intersect(&side).unwrap().point
+        );
+        assert_eq!(None, capsule.intersect(&miss));
+    }
+}
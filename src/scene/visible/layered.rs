@@ -0,0 +1,785 @@
+use crate::common::{Intersection, Vec3, VertexFormat};
+use crate::sampler::Sampler;
+use crate::scene::light::IncidentLight;
+use crate::scene::visible::bsdf::{Bsdf, ScatterSample};
+use crate::scene::visible::pbr::{
+    ggx_distribution, ggx_reflection_pdf, sample_ggx_reflection, smith_g1, MIN_ROUGHNESS,
+};
+use crate::scene::visible::texture::{luminance, Texture};
+use std::rc::Rc;
+
+// Materials built out of other materials. Every part is given its own shading intersection, so
+// normal and bump maps only apply to the parts they were set on.
+
+// Two materials, each scaled by a weight. Paths pick the second material with the given chance.
+struct Pair<'a, T: VertexFormat> {
+    first: &'a dyn Bsdf<T>,
+    second: &'a dyn Bsdf<T>,
+    weights: (T, T),
+    chance: T,
+}
+
+impl<'a, T: VertexFormat> Pair<'a, T> {
+    fn evaluate(&self, intersection: &Intersection<T>, wo: &Vec3<T>, wi: &Vec3<T>) -> Vec3<T> {
+        let first = self
+            .first
+            .evaluate(&self.first.shading_intersection(intersection), wo, wi);
+        let second = self
+            .second
+            .evaluate(&self.second.shading_intersection(intersection), wo, wi);
+
+        first.mul(self.weights.0).add(&second.mul(self.weights.1))
+    }
+
+    fn pdf(&self, intersection: &Intersection<T>, wo: &Vec3<T>, wi: &Vec3<T>) -> T {
+        let first = self
+            .first
+            .pdf(&self.first.shading_intersection(intersection), wo, wi);
+        let second = self
+            .second
+            .pdf(&self.second.shading_intersection(intersection), wo, wi);
+
+        (T::one() - self.chance) * first + self.chance * second
+    }
+
+    fn sample(
+        &self,
+        intersection: &Intersection<T>,
+        wo: &Vec3<T>,
+        sampler: &mut dyn Sampler<T>,
+    ) -> Option<ScatterSample<T>> {
+        let (chosen, weight, chance) = if sampler.next_1d() < self.chance {
+            (self.second, self.weights.1, self.chance)
+        } else {
+            (self.first, self.weights.0, T::one() - self.chance)
+        };
+
+        let sample = chosen.sample(&chosen.shading_intersection(intersection), wo, sampler)?;
+
+        // specular lobes can't be evaluated for the other material, so they're weighted alone
+        if sample.specular {
+            return Some(ScatterSample {
+                weight: sample.weight.mul(weight / chance),
+                pdf: sample.pdf * chance,
+                ..sample
+            });
+        }
+
+        let pdf = self.pdf(intersection, wo, &sample.direction);
+        if pdf <= T::zero() {
+            return None;
+        }
+
+        Some(ScatterSample {
+            weight: self.evaluate(intersection, wo, &sample.direction).div(pdf),
+            direction: sample.direction,
+            pdf,
+            specular: false,
+        })
+    }
+
+    fn ambient(&self, intersection: &Intersection<T>) -> Vec3<T> {
+        let first = self
+            .first
+            .ambient(&self.first.shading_intersection(intersection));
+        let second = self
+            .second
+            .ambient(&self.second.shading_intersection(intersection));
+
+        first.mul(self.weights.0).add(&second.mul(self.weights.1))
+    }
+
+    fn shade(
+        &self,
+        intersection: &Intersection<T>,
+        lights: &[IncidentLight<T>],
+        viewpoint: &Vec3<T>,
+    ) -> Vec3<T> {
+        let first = self.first.shade(
+            &self.first.shading_intersection(intersection),
+            lights,
+            viewpoint,
+        );
+        let second = self.second.shade(
+            &self.second.shading_intersection(intersection),
+            lights,
+            viewpoint,
+        );
+
+        first.mul(self.weights.0).add(&second.mul(self.weights.1))
+    }
+
+    fn shade_parts(
+        &self,
+        intersection: &Intersection<T>,
+        lights: &[IncidentLight<T>],
+        viewpoint: &Vec3<T>,
+    ) -> (Vec3<T>, Vec3<T>) {
+        let first = self.first.shade_parts(
+            &self.first.shading_intersection(intersection),
+            lights,
+            viewpoint,
+        );
+        let second = self.second.shade_parts(
+            &self.second.shading_intersection(intersection),
+            lights,
+            viewpoint,
+        );
+
+        (
+            first
+                .0
+                .mul(self.weights.0)
+                .add(&second.0.mul(self.weights.1)),
+            first
+                .1
+                .mul(self.weights.0)
+                .add(&second.1.mul(self.weights.1)),
+        )
+    }
+
+    fn reflection_coefficient(&self, intersection: &Intersection<T>, wo: &Vec3<T>) -> T {
+        let first = self
+            .first
+            .reflection_coefficient(&self.first.shading_intersection(intersection), wo);
+        let second = self
+            .second
+            .reflection_coefficient(&self.second.shading_intersection(intersection), wo);
+
+        first * self.weights.0 + second * self.weights.1
+    }
+
+    // average of the roughnesses, weighted like the reflections they blur
+    fn reflection_roughness(&self, intersection: &Intersection<T>, wo: &Vec3<T>) -> T {
+        let first = self.first.shading_intersection(intersection);
+        let second = self.second.shading_intersection(intersection);
+        let first_weight = self.first.reflection_coefficient(&first, wo) * self.weights.0;
+        let second_weight = self.second.reflection_coefficient(&second, wo) * self.weights.1;
+        if first_weight + second_weight <= T::zero() {
+            return T::zero();
+        }
+
+        (self.first.reflection_roughness(&first, wo) * first_weight
+            + self.second.reflection_roughness(&second, wo) * second_weight)
+            / (first_weight + second_weight)
+    }
+
+    // the Whitted renderer follows a single refracted ray, so only the first material that
+    // refracts is used
+    fn refraction(
+        &self,
+        intersection: &Intersection<T>,
+        wo: &Vec3<T>,
+    ) -> Option<(Vec3<T>, Vec3<T>)> {
+        let first = self
+            .first
+            .refraction(&self.first.shading_intersection(intersection), wo)
+            .map(|(direction, weight)| (direction, weight.mul(self.weights.0)));
+
+        first.or_else(|| {
+            self.second
+                .refraction(&self.second.shading_intersection(intersection), wo)
+                .map(|(direction, weight)| (direction, weight.mul(self.weights.1)))
+        })
+    }
+}
+
+// Linear blend of two materials. The weight is the amount of the second material, like rust
+// patches on metal or dust settled on a surface.
+#[derive(Debug)]
+pub struct Mix<T: VertexFormat> {
+    first: Box<dyn Bsdf<T>>,
+    second: Box<dyn Bsdf<T>>,
+    weight: T,
+    // replaces the weight, using its luminance
+    weight_texture: Option<Rc<dyn Texture<T>>>,
+}
+
+impl<T: VertexFormat> Mix<T> {
+    pub fn new<A: Bsdf<T> + 'static, B: Bsdf<T> + 'static>(
+        first: A,
+        second: B,
+        weight: T,
+    ) -> Mix<T> {
+        Mix {
+            first: Box::new(first),
+            second: Box::new(second),
+            weight,
+            weight_texture: None,
+        }
+    }
+
+    pub fn set_weight_texture(&mut self, texture: Rc<dyn Texture<T>>) {
+        self.weight_texture = Some(texture);
+    }
+
+    fn pair(&self, intersection: &Intersection<T>) -> Pair<'_, T> {
+        let weight = match &self.weight_texture {
+            Some(texture) => luminance(&texture.value(intersection)),
+            None => self.weight,
+        }
+        .max(T::zero())
+        .min(T::one());
+
+        Pair {
+            first: self.first.as_ref(),
+            second: self.second.as_ref(),
+            weights: (T::one() - weight, weight),
+            chance: weight,
+        }
+    }
+}
+
+impl<T: VertexFormat> Bsdf<T> for Mix<T> {
+    fn evaluate(&self, intersection: &Intersection<T>, wo: &Vec3<T>, wi: &Vec3<T>) -> Vec3<T> {
+        self.pair(intersection).evaluate(intersection, wo, wi)
+    }
+
+    fn pdf(&self, intersection: &Intersection<T>, wo: &Vec3<T>, wi: &Vec3<T>) -> T {
+        self.pair(intersection).pdf(intersection, wo, wi)
+    }
+
+    fn sample(
+        &self,
+        intersection: &Intersection<T>,
+        wo: &Vec3<T>,
+        sampler: &mut dyn Sampler<T>,
+    ) -> Option<ScatterSample<T>> {
+        self.pair(intersection).sample(intersection, wo, sampler)
+    }
+
+    fn ambient(&self, intersection: &Intersection<T>) -> Vec3<T> {
+        self.pair(intersection).ambient(intersection)
+    }
+
+    fn shade(
+        &self,
+        intersection: &Intersection<T>,
+        lights: &[IncidentLight<T>],
+        viewpoint: &Vec3<T>,
+    ) -> Vec3<T> {
+        self.pair(intersection)
+            .shade(intersection, lights, viewpoint)
+    }
+
+    fn shade_parts(
+        &self,
+        intersection: &Intersection<T>,
+        lights: &[IncidentLight<T>],
+        viewpoint: &Vec3<T>,
+    ) -> (Vec3<T>, Vec3<T>) {
+        self.pair(intersection)
+            .shade_parts(intersection, lights, viewpoint)
+    }
+
+    fn is_reflective(&self) -> bool {
+        self.first.is_reflective() || self.second.is_reflective()
+    }
+
+    fn reflection_coefficient(&self, intersection: &Intersection<T>, wo: &Vec3<T>) -> T {
+        self.pair(intersection)
+            .reflection_coefficient(intersection, wo)
+    }
+
+    fn reflection_roughness(&self, intersection: &Intersection<T>, wo: &Vec3<T>) -> T {
+        self.pair(intersection)
+            .reflection_roughness(intersection, wo)
+    }
+
+    fn refraction(
+        &self,
+        intersection: &Intersection<T>,
+        wo: &Vec3<T>,
+    ) -> Option<(Vec3<T>, Vec3<T>)> {
+        self.pair(intersection).refraction(intersection, wo)
+    }
+}
+
+// Sum of two materials, for layers that add light without taking any away from what's below,
+// like a sheen or a thin glossy film. The sum can reflect more light than it receives, unless the
+// two materials together reflect at most all of it.
+#[derive(Debug)]
+pub struct Additive<T: VertexFormat> {
+    first: Box<dyn Bsdf<T>>,
+    second: Box<dyn Bsdf<T>>,
+}
+
+impl<T: VertexFormat> Additive<T> {
+    pub fn new<A: Bsdf<T> + 'static, B: Bsdf<T> + 'static>(first: A, second: B) -> Additive<T> {
+        Additive {
+            first: Box::new(first),
+            second: Box::new(second),
+        }
+    }
+
+    fn pair(&self) -> Pair<'_, T> {
+        Pair {
+            first: self.first.as_ref(),
+            second: self.second.as_ref(),
+            weights: (T::one(), T::one()),
+            chance: T::from(0.5).unwrap(),
+        }
+    }
+}
+
+impl<T: VertexFormat> Bsdf<T> for Additive<T> {
+    fn evaluate(&self, intersection: &Intersection<T>, wo: &Vec3<T>, wi: &Vec3<T>) -> Vec3<T> {
+        self.pair().evaluate(intersection, wo, wi)
+    }
+
+    fn pdf(&self, intersection: &Intersection<T>, wo: &Vec3<T>, wi: &Vec3<T>) -> T {
+        self.pair().pdf(intersection, wo, wi)
+    }
+
+    fn sample(
+        &self,
+        intersection: &Intersection<T>,
+        wo: &Vec3<T>,
+        sampler: &mut dyn Sampler<T>,
+    ) -> Option<ScatterSample<T>> {
+        self.pair().sample(intersection, wo, sampler)
+    }
+
+    fn ambient(&self, intersection: &Intersection<T>) -> Vec3<T> {
+        self.pair().ambient(intersection)
+    }
+
+    fn shade(
+        &self,
+        intersection: &Intersection<T>,
+        lights: &[IncidentLight<T>],
+        viewpoint: &Vec3<T>,
+    ) -> Vec3<T> {
+        self.pair().shade(intersection, lights, viewpoint)
+    }
+
+    fn shade_parts(
+        &self,
+        intersection: &Intersection<T>,
+        lights: &[IncidentLight<T>],
+        viewpoint: &Vec3<T>,
+    ) -> (Vec3<T>, Vec3<T>) {
+        self.pair().shade_parts(intersection, lights, viewpoint)
+    }
+
+    fn is_reflective(&self) -> bool {
+        self.first.is_reflective() || self.second.is_reflective()
+    }
+
+    fn reflection_coefficient(&self, intersection: &Intersection<T>, wo: &Vec3<T>) -> T {
+        self.pair().reflection_coefficient(intersection, wo)
+    }
+
+    fn reflection_roughness(&self, intersection: &Intersection<T>, wo: &Vec3<T>) -> T {
+        self.pair().reflection_roughness(intersection, wo)
+    }
+
+    fn refraction(
+        &self,
+        intersection: &Intersection<T>,
+        wo: &Vec3<T>,
+    ) -> Option<(Vec3<T>, Vec3<T>)> {
+        self.pair().refraction(intersection, wo)
+    }
+}
+
+// Clear lacquer over a base material, like car paint or varnished wood. The coat is a dielectric
+// GGX reflection, and the light reaching the base has passed through the coat on the way in and
+// out, losing what the coat reflected each time.
+#[derive(Debug)]
+pub struct ClearCoat<T: VertexFormat> {
+    base: Box<dyn Bsdf<T>>,
+    roughness: T,
+    ior: T,
+}
+
+impl<T: VertexFormat> ClearCoat<T> {
+    pub fn new<B: Bsdf<T> + 'static>(base: B, roughness: T) -> ClearCoat<T> {
+        ClearCoat {
+            base: Box::new(base),
+            roughness,
+            ior: T::from(1.5).unwrap(),
+        }
+    }
+
+    // index of refraction of the coat, which sets how strongly it reflects
+    pub fn set_ior(&mut self, ior: T) {
+        self.ior = ior;
+    }
+
+    fn alpha(&self) -> T {
+        let roughness = self.roughness.max(T::from(MIN_ROUGHNESS).unwrap());
+
+        roughness * roughness
+    }
+
+    // Schlick's approximation of the coat's Fresnel reflectance
+    fn fresnel(&self, cos: T) -> T {
+        let f0 = ((self.ior - T::one()) / (self.ior + T::one())).powi(2);
+
+        f0 + (T::one() - f0) * (T::one() - cos).max(T::zero()).powi(5)
+    }
+
+    // fraction of the light passing through the coat in both directions
+    fn attenuation(&self, intersection: &Intersection<T>, wo: &Vec3<T>, wi: &Vec3<T>) -> T {
+        let cos_o = intersection.normal.dot(wo).max(T::zero());
+        let cos_i = intersection.normal.dot(wi).max(T::zero());
+
+        (T::one() - self.fresnel(cos_o)) * (T::one() - self.fresnel(cos_i))
+    }
+
+    // chance of sampling the coat, at least a quarter so its highlights show up quickly
+    fn coat_chance(&self, intersection: &Intersection<T>, wo: &Vec3<T>) -> T {
+        let cos_o = intersection.normal.dot(wo).max(T::zero());
+        let quarter = T::from(0.25).unwrap();
+
+        quarter + (T::one() - quarter) * self.fresnel(cos_o)
+    }
+
+    fn coat(&self, intersection: &Intersection<T>, wo: &Vec3<T>, wi: &Vec3<T>) -> T {
+        let normal = &intersection.normal;
+        let cos_o = normal.dot(wo);
+        let cos_i = normal.dot(wi);
+        if cos_o <= T::zero() || cos_i <= T::zero() {
+            return T::zero();
+        }
+
+        let alpha = self.alpha();
+        let half = wo.add(wi).normalize();
+        let distribution = ggx_distribution(normal.dot(&half), alpha);
+        let masking = smith_g1(cos_o, alpha) * smith_g1(cos_i, alpha);
+
+        self.fresnel(wo.dot(&half)) * distribution * masking / (T::from(4.0).unwrap() * cos_o)
+    }
+
+    // light the coat reflects straight from the lights
+    fn highlights(
+        &self,
+        intersection: &Intersection<T>,
+        lights: &[IncidentLight<T>],
+        wo: &Vec3<T>,
+    ) -> Vec3<T> {
+        let pi = T::from(std::f64::consts::PI).unwrap();
+        let mut color = Vec3::new(T::zero(), T::zero(), T::zero());
+
+        for light in lights {
+            let wi = light.light_vector(&intersection.point);
+            let coat = self.coat(intersection, wo, &wi);
+
+            color.mut_add(&light.color_at(&intersection.point).mul(coat * pi));
+        }
+
+        color
+    }
+
+    fn coat_pdf(&self, intersection: &Intersection<T>, wo: &Vec3<T>, wi: &Vec3<T>) -> T {
+        let normal = &intersection.normal;
+        if normal.dot(wo) <= T::zero() || normal.dot(wi) <= T::zero() {
+            return T::zero();
+        }
+
+        ggx_reflection_pdf(normal, wo, wi, self.alpha())
+    }
+}
+
+impl<T: VertexFormat> Bsdf<T> for ClearCoat<T> {
+    fn evaluate(&self, intersection: &Intersection<T>, wo: &Vec3<T>, wi: &Vec3<T>) -> Vec3<T> {
+        let coat = self.coat(intersection, wo, wi);
+        let base = self
+            .base
+            .evaluate(&self.base.shading_intersection(intersection), wo, wi)
+            .mul(self.attenuation(intersection, wo, wi));
+
+        base.add(&Vec3::new(coat, coat, coat))
+    }
+
+    fn pdf(&self, intersection: &Intersection<T>, wo: &Vec3<T>, wi: &Vec3<T>) -> T {
+        let chance = self.coat_chance(intersection, wo);
+        let base = self
+            .base
+            .pdf(&self.base.shading_intersection(intersection), wo, wi);
+
+        chance * self.coat_pdf(intersection, wo, wi) + (T::one() - chance) * base
+    }
+
+    fn sample(
+        &self,
+        intersection: &Intersection<T>,
+        wo: &Vec3<T>,
+        sampler: &mut dyn Sampler<T>,
+    ) -> Option<ScatterSample<T>> {
+        if intersection.normal.dot(wo) <= T::zero() {
+            return None;
+        }
+
+        let chance = self.coat_chance(intersection, wo);
+        let direction = if sampler.next_1d() < chance {
+            sample_ggx_reflection(intersection, wo, self.alpha(), sampler.next_2d())
+        } else {
+            let shading = self.base.shading_intersection(intersection);
+            let sample = self.base.sample(&shading, wo, sampler)?;
+
+            if sample.specular {
+                let attenuation = self.attenuation(intersection, wo, &sample.direction);
+
+                return Some(ScatterSample {
+                    weight: sample.weight.mul(attenuation / (T::one() - chance)),
+                    pdf: sample.pdf * (T::one() - chance),
+                    ..sample
+                });
+            }
+
+            sample.direction
+        };
+
+        let pdf = self.pdf(intersection, wo, &direction);
+        if pdf <= T::zero() {
+            return None;
+        }
+
+        Some(ScatterSample {
+            weight: self.evaluate(intersection, wo, &direction).div(pdf),
+            direction,
+            pdf,
+            specular: false,
+        })
+    }
+
+    fn ambient(&self, intersection: &Intersection<T>) -> Vec3<T> {
+        let through = T::one() - self.fresnel(T::one());
+
+        self.base
+            .ambient(&self.base.shading_intersection(intersection))
+            .mul(through * through)
+    }
+
+    // the base's own shading, dimmed by the coat, with the coat's highlights on top
+    fn shade(
+        &self,
+        intersection: &Intersection<T>,
+        lights: &[IncidentLight<T>],
+        viewpoint: &Vec3<T>,
+    ) -> Vec3<T> {
+        let wo = viewpoint.sub(&intersection.point).normalize();
+        let through = T::one() - self.fresnel(intersection.normal.dot(&wo).max(T::zero()));
+
+        self.base
+            .shade(
+                &self.base.shading_intersection(intersection),
+                lights,
+                viewpoint,
+            )
+            .mul(through * through)
+            .add(&self.highlights(intersection, lights, &wo))
+    }
+
+    // the coat's highlights count as specular, along with those of the base
+    fn shade_parts(
+        &self,
+        intersection: &Intersection<T>,
+        lights: &[IncidentLight<T>],
+        viewpoint: &Vec3<T>,
+    ) -> (Vec3<T>, Vec3<T>) {
+        let wo = viewpoint.sub(&intersection.point).normalize();
+        let through = T::one() - self.fresnel(intersection.normal.dot(&wo).max(T::zero()));
+
+        let (diffuse, specular) = self.base.shade_parts(
+            &self.base.shading_intersection(intersection),
+            lights,
+            viewpoint,
+        );
+
+        (
+            diffuse.mul(through * through),
+            specular
+                .mul(through * through)
+                .add(&self.highlights(intersection, lights, &wo)),
+        )
+    }
+
+    fn is_reflective(&self) -> bool {
+        true
+    }
+
+    // mirror reflections of the coat fade out with its roughness, like they do for PbrMaterial
+    fn reflection_coefficient(&self, intersection: &Intersection<T>, wo: &Vec3<T>) -> T {
+        let fresnel = self.fresnel(intersection.normal.dot(wo).max(T::zero()));
+        let smoothness = T::one() - self.roughness;
+        let base = self
+            .base
+            .reflection_coefficient(&self.base.shading_intersection(intersection), wo);
+
+        fresnel * smoothness * smoothness + (T::one() - fresnel).powi(2) * base
+    }
+
+    // roughness of the coat and base, weighted like the reflections they blur
+    fn reflection_roughness(&self, intersection: &Intersection<T>, wo: &Vec3<T>) -> T {
+        let fresnel = self.fresnel(intersection.normal.dot(wo).max(T::zero()));
+        let smoothness = T::one() - self.roughness;
+        let shading = self.base.shading_intersection(intersection);
+
+        let coat_weight = fresnel * smoothness * smoothness;
+        let base_weight =
+            (T::one() - fresnel).powi(2) * self.base.reflection_coefficient(&shading, wo);
+        if coat_weight + base_weight <= T::zero() {
+            return T::zero();
+        }
+
+        (self.roughness * coat_weight + self.base.reflection_roughness(&shading, wo) * base_weight)
+            / (coat_weight + base_weight)
+    }
+
+    fn refraction(
+        &self,
+        intersection: &Intersection<T>,
+        wo: &Vec3<T>,
+    ) -> Option<(Vec3<T>, Vec3<T>)> {
+        let through = T::one() - self.fresnel(intersection.normal.dot(wo).abs());
+
+        self.base
+            .refraction(&self.base.shading_intersection(intersection), wo)
+            .map(|(direction, weight)| (direction, weight.mul(through)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::Color;
+    use crate::sampler::RandomSampler;
+    use crate::scene::light::PointLight;
+    use crate::scene::visible::bsdf::Lambertian;
+    use crate::scene::visible::pbr::PbrMaterial;
+    use crate::scene::visible::specular::Conductor;
+
+    fn surface() -> Intersection<f64> {
+        Intersection::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0))
+    }
+
+    fn gray(value: f64) -> Lambertian<f64> {
+        Lambertian::new(Color::new(value, value, value).unwrap())
+    }
+
+    #[test]
+    fn mix_blends_linearly() {
+        let mix = Mix::new(gray(0.2), gray(0.6), 0.25);
+        let wo = Vec3::new(0.0, 0.6, 0.8);
+        let wi = Vec3::new(0.8, 0.0, 0.6);
+
+        let expected = gray(0.3).evaluate(&surface(), &wo, &wi);
+
+        assert!(mix.evaluate(&surface(), &wo, &wi).sub(&expected).mag_sqrd() < 1e-20);
+    }
+
+    #[test]
+    fn mix_shades_with_blended_parts() {
+        let mix = Mix::new(gray(0.2), gray(0.6), 0.25);
+        let light = PointLight::new(Color::new(1.0, 1.0, 1.0).unwrap(), Vec3::new(3.0, 0.0, 4.0));
+        let lights = [IncidentLight::new(&light)];
+        let viewpoint = Vec3::new(0.0, 0.0, 5.0);
+
+        let expected = gray(0.3).shade(&surface(), &lights, &viewpoint);
+
+        assert!(
+            mix.shade(&surface(), &lights, &viewpoint)
+                .sub(&expected)
+                .mag_sqrd()
+                < 1e-20
+        );
+    }
+
+    #[test]
+    fn additive_sums_layers() {
+        let additive = Additive::new(gray(0.2), gray(0.3));
+        let up = Vec3::new(0.0, 0.0, 1.0);
+
+        let expected = gray(0.5).evaluate(&surface(), &up, &up);
+
+        assert!(
+            additive
+                .evaluate(&surface(), &up, &up)
+                .sub(&expected)
+                .mag_sqrd()
+                < 1e-20
+        );
+    }
+
+    #[test]
+    fn mixed_specular_samples_keep_their_share() {
+        let mix = Mix::new(
+            gray(0.5),
+            Conductor::new(Color::new(1.0, 1.0, 1.0).unwrap()),
+            0.3,
+        );
+        let wo = Vec3::new(0.0, 0.6, 0.8);
+        let mut sampler = RandomSampler::new(2);
+
+        for _ in 0..50 {
+            let sample = mix.sample(&surface(), &wo, &mut sampler).unwrap();
+
+            if sample.specular {
+                assert!((sample.weight.x - 1.0).abs() < 1e-12);
+                assert!((sample.pdf - 0.3).abs() < 1e-12);
+            } else {
+                let pdf = mix.pdf(&surface(), &wo, &sample.direction);
+                assert!((sample.pdf - pdf).abs() < 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn clear_coat_conserves_energy() {
+        // a white base reflecting everything, so the coat can only redistribute light
+        let coat = ClearCoat::new(gray(1.0), 0.1);
+        let wo = Vec3::new(0.6, 0.0, 0.8);
+        let mut sampler = RandomSampler::new(4);
+
+        let count = 20000;
+        let mut total = 0.0;
+        for _ in 0..count {
+            if let Some(sample) = coat.sample(&surface(), &wo, &mut sampler) {
+                total += sample.weight.x;
+            }
+        }
+        let albedo = total / count as f64;
+
+        assert!(albedo < 1.02, "albedo {}", albedo);
+        assert!(albedo > 0.85, "albedo {}", albedo);
+    }
+
+    #[test]
+    fn clear_coat_dims_base_head_on() {
+        let base = PbrMaterial::new(Color::new(0.8, 0.1, 0.1).unwrap(), 0.0, 0.5);
+        let coat = ClearCoat::new(base.clone(), 0.1);
+        let up = Vec3::new(0.0, 0.0, 1.0);
+        let wi = Vec3::new(0.0, 0.8, 0.6);
+
+        // off the coat's highlight, only the base shows, through the coat twice
+        let expected =
+            base.evaluate(&surface(), &up, &wi)
+                .mul(coat.attenuation(&surface(), &up, &wi));
+
+        assert!(
+            coat.evaluate(&surface(), &up, &wi)
+                .sub(&expected)
+                .mag_sqrd()
+                < 1e-8
+        );
+        assert!(coat.attenuation(&surface(), &up, &wi) < 0.96 * 0.96);
+    }
+
+    #[test]
+    fn clear_coat_highlights_are_specular() {
+        let coat = ClearCoat::new(gray(0.5), 0.1);
+        let light = PointLight::new(Color::new(1.0, 1.0, 1.0).unwrap(), Vec3::new(0.0, 0.0, 5.0));
+        let lights = [IncidentLight::new(&light)];
+        let viewpoint = Vec3::new(0.0, 0.0, 5.0);
+
+        // the lambertian base has no highlights of its own
+        let (diffuse, specular) = coat.shade_parts(&surface(), &lights, &viewpoint);
+        let shade = coat.shade(&surface(), &lights, &viewpoint);
+        assert!(specular.x > 0.0);
+        assert!(diffuse.x < gray(0.5).shade(&surface(), &lights, &viewpoint).x);
+        assert!(diffuse.add(&specular).sub(&shade).mag_sqrd() < 1e-20);
+    }
+}
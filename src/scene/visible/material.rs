@@ -1,5 +1,9 @@
 use crate::common::{Color, Intersection, Vec3, VertexFormat};
-use crate::scene::light::LightSource;
+use crate::sampler::{cone_direction, cosine_hemisphere, reflect, to_world, Sampler};
+use crate::scene::light::IncidentLight;
+use crate::scene::visible::bsdf::{schlick, Bsdf, ScatterSample};
+use crate::scene::visible::texture::{luminance, Texture};
+use std::rc::Rc;
 
 #[derive(Debug, Clone)]
 pub struct Material<T: VertexFormat> {
@@ -11,6 +15,26 @@ pub struct Material<T: VertexFormat> {
     phong_exponent: T,
     specular_color: Color<T>,
     reflective_coefficient: T,
+    reflection_roughness: T,
+    // textures replace the constant color or coefficient they're set for
+    diffuse_texture: Option<Rc<dyn Texture<T>>>,
+    ambient_texture: Option<Rc<dyn Texture<T>>>,
+    specular_texture: Option<Rc<dyn Texture<T>>>,
+    reflectivity_texture: Option<Rc<dyn Texture<T>>>,
+    diffuse_coefficient_texture: Option<Rc<dyn Texture<T>>>,
+    specular_coefficient_texture: Option<Rc<dyn Texture<T>>>,
+    ambient_coefficient_texture: Option<Rc<dyn Texture<T>>>,
+    phong_exponent_texture: Option<Rc<dyn Texture<T>>>,
+    // perturb the shading normal. Normal maps hold tangent space normals, with each component
+    // mapped from [-1, 1] to [0, 1]. Bump maps hold heights, as luminance.
+    normal_map: Option<Rc<dyn Texture<T>>>,
+    bump_map: Option<Rc<dyn Texture<T>>>,
+    bump_strength: T,
+    // exponents of the anisotropic specular lobe along the tangent and bitangent, replacing the
+    // phong exponent
+    anisotropic_exponents: Option<(T, T)>,
+    // tangent space directions the tangent is turned towards, mapped like normal maps
+    tangent_map: Option<Rc<dyn Texture<T>>>,
 }
 
 impl<T: VertexFormat> Material<T> {
@@ -33,20 +57,113 @@ impl<T: VertexFormat> Material<T> {
             phong_exponent,
             specular_color,
             reflective_coefficient,
+            reflection_roughness: T::zero(),
+            diffuse_texture: None,
+            ambient_texture: None,
+            specular_texture: None,
+            reflectivity_texture: None,
+            diffuse_coefficient_texture: None,
+            specular_coefficient_texture: None,
+            ambient_coefficient_texture: None,
+            phong_exponent_texture: None,
+            normal_map: None,
+            bump_map: None,
+            bump_strength: T::one(),
+            anisotropic_exponents: None,
+            tangent_map: None,
         }
     }
 
-    pub fn ambient(&self) -> Vec3<T> {
-        self.ambient_color
-            .color_vector()
-            .scalar_mul(&self.diffuse_color.color_vector())
-            .mul(self.ambient_coefficient)
+    pub fn set_diffuse_texture(&mut self, texture: Rc<dyn Texture<T>>) {
+        self.diffuse_texture = Some(texture);
+    }
+
+    pub fn set_ambient_texture(&mut self, texture: Rc<dyn Texture<T>>) {
+        self.ambient_texture = Some(texture);
+    }
+
+    pub fn set_specular_texture(&mut self, texture: Rc<dyn Texture<T>>) {
+        self.specular_texture = Some(texture);
+    }
+
+    // the texture's luminance is used as the reflective coefficient
+    pub fn set_reflectivity_texture(&mut self, texture: Rc<dyn Texture<T>>) {
+        self.reflectivity_texture = Some(texture);
+    }
+
+    // the texture's luminance is used as the diffuse coefficient
+    pub fn set_diffuse_coefficient_texture(&mut self, texture: Rc<dyn Texture<T>>) {
+        self.diffuse_coefficient_texture = Some(texture);
+    }
+
+    // the texture's luminance is used as the specular coefficient
+    pub fn set_specular_coefficient_texture(&mut self, texture: Rc<dyn Texture<T>>) {
+        self.specular_coefficient_texture = Some(texture);
+    }
+
+    // the texture's luminance is used as the ambient coefficient
+    pub fn set_ambient_coefficient_texture(&mut self, texture: Rc<dyn Texture<T>>) {
+        self.ambient_coefficient_texture = Some(texture);
+    }
+
+    // The texture's luminance scales the phong exponent, since textures don't go past one.
+    // Darker areas get broader, duller highlights.
+    pub fn set_phong_exponent_texture(&mut self, texture: Rc<dyn Texture<T>>) {
+        self.phong_exponent_texture = Some(texture);
+    }
+
+    // blurs the reflection, from a perfect mirror at 0 to fully diffuse at 1
+    pub fn set_reflection_roughness(&mut self, roughness: T) {
+        self.reflection_roughness = roughness;
+    }
+
+    pub fn set_normal_map(&mut self, texture: Rc<dyn Texture<T>>) {
+        self.normal_map = Some(texture);
+    }
+
+    // strength scales the height differences of the bump map
+    pub fn set_bump_map(&mut self, texture: Rc<dyn Texture<T>>, strength: T) {
+        self.bump_map = Some(texture);
+        self.bump_strength = strength;
+    }
+
+    // Replaces the Phong highlights with Ashikhmin-Shirley ones, which have separate exponents
+    // along the tangent and the bitangent. The lower exponent stretches highlights along its
+    // direction, so brushed metal gets a low exponent across the brushing.
+    pub fn set_anisotropic_exponents(&mut self, tangent_exponent: T, bitangent_exponent: T) {
+        self.anisotropic_exponents = Some((tangent_exponent, bitangent_exponent));
+    }
+
+    // turns the tangent, and with it anisotropic highlights, like for circular brushing
+    pub fn set_tangent_map(&mut self, texture: Rc<dyn Texture<T>>) {
+        self.tangent_map = Some(texture);
+    }
+
+    fn diffuse_color(&self, intersection: &Intersection<T>) -> Vec3<T> {
+        match &self.diffuse_texture {
+            Some(texture) => texture.value(intersection),
+            None => self.diffuse_color.color_vector().clone(),
+        }
+    }
+
+    fn ambient_color(&self, intersection: &Intersection<T>) -> Vec3<T> {
+        match &self.ambient_texture {
+            Some(texture) => texture.value(intersection),
+            None => self.ambient_color.color_vector().clone(),
+        }
+    }
+
+    fn specular_color(&self, intersection: &Intersection<T>) -> Vec3<T> {
+        match &self.specular_texture {
+            Some(texture) => texture.value(intersection),
+            None => self.specular_color.color_vector().clone(),
+        }
     }
 
     pub fn diffuse(
         &self,
         intersection: &Intersection<T>,
-        light_source: &Box<dyn LightSource<T>>,
+        light_source: &IncidentLight<T>,
     ) -> Vec3<T> {
         // normalized vector from intersection point to light source
         let l = light_source.light_vector(&intersection.point);
@@ -54,17 +171,16 @@ impl<T: VertexFormat> Material<T> {
 
         // diffuse color calculation
         light_source
-            .color()
-            .color_vector()
-            .scalar_mul(self.diffuse_color.color_vector())
-            .mul(self.diffuse_coefficient)
+            .color_at(&intersection.point)
+            .scalar_mul(&self.diffuse_color(intersection))
+            .mul(self.diffuse_coefficient(intersection))
             .mul(angle)
     }
 
     pub fn specular(
         &self,
         intersection: &Intersection<T>,
-        light_source: &Box<dyn LightSource<T>>,
+        light_source: &IncidentLight<T>,
         viewpoint: &Vec3<T>,
     ) -> Vec3<T> {
         let l = light_source.light_vector(&intersection.point);
@@ -77,26 +193,443 @@ impl<T: VertexFormat> Material<T> {
 
         let v = viewpoint.sub(&intersection.point).normalize();
 
-        self.specular_color
-            .color_vector()
-            .scalar_mul(light_source.color().color_vector())
-            .mul(self.specular_coefficient)
-            .mul(T::zero().max(v.dot(&r)).powf(self.phong_exponent))
+        if self.anisotropic_exponents.is_some() {
+            // the brdf times the cosine, for lights whose color is their irradiance over pi
+            let cos_l = intersection.normal.dot(&l);
+            if cos_l <= T::zero() || intersection.normal.dot(&v) <= T::zero() {
+                return Vec3::new(T::zero(), T::zero(), T::zero());
+            }
+
+            let pi = T::from(std::f64::consts::PI).unwrap();
+            return self
+                .specular_lobe(intersection, &v, &l)
+                .scalar_mul(&light_source.color_at(&intersection.point))
+                .mul(cos_l * pi);
+        }
+
+        self.specular_color(intersection)
+            .scalar_mul(&light_source.color_at(&intersection.point))
+            .mul(self.specular_coefficient(intersection))
+            .mul(
+                T::zero()
+                    .max(v.dot(&r))
+                    .powf(self.phong_exponent(intersection)),
+            )
+    }
+
+    fn mirror_coefficient(&self, intersection: &Intersection<T>) -> T {
+        match &self.reflectivity_texture {
+            Some(texture) => luminance(&texture.value(intersection)),
+            None => self.reflective_coefficient,
+        }
+    }
+
+    fn diffuse_coefficient(&self, intersection: &Intersection<T>) -> T {
+        match &self.diffuse_coefficient_texture {
+            Some(texture) => luminance(&texture.value(intersection)),
+            None => self.diffuse_coefficient,
+        }
+    }
+
+    fn specular_coefficient(&self, intersection: &Intersection<T>) -> T {
+        match &self.specular_coefficient_texture {
+            Some(texture) => luminance(&texture.value(intersection)),
+            None => self.specular_coefficient,
+        }
+    }
+
+    fn ambient_coefficient(&self, intersection: &Intersection<T>) -> T {
+        match &self.ambient_coefficient_texture {
+            Some(texture) => luminance(&texture.value(intersection)),
+            None => self.ambient_coefficient,
+        }
+    }
+
+    fn phong_exponent(&self, intersection: &Intersection<T>) -> T {
+        match &self.phong_exponent_texture {
+            Some(texture) => self.phong_exponent * luminance(&texture.value(intersection)),
+            None => self.phong_exponent,
+        }
+    }
+
+    // probability of sampling the mirror, and of sampling the diffuse lobe otherwise
+    fn lobe_probabilities(&self, intersection: &Intersection<T>) -> (T, T) {
+        let mirror = self
+            .mirror_coefficient(intersection)
+            .min(T::from(0.9).unwrap());
+
+        let diffuse =
+            luminance(&self.diffuse_color(intersection)) * self.diffuse_coefficient(intersection);
+        let specular =
+            luminance(&self.specular_color(intersection)) * self.specular_coefficient(intersection);
+        let diffuse = if diffuse + specular > T::zero() {
+            diffuse / (diffuse + specular)
+        } else {
+            T::one()
+        };
+
+        (mirror, diffuse)
     }
 
-    pub fn is_reflective(&self) -> bool {
-        self.reflective_coefficient > T::zero()
+    // specular brdf, normalized Phong or Ashikhmin-Shirley
+    fn specular_lobe(&self, intersection: &Intersection<T>, wo: &Vec3<T>, wi: &Vec3<T>) -> Vec3<T> {
+        let pi = T::from(std::f64::consts::PI).unwrap();
+        let reflectance = self
+            .specular_color(intersection)
+            .mul(self.specular_coefficient(intersection));
+
+        match self.anisotropic_exponents {
+            Some((nu, nv)) => {
+                let normal = &intersection.normal;
+                let half = wo.add(wi).normalize();
+                let cos_h = normal.dot(&half).max(T::zero());
+                let exponent = anisotropic_exponent(intersection, &half, (nu, nv));
+
+                let lobe = ((nu + T::one()) * (nv + T::one())).sqrt()
+                    / (T::from(8.0).unwrap() * pi)
+                    * cos_h.powf(exponent)
+                    / (wi.dot(&half) * normal.dot(wo).max(normal.dot(wi)));
+
+                schlick(&reflectance, wi.dot(&half)).mul(lobe)
+            }
+            None => {
+                let reflected = reflect(wo, &intersection.normal);
+                let exponent = self.phong_exponent(intersection);
+                let lobe = (exponent + T::from(2.0).unwrap()) / (T::from(2.0).unwrap() * pi)
+                    * T::zero().max(reflected.dot(wi)).powf(exponent);
+
+                reflectance.mul(lobe)
+            }
+        }
     }
 
-    pub fn reflective_coefficient(&self) -> &T {
-        &self.reflective_coefficient
+    fn specular_lobe_pdf(&self, intersection: &Intersection<T>, wo: &Vec3<T>, wi: &Vec3<T>) -> T {
+        let pi = T::from(std::f64::consts::PI).unwrap();
+
+        match self.anisotropic_exponents {
+            Some((nu, nv)) => {
+                // density of the half vector, converted to a density of the reflected direction
+                let half = wo.add(wi).normalize();
+                let cos_h = intersection.normal.dot(&half).max(T::zero());
+                let exponent = anisotropic_exponent(intersection, &half, (nu, nv));
+                let half_pdf = ((nu + T::one()) * (nv + T::one())).sqrt()
+                    / (T::from(2.0).unwrap() * pi)
+                    * cos_h.powf(exponent);
+
+                half_pdf / (T::from(4.0).unwrap() * wo.dot(&half))
+            }
+            None => {
+                let reflected = reflect(wo, &intersection.normal);
+                let exponent = self.phong_exponent(intersection);
+
+                (exponent + T::one()) / (T::from(2.0).unwrap() * pi)
+                    * T::zero().max(reflected.dot(wi)).powf(exponent)
+            }
+        }
+    }
+
+    fn sample_specular_lobe(
+        &self,
+        intersection: &Intersection<T>,
+        wo: &Vec3<T>,
+        sample: (T, T),
+    ) -> Vec3<T> {
+        match self.anisotropic_exponents {
+            Some(exponents) => {
+                let half = to_world(
+                    &anisotropic_half_vector(exponents, sample),
+                    &intersection.tangent,
+                    &intersection.bitangent,
+                    &intersection.normal,
+                );
+                reflect(wo, &half)
+            }
+            None => {
+                let reflected = reflect(wo, &intersection.normal);
+                let cos_theta = sample
+                    .0
+                    .powf(T::one() / (self.phong_exponent(intersection) + T::one()));
+                let (x, y) = reflected.orthonormal_basis();
+                to_world(&cone_direction(cos_theta, sample.1), &x, &y, &reflected)
+            }
+        }
+    }
+}
+
+// Exponent of the Ashikhmin-Shirley lobe for a half vector, blending the exponents along the
+// tangent and bitangent by the direction the half vector leans in
+fn anisotropic_exponent<T: VertexFormat>(
+    intersection: &Intersection<T>,
+    half: &Vec3<T>,
+    exponents: (T, T),
+) -> T {
+    let (nu, nv) = exponents;
+    let hu = half.dot(&intersection.tangent);
+    let hv = half.dot(&intersection.bitangent);
+    let sin2 = hu * hu + hv * hv;
+    if sin2 <= T::zero() {
+        return nu;
+    }
+
+    (nu * hu * hu + nv * hv * hv) / sin2
+}
+
+// Half vector drawn from the Ashikhmin-Shirley distribution, in the frame around +z with the
+// exponents along x and y
+fn anisotropic_half_vector<T: VertexFormat>(exponents: (T, T), sample: (T, T)) -> Vec3<T> {
+    let (nu, nv) = exponents;
+    let (u1, u2) = sample;
+    let pi = T::from(std::f64::consts::PI).unwrap();
+    let half_pi = pi / T::from(2.0).unwrap();
+    let quarter = T::from(0.25).unwrap();
+
+    // azimuth within the first quadrant, mirrored into the quadrant u1 falls in
+    let azimuth = |u: T| {
+        let ratio = ((nu + T::one()) / (nv + T::one())).sqrt();
+        (ratio * (half_pi * u).tan()).atan()
+    };
+    let four = T::from(4.0).unwrap();
+    let phi = if u1 < quarter {
+        azimuth(four * u1)
+    } else if u1 < T::from(0.5).unwrap() {
+        pi - azimuth(four * (T::from(0.5).unwrap() - u1))
+    } else if u1 < T::from(0.75).unwrap() {
+        pi + azimuth(four * (u1 - T::from(0.5).unwrap()))
+    } else {
+        T::from(2.0).unwrap() * pi - azimuth(four * (T::one() - u1))
+    };
+
+    let (sin_phi, cos_phi) = phi.sin_cos();
+    let exponent = nu * cos_phi * cos_phi + nv * sin_phi * sin_phi;
+    let cos_theta = (T::one() - u2).powf(T::one() / (exponent + T::one()));
+    let sin_theta = (T::one() - cos_theta * cos_theta).max(T::zero()).sqrt();
+
+    Vec3::new(sin_theta * cos_phi, sin_theta * sin_phi, cos_theta)
+}
+
+impl<T: VertexFormat> Bsdf<T> for Material<T> {
+    // Copy of the intersection with the normal perturbed by the normal and bump maps, for use in
+    // the lighting calculations. Shadow and reflection rays keep the geometric normal.
+    fn shading_intersection(&self, intersection: &Intersection<T>) -> Intersection<T> {
+        let mut shading = intersection.clone();
+
+        if let Some(normal_map) = &self.normal_map {
+            let mapped = normal_map
+                .value(intersection)
+                .mul(T::from(2.0).unwrap())
+                .sub(&Vec3::new(T::one(), T::one(), T::one()));
+
+            shading.normal = shading
+                .tangent
+                .mul(mapped.x)
+                .add(&shading.bitangent.mul(mapped.y))
+                .add(&shading.normal.mul(mapped.z))
+                .normalize();
+        }
+
+        if let Some(bump_map) = &self.bump_map {
+            // forward differences of the height, moving both the texture coordinates and the
+            // point, so bump maps work for uv and position based textures alike
+            let delta = T::from(1e-3).unwrap();
+            let height = |du: T, dv: T| {
+                let mut offset = intersection.clone();
+                offset.uv = (intersection.uv.0 + du, intersection.uv.1 + dv);
+                offset.point = intersection
+                    .point
+                    .add(&intersection.tangent.mul(du))
+                    .add(&intersection.bitangent.mul(dv));
+                luminance(&bump_map.value(&offset))
+            };
+
+            let base = height(T::zero(), T::zero());
+            let slope_u = (height(delta, T::zero()) - base) / delta * self.bump_strength;
+            let slope_v = (height(T::zero(), delta) - base) / delta * self.bump_strength;
+
+            shading.normal = shading
+                .normal
+                .sub(&shading.tangent.mul(slope_u))
+                .sub(&shading.bitangent.mul(slope_v))
+                .normalize();
+        }
+
+        if let Some(tangent_map) = &self.tangent_map {
+            let mapped = tangent_map
+                .value(intersection)
+                .mul(T::from(2.0).unwrap())
+                .sub(&Vec3::new(T::one(), T::one(), T::one()));
+
+            let tangent = shading
+                .tangent
+                .mul(mapped.x)
+                .add(&shading.bitangent.mul(mapped.y));
+            let bitangent = shading.normal.cross(&tangent);
+            shading.tangent = tangent;
+            shading.bitangent = bitangent;
+        }
+
+        let (tangent, bitangent) = (shading.tangent.clone(), shading.bitangent.clone());
+        shading.set_tangent_frame(&tangent, &bitangent);
+
+        shading
+    }
+
+    fn ambient(&self, intersection: &Intersection<T>) -> Vec3<T> {
+        self.ambient_color(intersection)
+            .scalar_mul(&self.diffuse_color(intersection))
+            .mul(self.ambient_coefficient(intersection))
+    }
+
+    // the classic Phong model: ambient, plus diffuse and specular highlights for every light
+    fn shade(
+        &self,
+        intersection: &Intersection<T>,
+        lights: &[IncidentLight<T>],
+        viewpoint: &Vec3<T>,
+    ) -> Vec3<T> {
+        let mut color = self.ambient(intersection);
+
+        for light in lights {
+            color.mut_add(&self.diffuse(intersection, light));
+            color.mut_add(&self.specular(intersection, light, viewpoint));
+        }
+
+        color
+    }
+
+    fn shade_parts(
+        &self,
+        intersection: &Intersection<T>,
+        lights: &[IncidentLight<T>],
+        viewpoint: &Vec3<T>,
+    ) -> (Vec3<T>, Vec3<T>) {
+        let mut diffuse = Vec3::new(T::zero(), T::zero(), T::zero());
+        let mut specular = Vec3::new(T::zero(), T::zero(), T::zero());
+
+        for light in lights {
+            diffuse.mut_add(&self.diffuse(intersection, light));
+            specular.mut_add(&self.specular(intersection, light, viewpoint));
+        }
+
+        (diffuse, specular)
+    }
+
+    // the diffuse color, since the highlights only show towards the lights
+    fn reflectance(&self, intersection: &Intersection<T>, _wo: &Vec3<T>) -> Vec3<T> {
+        self.diffuse_color(intersection)
+    }
+
+    fn is_reflective(&self) -> bool {
+        self.reflective_coefficient > T::zero() || self.reflectivity_texture.is_some()
+    }
+
+    fn reflection_coefficient(&self, intersection: &Intersection<T>, _wo: &Vec3<T>) -> T {
+        self.mirror_coefficient(intersection)
+    }
+
+    fn reflection_roughness(&self, _intersection: &Intersection<T>, _wo: &Vec3<T>) -> T {
+        self.reflection_roughness
+    }
+
+    // The sampled integrators treat the material as a normalized Phong brdf, with a perfect
+    // mirror on top that is weighted by the reflective coefficient.
+
+    fn evaluate(&self, intersection: &Intersection<T>, wo: &Vec3<T>, wi: &Vec3<T>) -> Vec3<T> {
+        let normal = &intersection.normal;
+        let cos_i = normal.dot(wi);
+        if cos_i <= T::zero() || normal.dot(wo) <= T::zero() {
+            return Vec3::new(T::zero(), T::zero(), T::zero());
+        }
+
+        let pi = T::from(std::f64::consts::PI).unwrap();
+        let diffuse = self
+            .diffuse_color(intersection)
+            .mul(self.diffuse_coefficient(intersection) / pi);
+
+        let specular = self.specular_lobe(intersection, wo, wi);
+
+        diffuse.add(&specular).mul(cos_i)
+    }
+
+    fn pdf(&self, intersection: &Intersection<T>, wo: &Vec3<T>, wi: &Vec3<T>) -> T {
+        let cos_i = intersection.normal.dot(wi);
+        if cos_i <= T::zero() {
+            return T::zero();
+        }
+
+        let (mirror, diffuse) = self.lobe_probabilities(intersection);
+        let pi = T::from(std::f64::consts::PI).unwrap();
+        let specular = self.specular_lobe_pdf(intersection, wo, wi);
+
+        (T::one() - mirror) * (diffuse * cos_i / pi + (T::one() - diffuse) * specular)
+    }
+
+    fn sample(
+        &self,
+        intersection: &Intersection<T>,
+        wo: &Vec3<T>,
+        sampler: &mut dyn Sampler<T>,
+    ) -> Option<ScatterSample<T>> {
+        let normal = &intersection.normal;
+        let (mirror, diffuse) = self.lobe_probabilities(intersection);
+        let reflected = reflect(wo, normal);
+
+        if sampler.next_1d() < mirror {
+            let weight = self.mirror_coefficient(intersection) / mirror;
+
+            return Some(ScatterSample {
+                direction: reflected,
+                weight: Vec3::new(weight, weight, weight),
+                pdf: mirror,
+                specular: true,
+            });
+        }
+
+        let direction = if sampler.next_1d() < diffuse {
+            let local = cosine_hemisphere(sampler.next_2d());
+            to_world(
+                &local,
+                &intersection.tangent,
+                &intersection.bitangent,
+                normal,
+            )
+        } else {
+            self.sample_specular_lobe(intersection, wo, sampler.next_2d())
+        };
+
+        let pdf = self.pdf(intersection, wo, &direction);
+        if pdf <= T::zero() {
+            return None;
+        }
+
+        Some(ScatterSample {
+            weight: self.evaluate(intersection, wo, &direction).div(pdf),
+            direction,
+            pdf,
+            specular: false,
+        })
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::sampler::RandomSampler;
     use crate::scene::light::PointLight;
+    use crate::scene::visible::procedural::{Gradient, Mapping};
+    use crate::scene::visible::texture::ConstantTexture;
+
+    fn plain_material() -> Material<f64> {
+        Material::new(
+            0.3,
+            Color::new(1.0, 0.0, 0.0).unwrap(),
+            0.3,
+            Color::new(1.0, 0.1, 0.1).unwrap(),
+            32.0,
+            0.1,
+            Color::new(0.2, 0.2, 0.2).unwrap(),
+            0.0,
+        )
+    }
 
     #[test]
     fn correct_ambient_light_calculation() {
@@ -111,9 +644,14 @@ mod tests {
             0.0,
         );
 
+        let intersection = Intersection::new(Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0));
+
         let expected_diffuse = Color::new(0.2 * 0.1, 0.0, 0.0).unwrap();
 
-        assert_eq!(expected_diffuse.color_vector(), &material.ambient());
+        assert_eq!(
+            expected_diffuse.color_vector(),
+            &material.ambient(&intersection)
+        );
     }
 
     #[test]
@@ -129,15 +667,9 @@ mod tests {
             0.0,
         );
 
-        let intersection = Intersection {
-            point: Vec3::new(0.0, 0.0, 1.0),
-            normal: Vec3::new(0.0, 0.0, -1.0),
-        };
+        let intersection = Intersection::new(Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0));
 
-        let light: Box<dyn LightSource<f64>> = Box::new(PointLight::new(
-            Color::new(0.5, 0.5, 0.5).unwrap(),
-            Vec3::new(0.0, 0.0, 0.0),
-        ));
+        let light = PointLight::new(Color::new(0.5, 0.5, 0.5).unwrap(), Vec3::new(0.0, 0.0, 0.0));
 
         // l dot n = 1
         // diffuse color * light color: (1.0, 0.0, 0.0) * (0.5, 0.5, 0.5) = (0.5, 0.0, 0.0)
@@ -147,7 +679,7 @@ mod tests {
 
         assert_eq!(
             expected_diffuse.color_vector(),
-            &material.diffuse(&intersection, &light)
+            &material.diffuse(&intersection, &IncidentLight::new(&light))
         );
     }
 
@@ -164,15 +696,9 @@ mod tests {
             0.0,
         );
 
-        let intersection = Intersection {
-            point: Vec3::new(0.0, 0.0, 1.0),
-            normal: Vec3::new(0.0, 0.0, -1.0),
-        };
+        let intersection = Intersection::new(Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0));
 
-        let light: Box<dyn LightSource<f64>> = Box::new(PointLight::new(
-            Color::new(0.5, 0.5, 0.5).unwrap(),
-            Vec3::new(0.0, 0.0, 0.0),
-        ));
+        let light = PointLight::new(Color::new(0.5, 0.5, 0.5).unwrap(), Vec3::new(0.0, 0.0, 0.0));
 
         let viewpoint = Vec3::new(0.0, 0.0, 0.0);
 
@@ -188,7 +714,150 @@ mod tests {
 
         assert_eq!(
             expected_specular.color_vector(),
-            &material.specular(&intersection, &light, &viewpoint)
+            &material.specular(&intersection, &IncidentLight::new(&light), &viewpoint)
         )
     }
+
+    #[test]
+    fn textures_drive_coefficients() {
+        let mut material = plain_material();
+        let grey = |value: f64| {
+            Rc::new(ConstantTexture::new(
+                Color::new(value, value, value).unwrap(),
+            ))
+        };
+        material.set_ambient_coefficient_texture(grey(0.5));
+        material.set_diffuse_coefficient_texture(grey(0.6));
+        material.set_specular_coefficient_texture(grey(0.0));
+
+        let intersection = Intersection::new(Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let light = PointLight::new(Color::new(0.5, 0.5, 0.5).unwrap(), Vec3::new(0.0, 0.0, 0.0));
+        let viewpoint = Vec3::new(0.0, 0.0, 0.0);
+
+        assert_eq!(
+            Vec3::new(0.2 * 0.5, 0.0, 0.0),
+            material.ambient(&intersection)
+        );
+        assert_eq!(
+            Vec3::new(0.5 * 0.6, 0.0, 0.0),
+            material.diffuse(&intersection, &IncidentLight::new(&light))
+        );
+        assert_eq!(
+            Vec3::new(0.0, 0.0, 0.0),
+            material.specular(&intersection, &IncidentLight::new(&light), &viewpoint)
+        );
+
+        // a darker exponent texture lowers the peak of the highlight and widens it
+        let wo = Vec3::new(0.0, 0.0, -1.0);
+        let wi = Vec3::new(0.0, 0.75, -1.0).normalize();
+        let mut dull = plain_material();
+        dull.set_phong_exponent_texture(grey(0.25));
+        let sharp = plain_material();
+        assert!(
+            dull.specular_lobe_pdf(&intersection, &wo, &wo)
+                < sharp.specular_lobe_pdf(&intersection, &wo, &wo)
+        );
+        assert!(
+            dull.specular_lobe_pdf(&intersection, &wo, &wi)
+                > sharp.specular_lobe_pdf(&intersection, &wo, &wi)
+        );
+    }
+
+    #[test]
+    fn normal_map_in_tangent_space() {
+        let mut material = plain_material();
+        // tangent space normal (1, 0, 0), pointing along the tangent
+        material.set_normal_map(Rc::new(ConstantTexture::new(
+            Color::new(1.0, 0.5, 0.5).unwrap(),
+        )));
+
+        let intersection = Intersection::new(Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, 1.0));
+
+        let shading = material.shading_intersection(&intersection);
+
+        assert_eq!(intersection.tangent, shading.normal);
+        // the geometric normal is left alone
+        assert_eq!(Vec3::new(0.0, 0.0, 1.0), intersection.normal);
+    }
+
+    #[test]
+    fn bump_map_tilts_away_from_rising_height() {
+        let mut material = plain_material();
+        // height rising along x
+        let height = Gradient::new(
+            Rc::new(ConstantTexture::new(Color::new(0.0, 0.0, 0.0).unwrap())),
+            Rc::new(ConstantTexture::new(Color::new(1.0, 1.0, 1.0).unwrap())),
+            Vec3::new(1.0, 0.0, 0.0),
+            Mapping::Point,
+        );
+        material.set_bump_map(Rc::new(height), 1.0);
+
+        let intersection = Intersection::new(Vec3::new(0.5, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0));
+
+        let shading = material.shading_intersection(&intersection);
+        let half_sqrt2 = 0.5_f64.sqrt();
+
+        assert!((shading.normal.x + half_sqrt2).abs() < 1e-6);
+        assert!(shading.normal.y.abs() < 1e-6);
+        assert!((shading.normal.z - half_sqrt2).abs() < 1e-6);
+    }
+
+    fn brushed_material() -> Material<f64> {
+        let mut material = plain_material();
+        material.set_anisotropic_exponents(10.0, 1000.0);
+        material
+    }
+
+    fn flat_surface() -> Intersection<f64> {
+        let mut intersection =
+            Intersection::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0));
+        intersection.set_tangent_frame(&Vec3::new(1.0, 0.0, 0.0), &Vec3::new(0.0, 1.0, 0.0));
+        intersection
+    }
+
+    #[test]
+    fn anisotropic_highlight_spreads_along_low_exponent() {
+        let material = brushed_material();
+        let intersection = flat_surface();
+        let up = Vec3::new(0.0, 0.0, 1.0);
+
+        let along_tangent =
+            material.specular_lobe(&intersection, &up, &Vec3::new(0.2, 0.0, 1.0).normalize());
+        let along_bitangent =
+            material.specular_lobe(&intersection, &up, &Vec3::new(0.0, 0.2, 1.0).normalize());
+
+        assert!(along_tangent.x > 100.0 * along_bitangent.x);
+    }
+
+    #[test]
+    fn anisotropic_samples_match_pdf() {
+        let material = brushed_material();
+        let intersection = flat_surface();
+        let wo = Vec3::new(0.3, 0.4, 1.0).normalize();
+        let mut sampler = RandomSampler::new(8);
+
+        for _ in 0..200 {
+            if let Some(sample) = material.sample(&intersection, &wo, &mut sampler) {
+                let pdf = material.pdf(&intersection, &wo, &sample.direction);
+
+                assert!((sample.pdf - pdf).abs() < 1e-9 * pdf.max(1.0));
+                assert!(sample.direction.z > 0.0);
+            }
+        }
+    }
+
+    #[test]
+    fn tangent_map_turns_tangent() {
+        let mut material = plain_material();
+        // tangent space direction (0, 1, 0), along the bitangent
+        material.set_tangent_map(Rc::new(ConstantTexture::new(
+            Color::new(0.5, 1.0, 0.5).unwrap(),
+        )));
+        let intersection = flat_surface();
+
+        let shading = material.shading_intersection(&intersection);
+
+        assert!(shading.tangent.sub(&intersection.bitangent).mag_sqrd() < 1e-20);
+        assert_eq!(intersection.normal, shading.normal);
+    }
 }
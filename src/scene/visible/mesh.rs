@@ -7,6 +7,11 @@ pub struct Triangle<T: VertexFormat> {
     normal: Vec3<T>,
     // distance to origin
     d: T,
+    // texture coordinates at each vertex
+    uvs: [(T, T); 3],
+    // change of position along u and along v, for the tangent frame
+    dp_du: Vec3<T>,
+    dp_dv: Vec3<T>,
 }
 
 impl<T: VertexFormat> Triangle<T> {
@@ -20,11 +25,75 @@ impl<T: VertexFormat> Triangle<T> {
             .sub(&v1)
             .dot(&normal);
 
-        Triangle {
+        let mut triangle = Triangle {
             vertices: vec![v1, v2, v3],
             normal,
             d,
+            uvs: [
+                (T::zero(), T::zero()),
+                (T::one(), T::zero()),
+                (T::zero(), T::one()),
+            ],
+            dp_du: vector1,
+            dp_dv: vector2,
+        };
+        triangle.update_derivatives();
+        triangle
+    }
+
+    pub fn with_uvs(v1: Vec3<T>, v2: Vec3<T>, v3: Vec3<T>, uvs: [(T, T); 3]) -> Triangle<T> {
+        let mut triangle = Triangle::new(v1, v2, v3);
+        triangle.uvs = uvs;
+        triangle.update_derivatives();
+        triangle
+    }
+
+    // solves edge1 = du1 dp_du + dv1 dp_dv and edge2 = du2 dp_du + dv2 dp_dv. Keeps the edges
+    // themselves if the uvs don't span an area.
+    fn update_derivatives(&mut self) {
+        let edge1 = self.vertices[1].sub(&self.vertices[0]);
+        let edge2 = self.vertices[2].sub(&self.vertices[0]);
+        let (du1, dv1) = (self.uvs[1].0 - self.uvs[0].0, self.uvs[1].1 - self.uvs[0].1);
+        let (du2, dv2) = (self.uvs[2].0 - self.uvs[0].0, self.uvs[2].1 - self.uvs[0].1);
+
+        let determinant = du1 * dv2 - du2 * dv1;
+        if determinant.abs() < T::from(1e-12).unwrap() {
+            self.dp_du = edge1;
+            self.dp_dv = edge2;
+            return;
         }
+
+        self.dp_du = edge1.mul(dv2).sub(&edge2.mul(dv1)).div(determinant);
+        self.dp_dv = edge2.mul(du1).sub(&edge1.mul(du2)).div(determinant);
+    }
+
+    // barycentric weights of a point in the triangle's plane, for the first, second and third
+    // vertex
+    fn barycentric(&self, point: &Vec3<T>) -> (T, T, T) {
+        let edge1 = self.vertices[1].sub(&self.vertices[0]);
+        let edge2 = self.vertices[2].sub(&self.vertices[0]);
+        let p = point.sub(&self.vertices[0]);
+
+        let d11 = edge1.dot(&edge1);
+        let d12 = edge1.dot(&edge2);
+        let d22 = edge2.dot(&edge2);
+        let dp1 = p.dot(&edge1);
+        let dp2 = p.dot(&edge2);
+        let denominator = d11 * d22 - d12 * d12;
+
+        let w2 = (d22 * dp1 - d12 * dp2) / denominator;
+        let w3 = (d11 * dp2 - d12 * dp1) / denominator;
+
+        (T::one() - w2 - w3, w2, w3)
+    }
+
+    fn uv(&self, point: &Vec3<T>) -> (T, T) {
+        let (w1, w2, w3) = self.barycentric(point);
+
+        (
+            self.uvs[0].0 * w1 + self.uvs[1].0 * w2 + self.uvs[2].0 * w3,
+            self.uvs[0].1 * w1 + self.uvs[1].1 * w2 + self.uvs[2].1 * w3,
+        )
     }
 
     fn axis_to_drop(&self) -> u8 {
@@ -114,17 +183,21 @@ impl<T: VertexFormat> Intersectable<T> for Triangle<T> {
         let mut normal = self.normal.clone();
 
         // comment out if one sided planes
-        if v_d > T::zero() {
+        let front_face = v_d < T::zero();
+        if !front_face {
             normal = normal.mul(T::one().neg());
         }
 
         let intersection_point = ray.origin.add(&ray.direction.mul(t));
 
         if self.projection_intersection(&intersection_point) {
-            Some(Intersection {
-                point: intersection_point,
-                normal,
-            })
+            let mut intersection = Intersection::new(intersection_point, normal);
+            intersection.uv = self.uv(&intersection.point);
+            intersection.barycentric = Some(self.barycentric(&intersection.point));
+            intersection.set_tangent_frame(&self.dp_du, &self.dp_dv);
+            intersection.front_face = front_face;
+
+            Some(intersection)
         } else {
             None
         }
@@ -152,10 +225,12 @@ mod tests {
 
         let ray = Ray::new(Vec3::new(0.0, 0.0, 10.0), Vec3::new(0.0, 0.0, -1.0));
 
-        let exptected_intersection = Intersection {
-            point: Vec3::new(0.0, 0.0, -1.0),
-            normal: Vec3::new(0.0, 0.0, 1.0),
-        };
+        let mut exptected_intersection =
+            Intersection::new(Vec3::new(0.0, 0.0, -1.0), Vec3::new(0.0, 0.0, 1.0));
+        exptected_intersection.uv = (0.25, 0.5);
+        exptected_intersection.tangent = Vec3::new(1.0, 0.0, 0.0);
+        exptected_intersection.bitangent = Vec3::new(0.0, 1.0, 0.0);
+        exptected_intersection.barycentric = Some((0.25, 0.25, 0.5));
 
         assert_eq!(exptected_intersection, mesh.intersect(&ray).unwrap());
     }
@@ -172,4 +247,35 @@ mod tests {
 
         assert_eq!(None, mesh.intersect(&ray));
     }
+
+    #[test]
+    fn interpolated_vertex_uvs() {
+        let mesh = Triangle::with_uvs(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(2.0, 0.0, 0.0),
+            Vec3::new(0.0, 2.0, 0.0),
+            [(0.0, 1.0), (1.0, 1.0), (0.0, 0.0)],
+        );
+
+        let ray = Ray::new(Vec3::new(0.5, 1.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+
+        assert_eq!((0.25, 0.5), mesh.intersect(&ray).unwrap().uv);
+    }
+
+    #[test]
+    fn tangent_frame_follows_uvs() {
+        // v runs down the triangle, so the bitangent points along -y
+        let mesh = Triangle::with_uvs(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(2.0, 0.0, 0.0),
+            Vec3::new(0.0, 2.0, 0.0),
+            [(0.0, 1.0), (1.0, 1.0), (0.0, 0.0)],
+        );
+
+        let ray = Ray::new(Vec3::new(0.5, 0.5, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        let intersection = mesh.intersect(&ray).unwrap();
+
+        assert_eq!(Vec3::new(1.0, 0.0, 0.0), intersection.tangent);
+        assert_eq!(Vec3::new(0.0, -1.0, 0.0), intersection.bitangent);
+    }
 }
@@ -1,36 +1,150 @@
-use crate::common::{Color, Intersection, Ray, Spacial, Vec3, VertexFormat};
-use crate::scene::light::LightSource;
-use crate::scene::visible::material::Material;
+use crate::common::{Intersection, Ray, Spacial, Vec3, VertexFormat};
+use crate::scene::visible::bsdf::Bsdf;
 
+pub mod bsdf;
+pub mod cone;
+pub mod csg;
+pub mod cuboid;
+pub mod cylinder;
+pub mod layered;
 pub mod material;
 pub mod mesh;
+pub mod pbr;
+pub mod plane;
+pub mod procedural;
+pub mod sdf;
+pub mod specular;
 pub mod sphere;
+pub mod subsurface;
+pub mod texture;
+pub mod torus;
 
 pub trait Visible<T: VertexFormat>: Intersectable<T> {
-    fn calculate_lighting(
-        &self,
-        intersection: &Intersection<T>,
-        lights: &Vec<&Box<dyn LightSource<T>>>,
-        viewpoint: &Vec3<T>,
-    ) -> Color<T>;
-
-    fn reflection_coefficient(&self) -> T;
-
-    fn is_reflective(&self) -> bool;
+    // how the surface scatters light, which every integrator shades it with
+    fn bsdf(&self) -> &dyn Bsdf<T>;
 }
 
 pub trait Intersectable<T: VertexFormat>: Spacial<T> {
     fn intersect(&self, ray: &Ray<T>) -> Option<Intersection<T>>;
 }
 
+// Closed shapes, that can report every span of a ray's line that lies inside of them. Needed for
+// constructive solid geometry, which combines these spans.
+pub trait Solid<T: VertexFormat>: Intersectable<T> {
+    // spans of the line through the ray, in order along the ray and not overlapping. Spans behind
+    // the ray origin are included, so they can have negative ray parameters.
+    fn intervals(&self, ray: &Ray<T>) -> Vec<Interval<T>>;
+}
+
+// Point where a ray crosses the surface of a solid, with the surface normal pointing out of the
+// solid
+#[derive(Debug, Clone)]
+pub struct Hit<T: VertexFormat> {
+    pub t: T,
+    pub normal: Vec3<T>,
+    pub uv: (T, T),
+}
+
+// Span of a ray that lies inside a solid
+#[derive(Debug, Clone)]
+pub struct Interval<T: VertexFormat> {
+    pub entry: Hit<T>,
+    pub exit: Hit<T>,
+}
+
+// Maps a point on a surface and its outward normal to texture coordinates
+pub(crate) type UvMapping<'a, T> = &'a dyn Fn(&Vec3<T>, &Vec3<T>) -> (T, T);
+
+// Pairs up every hit of a ray's line with a closed surface into the spans inside of it. Hits at
+// distances that aren't finite can't be ordered along the line, so they are left out.
+pub(crate) fn intervals_from_hits<T: VertexFormat>(
+    ray: &Ray<T>,
+    hits: Vec<(T, Vec3<T>)>,
+    uv: UvMapping<T>,
+) -> Vec<Interval<T>> {
+    let mut hits: Vec<Hit<T>> = hits
+        .into_iter()
+        .filter(|(t, _)| t.is_finite())
+        .map(|(t, normal)| Hit {
+            t,
+            uv: uv(&ray.origin.add(&ray.direction.mul(t)), &normal),
+            normal,
+        })
+        .collect();
+    hits.sort_by(|a, b| a.t.partial_cmp(&b.t).unwrap());
+
+    hits.chunks_exact(2)
+        .map(|pair| Interval {
+            entry: pair[0].clone(),
+            exit: pair[1].clone(),
+        })
+        .collect()
+}
+
+// Picks the nearest hit in front of the ray origin, out of all the hits of a ray with a shape.
+// Hits are given as the ray parameter and the outward facing normal at that point. Open surfaces
+// are two sided, so their normal is flipped to face the ray origin. Distances that aren't finite,
+// like those of rays grazing degenerate shapes, don't count as hits.
+pub(crate) fn nearest_hit<T: VertexFormat>(
+    ray: &Ray<T>,
+    hits: &[(T, Vec3<T>)],
+    two_sided: bool,
+    uv: UvMapping<T>,
+) -> Option<Intersection<T>> {
+    let (t, normal) = hits
+        .iter()
+        .filter(|(t, _)| t.is_finite() && *t >= T::zero())
+        .min_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap())?;
+
+    let point = ray.origin.add(&ray.direction.mul(*t));
+    let uv = uv(&point, normal);
+
+    let front_face = normal.dot(&ray.direction) <= T::zero();
+    let mut normal = normal.clone();
+    if two_sided && !front_face {
+        normal = normal.mul(T::one().neg());
+    }
+
+    let mut intersection = Intersection::new(point, normal);
+    intersection.uv = uv;
+    intersection.front_face = front_face;
+
+    Some(intersection)
+}
+
+// Texture coordinates of a direction from the center of a sphere. u runs around the y axis, and v
+// from the bottom of the sphere to the top.
+pub(crate) fn spherical_uv<T: VertexFormat>(direction: &Vec3<T>) -> (T, T) {
+    let pi = T::from(std::f64::consts::PI).unwrap();
+    let y = direction.y.max(T::one().neg()).min(T::one());
+
+    let theta = (-y).acos();
+    let phi = (-direction.z).atan2(direction.x) + pi;
+
+    (phi / (T::from(2.0).unwrap() * pi), theta / pi)
+}
+
+// Angle of an offset around an axis, as a fraction of a full turn. Measured from the tangent of the
+// axis' orthonormal basis.
+pub(crate) fn angle_around<T: VertexFormat>(offset: &Vec3<T>, axis: &Vec3<T>) -> T {
+    let pi = T::from(std::f64::consts::PI).unwrap();
+    let (tangent, bitangent) = axis.orthonormal_basis();
+
+    (offset.dot(&bitangent).atan2(offset.dot(&tangent)) + pi) / (T::from(2.0).unwrap() * pi)
+}
+
+// Shape with any material
 pub struct Body<T: VertexFormat> {
     shape: Box<dyn Intersectable<T>>,
-    material: Material<T>,
+    material: Box<dyn Bsdf<T>>,
 }
 
 impl<T: VertexFormat> Body<T> {
-    pub fn new(shape: Box<dyn Intersectable<T>>, material: Material<T>) -> Body<T> {
-        Body { shape, material }
+    pub fn new<M: Bsdf<T> + 'static>(shape: Box<dyn Intersectable<T>>, material: M) -> Body<T> {
+        Body {
+            shape,
+            material: Box::new(material),
+        }
     }
 }
 
@@ -47,30 +161,7 @@ impl<T: VertexFormat> Intersectable<T> for Body<T> {
 }
 
 impl<T: VertexFormat> Visible<T> for Body<T> {
-    fn calculate_lighting(
-        &self,
-        intersection: &Intersection<T>,
-        lights: &Vec<&Box<dyn LightSource<T>>>,
-        viewpoint: &Vec3<T>,
-    ) -> Color<T> {
-        let mut color = self.material.ambient();
-
-        for light in lights {
-            let diffuse = self.material.diffuse(intersection, light);
-            let specular = self.material.specular(intersection, light, viewpoint);
-
-            color.mut_add(&diffuse);
-            color.mut_add(&specular)
-        }
-
-        Color::clipped(color)
-    }
-
-    fn reflection_coefficient(&self) -> T {
-        *self.material.reflective_coefficient()
-    }
-
-    fn is_reflective(&self) -> bool {
-        self.material.is_reflective()
+    fn bsdf(&self) -> &dyn Bsdf<T> {
+        self.material.as_ref()
     }
 }
@@ -0,0 +1,399 @@
+use crate::common::{Color, Intersection, Vec3, VertexFormat};
+use crate::sampler::{cone_direction, cosine_hemisphere, reflect, to_world, Sampler};
+use crate::scene::light::IncidentLight;
+use crate::scene::visible::bsdf::{schlick, Bsdf, ScatterSample};
+use crate::scene::visible::texture::{luminance, Texture};
+use std::rc::Rc;
+
+// reflectance at normal incidence of non metals
+const DIELECTRIC_REFLECTANCE: f64 = 0.04;
+
+// roughness is clamped to this, since a perfectly smooth GGX lobe can't be evaluated
+pub(crate) const MIN_ROUGHNESS: f64 = 0.03;
+
+fn pi<T: VertexFormat>() -> T {
+    T::from(std::f64::consts::PI).unwrap()
+}
+
+// GGX (Trowbridge-Reitz) normal distribution, for the cosine between the half vector and normal
+pub(crate) fn ggx_distribution<T: VertexFormat>(cos_h: T, alpha: T) -> T {
+    let alpha2 = alpha * alpha;
+    let denominator = cos_h * cos_h * (alpha2 - T::one()) + T::one();
+
+    alpha2 / (pi::<T>() * denominator * denominator)
+}
+
+// Smith masking term of the GGX distribution for a single direction
+pub(crate) fn smith_g1<T: VertexFormat>(cos: T, alpha: T) -> T {
+    let alpha2 = alpha * alpha;
+
+    T::from(2.0).unwrap() * cos / (cos + (alpha2 + (T::one() - alpha2) * cos * cos).sqrt())
+}
+
+// Mirrors wo about a half vector drawn from the GGX distribution
+pub(crate) fn sample_ggx_reflection<T: VertexFormat>(
+    intersection: &Intersection<T>,
+    wo: &Vec3<T>,
+    alpha: T,
+    sample: (T, T),
+) -> Vec3<T> {
+    let (u1, u2) = sample;
+    let tan2_theta = alpha * alpha * u1 / (T::one() - u1);
+    let cos_theta = T::one() / (T::one() + tan2_theta).sqrt();
+
+    let half = to_world(
+        &cone_direction(cos_theta, u2),
+        &intersection.tangent,
+        &intersection.bitangent,
+        &intersection.normal,
+    );
+    reflect(wo, &half)
+}
+
+// Density with which sample_ggx_reflection picks wi. The density of the half vector is converted
+// to a density of the reflected direction.
+pub(crate) fn ggx_reflection_pdf<T: VertexFormat>(
+    normal: &Vec3<T>,
+    wo: &Vec3<T>,
+    wi: &Vec3<T>,
+    alpha: T,
+) -> T {
+    let half = wo.add(wi).normalize();
+    let cos_h = normal.dot(&half);
+
+    ggx_distribution(cos_h, alpha) * cos_h / (T::from(4.0).unwrap() * wo.dot(&half))
+}
+
+// Metallic-roughness material, as used by Substance, Blender's principled shader and glTF. The
+// specular lobe is Cook-Torrance with a GGX distribution, Smith masking-shadowing and Schlick
+// Fresnel, over a lambertian base for non metals.
+#[derive(Debug, Clone)]
+pub struct PbrMaterial<T: VertexFormat> {
+    base_color: Color<T>,
+    metallic: T,
+    roughness: T,
+    // only used by the Whitted renderer, which has no indirect light
+    ambient_color: Color<T>,
+    // textures replace the constant they're set for. Metallic and roughness use the luminance.
+    base_color_texture: Option<Rc<dyn Texture<T>>>,
+    metallic_texture: Option<Rc<dyn Texture<T>>>,
+    roughness_texture: Option<Rc<dyn Texture<T>>>,
+}
+
+// material parameters at a point
+struct Parameters<T: VertexFormat> {
+    base_color: Vec3<T>,
+    metallic: T,
+    roughness: T,
+    // GGX width, the square of the roughness
+    alpha: T,
+}
+
+impl<T: VertexFormat> PbrMaterial<T> {
+    pub fn new(base_color: Color<T>, metallic: T, roughness: T) -> PbrMaterial<T> {
+        PbrMaterial {
+            base_color,
+            metallic,
+            roughness,
+            ambient_color: Color::new(T::zero(), T::zero(), T::zero()).unwrap(),
+            base_color_texture: None,
+            metallic_texture: None,
+            roughness_texture: None,
+        }
+    }
+
+    pub fn set_ambient_color(&mut self, color: Color<T>) {
+        self.ambient_color = color;
+    }
+
+    pub fn set_base_color_texture(&mut self, texture: Rc<dyn Texture<T>>) {
+        self.base_color_texture = Some(texture);
+    }
+
+    pub fn set_metallic_texture(&mut self, texture: Rc<dyn Texture<T>>) {
+        self.metallic_texture = Some(texture);
+    }
+
+    pub fn set_roughness_texture(&mut self, texture: Rc<dyn Texture<T>>) {
+        self.roughness_texture = Some(texture);
+    }
+
+    fn parameters(&self, intersection: &Intersection<T>) -> Parameters<T> {
+        let base_color = match &self.base_color_texture {
+            Some(texture) => texture.value(intersection),
+            None => self.base_color.color_vector().clone(),
+        };
+        let metallic = match &self.metallic_texture {
+            Some(texture) => luminance(&texture.value(intersection)),
+            None => self.metallic,
+        };
+        let roughness = match &self.roughness_texture {
+            Some(texture) => luminance(&texture.value(intersection)),
+            None => self.roughness,
+        }
+        .max(T::from(MIN_ROUGHNESS).unwrap());
+
+        Parameters {
+            base_color,
+            metallic,
+            roughness,
+            alpha: roughness * roughness,
+        }
+    }
+
+    // reflectance at normal incidence, tinted by the base color for metals
+    fn f0(parameters: &Parameters<T>) -> Vec3<T> {
+        let dielectric = T::from(DIELECTRIC_REFLECTANCE).unwrap();
+
+        Vec3::new(dielectric, dielectric, dielectric)
+            .mul(T::one() - parameters.metallic)
+            .add(&parameters.base_color.mul(parameters.metallic))
+    }
+
+    // chance of sampling the specular lobe rather than the diffuse one
+    fn specular_probability(parameters: &Parameters<T>) -> T {
+        (T::one() + parameters.metallic) / T::from(2.0).unwrap()
+    }
+
+    // the diffuse and specular parts of evaluate
+    fn lobes(
+        &self,
+        intersection: &Intersection<T>,
+        wo: &Vec3<T>,
+        wi: &Vec3<T>,
+    ) -> (Vec3<T>, Vec3<T>) {
+        let normal = &intersection.normal;
+        let cos_o = normal.dot(wo);
+        let cos_i = normal.dot(wi);
+        let black = Vec3::new(T::zero(), T::zero(), T::zero());
+        if cos_o <= T::zero() || cos_i <= T::zero() {
+            return (black.clone(), black);
+        }
+
+        let parameters = self.parameters(intersection);
+        let half = wo.add(wi).normalize();
+        let cos_h = normal.dot(&half);
+
+        let fresnel = schlick(&PbrMaterial::f0(&parameters), wo.dot(&half));
+        let distribution = ggx_distribution(cos_h, parameters.alpha);
+        let masking = smith_g1(cos_o, parameters.alpha) * smith_g1(cos_i, parameters.alpha);
+        let specular =
+            fresnel.mul(distribution * masking / (T::from(4.0).unwrap() * cos_o * cos_i));
+
+        // light that isn't reflected at the surface enters it, and only non metals scatter it
+        // back out diffusely
+        let one = Vec3::new(T::one(), T::one(), T::one());
+        let diffuse = one
+            .sub(&fresnel)
+            .scalar_mul(&parameters.base_color)
+            .mul((T::one() - parameters.metallic) / pi());
+
+        (diffuse.mul(cos_i), specular.mul(cos_i))
+    }
+}
+
+impl<T: VertexFormat> Bsdf<T> for PbrMaterial<T> {
+    fn ambient(&self, intersection: &Intersection<T>) -> Vec3<T> {
+        let parameters = self.parameters(intersection);
+
+        self.ambient_color
+            .color_vector()
+            .scalar_mul(&parameters.base_color)
+            .mul(T::one() - parameters.metallic)
+    }
+
+    // Whitted style mirror reflections only make sense for smooth surfaces, so they fade out with
+    // roughness
+    fn reflection_coefficient(&self, intersection: &Intersection<T>, _wo: &Vec3<T>) -> T {
+        let parameters = self.parameters(intersection);
+        let smoothness = T::one() - parameters.roughness;
+
+        luminance(&PbrMaterial::f0(&parameters)) * smoothness * smoothness
+    }
+
+    fn is_reflective(&self) -> bool {
+        true
+    }
+
+    fn reflection_roughness(&self, intersection: &Intersection<T>, _wo: &Vec3<T>) -> T {
+        self.parameters(intersection).roughness
+    }
+
+    // the GGX highlights are the specular part, and the light scattered back out the diffuse one
+    fn shade_parts(
+        &self,
+        intersection: &Intersection<T>,
+        lights: &[IncidentLight<T>],
+        viewpoint: &Vec3<T>,
+    ) -> (Vec3<T>, Vec3<T>) {
+        let wo = viewpoint.sub(&intersection.point).normalize();
+        let mut diffuse = Vec3::new(T::zero(), T::zero(), T::zero());
+        let mut specular = Vec3::new(T::zero(), T::zero(), T::zero());
+
+        for light in lights {
+            let wi = light.light_vector(&intersection.point);
+            let color = light.color_at(&intersection.point).mul(pi());
+            let lobes = self.lobes(intersection, &wo, &wi);
+
+            diffuse.mut_add(&lobes.0.scalar_mul(&color));
+            specular.mut_add(&lobes.1.scalar_mul(&color));
+        }
+
+        (diffuse, specular)
+    }
+
+    fn evaluate(&self, intersection: &Intersection<T>, wo: &Vec3<T>, wi: &Vec3<T>) -> Vec3<T> {
+        let (diffuse, specular) = self.lobes(intersection, wo, wi);
+
+        diffuse.add(&specular)
+    }
+
+    fn pdf(&self, intersection: &Intersection<T>, wo: &Vec3<T>, wi: &Vec3<T>) -> T {
+        let normal = &intersection.normal;
+        let cos_i = normal.dot(wi);
+        if cos_i <= T::zero() || normal.dot(wo) <= T::zero() {
+            return T::zero();
+        }
+
+        let parameters = self.parameters(intersection);
+        let specular = PbrMaterial::specular_probability(&parameters);
+
+        let specular_pdf = ggx_reflection_pdf(normal, wo, wi, parameters.alpha);
+
+        specular * specular_pdf + (T::one() - specular) * cos_i / pi()
+    }
+
+    // picks the specular lobe by importance sampling the GGX distribution of half vectors, or the
+    // diffuse lobe by cosine weighted sampling
+    fn sample(
+        &self,
+        intersection: &Intersection<T>,
+        wo: &Vec3<T>,
+        sampler: &mut dyn Sampler<T>,
+    ) -> Option<ScatterSample<T>> {
+        let normal = &intersection.normal;
+        if normal.dot(wo) <= T::zero() {
+            return None;
+        }
+
+        let parameters = self.parameters(intersection);
+
+        let direction = if sampler.next_1d() < PbrMaterial::specular_probability(&parameters) {
+            sample_ggx_reflection(intersection, wo, parameters.alpha, sampler.next_2d())
+        } else {
+            to_world(
+                &cosine_hemisphere(sampler.next_2d()),
+                &intersection.tangent,
+                &intersection.bitangent,
+                normal,
+            )
+        };
+
+        let pdf = self.pdf(intersection, wo, &direction);
+        if pdf <= T::zero() {
+            return None;
+        }
+
+        Some(ScatterSample {
+            weight: self.evaluate(intersection, wo, &direction).div(pdf),
+            direction,
+            pdf,
+            specular: false,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::RandomSampler;
+    use crate::scene::light::PointLight;
+
+    fn surface() -> Intersection<f64> {
+        Intersection::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0))
+    }
+
+    #[test]
+    fn schlick_fresnel_limits() {
+        let f0 = Vec3::new(0.04, 0.5, 1.0);
+
+        assert_eq!(f0, schlick(&f0, 1.0));
+        assert_eq!(Vec3::new(1.0, 1.0, 1.0), schlick(&f0, 0.0));
+    }
+
+    #[test]
+    fn shade_parts_split_highlights_from_diffuse() {
+        let light = PointLight::new(
+            Color::new(1.0, 1.0, 1.0).unwrap(),
+            Vec3::new(-3.0, 0.0, 4.0),
+        );
+        let lights = [IncidentLight::new(&light)];
+        // looking along the mirror direction of the light, into the highlight
+        let viewpoint = Vec3::new(3.0, 0.0, 4.0);
+
+        let plastic = PbrMaterial::new(Color::new(0.8, 0.2, 0.2).unwrap(), 0.0, 0.3);
+        let (diffuse, specular) = plastic.shade_parts(&surface(), &lights, &viewpoint);
+        let shade = plastic.shade(&surface(), &lights, &viewpoint);
+        assert!(diffuse.x > 0.0 && specular.x > 0.0);
+        assert!(diffuse.add(&specular).sub(&shade).mag_sqrd() < 1e-20);
+
+        // metals have no diffuse part
+        let gold = PbrMaterial::new(Color::new(1.0, 0.8, 0.3).unwrap(), 1.0, 0.3);
+        let (diffuse, specular) = gold.shade_parts(&surface(), &lights, &viewpoint);
+        assert_eq!(Vec3::new(0.0, 0.0, 0.0), diffuse);
+        assert!(specular.x > 0.0);
+    }
+
+    #[test]
+    fn sampled_pdf_matches_evaluated_pdf() {
+        let material = PbrMaterial::new(Color::new(0.8, 0.6, 0.2).unwrap(), 0.5, 0.4);
+        let intersection = surface();
+        let wo = Vec3::new(0.3, -0.2, 1.0).normalize();
+        let mut sampler = RandomSampler::new(3);
+
+        for _ in 0..100 {
+            if let Some(sample) = material.sample(&intersection, &wo, &mut sampler) {
+                let pdf = material.pdf(&intersection, &wo, &sample.direction);
+
+                assert!((sample.pdf - pdf).abs() < 1e-9 * pdf.max(1.0));
+                assert!(sample.direction.z > 0.0);
+            }
+        }
+    }
+
+    #[test]
+    fn white_furnace_conserves_energy() {
+        // the average sample weight estimates the fraction of light reflected, which can't be
+        // more than all of it
+        for (metallic, roughness) in &[(0.0, 0.2), (0.0, 0.9), (1.0, 0.3), (1.0, 1.0)] {
+            let material =
+                PbrMaterial::new(Color::new(1.0, 1.0, 1.0).unwrap(), *metallic, *roughness);
+            let intersection = surface();
+            let wo = Vec3::new(0.5, 0.0, 1.0).normalize();
+            let mut sampler = RandomSampler::new(11);
+
+            let count = 20000;
+            let mut total = 0.0;
+            for _ in 0..count {
+                if let Some(sample) = material.sample(&intersection, &wo, &mut sampler) {
+                    total += sample.weight.y;
+                }
+            }
+            let albedo = total / count as f64;
+
+            assert!(
+                albedo < 1.02,
+                "albedo {} for {:?}",
+                albedo,
+                (metallic, roughness)
+            );
+            // single scattering GGX loses a good part of the energy on very rough surfaces
+            assert!(
+                albedo > 0.25,
+                "albedo {} for {:?}",
+                albedo,
+                (metallic, roughness)
+            );
+        }
+    }
+}
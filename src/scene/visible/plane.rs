@@ -0,0 +1,246 @@
+use crate::common::{Intersection, Ray, Spacial, Vec3, VertexFormat};
+use crate::scene::visible::Intersectable;
+
+// Intersects a ray with the infinite plane through `point` with normal `normal`. Returns the ray
+// parameter and the point of intersection. Planes are two sided, so the returned normal always
+// faces the ray origin, and last comes whether the ray hit the side `normal` points to.
+fn plane_intersection<T: VertexFormat>(
+    point: &Vec3<T>,
+    normal: &Vec3<T>,
+    ray: &Ray<T>,
+) -> Option<(T, Vec3<T>, Vec3<T>, bool)> {
+    let v_d = normal.dot(&ray.direction);
+
+    if v_d == T::zero() {
+        return None;
+    }
+
+    let t = point.sub(&ray.origin).dot(normal) / v_d;
+    if t < T::zero() {
+        return None;
+    }
+
+    let front_face = v_d < T::zero();
+    let mut facing_normal = normal.clone();
+    if !front_face {
+        facing_normal = facing_normal.mul(T::one().neg());
+    }
+
+    Some((
+        t,
+        ray.origin.add(&ray.direction.mul(t)),
+        facing_normal,
+        front_face,
+    ))
+}
+
+// Infinite plane, defined by a point on the plane and the plane normal
+#[derive(Debug)]
+pub struct Plane<T: VertexFormat> {
+    point: Vec3<T>,
+    normal: Vec3<T>,
+}
+
+impl<T: VertexFormat> Plane<T> {
+    pub fn new(point: Vec3<T>, normal: Vec3<T>) -> Plane<T> {
+        Plane {
+            point,
+            normal: normal.normalize(),
+        }
+    }
+}
+
+impl<T: VertexFormat> Intersectable<T> for Plane<T> {
+    fn intersect(&self, ray: &Ray<T>) -> Option<Intersection<T>> {
+        let (_, point, normal, front_face) = plane_intersection(&self.point, &self.normal, ray)?;
+
+        // texture coordinates are distances along the plane, so textures repeat every unit
+        let (tangent, bitangent) = self.normal.orthonormal_basis();
+        let offset = point.sub(&self.point);
+
+        let mut intersection = Intersection::new(point, normal);
+        intersection.front_face = front_face;
+        intersection.uv = (offset.dot(&tangent), offset.dot(&bitangent));
+        intersection.set_tangent_frame(&tangent, &bitangent);
+
+        Some(intersection)
+    }
+}
+
+impl<T: VertexFormat> Spacial<T> for Plane<T> {
+    fn location(&self) -> &Vec3<T> {
+        &self.point
+    }
+}
+
+// Flat, circular disk
+#[derive(Debug)]
+pub struct Disk<T: VertexFormat> {
+    center: Vec3<T>,
+    normal: Vec3<T>,
+    radius: T,
+}
+
+impl<T: VertexFormat> Disk<T> {
+    pub fn new(center: Vec3<T>, normal: Vec3<T>, radius: T) -> Disk<T> {
+        Disk {
+            center,
+            normal: normal.normalize(),
+            radius,
+        }
+    }
+}
+
+impl<T: VertexFormat> Intersectable<T> for Disk<T> {
+    fn intersect(&self, ray: &Ray<T>) -> Option<Intersection<T>> {
+        let (_, point, normal, front_face) = plane_intersection(&self.center, &self.normal, ray)?;
+
+        let offset = point.sub(&self.center);
+        if offset.mag_sqrd() > self.radius * self.radius {
+            return None;
+        }
+
+        // the texture's unit square is stretched over the bounding square of the disk
+        let (tangent, bitangent) = self.normal.orthonormal_basis();
+        let half = T::from(0.5).unwrap();
+        let diameter = self.radius * T::from(2.0).unwrap();
+
+        let mut intersection = Intersection::new(point, normal);
+        intersection.front_face = front_face;
+        intersection.uv = (
+            offset.dot(&tangent) / diameter + half,
+            offset.dot(&bitangent) / diameter + half,
+        );
+        intersection.set_tangent_frame(&tangent, &bitangent);
+
+        Some(intersection)
+    }
+}
+
+impl<T: VertexFormat> Spacial<T> for Disk<T> {
+    fn location(&self) -> &Vec3<T> {
+        &self.center
+    }
+}
+
+// Parallelogram spanned by the edges `u` and `v` from `corner`. With perpendicular edges this is a
+// rectangle.
+#[derive(Debug)]
+pub struct Quad<T: VertexFormat> {
+    corner: Vec3<T>,
+    u: Vec3<T>,
+    v: Vec3<T>,
+    normal: Vec3<T>,
+    // vector used to project a point on the plane onto the u, v edges. w = n / (n . n) for the
+    // unnormalized normal n = u x v
+    w: Vec3<T>,
+}
+
+impl<T: VertexFormat> Quad<T> {
+    pub fn new(corner: Vec3<T>, u: Vec3<T>, v: Vec3<T>) -> Quad<T> {
+        let n = u.cross(&v);
+        let w = n.div(n.mag_sqrd());
+
+        Quad {
+            corner,
+            u,
+            v,
+            normal: n.normalize(),
+            w,
+        }
+    }
+
+    // coordinates of a point on the quad's plane, in terms of the u and v edges. Both are in
+    // [0, 1] for points within the quad.
+    fn plane_coordinates(&self, point: &Vec3<T>) -> (T, T) {
+        let p = point.sub(&self.corner);
+
+        (self.w.dot(&p.cross(&self.v)), self.w.dot(&self.u.cross(&p)))
+    }
+}
+
+impl<T: VertexFormat> Intersectable<T> for Quad<T> {
+    fn intersect(&self, ray: &Ray<T>) -> Option<Intersection<T>> {
+        let (_, point, normal, front_face) = plane_intersection(&self.corner, &self.normal, ray)?;
+
+        let (alpha, beta) = self.plane_coordinates(&point);
+        let unit = T::zero()..=T::one();
+        if !unit.contains(&alpha) || !unit.contains(&beta) {
+            return None;
+        }
+
+        let mut intersection = Intersection::new(point, normal);
+        intersection.front_face = front_face;
+        intersection.uv = (alpha, beta);
+        intersection.set_tangent_frame(&self.u, &self.v);
+
+        Some(intersection)
+    }
+}
+
+impl<T: VertexFormat> Spacial<T> for Quad<T> {
+    fn location(&self) -> &Vec3<T> {
+        &self.corner
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plane_intersection_faces_ray() {
+        let plane = Plane::new(Vec3::new(0.0, -1.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+
+        let ray = Ray::new(Vec3::new(0.0, -5.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+
+        let intersection = plane.intersect(&ray).unwrap();
+
+        assert_eq!(Vec3::new(0.0, -1.0, 0.0), intersection.point);
+        assert_eq!(Vec3::new(0.0, -1.0, 0.0), intersection.normal);
+    }
+
+    #[test]
+    fn parallel_ray_misses_plane() {
+        let plane = Plane::new(Vec3::new(0.0, -1.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+
+        assert_eq!(None, plane.intersect(&ray));
+    }
+
+    #[test]
+    fn disk_hit_and_miss() {
+        let disk = Disk::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0), 1.0);
+
+        let hit = Ray::new(Vec3::new(0.5, 0.5, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        let miss = Ray::new(Vec3::new(1.0, 1.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+
+        assert_eq!(
+            Vec3::new(0.5, 0.5, 0.0),
+            disk.intersect(&hit).unwrap().point
+        );
+        assert_eq!(None, disk.intersect(&miss));
+    }
+
+    #[test]
+    fn parallelogram_hit_and_miss() {
+        let quad = Quad::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(2.0, 0.0, 0.0),
+            Vec3::new(1.0, 1.0, 0.0),
+        );
+
+        // inside the slanted parallelogram, but outside of its bounding unit square
+        let hit = Ray::new(Vec3::new(2.5, 0.75, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        // inside the bounding square, but left of the slanted edge
+        let miss = Ray::new(Vec3::new(0.25, 0.75, 5.0), Vec3::new(0.0, 0.0, -1.0));
+
+        let intersection = quad.intersect(&hit).unwrap();
+
+        assert_eq!(Vec3::new(2.5, 0.75, 0.0), intersection.point);
+        assert_eq!(Vec3::new(0.0, 0.0, 1.0), intersection.normal);
+        assert_eq!((0.875, 0.75), intersection.uv);
+        assert_eq!(None, quad.intersect(&miss));
+    }
+}
@@ -0,0 +1,431 @@
+use crate::common::{Intersection, Vec3, VertexFormat};
+use crate::noise::{fbm, turbulence, worley};
+use crate::scene::visible::texture::Texture;
+use std::rc::Rc;
+
+// Where a procedural texture is evaluated
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mapping {
+    // the intersection's texture coordinates, as the point (u, v, 0)
+    Uv,
+    // the intersection point itself, so the pattern runs through shapes like a solid material
+    Point,
+}
+
+// point the pattern is evaluated at. A larger scale makes the pattern smaller.
+fn coordinates<T: VertexFormat>(
+    mapping: Mapping,
+    scale: T,
+    intersection: &Intersection<T>,
+) -> Vec3<T> {
+    let point = match mapping {
+        Mapping::Uv => Vec3::new(intersection.uv.0, intersection.uv.1, T::zero()),
+        Mapping::Point => intersection.point.clone(),
+    };
+
+    point.mul(scale)
+}
+
+// mixes the values of two textures, with t = 0 giving all of the first one
+fn blend<T: VertexFormat>(
+    first: &Rc<dyn Texture<T>>,
+    second: &Rc<dyn Texture<T>>,
+    t: T,
+    intersection: &Intersection<T>,
+) -> Vec3<T> {
+    let t = t.max(T::zero()).min(T::one());
+
+    first
+        .value(intersection)
+        .mul(T::one() - t)
+        .add(&second.value(intersection).mul(t))
+}
+
+// Alternates between two textures on a grid of unit cells. With uv mapping this is a flat
+// checkerboard, with point mapping a 3D one.
+#[derive(Debug)]
+pub struct Checker<T: VertexFormat> {
+    even: Rc<dyn Texture<T>>,
+    odd: Rc<dyn Texture<T>>,
+    mapping: Mapping,
+    scale: T,
+}
+
+impl<T: VertexFormat> Checker<T> {
+    pub fn new(even: Rc<dyn Texture<T>>, odd: Rc<dyn Texture<T>>, mapping: Mapping) -> Checker<T> {
+        Checker {
+            even,
+            odd,
+            mapping,
+            scale: T::one(),
+        }
+    }
+
+    pub fn set_scale(&mut self, scale: T) {
+        self.scale = scale;
+    }
+}
+
+impl<T: VertexFormat> Texture<T> for Checker<T> {
+    fn value(&self, intersection: &Intersection<T>) -> Vec3<T> {
+        let p = coordinates(self.mapping, self.scale, intersection);
+        let cell = p.x.floor() + p.y.floor() + p.z.floor();
+
+        // parity is taken in floating point, as far away cells don't fit an integer
+        if cell % T::from(2.0).unwrap() == T::zero() {
+            self.even.value(intersection)
+        } else {
+            self.odd.value(intersection)
+        }
+    }
+}
+
+// Linear blend from start to end, going from the origin to the point at direction
+#[derive(Debug)]
+pub struct Gradient<T: VertexFormat> {
+    start: Rc<dyn Texture<T>>,
+    end: Rc<dyn Texture<T>>,
+    direction: Vec3<T>,
+    mapping: Mapping,
+}
+
+impl<T: VertexFormat> Gradient<T> {
+    pub fn new(
+        start: Rc<dyn Texture<T>>,
+        end: Rc<dyn Texture<T>>,
+        direction: Vec3<T>,
+        mapping: Mapping,
+    ) -> Gradient<T> {
+        Gradient {
+            start,
+            end,
+            direction,
+            mapping,
+        }
+    }
+}
+
+impl<T: VertexFormat> Texture<T> for Gradient<T> {
+    fn value(&self, intersection: &Intersection<T>) -> Vec3<T> {
+        let p = coordinates(self.mapping, T::one(), intersection);
+        let t = p.dot(&self.direction) / self.direction.mag_sqrd();
+
+        blend(&self.start, &self.end, t, intersection)
+    }
+}
+
+// Blend between two textures driven by Perlin noise. With more than one octave this is fractal
+// (fBm) noise.
+#[derive(Debug)]
+pub struct Noise<T: VertexFormat> {
+    low: Rc<dyn Texture<T>>,
+    high: Rc<dyn Texture<T>>,
+    mapping: Mapping,
+    scale: T,
+    octaves: u32,
+    lacunarity: T,
+    gain: T,
+    seed: u64,
+}
+
+impl<T: VertexFormat> Noise<T> {
+    pub fn new(low: Rc<dyn Texture<T>>, high: Rc<dyn Texture<T>>, mapping: Mapping) -> Noise<T> {
+        Noise {
+            low,
+            high,
+            mapping,
+            scale: T::one(),
+            octaves: 1,
+            lacunarity: T::from(2.0).unwrap(),
+            gain: T::from(0.5).unwrap(),
+            seed: 0,
+        }
+    }
+
+    pub fn set_scale(&mut self, scale: T) {
+        self.scale = scale;
+    }
+
+    pub fn set_octaves(&mut self, octaves: u32) {
+        self.octaves = octaves;
+    }
+
+    // frequency multiplier between octaves
+    pub fn set_lacunarity(&mut self, lacunarity: T) {
+        self.lacunarity = lacunarity;
+    }
+
+    // amplitude multiplier between octaves
+    pub fn set_gain(&mut self, gain: T) {
+        self.gain = gain;
+    }
+
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+}
+
+impl<T: VertexFormat> Texture<T> for Noise<T> {
+    fn value(&self, intersection: &Intersection<T>) -> Vec3<T> {
+        let p = coordinates(self.mapping, self.scale, intersection);
+        let noise = fbm(&p, self.octaves, self.lacunarity, self.gain, self.seed);
+
+        let half = T::from(0.5).unwrap();
+        blend(
+            &self.low,
+            &self.high,
+            (noise + T::one()) * half,
+            intersection,
+        )
+    }
+}
+
+// Cellular pattern, blending from near at the center of each cell to far at its edges
+#[derive(Debug)]
+pub struct Worley<T: VertexFormat> {
+    near: Rc<dyn Texture<T>>,
+    far: Rc<dyn Texture<T>>,
+    mapping: Mapping,
+    scale: T,
+    seed: u64,
+}
+
+impl<T: VertexFormat> Worley<T> {
+    pub fn new(near: Rc<dyn Texture<T>>, far: Rc<dyn Texture<T>>, mapping: Mapping) -> Worley<T> {
+        Worley {
+            near,
+            far,
+            mapping,
+            scale: T::one(),
+            seed: 0,
+        }
+    }
+
+    pub fn set_scale(&mut self, scale: T) {
+        self.scale = scale;
+    }
+
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+}
+
+impl<T: VertexFormat> Texture<T> for Worley<T> {
+    fn value(&self, intersection: &Intersection<T>) -> Vec3<T> {
+        let p = coordinates(self.mapping, self.scale, intersection);
+
+        blend(&self.near, &self.far, worley(&p, self.seed), intersection)
+    }
+}
+
+// Veins running across the x axis, distorted by turbulence
+#[derive(Debug)]
+pub struct Marble<T: VertexFormat> {
+    base: Rc<dyn Texture<T>>,
+    vein: Rc<dyn Texture<T>>,
+    mapping: Mapping,
+    scale: T,
+    // how far the veins are pushed around by the turbulence
+    distortion: T,
+    seed: u64,
+}
+
+impl<T: VertexFormat> Marble<T> {
+    pub fn new(base: Rc<dyn Texture<T>>, vein: Rc<dyn Texture<T>>, mapping: Mapping) -> Marble<T> {
+        Marble {
+            base,
+            vein,
+            mapping,
+            scale: T::one(),
+            distortion: T::from(5.0).unwrap(),
+            seed: 0,
+        }
+    }
+
+    pub fn set_scale(&mut self, scale: T) {
+        self.scale = scale;
+    }
+
+    pub fn set_distortion(&mut self, distortion: T) {
+        self.distortion = distortion;
+    }
+
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+}
+
+impl<T: VertexFormat> Texture<T> for Marble<T> {
+    fn value(&self, intersection: &Intersection<T>) -> Vec3<T> {
+        let p = coordinates(self.mapping, self.scale, intersection);
+        let two = T::from(2.0).unwrap();
+        let turbulence = turbulence(&p, 6, two, T::from(0.5).unwrap(), self.seed);
+
+        let phase = (p.x + self.distortion * turbulence) * T::from(std::f64::consts::PI).unwrap();
+        // narrow veins, where the sine wave crosses zero
+        let t = T::one() - phase.sin().abs().sqrt();
+
+        blend(&self.base, &self.vein, t, intersection)
+    }
+}
+
+// Concentric growth rings around the y axis, made irregular by noise
+#[derive(Debug)]
+pub struct Wood<T: VertexFormat> {
+    light: Rc<dyn Texture<T>>,
+    dark: Rc<dyn Texture<T>>,
+    mapping: Mapping,
+    scale: T,
+    distortion: T,
+    seed: u64,
+}
+
+impl<T: VertexFormat> Wood<T> {
+    pub fn new(light: Rc<dyn Texture<T>>, dark: Rc<dyn Texture<T>>, mapping: Mapping) -> Wood<T> {
+        Wood {
+            light,
+            dark,
+            mapping,
+            scale: T::one(),
+            distortion: T::from(0.3).unwrap(),
+            seed: 0,
+        }
+    }
+
+    pub fn set_scale(&mut self, scale: T) {
+        self.scale = scale;
+    }
+
+    pub fn set_distortion(&mut self, distortion: T) {
+        self.distortion = distortion;
+    }
+
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+}
+
+impl<T: VertexFormat> Texture<T> for Wood<T> {
+    fn value(&self, intersection: &Intersection<T>) -> Vec3<T> {
+        let p = coordinates(self.mapping, self.scale, intersection);
+        let two = T::from(2.0).unwrap();
+        let noise = fbm(&p, 3, two, T::from(0.5).unwrap(), self.seed);
+
+        let rings = (p.x * p.x + p.z * p.z).sqrt() + self.distortion * noise;
+        // each ring fades from light to dark
+        let t = rings - rings.floor();
+
+        blend(&self.light, &self.dark, t * t, intersection)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::Color;
+    use crate::scene::visible::texture::ConstantTexture;
+
+    fn black_and_white() -> (Rc<dyn Texture<f64>>, Rc<dyn Texture<f64>>) {
+        (
+            Rc::new(ConstantTexture::new(Color::new(0.0, 0.0, 0.0).unwrap())),
+            Rc::new(ConstantTexture::new(Color::new(1.0, 1.0, 1.0).unwrap())),
+        )
+    }
+
+    fn at_point(x: f64, y: f64, z: f64) -> Intersection<f64> {
+        Intersection::new(Vec3::new(x, y, z), Vec3::new(0.0, 1.0, 0.0))
+    }
+
+    #[test]
+    fn checker_alternates() {
+        let (black, white) = black_and_white();
+        let mut checker = Checker::new(black, white, Mapping::Point);
+        checker.set_scale(2.0);
+
+        assert_eq!(
+            Vec3::new(0.0, 0.0, 0.0),
+            checker.value(&at_point(0.1, 0.1, 0.1))
+        );
+        assert_eq!(
+            Vec3::new(1.0, 1.0, 1.0),
+            checker.value(&at_point(0.6, 0.1, 0.1))
+        );
+        assert_eq!(
+            Vec3::new(0.0, 0.0, 0.0),
+            checker.value(&at_point(-0.4, 0.1, -0.4))
+        );
+    }
+
+    #[test]
+    fn far_away_hits_are_textured() {
+        let (black, white) = black_and_white();
+        let mut checker = Checker::new(black.clone(), white.clone(), Mapping::Point);
+        checker.set_scale(10.0);
+        let noise = Noise::new(black.clone(), white.clone(), Mapping::Point);
+        let worley = Worley::new(black.clone(), white.clone(), Mapping::Point);
+        let marble = Marble::new(black.clone(), white.clone(), Mapping::Point);
+        let wood = Wood::new(black, white, Mapping::Point);
+
+        // on a ground plane close to the horizon
+        let far = at_point(3.0, 0.0, 1e20);
+
+        assert_eq!(Vec3::new(0.0, 0.0, 0.0), checker.value(&far));
+        for texture in [&noise as &dyn Texture<f64>, &worley, &marble, &wood].iter() {
+            let value = texture.value(&far);
+            assert!((0.0..=1.0).contains(&value.x));
+        }
+    }
+
+    #[test]
+    fn checker_on_uvs() {
+        let (black, white) = black_and_white();
+        let checker = Checker::new(black, white, Mapping::Uv);
+
+        let mut intersection = at_point(0.0, 0.0, 0.0);
+        intersection.uv = (1.5, 0.5);
+
+        assert_eq!(Vec3::new(1.0, 1.0, 1.0), checker.value(&intersection));
+    }
+
+    #[test]
+    fn gradient_clamped_between_ends() {
+        let (black, white) = black_and_white();
+        let gradient = Gradient::new(black, white, Vec3::new(2.0, 0.0, 0.0), Mapping::Point);
+
+        assert_eq!(
+            Vec3::new(0.0, 0.0, 0.0),
+            gradient.value(&at_point(-1.0, 0.0, 0.0))
+        );
+        assert_eq!(
+            Vec3::new(0.5, 0.5, 0.5),
+            gradient.value(&at_point(1.0, 5.0, 0.0))
+        );
+        assert_eq!(
+            Vec3::new(1.0, 1.0, 1.0),
+            gradient.value(&at_point(3.0, 0.0, 0.0))
+        );
+    }
+
+    #[test]
+    fn noise_textures_stay_between_inputs() {
+        let (black, white) = black_and_white();
+        let mut noise = Noise::new(black.clone(), white.clone(), Mapping::Point);
+        noise.set_octaves(4);
+        let textures: Vec<Box<dyn Texture<f64>>> = vec![
+            Box::new(noise),
+            Box::new(Worley::new(black.clone(), white.clone(), Mapping::Point)),
+            Box::new(Marble::new(black.clone(), white.clone(), Mapping::Point)),
+            Box::new(Wood::new(black, white, Mapping::Point)),
+        ];
+
+        for i in 0..50 {
+            let i = i as f64;
+            let intersection = at_point(i * 0.31, -i * 0.17, i * 0.07);
+
+            for texture in &textures {
+                let value = texture.value(&intersection);
+                assert!((0.0..=1.0).contains(&value.x), "{:?}", texture);
+            }
+        }
+    }
+}
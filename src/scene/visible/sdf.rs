@@ -0,0 +1,415 @@
+use crate::common::{Intersection, Ray, Spacial, Vec3, VertexFormat};
+use crate::scene::visible::{spherical_uv, Intersectable};
+
+// Signed distance function: distance from a point to the nearest surface, negative inside of the
+// shape. Functions that only bound the distance from below work as well, as long as the shape's
+// step scale is reduced to match.
+pub type DistanceFunction<T> = Box<dyn Fn(&Vec3<T>) -> T>;
+
+const MAX_STEPS: u32 = 256;
+const MAX_DISTANCE: f64 = 100.0;
+const HIT_EPSILON: f64 = 0.0001;
+
+// Shape defined by a signed distance function, rendered using sphere tracing
+pub struct SdfShape<T: VertexFormat> {
+    distance: DistanceFunction<T>,
+    location: Vec3<T>,
+    max_steps: u32,
+    max_distance: T,
+    epsilon: T,
+    step_scale: T,
+}
+
+impl<T: VertexFormat> SdfShape<T> {
+    pub fn new(distance: DistanceFunction<T>, location: Vec3<T>) -> SdfShape<T> {
+        SdfShape {
+            distance,
+            location,
+            max_steps: MAX_STEPS,
+            max_distance: T::from(MAX_DISTANCE).unwrap(),
+            epsilon: T::from(HIT_EPSILON).unwrap(),
+            step_scale: T::one(),
+        }
+    }
+
+    // maximum number of steps taken along a ray, before giving up on finding a surface
+    pub fn set_max_steps(&mut self, max_steps: u32) {
+        self.max_steps = max_steps;
+    }
+
+    // how far along a ray to look for a surface
+    pub fn set_max_distance(&mut self, max_distance: T) {
+        self.max_distance = max_distance;
+    }
+
+    // distance from the surface at which a ray counts as hitting it
+    pub fn set_epsilon(&mut self, epsilon: T) {
+        self.epsilon = epsilon;
+    }
+
+    // fraction of the distance bound taken per step. Distance functions that overestimate the
+    // distance, like twisted shapes, need a value below one to avoid stepping through surfaces.
+    pub fn set_step_scale(&mut self, step_scale: T) {
+        self.step_scale = step_scale;
+    }
+
+    pub fn distance(&self, point: &Vec3<T>) -> T {
+        (self.distance)(point)
+    }
+
+    // surface normal, from the gradient of the distance function using central differences
+    pub fn normal(&self, point: &Vec3<T>) -> Vec3<T> {
+        let h = self.epsilon;
+        let dx = Vec3::new(h, T::zero(), T::zero());
+        let dy = Vec3::new(T::zero(), h, T::zero());
+        let dz = Vec3::new(T::zero(), T::zero(), h);
+
+        Vec3::new(
+            self.distance(&point.add(&dx)) - self.distance(&point.sub(&dx)),
+            self.distance(&point.add(&dy)) - self.distance(&point.sub(&dy)),
+            self.distance(&point.add(&dz)) - self.distance(&point.sub(&dz)),
+        )
+        .normalize()
+    }
+}
+
+impl<T: VertexFormat> Intersectable<T> for SdfShape<T> {
+    fn intersect(&self, ray: &Ray<T>) -> Option<Intersection<T>> {
+        let mut t = T::zero();
+        let start_distance = self.distance(&ray.origin);
+
+        // rays starting on the surface, like reflection and shadow rays, first need to get clear
+        // of it, or they would immediately hit it again
+        if start_distance.abs() < self.epsilon {
+            t = self.epsilon * T::from(10.0).unwrap();
+        }
+
+        // rays starting inside the shape march towards the surface from the inside
+        let side = if (self.distance)(&ray.origin.add(&ray.direction.mul(t))) < T::zero() {
+            T::one().neg()
+        } else {
+            T::one()
+        };
+
+        for _ in 0..self.max_steps {
+            let point = ray.origin.add(&ray.direction.mul(t));
+            let distance = self.distance(&point) * side;
+
+            if distance < self.epsilon {
+                // there is no natural parameterization of an arbitrary distance field, so
+                // textures are mapped spherically around the shape's location
+                let uv = spherical_uv(&point.sub(&self.location).normalize());
+                let normal = self.normal(&point);
+
+                let mut intersection = Intersection::new(point, normal);
+                intersection.uv = uv;
+                intersection.front_face = side > T::zero();
+
+                return Some(intersection);
+            }
+
+            t = t + distance * self.step_scale;
+            if t > self.max_distance {
+                return None;
+            }
+        }
+
+        None
+    }
+}
+
+impl<T: VertexFormat> Spacial<T> for SdfShape<T> {
+    fn location(&self) -> &Vec3<T> {
+        &self.location
+    }
+}
+
+// Primitives. These are all centered on the origin, and can be moved using translate.
+
+pub fn sphere<T: VertexFormat + 'static>(radius: T) -> DistanceFunction<T> {
+    Box::new(move |p| p.mag_sqrd().sqrt() - radius)
+}
+
+// box with the given half size along each axis
+pub fn cuboid<T: VertexFormat + 'static>(half_extents: Vec3<T>) -> DistanceFunction<T> {
+    Box::new(move |p| {
+        let q = Vec3::new(
+            p.x.abs() - half_extents.x,
+            p.y.abs() - half_extents.y,
+            p.z.abs() - half_extents.z,
+        );
+        let outside = Vec3::new(q.x.max(T::zero()), q.y.max(T::zero()), q.z.max(T::zero()));
+
+        outside.mag_sqrd().sqrt() + q.x.max(q.y.max(q.z)).min(T::zero())
+    })
+}
+
+// box with its edges rounded off by radius. The rounding stays within the given half extents.
+pub fn round_box<T: VertexFormat + 'static>(
+    half_extents: Vec3<T>,
+    radius: T,
+) -> DistanceFunction<T> {
+    let inner = Vec3::new(
+        half_extents.x - radius,
+        half_extents.y - radius,
+        half_extents.z - radius,
+    );
+
+    round(cuboid(inner), radius)
+}
+
+// torus lying in the xz plane
+pub fn torus<T: VertexFormat + 'static>(major_radius: T, minor_radius: T) -> DistanceFunction<T> {
+    Box::new(move |p| {
+        let ring = (p.x * p.x + p.z * p.z).sqrt() - major_radius;
+
+        (ring * ring + p.y * p.y).sqrt() - minor_radius
+    })
+}
+
+// infinite plane through the origin, with everything on the opposite side of the normal inside
+pub fn plane<T: VertexFormat + 'static>(normal: Vec3<T>) -> DistanceFunction<T> {
+    let normal = normal.normalize();
+
+    Box::new(move |p| p.dot(&normal))
+}
+
+// Mandelbulb fractal of the given power, using the distance estimate from the escape time of the
+// iteration. Use a step scale below one when tracing it.
+pub fn mandelbulb<T: VertexFormat + 'static>(power: T, iterations: u32) -> DistanceFunction<T> {
+    Box::new(move |p| {
+        let bailout = T::from(2.0).unwrap();
+
+        let mut z = p.clone();
+        let mut dr = T::one();
+        let mut r = T::zero();
+
+        for _ in 0..iterations {
+            r = z.mag_sqrd().sqrt();
+            if r > bailout || r == T::zero() {
+                break;
+            }
+
+            // convert to polar coordinates, and scale and rotate the point
+            let theta = (z.z / r).acos() * power;
+            let phi = z.y.atan2(z.x) * power;
+            dr = r.powf(power - T::one()) * power * dr + T::one();
+
+            let zr = r.powf(power);
+            z = Vec3::new(
+                theta.sin() * phi.cos(),
+                phi.sin() * theta.sin(),
+                theta.cos(),
+            )
+            .mul(zr)
+            .add(p);
+        }
+
+        if r == T::zero() {
+            return T::zero();
+        }
+
+        T::from(0.5).unwrap() * r.ln() * r / dr
+    })
+}
+
+// Transformations
+
+pub fn translate<T: VertexFormat + 'static>(
+    shape: DistanceFunction<T>,
+    offset: Vec3<T>,
+) -> DistanceFunction<T> {
+    Box::new(move |p| shape(&p.sub(&offset)))
+}
+
+// uniform scale around the origin
+pub fn scale<T: VertexFormat + 'static>(
+    shape: DistanceFunction<T>,
+    factor: T,
+) -> DistanceFunction<T> {
+    Box::new(move |p| shape(&p.div(factor)) * factor)
+}
+
+// grows the shape outwards by radius, rounding off its edges
+pub fn round<T: VertexFormat + 'static>(
+    shape: DistanceFunction<T>,
+    radius: T,
+) -> DistanceFunction<T> {
+    Box::new(move |p| shape(p) - radius)
+}
+
+// Infinitely repeats the space around the origin, in cells of the given size. The shape should fit
+// within a single cell. Zero period components don't repeat along that axis.
+pub fn repetition<T: VertexFormat + 'static>(
+    shape: DistanceFunction<T>,
+    period: Vec3<T>,
+) -> DistanceFunction<T> {
+    let repeat = move |value: T, period: T| {
+        if period == T::zero() {
+            value
+        } else {
+            value - period * (value / period).round()
+        }
+    };
+
+    Box::new(move |p| {
+        shape(&Vec3::new(
+            repeat(p.x, period.x),
+            repeat(p.y, period.y),
+            repeat(p.z, period.z),
+        ))
+    })
+}
+
+// Twists the shape around the y axis, by rate radians per unit of height. This stretches the
+// distance field, so the shape's step scale should be reduced for stronger twists.
+pub fn twist<T: VertexFormat + 'static>(
+    shape: DistanceFunction<T>,
+    rate: T,
+) -> DistanceFunction<T> {
+    Box::new(move |p| {
+        let angle = rate * p.y;
+        let (sin, cos) = angle.sin_cos();
+
+        shape(&Vec3::new(
+            cos * p.x - sin * p.z,
+            p.y,
+            sin * p.x + cos * p.z,
+        ))
+    })
+}
+
+// Combinators
+
+pub fn union<T: VertexFormat + 'static>(
+    a: DistanceFunction<T>,
+    b: DistanceFunction<T>,
+) -> DistanceFunction<T> {
+    Box::new(move |p| a(p).min(b(p)))
+}
+
+pub fn intersection<T: VertexFormat + 'static>(
+    a: DistanceFunction<T>,
+    b: DistanceFunction<T>,
+) -> DistanceFunction<T> {
+    Box::new(move |p| a(p).max(b(p)))
+}
+
+// removes b from a
+pub fn subtraction<T: VertexFormat + 'static>(
+    a: DistanceFunction<T>,
+    b: DistanceFunction<T>,
+) -> DistanceFunction<T> {
+    Box::new(move |p| a(p).max(-b(p)))
+}
+
+// union that blends the shapes together where they are within k of each other
+pub fn smooth_union<T: VertexFormat + 'static>(
+    a: DistanceFunction<T>,
+    b: DistanceFunction<T>,
+    k: T,
+) -> DistanceFunction<T> {
+    Box::new(move |p| {
+        let (da, db) = (a(p), b(p));
+        let h = (T::from(0.5).unwrap() + T::from(0.5).unwrap() * (db - da) / k)
+            .max(T::zero())
+            .min(T::one());
+
+        mix(db, da, h) - k * h * (T::one() - h)
+    })
+}
+
+// subtraction of b from a, with the cut edges rounded over a width of k
+pub fn smooth_subtraction<T: VertexFormat + 'static>(
+    a: DistanceFunction<T>,
+    b: DistanceFunction<T>,
+    k: T,
+) -> DistanceFunction<T> {
+    Box::new(move |p| {
+        let (da, db) = (a(p), b(p));
+        let h = (T::from(0.5).unwrap() - T::from(0.5).unwrap() * (da + db) / k)
+            .max(T::zero())
+            .min(T::one());
+
+        mix(da, -db, h) + k * h * (T::one() - h)
+    })
+}
+
+fn mix<T: VertexFormat>(a: T, b: T, amount: T) -> T {
+    a * (T::one() - amount) + b * amount
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trace_sphere() {
+        let shape = SdfShape::new(
+            translate(sphere(1.0_f64), Vec3::new(0.0, 0.0, -5.0)),
+            Vec3::new(0.0, 0.0, -5.0),
+        );
+
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+
+        let intersection = shape.intersect(&ray).unwrap();
+
+        assert!((intersection.point.z + 4.0).abs() < 1e-3);
+        assert!((intersection.normal.z - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn trace_miss() {
+        let shape = SdfShape::new(sphere(1.0_f64), Vec3::new(0.0, 0.0, 0.0));
+
+        let ray = Ray::new(Vec3::new(0.0, 2.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+
+        assert_eq!(None, shape.intersect(&ray));
+    }
+
+    #[test]
+    fn smooth_union_blends_below_union() {
+        let hard = union(
+            translate(sphere(1.0_f64), Vec3::new(-0.9, 0.0, 0.0)),
+            translate(sphere(1.0_f64), Vec3::new(0.9, 0.0, 0.0)),
+        );
+        let smooth = smooth_union(
+            translate(sphere(1.0_f64), Vec3::new(-0.9, 0.0, 0.0)),
+            translate(sphere(1.0_f64), Vec3::new(0.9, 0.0, 0.0)),
+            0.5,
+        );
+
+        // between the spheres, the blend fills in the crease
+        let crease = Vec3::new(0.0, 0.45, 0.0);
+        assert!(smooth(&crease) < hard(&crease));
+
+        // far away from the crease, both agree
+        let far = Vec3::new(-2.9, 0.0, 0.0);
+        assert!((smooth(&far) - hard(&far)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn repetition_is_periodic() {
+        let repeated = repetition(sphere(0.25_f64), Vec3::new(1.0, 0.0, 1.0));
+
+        let point = Vec3::new(0.1, 0.3, 0.2);
+        let shifted = Vec3::new(3.1, 0.3, -4.8);
+
+        assert!((repeated(&point) - repeated(&shifted)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn mandelbulb_surface_is_found() {
+        let mut shape = SdfShape::new(mandelbulb(8.0_f64, 8), Vec3::new(0.0, 0.0, 0.0));
+        shape.set_step_scale(0.8);
+
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 3.0), Vec3::new(0.0, 0.0, -1.0));
+
+        let intersection = shape.intersect(&ray).unwrap();
+
+        // the bulb lies within the bailout radius
+        assert!(intersection.point.mag_sqrd() < 4.0);
+        assert!(shape.distance(&intersection.point) < 1e-4);
+        assert!((intersection.normal.mag_sqrd() - 1.0).abs() < 1e-9);
+    }
+}
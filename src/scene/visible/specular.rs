@@ -0,0 +1,285 @@
+use crate::common::{Color, Intersection, Vec3, VertexFormat};
+use crate::sampler::{reflect, Sampler};
+use crate::scene::visible::bsdf::{schlick, Bsdf, ScatterSample};
+use crate::scene::visible::texture::luminance;
+
+// Perfectly smooth surfaces. All of their scattering happens in single directions, so evaluate
+// and pdf are zero, and the integrators only find these directions through sample.
+
+// Polished metal, reflecting like a mirror tinted by its color at normal incidence
+#[derive(Debug, Clone)]
+pub struct Conductor<T: VertexFormat> {
+    color: Color<T>,
+}
+
+impl<T: VertexFormat> Conductor<T> {
+    pub fn new(color: Color<T>) -> Conductor<T> {
+        Conductor { color }
+    }
+
+    fn reflectance(&self, intersection: &Intersection<T>, wo: &Vec3<T>) -> Vec3<T> {
+        let cos_o = intersection.normal.dot(wo).max(T::zero());
+
+        schlick(self.color.color_vector(), cos_o)
+    }
+}
+
+impl<T: VertexFormat> Bsdf<T> for Conductor<T> {
+    fn evaluate(&self, _intersection: &Intersection<T>, _wo: &Vec3<T>, _wi: &Vec3<T>) -> Vec3<T> {
+        Vec3::new(T::zero(), T::zero(), T::zero())
+    }
+
+    fn pdf(&self, _intersection: &Intersection<T>, _wo: &Vec3<T>, _wi: &Vec3<T>) -> T {
+        T::zero()
+    }
+
+    fn sample(
+        &self,
+        intersection: &Intersection<T>,
+        wo: &Vec3<T>,
+        _sampler: &mut dyn Sampler<T>,
+    ) -> Option<ScatterSample<T>> {
+        if intersection.normal.dot(wo) <= T::zero() {
+            return None;
+        }
+
+        Some(ScatterSample {
+            direction: reflect(wo, &intersection.normal),
+            weight: self.reflectance(intersection, wo),
+            pdf: T::one(),
+            specular: true,
+        })
+    }
+
+    fn is_reflective(&self) -> bool {
+        true
+    }
+
+    fn reflection_coefficient(&self, intersection: &Intersection<T>, wo: &Vec3<T>) -> T {
+        luminance(&self.reflectance(intersection, wo))
+    }
+}
+
+// Clear material like glass or water, with ior its index of refraction. Light passing through is
+// tinted by the color. Both sides of the surface are handled, with the intersection's front face
+// being the outside of the material.
+#[derive(Debug, Clone)]
+pub struct Dielectric<T: VertexFormat> {
+    ior: T,
+    color: Color<T>,
+}
+
+// how light meets the surface from the side of wo
+struct Interface<T: VertexFormat> {
+    // normal on the side of wo
+    normal: Vec3<T>,
+    // fraction reflected
+    fresnel: T,
+    // refracted direction, unless all of the light is reflected
+    refracted: Option<Vec3<T>>,
+}
+
+impl<T: VertexFormat> Dielectric<T> {
+    pub fn new(ior: T) -> Dielectric<T> {
+        Dielectric {
+            ior,
+            color: Color::new(T::one(), T::one(), T::one()).unwrap(),
+        }
+    }
+
+    pub fn set_color(&mut self, color: Color<T>) {
+        self.color = color;
+    }
+
+    fn interface(&self, intersection: &Intersection<T>, wo: &Vec3<T>) -> Interface<T> {
+        let mut normal = intersection.normal.clone();
+        let mut cos_o = normal.dot(wo);
+        if cos_o < T::zero() {
+            normal = normal.mul(T::one().neg());
+            cos_o = cos_o.neg();
+        }
+
+        // indices of refraction on the side of wo, and on the other side
+        let (mut eta_o, mut eta_t) = (T::one(), self.ior);
+        if !intersection.front_face {
+            std::mem::swap(&mut eta_o, &mut eta_t);
+        }
+
+        let eta = eta_o / eta_t;
+        let sin2_t = eta * eta * (T::one() - cos_o * cos_o);
+        if sin2_t >= T::one() {
+            return Interface {
+                normal,
+                fresnel: T::one(),
+                refracted: None,
+            };
+        }
+
+        let cos_t = (T::one() - sin2_t).sqrt();
+        let parallel = (eta_t * cos_o - eta_o * cos_t) / (eta_t * cos_o + eta_o * cos_t);
+        let perpendicular = (eta_o * cos_o - eta_t * cos_t) / (eta_o * cos_o + eta_t * cos_t);
+
+        Interface {
+            refracted: Some(wo.mul(eta.neg()).add(&normal.mul(eta * cos_o - cos_t))),
+            normal,
+            fresnel: (parallel * parallel + perpendicular * perpendicular) / T::from(2.0).unwrap(),
+        }
+    }
+}
+
+impl<T: VertexFormat> Bsdf<T> for Dielectric<T> {
+    fn evaluate(&self, _intersection: &Intersection<T>, _wo: &Vec3<T>, _wi: &Vec3<T>) -> Vec3<T> {
+        Vec3::new(T::zero(), T::zero(), T::zero())
+    }
+
+    fn pdf(&self, _intersection: &Intersection<T>, _wo: &Vec3<T>, _wi: &Vec3<T>) -> T {
+        T::zero()
+    }
+
+    // reflects or refracts with the chance of each, so the weight only holds the tint
+    fn sample(
+        &self,
+        intersection: &Intersection<T>,
+        wo: &Vec3<T>,
+        sampler: &mut dyn Sampler<T>,
+    ) -> Option<ScatterSample<T>> {
+        let interface = self.interface(intersection, wo);
+
+        match interface.refracted {
+            Some(direction) if sampler.next_1d() >= interface.fresnel => Some(ScatterSample {
+                direction,
+                weight: self.color.color_vector().clone(),
+                pdf: T::one() - interface.fresnel,
+                specular: true,
+            }),
+            _ => Some(ScatterSample {
+                direction: reflect(wo, &interface.normal),
+                weight: Vec3::new(T::one(), T::one(), T::one()),
+                pdf: interface.fresnel,
+                specular: true,
+            }),
+        }
+    }
+
+    fn is_reflective(&self) -> bool {
+        true
+    }
+
+    fn reflection_coefficient(&self, intersection: &Intersection<T>, wo: &Vec3<T>) -> T {
+        self.interface(intersection, wo).fresnel
+    }
+
+    fn refraction(
+        &self,
+        intersection: &Intersection<T>,
+        wo: &Vec3<T>,
+    ) -> Option<(Vec3<T>, Vec3<T>)> {
+        let interface = self.interface(intersection, wo);
+        let transmitted = T::one() - interface.fresnel;
+
+        interface
+            .refracted
+            .map(|direction| (direction, self.color.color_vector().mul(transmitted)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::Ray;
+    use crate::sampler::RandomSampler;
+    use crate::scene::visible::plane::Quad;
+    use crate::scene::visible::Intersectable;
+
+    fn surface() -> Intersection<f64> {
+        Intersection::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0))
+    }
+
+    #[test]
+    fn glass_reflects_four_percent_head_on() {
+        let glass = Dielectric::new(1.5);
+        let up = Vec3::new(0.0, 0.0, 1.0);
+
+        assert!((glass.reflection_coefficient(&surface(), &up) - 0.04).abs() < 1e-12);
+
+        let (direction, weight) = glass.refraction(&surface(), &up).unwrap();
+        assert!((direction.z + 1.0).abs() < 1e-12);
+        assert!((weight.x - 0.96).abs() < 1e-12);
+    }
+
+    #[test]
+    fn refraction_bends_towards_the_normal() {
+        let glass = Dielectric::new(1.5);
+        let wo = Vec3::new(0.6, 0.0, 0.8);
+
+        let (direction, _) = glass.refraction(&surface(), &wo).unwrap();
+
+        // Snell's law: sin_t = sin_o / 1.5
+        assert!((direction.x + 0.4).abs() < 1e-12);
+        assert!((direction.mag_sqrd() - 1.0).abs() < 1e-12);
+        assert!(direction.z < 0.0);
+    }
+
+    #[test]
+    fn total_internal_reflection_from_inside() {
+        let glass = Dielectric::new(1.5);
+        // leaving the glass at a grazing angle, from below the surface
+        let wo = Vec3::new(0.8, 0.0, -0.6);
+        let mut inside = surface();
+        inside.front_face = false;
+        let mut sampler = RandomSampler::new(0);
+
+        assert!(glass.refraction(&inside, &wo).is_none());
+        assert_eq!(1.0, glass.reflection_coefficient(&inside, &wo));
+
+        let sample = glass.sample(&inside, &wo, &mut sampler).unwrap();
+        assert_eq!(Vec3::new(-0.8, 0.0, -0.6), sample.direction);
+    }
+
+    #[test]
+    fn two_sided_surfaces_know_which_side_is_inside() {
+        let glass = Dielectric::new(1.5);
+        let quad = Quad::new(
+            Vec3::new(-1.0, -1.0, 0.0),
+            Vec3::new(2.0, 0.0, 0.0),
+            Vec3::new(0.0, 2.0, 0.0),
+        );
+
+        // the quad's normal points up the z axis, and is turned toward rays from below
+        let from_below = quad
+            .intersect(&Ray::new(
+                Vec3::new(0.8, 0.0, -0.6),
+                Vec3::new(-0.8, 0.0, 0.6),
+            ))
+            .unwrap();
+        let from_above = quad
+            .intersect(&Ray::new(
+                Vec3::new(0.8, 0.0, 0.6),
+                Vec3::new(-0.8, 0.0, -0.6),
+            ))
+            .unwrap();
+        assert_eq!(Vec3::new(0.0, 0.0, -1.0), from_below.normal);
+        assert!(!from_below.front_face);
+        assert!(from_above.front_face);
+
+        // at this grazing angle, light inside the glass is totally reflected
+        assert!(glass
+            .refraction(&from_below, &Vec3::new(0.8, 0.0, -0.6))
+            .is_none());
+        assert!(glass
+            .refraction(&from_above, &Vec3::new(0.8, 0.0, 0.6))
+            .is_some());
+    }
+
+    #[test]
+    fn conductor_reflects_its_color_head_on() {
+        let gold = Conductor::new(Color::new(1.0, 0.8, 0.3).unwrap());
+        let up = Vec3::new(0.0, 0.0, 1.0);
+        let mut sampler = RandomSampler::new(0);
+
+        let sample = gold.sample(&surface(), &up, &mut sampler).unwrap();
+
+        assert_eq!(up, sample.direction);
+        assert_eq!(Vec3::new(1.0, 0.8, 0.3), sample.weight);
+    }
+}
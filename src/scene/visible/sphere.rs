@@ -2,6 +2,8 @@ use crate::common::Ray;
 use crate::common::Vec3;
 use crate::common::VertexFormat;
 use crate::common::{Intersection, Spacial};
+use crate::polynomial::solve_quadratic;
+use crate::scene::visible::{intervals_from_hits, spherical_uv, Interval, Solid};
 use crate::scene::visible::{Intersectable, Visible};
 
 #[derive(Debug)]
@@ -42,9 +44,35 @@ impl<T: VertexFormat> Intersectable<T> for Sphere<T> {
         }
 
         let point = ray.origin.add(&ray.direction.mul(t));
-        let mut normal = point.sub(&self.center).div(self.radius);
+        let normal = point.sub(&self.center).div(self.radius);
 
-        Some(Intersection { point, normal })
+        // derivatives of the point along the u and v of the spherical mapping
+        let tangent = Vec3::new(normal.z, T::zero(), normal.x.neg());
+        let bitangent = normal.cross(&tangent);
+
+        let mut intersection = Intersection::new(point, normal);
+        intersection.uv = spherical_uv(&intersection.normal);
+        intersection.set_tangent_frame(&tangent, &bitangent);
+        intersection.front_face = !inside_sphere;
+
+        Some(intersection)
+    }
+}
+
+impl<T: VertexFormat> Solid<T> for Sphere<T> {
+    fn intervals(&self, ray: &Ray<T>) -> Vec<Interval<T>> {
+        let oc = ray.origin.sub(&self.center);
+
+        let hits = solve_quadratic(
+            T::one(),
+            T::from(2.0).unwrap() * oc.dot(&ray.direction),
+            oc.mag_sqrd() - self.radius * self.radius,
+        )
+        .into_iter()
+        .map(|t| (t, oc.add(&ray.direction.mul(t)).div(self.radius)))
+        .collect();
+
+        intervals_from_hits(ray, hits, &|_, normal| spherical_uv(normal))
     }
 }
 
@@ -108,4 +136,16 @@ mod tests {
         assert_eq!(None, intersection);
         // let ray = Ray::new(, )
     }
+
+    #[test]
+    fn tangent_frame_follows_spherical_uv() {
+        let sphere = Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0);
+
+        let ray = Ray::new(Vec3::new(10.0, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0));
+
+        let intersection = sphere.intersect(&ray).unwrap();
+
+        assert_eq!(Vec3::new(0.0, 0.0, -1.0), intersection.tangent);
+        assert_eq!(Vec3::new(0.0, 1.0, 0.0), intersection.bitangent);
+    }
 }
@@ -0,0 +1,343 @@
+use crate::common::{Color, Intersection, Vec3, VertexFormat};
+use crate::sampler::{cosine_hemisphere, reflect, to_world, Sampler};
+use crate::scene::light::IncidentLight;
+use crate::scene::medium::Medium;
+use crate::scene::visible::bsdf::{schlick, Bsdf, ScatterSample};
+
+// Translucent material like skin, wax or marble, where light enters the surface, scatters around
+// inside and leaves again somewhere else. The path tracer follows it on a random walk through the
+// medium inside the shape, which needs to be closed, like a sphere or a closed mesh. Light crosses
+// the smooth surface as diffuse transmission, and some of it is reflected off the surface like
+// off of glass.
+#[derive(Debug, Clone)]
+pub struct Subsurface<T: VertexFormat> {
+    albedo: Vec3<T>,
+    mean_free_path: Vec3<T>,
+    asymmetry: T,
+    ior: T,
+    medium: Medium<T>,
+}
+
+impl<T: VertexFormat> Subsurface<T> {
+    // The albedo is the color of the material once all the scattering inside is done, and the
+    // mean free path the average distance light travels inside between scattering, for each color.
+    // Light travelling further in red than in blue gives skin its look.
+    pub fn new(albedo: Color<T>, mean_free_path: Vec3<T>) -> Subsurface<T> {
+        let albedo = albedo.color_vector().clone();
+        let medium = interior_medium(&albedo, &mean_free_path, T::zero());
+
+        Subsurface {
+            albedo,
+            mean_free_path,
+            asymmetry: T::zero(),
+            ior: T::from(1.4).unwrap(),
+            medium,
+        }
+    }
+
+    pub fn set_ior(&mut self, ior: T) {
+        self.ior = ior;
+    }
+
+    // of the Henyey-Greenstein phase function of the medium inside
+    pub fn set_asymmetry(&mut self, asymmetry: T) {
+        self.asymmetry = asymmetry;
+        self.medium = interior_medium(&self.albedo, &self.mean_free_path, asymmetry);
+    }
+
+    fn fresnel(&self, cos: T) -> T {
+        let f0 = ((self.ior - T::one()) / (self.ior + T::one())).powi(2);
+
+        schlick(&Vec3::new(f0, f0, f0), cos.abs()).x
+    }
+
+    // chance of light leaving from the inside to be reflected back in: the cosine weighted
+    // average of the Fresnel term, which for Schlick's approximation is f0 + (1 - f0) / 21
+    fn internal_reflectance(&self) -> T {
+        let f0 = ((self.ior - T::one()) / (self.ior + T::one())).powi(2);
+
+        f0 + (T::one() - f0) / T::from(21.0).unwrap()
+    }
+
+    fn cosine_direction(
+        intersection: &Intersection<T>,
+        sign: T,
+        sampler: &mut dyn Sampler<T>,
+    ) -> Vec3<T> {
+        let local = cosine_hemisphere(sampler.next_2d());
+
+        to_world(
+            &local,
+            &intersection.tangent,
+            &intersection.bitangent,
+            &intersection.normal.mul(sign),
+        )
+    }
+}
+
+// Medium giving the albedo after many scattering events, with the mapping from Chiang et al.'s
+// "Practical and Controllable Subsurface Scattering" to the albedo of a single one
+fn interior_medium<T: VertexFormat>(
+    albedo: &Vec3<T>,
+    mean_free_path: &Vec3<T>,
+    asymmetry: T,
+) -> Medium<T> {
+    let single = |a: T| {
+        let a = a.max(T::zero()).min(T::one());
+        let root = (T::from(9.59217).unwrap()
+            + T::from(41.6808).unwrap() * a
+            + T::from(17.7126).unwrap() * a * a)
+            .sqrt();
+
+        T::one()
+            - (T::from(4.09712).unwrap() + T::from(4.20863).unwrap() * a - root)
+                .powi(2)
+                .min(T::one())
+    };
+    let extinction = |path: T| T::one() / path.max(T::from(1e-6).unwrap());
+
+    let extinction = Vec3::new(
+        extinction(mean_free_path.x),
+        extinction(mean_free_path.y),
+        extinction(mean_free_path.z),
+    );
+    let scattering = Vec3::new(
+        single(albedo.x) * extinction.x,
+        single(albedo.y) * extinction.y,
+        single(albedo.z) * extinction.z,
+    );
+
+    Medium::new(extinction.sub(&scattering), scattering, asymmetry)
+}
+
+impl<T: VertexFormat> Bsdf<T> for Subsurface<T> {
+    // only light coming from the inside can be evaluated, whether it leaves or is reflected back
+    // in, the reflection off the outside being perfectly specular
+    fn evaluate(&self, intersection: &Intersection<T>, wo: &Vec3<T>, wi: &Vec3<T>) -> Vec3<T> {
+        let pdf = self.pdf(intersection, wo, wi);
+        if intersection.normal.dot(wo) >= T::zero() || pdf <= T::zero() {
+            return Vec3::new(T::zero(), T::zero(), T::zero());
+        }
+
+        Vec3::new(pdf, pdf, pdf)
+    }
+
+    fn pdf(&self, intersection: &Intersection<T>, wo: &Vec3<T>, wi: &Vec3<T>) -> T {
+        let pi = T::from(std::f64::consts::PI).unwrap();
+        let cos_o = intersection.normal.dot(wo);
+        let cos_i = intersection.normal.dot(wi);
+
+        if cos_o > T::zero() && cos_i < T::zero() {
+            // entering, from outside
+            (T::one() - self.fresnel(cos_o)) * cos_i.neg() / pi
+        } else if cos_o < T::zero() && cos_i > T::zero() {
+            // leaving, from the inside
+            (T::one() - self.fresnel(cos_i)) * cos_i / pi
+        } else if cos_o < T::zero() && cos_i < T::zero() {
+            // reflected back in, which happens to every direction that would have left with its
+            // Fresnel chance
+            self.internal_reflectance() * cos_i.neg() / pi
+        } else {
+            T::zero()
+        }
+    }
+
+    fn sample(
+        &self,
+        intersection: &Intersection<T>,
+        wo: &Vec3<T>,
+        sampler: &mut dyn Sampler<T>,
+    ) -> Option<ScatterSample<T>> {
+        let one = Vec3::new(T::one(), T::one(), T::one());
+        let cos_o = intersection.normal.dot(wo);
+
+        if cos_o > T::zero() {
+            let fresnel = self.fresnel(cos_o);
+            if sampler.next_1d() < fresnel {
+                return Some(ScatterSample {
+                    direction: reflect(wo, &intersection.normal),
+                    weight: one,
+                    pdf: fresnel,
+                    specular: true,
+                });
+            }
+
+            let direction = Subsurface::cosine_direction(intersection, T::one().neg(), sampler);
+            let pdf = self.pdf(intersection, wo, &direction);
+            return Some(ScatterSample {
+                direction,
+                weight: one,
+                pdf,
+                specular: false,
+            });
+        }
+
+        // leaving from the inside, unless the surface reflects it back in
+        let direction = Subsurface::cosine_direction(intersection, T::one(), sampler);
+        let cos_i = intersection.normal.dot(&direction);
+        if sampler.next_1d() < self.fresnel(cos_i) {
+            let direction = Subsurface::cosine_direction(intersection, T::one().neg(), sampler);
+            let pdf = self.pdf(intersection, wo, &direction);
+            return Some(ScatterSample {
+                direction,
+                weight: one,
+                pdf,
+                specular: false,
+            });
+        }
+
+        let pdf = self.pdf(intersection, wo, &direction);
+        Some(ScatterSample {
+            direction,
+            weight: one,
+            pdf,
+            specular: false,
+        })
+    }
+
+    fn interior(&self) -> Option<&Medium<T>> {
+        Some(&self.medium)
+    }
+
+    // Without a random walk, the light coming back out is taken to leave right where it entered,
+    // like off of a diffuse surface with the albedo
+    fn shade(
+        &self,
+        intersection: &Intersection<T>,
+        lights: &[IncidentLight<T>],
+        viewpoint: &Vec3<T>,
+    ) -> Vec3<T> {
+        let wo = viewpoint.sub(&intersection.point).normalize();
+        let through = T::one() - self.fresnel(intersection.normal.dot(&wo));
+
+        let mut color = Vec3::new(T::zero(), T::zero(), T::zero());
+        for light in lights {
+            let wi = light.light_vector(&intersection.point);
+            let cos_i = intersection.normal.dot(&wi).max(T::zero());
+
+            color.mut_add(
+                &light
+                    .color_at(&intersection.point)
+                    .scalar_mul(&self.albedo)
+                    .mul(through * (T::one() - self.fresnel(cos_i)) * cos_i),
+            );
+        }
+
+        color
+    }
+
+    fn is_reflective(&self) -> bool {
+        true
+    }
+
+    fn reflection_coefficient(&self, intersection: &Intersection<T>, wo: &Vec3<T>) -> T {
+        self.fresnel(intersection.normal.dot(wo))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::RandomSampler;
+
+    fn skin() -> Subsurface<f64> {
+        Subsurface::new(Color::new(0.8, 0.5, 0.4).unwrap(), Vec3::new(1.0, 0.4, 0.2))
+    }
+
+    #[test]
+    fn single_scattering_albedo_is_higher() {
+        let material = skin();
+        let medium = material.interior().unwrap();
+        let single = medium.scattering().x / medium.extinction().x;
+
+        // it takes a brighter medium to keep the same color after many bounces
+        assert!(single > 0.8 && single < 1.0);
+        assert!((medium.extinction().x - 1.0).abs() < 1e-12);
+        assert!((medium.extinction().z - 5.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn light_enters_or_reflects_from_outside() {
+        let material = skin();
+        let intersection = Intersection::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        let wo = Vec3::new(0.0, 1.0, 1.0).normalize();
+        let mut sampler = RandomSampler::new(2);
+
+        for _ in 0..200 {
+            let sample = material.sample(&intersection, &wo, &mut sampler).unwrap();
+            if sample.specular {
+                assert!(sample.direction.y > 0.0);
+            } else {
+                assert!(sample.direction.y < 0.0);
+                assert_eq!(
+                    Vec3::new(0.0, 0.0, 0.0),
+                    material.evaluate(&intersection, &wo, &sample.direction)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn leaving_light_matches_evaluate() {
+        let material = skin();
+        let intersection = Intersection::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        // from inside the material
+        let wo = Vec3::new(0.0, -1.0, 0.0);
+        let mut sampler = RandomSampler::new(4);
+
+        // the chance of leaving is the cosine weighted average of the transmitted fraction
+        let count = 20000;
+        let mut left = 0.0;
+        for _ in 0..count {
+            let sample = material.sample(&intersection, &wo, &mut sampler).unwrap();
+            if sample.direction.y > 0.0 {
+                left += 1.0;
+            }
+        }
+
+        let steps = 1000;
+        let mut expected = 0.0;
+        for i in 0..steps {
+            let cos = (i as f64 + 0.5) / steps as f64;
+            let wi = Vec3::new((1.0 - cos * cos).sqrt(), cos, 0.0);
+            // evaluate includes the cosine, over the hemisphere's solid angle
+            expected += material.evaluate(&intersection, &wo, &wi).x * 2.0 * std::f64::consts::PI
+                / steps as f64;
+        }
+
+        assert!((left / count as f64 - expected).abs() < 0.01);
+    }
+
+    #[test]
+    fn light_reflected_back_in_matches_pdf() {
+        let material = skin();
+        let intersection = Intersection::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        let wo = Vec3::new(0.0, -1.0, 1.0).normalize();
+        let mut sampler = RandomSampler::new(6);
+
+        let count = 20000;
+        let mut reflected = 0.0;
+        for _ in 0..count {
+            let sample = material.sample(&intersection, &wo, &mut sampler).unwrap();
+            let pdf = material.pdf(&intersection, &wo, &sample.direction);
+            assert!(!sample.specular);
+            assert!((sample.pdf - pdf).abs() < 1e-12);
+            if sample.direction.y < 0.0 {
+                assert!(pdf > 0.0);
+                reflected += 1.0;
+            }
+        }
+
+        // the pdf over the directions back in integrates to how often they are sampled
+        let steps = 1000;
+        let mut expected = 0.0;
+        for i in 0..steps {
+            let cos = (i as f64 + 0.5) / steps as f64;
+            let wi = Vec3::new((1.0 - cos * cos).sqrt(), -cos, 0.0);
+            expected +=
+                material.pdf(&intersection, &wo, &wi) * 2.0 * std::f64::consts::PI / steps as f64;
+        }
+
+        assert!((reflected / count as f64 - expected).abs() < 0.01);
+    }
+}
@@ -0,0 +1,228 @@
+use crate::common::{Color, Intersection, Vec3, VertexFormat};
+use crate::image::Image;
+use std::fmt::Debug;
+
+// Surface property that varies over a shape, looked up by the intersection's texture coordinates
+// or position
+pub trait Texture<T: VertexFormat>: Debug {
+    fn value(&self, intersection: &Intersection<T>) -> Vec3<T>;
+}
+
+// Perceived brightness of a color, used where a texture drives a single value
+pub fn luminance<T: VertexFormat>(color: &Vec3<T>) -> T {
+    color.x * T::from(0.2126).unwrap()
+        + color.y * T::from(0.7152).unwrap()
+        + color.z * T::from(0.0722).unwrap()
+}
+
+// Same color everywhere. Mostly useful as an input to the procedural textures.
+#[derive(Debug)]
+pub struct ConstantTexture<T: VertexFormat> {
+    color: Vec3<T>,
+}
+
+impl<T: VertexFormat> ConstantTexture<T> {
+    pub fn new(color: Color<T>) -> ConstantTexture<T> {
+        ConstantTexture {
+            color: color.color_vector().clone(),
+        }
+    }
+}
+
+impl<T: VertexFormat> Texture<T> for ConstantTexture<T> {
+    fn value(&self, _intersection: &Intersection<T>) -> Vec3<T> {
+        self.color.clone()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Filter {
+    // color of the texel the coordinates fall into
+    Nearest,
+    // weighted average of the four texels closest to the coordinates
+    Bilinear,
+}
+
+// How texture coordinates outside of [0, 1] are mapped back onto the image
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WrapMode {
+    Repeat,
+    Clamp,
+    // repeats the image, flipping every other copy
+    Mirror,
+}
+
+impl WrapMode {
+    fn wrap(&self, index: i64, size: u32) -> u32 {
+        let size = size as i64;
+
+        let wrapped = match self {
+            WrapMode::Repeat => index.rem_euclid(size),
+            WrapMode::Clamp => index.max(0).min(size - 1),
+            WrapMode::Mirror => {
+                let index = index.rem_euclid(2 * size);
+                if index < size {
+                    index
+                } else {
+                    2 * size - 1 - index
+                }
+            }
+        };
+
+        wrapped as u32
+    }
+
+    // Texel column or row that a coordinate falls into, before wrapping. The coordinate is
+    // wrapped in floating point first, so coordinates too far out to fit an integer still land
+    // on the texel they wrap to.
+    fn index<T: VertexFormat>(&self, coordinate: T, size: u32) -> i64 {
+        let size = T::from(size).unwrap();
+        let coordinate = match self {
+            WrapMode::Repeat => coordinate % size,
+            WrapMode::Clamp => coordinate.max(T::one().neg()).min(size),
+            WrapMode::Mirror => coordinate % (size + size),
+        };
+
+        coordinate.floor().to_i64().unwrap_or(0)
+    }
+}
+
+// Texture looked up from an image. The image covers the unit square of texture coordinates, with
+// v = 0 at its bottom row.
+#[derive(Debug)]
+pub struct ImageTexture<T: VertexFormat> {
+    image: Image<T>,
+    filter: Filter,
+    wrap: WrapMode,
+}
+
+impl<T: VertexFormat> ImageTexture<T> {
+    pub fn new(image: Image<T>, filter: Filter, wrap: WrapMode) -> ImageTexture<T> {
+        ImageTexture {
+            image,
+            filter,
+            wrap,
+        }
+    }
+
+    fn texel(&self, x: i64, y: i64) -> &Vec3<T> {
+        self.image
+            .pixel(
+                self.wrap.wrap(x, self.image.width()),
+                self.wrap.wrap(y, self.image.height()),
+            )
+            .color_vector()
+    }
+
+    // coordinates that aren't finite, as from degenerate hits, sample the corner at the origin
+    pub fn sample(&self, u: T, v: T) -> Vec3<T> {
+        let finite_or_zero = |coordinate: T| {
+            if coordinate.is_finite() {
+                coordinate
+            } else {
+                T::zero()
+            }
+        };
+        let (u, v) = (finite_or_zero(u), finite_or_zero(v));
+        let x = u * T::from(self.image.width()).unwrap();
+        let y = v * T::from(self.image.height()).unwrap();
+
+        match self.filter {
+            Filter::Nearest => self
+                .texel(
+                    self.wrap.index(x, self.image.width()),
+                    self.wrap.index(y, self.image.height()),
+                )
+                .clone(),
+            Filter::Bilinear => {
+                // texel centers are at half integer positions
+                let half = T::from(0.5).unwrap();
+                let x = x - half;
+                let y = y - half;
+                let (x0, y0) = (x.floor(), y.floor());
+                let (fx, fy) = (x - x0, y - y0);
+                let (x0, y0) = (
+                    self.wrap.index(x0, self.image.width()),
+                    self.wrap.index(y0, self.image.height()),
+                );
+
+                let bottom = self
+                    .texel(x0, y0)
+                    .mul(T::one() - fx)
+                    .add(&self.texel(x0 + 1, y0).mul(fx));
+                let top = self
+                    .texel(x0, y0 + 1)
+                    .mul(T::one() - fx)
+                    .add(&self.texel(x0 + 1, y0 + 1).mul(fx));
+
+                bottom.mul(T::one() - fy).add(&top.mul(fy))
+            }
+        }
+    }
+}
+
+impl<T: VertexFormat> Texture<T> for ImageTexture<T> {
+    fn value(&self, intersection: &Intersection<T>) -> Vec3<T> {
+        self.sample(intersection.uv.0, intersection.uv.1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2x1 image, black on the left and white on the right
+    fn black_white(filter: Filter, wrap: WrapMode) -> ImageTexture<f64> {
+        let mut image = Image::new(2, 1);
+        image.set_pixel(1, 0, Color::new(1.0, 1.0, 1.0).unwrap());
+
+        ImageTexture::new(image, filter, wrap)
+    }
+
+    #[test]
+    fn nearest_sampling() {
+        let texture = black_white(Filter::Nearest, WrapMode::Repeat);
+
+        assert_eq!(Vec3::new(0.0, 0.0, 0.0), texture.sample(0.25, 0.5));
+        assert_eq!(Vec3::new(1.0, 1.0, 1.0), texture.sample(0.75, 0.5));
+    }
+
+    #[test]
+    fn bilinear_sampling_blends_texels() {
+        let texture = black_white(Filter::Bilinear, WrapMode::Clamp);
+
+        assert_eq!(Vec3::new(0.5, 0.5, 0.5), texture.sample(0.5, 0.5));
+        // clamped to the edge texel beyond its center
+        assert_eq!(Vec3::new(1.0, 1.0, 1.0), texture.sample(1.0, 0.5));
+    }
+
+    #[test]
+    fn wrap_modes() {
+        let repeat = black_white(Filter::Nearest, WrapMode::Repeat);
+        let clamp = black_white(Filter::Nearest, WrapMode::Clamp);
+        let mirror = black_white(Filter::Nearest, WrapMode::Mirror);
+
+        assert_eq!(Vec3::new(0.0, 0.0, 0.0), repeat.sample(1.25, 0.5));
+        assert_eq!(Vec3::new(1.0, 1.0, 1.0), clamp.sample(1.25, 0.5));
+        assert_eq!(Vec3::new(1.0, 1.0, 1.0), mirror.sample(1.25, 0.5));
+        assert_eq!(Vec3::new(0.0, 0.0, 0.0), mirror.sample(-0.25, 0.5));
+    }
+
+    #[test]
+    fn coordinates_far_out_or_not_numbers_still_sample() {
+        for &filter in &[Filter::Nearest, Filter::Bilinear] {
+            for &wrap in &[WrapMode::Repeat, WrapMode::Clamp, WrapMode::Mirror] {
+                let texture = black_white(filter, wrap);
+
+                for &(u, v) in &[(1e20, 0.5), (-1e20, 0.5), (f64::NAN, f64::INFINITY)] {
+                    let color = texture.sample(u, v);
+                    assert!(color.x >= 0.0 && color.x <= 1.0);
+                }
+            }
+        }
+
+        // 1e20 is a whole number of repeats, so it lands on the left edge
+        let repeat = black_white(Filter::Nearest, WrapMode::Repeat);
+        assert_eq!(Vec3::new(0.0, 0.0, 0.0), repeat.sample(1e20, 0.5));
+    }
+}
@@ -0,0 +1,172 @@
+use crate::common::{Intersection, Ray, Spacial, Vec3, VertexFormat};
+use crate::polynomial::solve_quartic;
+use crate::scene::visible::{
+    angle_around, intervals_from_hits, nearest_hit, Intersectable, Interval, Solid,
+};
+
+// Ring shaped torus. The tube of radius minor_radius runs along a circle of radius major_radius
+// around the center, in the plane perpendicular to axis.
+#[derive(Debug)]
+pub struct Torus<T: VertexFormat> {
+    center: Vec3<T>,
+    axis: Vec3<T>,
+    major_radius: T,
+    minor_radius: T,
+}
+
+impl<T: VertexFormat> Torus<T> {
+    pub fn new(center: Vec3<T>, axis: Vec3<T>, major_radius: T, minor_radius: T) -> Torus<T> {
+        Torus {
+            center,
+            axis: axis.normalize(),
+            major_radius,
+            minor_radius,
+        }
+    }
+
+    fn normal(&self, point: &Vec3<T>) -> Vec3<T> {
+        let p = point.sub(&self.center);
+        let along = p.dot(&self.axis);
+
+        // nearest point on the circle running through the middle of the tube
+        let core = p
+            .sub(&self.axis.mul(along))
+            .normalize()
+            .mul(self.major_radius);
+
+        p.sub(&core).normalize()
+    }
+
+    // u runs around the axis of the torus, and v around the tube
+    fn uv(&self, point: &Vec3<T>) -> (T, T) {
+        let pi = T::from(std::f64::consts::PI).unwrap();
+        let p = point.sub(&self.center);
+        let along = p.dot(&self.axis);
+        let radial = p.sub(&self.axis.mul(along));
+
+        let tube_angle = along.atan2(radial.mag_sqrd().sqrt() - self.major_radius);
+
+        (
+            angle_around(&radial, &self.axis),
+            (tube_angle + pi) / (T::from(2.0).unwrap() * pi),
+        )
+    }
+
+    // every hit of the ray's line with the torus, with outward facing normals
+    fn hits(&self, ray: &Ray<T>) -> Vec<(T, Vec3<T>)> {
+        let two = T::from(2.0).unwrap();
+        let four = T::from(4.0).unwrap();
+
+        // solving relative to the point on the ray closest to the center keeps the quartic's
+        // coefficients small, which greatly improves precision for distant ray origins
+        let shift = -ray.origin.sub(&self.center).dot(&ray.direction);
+        let o = ray.origin.add(&ray.direction.mul(shift)).sub(&self.center);
+        let d = &ray.direction;
+
+        let r2 = self.major_radius * self.major_radius;
+        let o_d = o.dot(d);
+        let o_along = o.dot(&self.axis);
+        let d_along = d.dot(&self.axis);
+        let o_o = o.mag_sqrd();
+        let c0 = o_o + r2 - self.minor_radius * self.minor_radius;
+
+        // (|p|^2 + R^2 - r^2)^2 = 4 R^2 |p_perp|^2, with p = o + t d
+        let roots = solve_quartic(
+            T::one(),
+            four * o_d,
+            four * o_d * o_d + two * c0 - four * r2 * (T::one() - d_along * d_along),
+            four * o_d * c0 - T::from(8.0).unwrap() * r2 * (o_d - o_along * d_along),
+            c0 * c0 - four * r2 * (o_o - o_along * o_along),
+        );
+
+        roots
+            .into_iter()
+            .map(|s| {
+                let t = s + shift;
+                let point = ray.origin.add(&ray.direction.mul(t));
+                (t, self.normal(&point))
+            })
+            .collect()
+    }
+}
+
+impl<T: VertexFormat> Intersectable<T> for Torus<T> {
+    fn intersect(&self, ray: &Ray<T>) -> Option<Intersection<T>> {
+        nearest_hit(ray, &self.hits(ray), false, &|point, _| self.uv(point))
+    }
+}
+
+impl<T: VertexFormat> Solid<T> for Torus<T> {
+    fn intervals(&self, ray: &Ray<T>) -> Vec<Interval<T>> {
+        intervals_from_hits(ray, self.hits(ray), &|point, _| self.uv(point))
+    }
+}
+
+impl<T: VertexFormat> Spacial<T> for Torus<T> {
+    fn location(&self) -> &Vec3<T> {
+        &self.center
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(expected: &Vec3<f64>, actual: &Vec3<f64>) {
+        assert!(
+            expected.sub(actual).mag_sqrd() < 1e-12,
+            "expected {:?}, got {:?}",
+            expected,
+            actual
+        );
+    }
+
+    #[test]
+    fn torus_outer_intersection() {
+        let torus = Torus::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            1.0,
+            0.25,
+        );
+
+        let ray = Ray::new(Vec3::new(10.0, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0));
+
+        let intersection = torus.intersect(&ray).unwrap();
+
+        assert_close(&Vec3::new(1.25, 0.0, 0.0), &intersection.point);
+        assert_close(&Vec3::new(1.0, 0.0, 0.0), &intersection.normal);
+    }
+
+    #[test]
+    fn torus_hole_miss() {
+        let torus = Torus::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            1.0,
+            0.25,
+        );
+
+        let ray = Ray::new(Vec3::new(0.0, 10.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+
+        assert_eq!(None, torus.intersect(&ray));
+    }
+
+    #[test]
+    fn torus_from_inside_tube() {
+        let torus = Torus::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            1.0,
+            0.25,
+        );
+
+        // starts in the middle of the tube, heading towards the center of the torus
+        let ray = Ray::new(Vec3::new(1.0, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0));
+
+        let intersection = torus.intersect(&ray).unwrap();
+
+        assert_close(&Vec3::new(0.75, 0.0, 0.0), &intersection.point);
+        assert_close(&Vec3::new(-1.0, 0.0, 0.0), &intersection.normal);
+    }
+}
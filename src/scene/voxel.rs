@@ -0,0 +1,281 @@
+use crate::common::{Color, Ray, Vec3, VertexFormat};
+use crate::noise::fbm;
+use crate::sampler::Sampler;
+use crate::scene::medium::{clamp_asymmetry, henyey_greenstein, sample_henyey_greenstein};
+use crate::scene::visible::cuboid::AxisAlignedBox;
+use crate::scene::visible::Solid;
+
+// Densities on a regular grid of voxels, with x varying fastest, then y, then z. Values are taken
+// at the centers of the voxels, and interpolated in between.
+#[derive(Debug, Clone)]
+pub struct DensityGrid<T: VertexFormat> {
+    size: [usize; 3],
+    values: Vec<T>,
+    max: T,
+}
+
+impl<T: VertexFormat> DensityGrid<T> {
+    pub fn new(size: (usize, usize, usize), values: Vec<T>) -> DensityGrid<T> {
+        assert_eq!(size.0 * size.1 * size.2, values.len());
+        let max = values.iter().fold(T::zero(), |max, value| max.max(*value));
+
+        DensityGrid {
+            size: [size.0, size.1, size.2],
+            values,
+            max,
+        }
+    }
+
+    // A puff of smoke or cloud made of fractal noise, thinning out towards the edges of the grid.
+    // A larger scale gives smaller features.
+    pub fn from_noise(size: (usize, usize, usize), scale: T, seed: u64) -> DensityGrid<T> {
+        let half = T::from(0.5).unwrap();
+        let two = T::from(2.0).unwrap();
+        let mut values = Vec::with_capacity(size.0 * size.1 * size.2);
+
+        for z in 0..size.2 {
+            for y in 0..size.1 {
+                for x in 0..size.0 {
+                    let center = |i: usize, n: usize| {
+                        (T::from(i).unwrap() + half) / T::from(n).unwrap() - half
+                    };
+                    let p = Vec3::new(center(x, size.0), center(y, size.1), center(z, size.2));
+
+                    // one at the center of the grid, down to nothing at the sphere touching its
+                    // sides
+                    let falloff = (T::one() - p.mag_sqrd().sqrt() * two).max(T::zero());
+                    let noise = fbm(&p.mul(scale), 4, two, half, seed);
+
+                    values.push((falloff * two + noise - half).max(T::zero()) * falloff);
+                }
+            }
+        }
+
+        DensityGrid::new(size, values)
+    }
+
+    pub fn max(&self) -> T {
+        self.max
+    }
+
+    fn value(&self, x: usize, y: usize, z: usize) -> T {
+        self.values[(z * self.size[1] + y) * self.size[0] + x]
+    }
+
+    // density at a point given in [0, 1] across the grid along each axis
+    pub fn density(&self, point: &Vec3<T>) -> T {
+        let half = T::from(0.5).unwrap();
+        let coordinates = [point.x, point.y, point.z];
+
+        // voxel below the point on each axis, and how far the point is towards the next one
+        let mut cells = [(0, 0, T::zero()); 3];
+        for (axis, cell) in cells.iter_mut().enumerate() {
+            let n = self.size[axis];
+            let position = (coordinates[axis] * T::from(n).unwrap() - half)
+                .max(T::zero())
+                .min(T::from(n - 1).unwrap());
+            let low = position.floor().to_usize().unwrap_or(0).min(n - 1);
+
+            *cell = (low, (low + 1).min(n - 1), position - T::from(low).unwrap());
+        }
+
+        let lerp = |a: T, b: T, t: T| a + (b - a) * t;
+        let [(x0, x1, tx), (y0, y1, ty), (z0, z1, tz)] = cells;
+        let plane = |z: usize| {
+            lerp(
+                lerp(self.value(x0, y0, z), self.value(x1, y0, z), tx),
+                lerp(self.value(x0, y1, z), self.value(x1, y1, z), tx),
+                ty,
+            )
+        };
+
+        lerp(plane(z0), plane(z1), tz)
+    }
+}
+
+// Medium whose density varies through a box, following a grid. The extinction at a point is its
+// density times the volume's own density, which is the same for every color. Rendered with delta
+// and ratio tracking, against the grid's highest density as the majorant.
+pub struct GridVolume<T: VertexFormat> {
+    grid: DensityGrid<T>,
+    bounds: AxisAlignedBox<T>,
+    density: T,
+    albedo: Vec3<T>,
+    asymmetry: T,
+}
+
+impl<T: VertexFormat> GridVolume<T> {
+    // the grid is stretched to fill the box between the two corners
+    pub fn new(grid: DensityGrid<T>, corner1: Vec3<T>, corner2: Vec3<T>) -> GridVolume<T> {
+        GridVolume {
+            grid,
+            bounds: AxisAlignedBox::new(corner1, corner2),
+            density: T::one(),
+            albedo: Vec3::new(T::one(), T::one(), T::one()),
+            asymmetry: T::zero(),
+        }
+    }
+
+    // extinction per unit of distance where the grid's density is one
+    pub fn set_density(&mut self, density: T) {
+        self.density = density;
+    }
+
+    // fraction of the light lost to the medium that's scattered rather than absorbed
+    pub fn set_albedo(&mut self, albedo: Color<T>) {
+        self.albedo = albedo.color_vector().clone();
+    }
+
+    // of the Henyey-Greenstein phase function, from -1 for scattering back to 1 for forward
+    pub fn set_asymmetry(&mut self, asymmetry: T) {
+        self.asymmetry = clamp_asymmetry(asymmetry);
+    }
+
+    pub fn albedo(&self) -> &Vec3<T> {
+        &self.albedo
+    }
+
+    pub fn extinction(&self, point: &Vec3<T>) -> T {
+        let (min, max) = (self.bounds.min(), self.bounds.max());
+        let local = Vec3::new(
+            (point.x - min.x) / (max.x - min.x),
+            (point.y - min.y) / (max.y - min.y),
+            (point.z - min.z) / (max.z - min.z),
+        );
+
+        self.grid.density(&local) * self.density
+    }
+
+    fn majorant(&self) -> T {
+        self.grid.max() * self.density
+    }
+
+    pub fn phase(&self, direction: &Vec3<T>, wi: &Vec3<T>) -> T {
+        henyey_greenstein(self.asymmetry, direction, wi)
+    }
+
+    pub fn sample_phase(&self, direction: &Vec3<T>, sample: (T, T)) -> Vec3<T> {
+        sample_henyey_greenstein(self.asymmetry, direction, sample)
+    }
+
+    // part of the ray inside the box and short of the distance
+    fn span(&self, ray: &Ray<T>, distance: T) -> Option<(T, T)> {
+        let interval = self.bounds.intervals(ray).into_iter().next()?;
+        let (start, end) = (
+            interval.entry.t.max(T::zero()),
+            interval.exit.t.min(distance),
+        );
+
+        if start < end && self.majorant() > T::zero() {
+            Some((start, end))
+        } else {
+            None
+        }
+    }
+
+    // Delta tracking: distance along the ray, short of the given one, at which it collides with
+    // the medium. Tentative collisions are drawn against the majorant, and kept with the chance
+    // that the density there makes them real rather than null.
+    pub fn sample_collision(
+        &self,
+        ray: &Ray<T>,
+        distance: T,
+        sampler: &mut dyn Sampler<T>,
+    ) -> Option<T> {
+        let (mut t, end) = self.span(ray, distance)?;
+        let majorant = self.majorant();
+
+        loop {
+            t = t + (T::one() - sampler.next_1d()).ln().neg() / majorant;
+            if t >= end {
+                return None;
+            }
+
+            let point = ray.origin.add(&ray.direction.mul(t));
+            if sampler.next_1d() * majorant < self.extinction(&point) {
+                return Some(t);
+            }
+        }
+    }
+
+    // Ratio tracking: an unbiased estimate of the fraction of light making it along the ray up to
+    // the distance, made by stepping through tentative collisions and multiplying in the chance
+    // of each being a null one.
+    pub fn transmittance(&self, ray: &Ray<T>, distance: T, sampler: &mut dyn Sampler<T>) -> T {
+        let (mut t, end) = match self.span(ray, distance) {
+            Some(span) => span,
+            None => return T::one(),
+        };
+        let majorant = self.majorant();
+        let mut transmittance = T::one();
+
+        loop {
+            t = t + (T::one() - sampler.next_1d()).ln().neg() / majorant;
+            if t >= end {
+                return transmittance;
+            }
+
+            let point = ray.origin.add(&ray.direction.mul(t));
+            transmittance = transmittance * (T::one() - self.extinction(&point) / majorant);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::RandomSampler;
+
+    #[test]
+    fn density_interpolates_between_voxel_centers() {
+        let grid = DensityGrid::new((2, 1, 1), vec![0.0, 1.0]);
+
+        assert_eq!(0.0, grid.density(&Vec3::new(0.25, 0.5, 0.5)));
+        assert_eq!(0.5, grid.density(&Vec3::new(0.5, 0.5, 0.5)));
+        assert_eq!(1.0, grid.density(&Vec3::new(0.75, 0.5, 0.5)));
+        // and is held past the outer centers
+        assert_eq!(1.0, grid.density(&Vec3::new(1.0, 0.5, 0.5)));
+    }
+
+    fn uniform_volume(density: f64) -> GridVolume<f64> {
+        let grid = DensityGrid::new((2, 2, 2), vec![0.5; 8]);
+        let mut volume = GridVolume::new(grid, Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 1.0));
+        volume.set_density(density);
+
+        volume
+    }
+
+    #[test]
+    fn tracking_matches_uniform_transmittance() {
+        // half the grid's density of two, across the box
+        let volume = uniform_volume(2.0);
+        let ray = Ray::new(Vec3::new(-1.0, 0.5, 0.5), Vec3::new(1.0, 0.0, 0.0));
+        let expected = (-1.0_f64).exp();
+        let mut sampler = RandomSampler::new(5);
+
+        let count = 20000;
+        let mut transmitted = 0.0;
+        let mut missed = 0;
+        for _ in 0..count {
+            transmitted += volume.transmittance(&ray, f64::INFINITY, &mut sampler);
+            if volume
+                .sample_collision(&ray, f64::INFINITY, &mut sampler)
+                .is_none()
+            {
+                missed += 1;
+            }
+        }
+
+        assert!((transmitted / count as f64 - expected).abs() < 0.01);
+        assert!((missed as f64 / count as f64 - expected).abs() < 0.02);
+    }
+
+    #[test]
+    fn noise_grid_thins_out_at_its_edges() {
+        let grid: DensityGrid<f64> = DensityGrid::from_noise((16, 16, 16), 4.0, 7);
+
+        assert!(grid.max() > 0.0);
+        assert_eq!(0.0, grid.density(&Vec3::new(0.0, 0.0, 0.0)));
+        assert!(grid.density(&Vec3::new(0.5, 0.5, 0.5)) > 0.0);
+    }
+}